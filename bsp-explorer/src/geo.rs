@@ -8,6 +8,10 @@ use wgpu::util::DeviceExt;
 #[derive(Component)]
 pub struct Static();
 
+/// Geometry of the 3D skybox, already moved to surround the world. Drawn before everything else.
+#[derive(Component)]
+pub struct SkyLayer;
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct PropInstance {
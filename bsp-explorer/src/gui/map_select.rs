@@ -28,7 +28,9 @@ impl MapSelect {
         let file_names = entries
             .filter_map(|entry| {
                 let path = entry.ok()?.path();
-                if path.is_file() && path.extension().unwrap().to_str() == Some("bsp") {
+                // Map sources are listed too, to preview them before compiling
+                let extension = path.extension().and_then(|ext| ext.to_str());
+                if path.is_file() && matches!(extension, Some("bsp" | "vmf")) {
                     Some(root.join(path))
                 } else {
                     None
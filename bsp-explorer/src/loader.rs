@@ -2,11 +2,17 @@ use crate::geo::Static;
 use crate::v::VMesh;
 use common::prelude::*;
 use rayon::prelude::*;
-use source::bsp::gamelump::load_gamelump;
-use source::{bsp::gamelump::GameLump, prelude::*};
+use source::{
+    bsp::gamelump::GameLump,
+    compile_output::{LeakTrail, PortalFile},
+    meshes::{build_meshes, build_meshes_for_faces, MeshBuildOptions, MeshBuilder},
+    prelude::*,
+    skybox::Skybox3D,
+    vmf::{csg::build_vmf_meshes, Vmf},
+};
 
 use crate::{
-    geo::{InstancedProp, PropInstance},
+    geo::{InstancedProp, PropInstance, SkyLayer},
     state::{box_cmds, spawn_command_task, CommandTaskResult},
     transform::Transform,
     v::{vmesh::load_vmesh, vrenderer::VRenderer},
@@ -15,10 +21,9 @@ use crate::{
 use bevy_ecs::system::Commands;
 use glam::{ivec3, vec2, IVec3, Mat4, Quat, Vec3, Vec4};
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
     f32::consts::PI,
-    fs::File,
-    io::{BufReader, Read, Seek},
+    io::{Read, Seek},
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -39,6 +44,20 @@ pub fn load_bsp(
     });
 }
 
+/// Preview an uncompiled map from its brushes
+pub fn load_vmf(
+    map: &Path,
+    commands: &mut Commands,
+    game_data: Arc<GameData>,
+    renderer: &VRenderer,
+) {
+    let map_path = game_data.maps().join(map);
+    let instance = renderer.instance();
+    spawn_command_task(commands, "Loading map source", move || {
+        load_vmf_task(map_path, game_data, instance)
+    });
+}
+
 struct Shaders {
     shader_lines: Arc<VShader>,
     shader_tex: Arc<VShader>,
@@ -46,6 +65,20 @@ struct Shaders {
     shader_disp: Arc<VShader>,
     prop_shader: Arc<VShader>,
 }
+
+impl Shaders {
+    fn new(instance: &StateInstance) -> Self {
+        Self {
+            shader_lines: Arc::new(VShader::new_white_lines::<Vec3>(instance)),
+            shader_tex: Arc::new(VShader::new_textured(instance)),
+            _shader_tex_envmap: Arc::new(VShader::new_textured_envmap(instance)),
+            shader_disp: Arc::new(VShader::new_displacement(instance)),
+            prop_shader: Arc::new(VShader::new_instanced_prop::<UVAlphaVertex, PropInstance>(
+                instance,
+            )),
+        }
+    }
+}
 fn load_bsp_file_task(
     map_path: PathBuf,
     game_data: Arc<GameData>,
@@ -54,16 +87,14 @@ fn load_bsp_file_task(
 ) -> CommandTaskResult {
     match file_system_opt {
         Some(file_system) => {
-            let (header, mut buffer) = BSPHeader::load_file(&map_path, &file_system).unwrap();
-            load_bsp_task(game_data, instance, header, buffer)
+            let bsp = Bsp::load_file(&map_path, &file_system).unwrap();
+            load_bsp_task(game_data, instance, bsp)
         }
         None => {
             #[cfg(target_arch = "x86_64")]
             {
-                let file = File::open(map_path).unwrap();
-                let mut buffer = BufReader::new(file);
-                let header = BSPHeader::load_buf(&mut buffer).unwrap();
-                return load_bsp_task(game_data, instance, header, buffer);
+                let bsp = Bsp::load(&map_path).unwrap();
+                return load_bsp_task(game_data, instance, bsp);
             }
             panic!("Failed to load bsp without desktop")
         }
@@ -73,31 +104,16 @@ fn load_bsp_file_task(
 fn load_bsp_task(
     game_data: Arc<GameData>,
     instance: Arc<StateInstance>,
-    header: BSPHeader,
-    mut buffer: BufReader<impl Seek + Read>,
+    bsp: Bsp<impl Seek + Read>,
 ) -> CommandTaskResult {
-    header.validate();
+    bsp.header().validate();
 
     {
-        let v = header.version;
+        let v = bsp.header().version;
         println!("Loaded BSP File version {v}");
     }
 
-    let shader_lines = Arc::new(VShader::new_white_lines::<Vec3>(&instance));
-    let shader_tex = Arc::new(VShader::new_textured(&instance));
-    let _shader_tex_envmap = Arc::new(VShader::new_textured_envmap(&instance));
-    let shader_disp = Arc::new(VShader::new_displacement(&instance));
-    let prop_shader = Arc::new(VShader::new_instanced_prop::<UVAlphaVertex, PropInstance>(
-        &instance,
-    ));
-
-    let shaders = Arc::new(Shaders {
-        shader_lines,
-        shader_tex,
-        _shader_tex_envmap,
-        shader_disp,
-        prop_shader,
-    });
+    let shaders = Arc::new(Shaders::new(&instance));
 
     //let mut mesh = StateMesh::new(renderer, wgpu::PrimitiveTopology::TriangleList);
     //mesh.load_glb_mesh(instance.clone());
@@ -107,12 +123,12 @@ fn load_bsp_task(
     //mesh.load_debug_edges(instance.clone(), &header, &mut buffer);
     //state.add_mesh(mesh);
 
-    let faces = header.get_lump::<BSPFace>(&mut buffer);
-    let surf_edges = header.get_lump::<BSPSurfEdge>(&mut buffer);
-    let edges = header.get_lump::<BSPEdge>(&mut buffer);
-    let verts = header.get_lump::<Vec3>(&mut buffer);
-    let tex_info = header.get_lump::<BSPTexInfo>(&mut buffer);
-    let tex_data = header.get_lump::<BSPTexData>(&mut buffer);
+    let faces = bsp.faces().unwrap();
+    let surf_edges = bsp.surf_edges().unwrap();
+    let edges = bsp.edges().unwrap();
+    let verts = bsp.vertices().unwrap();
+    let tex_info = bsp.tex_info().unwrap();
+    let tex_data = bsp.tex_data().unwrap();
 
     //let mut annotated_verts = bytemuck::zeroed_slice_box::<UVVertex>(verts.len());
 
@@ -122,9 +138,9 @@ fn load_bsp_task(
 
     //let mut tris = Vec::<u16>::new();
     // for now, filter by texture of first face
-    let infos = header.get_lump::<BSPDispInfo>(&mut buffer);
-    let disp_verts = header.get_lump::<BSPDispVert>(&mut buffer);
-    let lighting = header.get_lump::<ColorRGBExp32>(&mut buffer);
+    let infos = bsp.disp_infos().unwrap();
+    let disp_verts = bsp.disp_verts().unwrap();
+    let lighting = bsp.lighting().unwrap();
 
     let mut lighting_cols: Vec<Vec4> = lighting.iter().map(|&x| x.into()).collect();
 
@@ -139,20 +155,41 @@ fn load_bsp_task(
 
     let textured_tris = build_meshes(
         faces,
-        &verts,
-        &disp_verts,
-        &tex_info,
-        &tex_data,
-        &infos,
-        &edges,
-        &surf_edges,
+        verts,
+        disp_verts,
+        tex_info,
+        tex_data,
+        infos,
+        edges,
+        surf_edges,
     );
 
-    let pak_header = header.get_lump_header(LumpType::PakFile);
-    let pak: Arc<VPKDirectory> = Arc::new(pak_header.read_binary(&mut buffer).unwrap());
+    let sky_3d = match Skybox3D::find(&bsp) {
+        Ok(sky_3d) => sky_3d,
+        Err(e) => {
+            println!("Failed to find 3D skybox: {e}");
+            None
+        }
+    };
+
+    // Skybox faces are drawn again on their own layer, moved and scaled to surround the world
+    let sky_tris = match &sky_3d {
+        Some(sky) => match build_meshes_for_faces(&bsp, &sky.faces, &MeshBuildOptions::default()) {
+            Ok(mut built) => {
+                for builder in built.meshes.values_mut() {
+                    builder.transform(sky.transform());
+                }
+                built.meshes
+            }
+            Err(e) => {
+                println!("Failed to build 3D skybox: {e}");
+                HashMap::new()
+            }
+        },
+        None => HashMap::new(),
+    };
 
-    let tex_data_string_table = header.get_lump::<BSPTexDataStringTable>(&mut buffer);
-    let tex_data_string_data = header.get_lump_header(LumpType::TexDataStringData);
+    let pak: Arc<VPKDirectory> = bsp.pak().unwrap().clone();
 
     let material_name_map: Arc<HashMap<_, _>> = Arc::new(
         textured_tris
@@ -160,14 +197,15 @@ fn load_bsp_task(
             .map(|(tex, _tris)| {
                 (
                     *tex,
-                    tex_data_string_table[tex_data[*tex as usize].name_string_table_id as usize]
-                        .get_filename(&mut buffer, tex_data_string_data),
+                    bsp.texture_name(*tex as usize)
+                        .unwrap_or_default()
+                        .to_owned(),
                 )
             })
             .collect(),
     );
 
-    let _models = header.get_lump::<BSPModel>(&mut buffer);
+    let _models = bsp.models().unwrap();
 
     // for m in models.iter() {
     //     commands.spawn((
@@ -175,17 +213,42 @@ fn load_bsp_task(
     //         Static(),
     //     ));
     // }
-    let gamelump = load_gamelump(header.get_lump_header(LumpType::GameLump), &mut buffer).unwrap();
+    let gamelump = bsp.game_lump().unwrap().clone();
+
+    // Vis portals and the leak trail from compiling, if they are next to the map
+    let mut debug_lines = Vec::new();
+    match PortalFile::load_for_map(&bsp) {
+        Ok(prt) => debug_lines.extend(prt.portals.iter().flat_map(|portal| portal.edges())),
+        Err(e) => println!("No portal file: {e}"),
+    }
+    if let Ok(lin) = LeakTrail::load_for_map(&bsp) {
+        println!("Map has a leak, drawing its pointfile");
+        debug_lines.extend(lin.segments());
+    }
 
     box_cmds(move |commands| {
         // Create a lighting buffer for use in all shaders
         insert_lighting_buffer(commands, &lighting_cols[..], &instance);
 
-        for (tex, builder) in textured_tris {
+        if !debug_lines.is_empty() {
+            let verts: Vec<Vec3> = debug_lines.iter().flat_map(|&(a, b)| [a, b]).collect();
+            let indices: Vec<u32> = (0..verts.len() as u32).collect();
+            commands.spawn((
+                VMesh::new(&instance.device, &verts, &indices, shaders.shader_lines.clone()),
+                Static(),
+            ));
+        }
+
+        let layers = textured_tris
+            .into_iter()
+            .map(|tris| (tris, false))
+            .chain(sky_tris.into_iter().map(|tris| (tris, true)));
+
+        for ((tex, builder), sky) in layers {
             let renderer = instance.clone();
             let game_data = game_data.clone();
             let shaders = shaders.clone();
-            let pak = pak.clone();
+            let pak = Some(pak.clone());
             let material_name_map = material_name_map.clone();
             spawn_command_task(commands, "Loading Static", move || {
                 load_static(
@@ -196,12 +259,13 @@ fn load_bsp_task(
                     builder,
                     pak,
                     shaders,
+                    sky,
                 )
             });
         }
 
         spawn_command_task(commands, "Loading game lump", move || {
-            load_props(game_data, instance, gamelump, shaders)
+            load_props(game_data, instance, gamelump, shaders, sky_3d)
         });
     })
 }
@@ -242,47 +306,52 @@ pub fn insert_lighting_buffer(
 fn load_props(
     game_data: Arc<GameData>,
     instance: Arc<StateInstance>,
-    gamelump: GameLump,
+    gamelump: Arc<GameLump>,
     shaders: Arc<Shaders>,
+    sky_3d: Option<Skybox3D>,
 ) -> CommandTaskResult {
+    // Keyed by prop type, and whether the instances are in the 3D skybox layer
     let mut instances = HashMap::new();
 
-    for prop in &gamelump.props {
+    for (i_prop, prop) in gamelump.props.iter().enumerate() {
         let path = gamelump.static_prop_names[prop.prop_type as usize].as_str();
 
-        let m = load_vmesh(
-            &VGlobalPath::new(&path),
-            &instance,
-            shaders.prop_shader.clone(),
-            &game_data,
-        );
-
-        match m {
-            Ok(m) => {
-                // euler angles in radians
-                let a = prop.angles * PI / 180.0;
-                let rot = Quat::from_axis_angle(Vec3::Z, a.y)
-                    * Quat::from_axis_angle(Vec3::X, a.z)
-                    * Quat::from_axis_angle(Vec3::Y, a.x);
-
-                let t = Transform::new(prop.m_origin.into(), rot);
-
-                let mat = PropInstance {
-                    transform: t.get_local_to_world(),
-                };
-
-                let e = instances
-                    .entry(prop.prop_type)
-                    .or_insert_with(|| (m, InstancedProp::default()));
-
-                e.1.transforms.push(mat);
-
-                // commands.spawn((
-                //     m,
-                //    ,
-                // ));
-            }
-            Err(e) => println!("Error loading {path}: {e}"),
+        // euler angles in radians
+        let a = prop.angles * PI / 180.0;
+        let rot = Quat::from_axis_angle(Vec3::Z, a.y)
+            * Quat::from_axis_angle(Vec3::X, a.z)
+            * Quat::from_axis_angle(Vec3::Y, a.x);
+
+        let t = Transform::new(prop.m_origin.into(), rot);
+        let transform = t.get_local_to_world();
+
+        // Props in the 3D skybox are drawn again, scaled up around the world
+        let mut placements = vec![(false, transform)];
+        if let Some(sky) = sky_3d
+            .as_ref()
+            .filter(|sky| sky.props.binary_search(&i_prop).is_ok())
+        {
+            placements.push((true, sky.transform() * transform));
+        }
+
+        for (sky, transform) in placements {
+            let e = match instances.entry((prop.prop_type, sky)) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => match load_vmesh(
+                    &VGlobalPath::new(&path),
+                    &instance,
+                    shaders.prop_shader.clone(),
+                    &game_data,
+                ) {
+                    Ok(m) => e.insert((m, InstancedProp::default())),
+                    Err(err) => {
+                        println!("Error loading {path}: {err}");
+                        break;
+                    }
+                },
+            };
+
+            e.1.transforms.push(PropInstance::new(transform));
         }
 
         // commands.spawn((
@@ -297,20 +366,81 @@ fn load_props(
     }
 
     box_cmds(|commands| {
-        for (i, bundle) in instances {
-            commands.spawn(bundle);
+        for ((_, sky), bundle) in instances {
+            if sky {
+                commands.spawn((bundle.0, bundle.1, SkyLayer));
+            } else {
+                commands.spawn(bundle);
+            }
         }
     })
 }
 
+fn load_vmf_task(
+    map_path: PathBuf,
+    game_data: Arc<GameData>,
+    instance: Arc<StateInstance>,
+) -> CommandTaskResult {
+    let vmf = match Vmf::load(&map_path) {
+        Ok(vmf) => vmf,
+        Err(e) => {
+            println!("Failed to load {}: {e}", map_path.display());
+            return box_cmds(|_| {});
+        }
+    };
+
+    let built = build_vmf_meshes(&vmf, &MeshBuildOptions::default(), |material| {
+        game_data.material_size(material)
+    });
+    if !built.degenerate.is_empty() {
+        println!("Brush sides clipped away entirely: {:?}", built.degenerate);
+    }
+
+    let shaders = Arc::new(Shaders::new(&instance));
+    let material_name_map: Arc<HashMap<_, _>> = Arc::new(
+        built
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (i as i32, name.clone()))
+            .collect(),
+    );
+
+    box_cmds(move |commands| {
+        // Nothing is lit before compiling, and the shaders draw faces without a lightmap fully lit
+        insert_lighting_buffer(commands, &[Vec4::ONE], &instance);
+
+        for (tex, builder) in built.meshes {
+            let renderer = instance.clone();
+            let game_data = game_data.clone();
+            let shaders = shaders.clone();
+            let material_name_map = material_name_map.clone();
+            spawn_command_task(commands, "Loading Static", move || {
+                load_static(
+                    game_data,
+                    renderer,
+                    material_name_map,
+                    tex,
+                    builder,
+                    None,
+                    shaders,
+                    false,
+                )
+            });
+        }
+    })
+}
+
+/// `pak` is the map's packed files, which are searched for materials before the game's
 fn load_static(
     game_data: Arc<GameData>,
     instance: Arc<StateInstance>,
     material_name_map: Arc<HashMap<i32, String>>,
     material: i32,
     builder: MeshBuilder<UVVertex>,
-    pak: Arc<VPKDirectory>,
+    pak: Option<Arc<VPKDirectory>>,
     shaders: Arc<Shaders>,
+    sky: bool,
 ) -> CommandTaskResult {
     // Load vmt
     let Some(mat_name) = material_name_map.get(&material) else {
@@ -318,9 +448,9 @@ fn load_static(
     };
 
     let mat_path = VLocalPath::new("materials", mat_name, "vmt");
-    let pak_vmt = pak.load_vmt(&mat_path);
+    let pak_vmt = pak.as_ref().map(|pak| pak.load_vmt(&mat_path));
 
-    let vmt = if let Ok(pak_vmt) = pak_vmt {
+    let vmt = if let Some(Ok(pak_vmt)) = pak_vmt {
         if pak_vmt.shader() == "patch" {
             // If this is a patch, link it to the other patch
             pak_vmt.patch.get_or_init(|| {
@@ -384,12 +514,7 @@ fn load_static(
 
     let mut mesh = VMesh::new_empty(&instance.device, shader);
 
-    mesh.from_verts_and_tris(
-        &instance.device,
-        bytemuck::cast_slice(&builder.verts),
-        bytemuck::cast_slice(&builder.tris),
-        builder.tris.len() as u32,
-    );
+    mesh.from_verts_and_tris(&instance.device, builder.verts(), builder.tris());
     let mut all_success = true;
     for (i, tex) in shader_textures.iter().enumerate() {
         let tex_path = {
@@ -406,12 +531,16 @@ fn load_static(
         let vtf = if let Some(vtf) = game_data.load_vtf(&vtf_path) {
             vtf
         } else {
-            match pak.load_vtf(&vtf_path) {
-                Ok(vtf) => vtf,
-                Err(x) => {
+            match pak.as_ref().map(|pak| pak.load_vtf(&vtf_path)) {
+                Some(Ok(vtf)) => vtf,
+                Some(Err(x)) => {
                     println!("ERROR: {x} Could not find vtf for {tex}: <{tex_path}>");
                     continue;
                 }
+                None => {
+                    println!("ERROR: Could not find vtf for {tex}: <{tex_path}>");
+                    continue;
+                }
             }
         };
 
@@ -425,7 +554,11 @@ fn load_static(
 
     box_cmds(move |commands| {
         if all_success {
-            commands.spawn((mesh, Static()));
+            if sky {
+                commands.spawn((mesh, Static(), SkyLayer));
+            } else {
+                commands.spawn((mesh, Static()));
+            }
         }
     })
 }
@@ -11,7 +11,7 @@ use crate::camera_controller::{
 use crate::gui::gui::Gui;
 //#[cfg(target_arch = "x86_64")]
 //use crate::gui::{Gui, GuiWindow, TaskViewer};
-use crate::loader::load_bsp;
+use crate::loader::{load_bsp, load_vmf};
 use crate::v::vrenderer::{draw_static, VRenderer};
 use bevy_ecs::prelude::*;
 use bevy_ecs::system::SystemState;
@@ -297,6 +297,12 @@ pub fn load_map(
         if let Some(game_data) = &game_data_opt {
             log::warn!("Loading map {:?}", e.0);
 
+            // Map sources are previewed from their brushes
+            if e.0.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("vmf")) {
+                load_vmf(&e.0, &mut commands, game_data.inner.clone(), &renderer);
+                continue;
+            }
+
             load_bsp(
                 &e.0,
                 &mut commands,
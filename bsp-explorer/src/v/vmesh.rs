@@ -9,6 +9,20 @@ use source::{prelude::{LightingData, BSPEdge, BSPHeader}, game_data::GameData, v
 use wgpu::util::DeviceExt;
 
 use super::{vrenderer::VRenderer};
+
+/// Integer types that can fill an index buffer
+pub trait VIndex: bytemuck::Pod {
+    const FORMAT: wgpu::IndexFormat;
+}
+
+impl VIndex for u16 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint16;
+}
+
+impl VIndex for u32 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
+}
+
 #[derive(Component)]
 pub struct VMesh {
     vertex_buffer: wgpu::Buffer,
@@ -95,12 +109,12 @@ impl VMesh {
         )
     }
     pub fn new_empty(device: &wgpu::Device, shader: Arc<VShader>) -> Self {
-        Self::new::<UVVertex>(device, &[], &[], shader)
+        Self::new::<UVVertex, u16>(device, &[], &[], shader)
     }
-    pub fn new<V: Vertex + bytemuck::Pod>(
+    pub fn new<V: Vertex + bytemuck::Pod, I: VIndex>(
         device: &wgpu::Device,
         verts_data: &[V],
-        indices_data: &[u16],
+        indices_data: &[I],
         shader: Arc<VShader>,
     ) -> Self {
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -121,7 +135,7 @@ impl VMesh {
             num_indices: indices_data.len() as u32,
             texture_bind_group: Default::default(),
             shader,
-            index_format: wgpu::IndexFormat::Uint16,
+            index_format: I::FORMAT,
         }
     }
 
@@ -131,8 +145,8 @@ impl VMesh {
         header: &BSPHeader,
         buffer: &mut BufReader<File>,
     ) {
-        let edges = header.get_lump::<BSPEdge>(buffer);
-        let verts = header.get_lump::<Vec3>(buffer);
+        let edges = header.get_lump::<BSPEdge>(buffer).unwrap();
+        let verts = header.get_lump::<Vec3>(buffer).unwrap();
 
         let mut annotated_verts = bytemuck::zeroed_slice_box::<UVVertex>(verts.len());
 
@@ -158,29 +172,28 @@ impl VMesh {
         self.index_format = wgpu::IndexFormat::Uint16;
     }
 
-    pub fn from_verts_and_tris(
+    pub fn from_verts_and_tris<V: Vertex, I: VIndex>(
         &mut self,
         device: &wgpu::Device,
-        verts: &[u8],
-        tris: &[u8],
-        num_indicies: u32,
+        verts: &[V],
+        tris: &[I],
     ) {
         self.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
-            contents: verts,
+            contents: bytemuck::cast_slice(verts),
             usage: wgpu::BufferUsages::VERTEX,
         });
 
         self.index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Index Buffer"),
-            contents: tris,
+            contents: bytemuck::cast_slice(tris),
             usage: wgpu::BufferUsages::INDEX,
         });
 
         // Update the value stored in this mesh
 
-        self.num_indices = num_indicies;
-        self.index_format = wgpu::IndexFormat::Uint16;
+        self.num_indices = tris.len() as u32;
+        self.index_format = I::FORMAT;
     }
 
     pub fn load_tex(&mut self, device: &wgpu::Device, bind_index: u32, texture: &VTexture) {
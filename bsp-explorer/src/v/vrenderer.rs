@@ -3,7 +3,7 @@ use std::sync::Arc;
 use crate::{
     camera::{Camera, CameraUniform},
     camera_controller::CameraController,
-    geo::{InstancedProp, Static},
+    geo::{InstancedProp, SkyLayer, Static},
     gui::gui::{Gui, GuiWindow},
 };
 use bevy_ecs::{
@@ -33,8 +33,8 @@ pub struct VRenderer {
 }
 
 pub fn draw_static(
-    static_meshes: Query<(&VMesh, &Static)>,
-    prop_meshes: Query<(&VMesh, &InstancedProp)>,
+    static_meshes: Query<(&VMesh, &Static, Option<&SkyLayer>)>,
+    prop_meshes: Query<(&VMesh, &InstancedProp, Option<&SkyLayer>)>,
     cameras: Query<(&CameraUniform,)>,
     gui_windows: Query<&mut GuiWindow>,
     mut gui: NonSendMut<Gui>,
@@ -68,23 +68,32 @@ pub fn draw_static(
         bytemuck::cast_slice(&[*cameras.single().0]),
     );
 
-    {
+    // The 3D skybox is drawn first, then the world over it with a fresh depth buffer
+    for sky_layer in [true, false] {
         //let meshes = self.meshes.lock().unwrap();
         let mut render_pass: wgpu::RenderPass<'_> =
             encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
+                label: Some(if sky_layer {
+                    "Sky Render Pass"
+                } else {
+                    "Render Pass"
+                }),
                 color_attachments: &[
                     // This is what @location(0) in the fragment shader targets
                     Some(wgpu::RenderPassColorAttachment {
                         view: &view,
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.1,
-                                g: 0.2,
-                                b: 0.3,
-                                a: 1.0,
-                            }),
+                            load: if sky_layer {
+                                wgpu::LoadOp::Clear(wgpu::Color {
+                                    r: 0.1,
+                                    g: 0.2,
+                                    b: 0.3,
+                                    a: 1.0,
+                                })
+                            } else {
+                                wgpu::LoadOp::Load
+                            },
                             store: true,
                         },
                     }),
@@ -100,12 +109,18 @@ pub fn draw_static(
             });
 
         if let Some(lighting) = &lighting_opt {
-            for (mesh, _) in static_meshes.iter() {
+            for (mesh, _, _) in static_meshes
+                .iter()
+                .filter(|(_, _, sky)| sky.is_some() == sky_layer)
+            {
                 mesh.draw(&renderer, &mut render_pass, lighting);
             }
         }
 
-        for (mesh, prop) in prop_meshes.iter() {
+        for (mesh, prop, _) in prop_meshes
+            .iter()
+            .filter(|(_, _, sky)| sky.is_some() == sky_layer)
+        {
             // renderer.queue().write_buffer(
             //     &prop.model.buffer,
             //     0,
@@ -56,6 +56,11 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 	// 	discard;
 	// }
 
+	// Faces without lightmap data are drawn fully lit
+	if in.color.x < 0 {
+		return vec4<f32>(t.rgb, 1.0);
+	}
+
 	var width = i32(in.color.y);
 	var first_light = i32(in.color.x);
 
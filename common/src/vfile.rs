@@ -36,3 +36,16 @@ impl VFileSystem {
         }
     }
 }
+
+/// Files already in memory by path, such as those uploaded to the web viewer
+impl FromIterator<(String, Vec<u8>)> for VFileSystem {
+    fn from_iter<I: IntoIterator<Item = (String, Vec<u8>)>>(iter: I) -> Self {
+        Self {
+            files: Arc::new(
+                iter.into_iter()
+                    .map(|(path, data)| (path, VFile { data }))
+                    .collect(),
+            ),
+        }
+    }
+}
@@ -4,7 +4,7 @@ pub mod vtf_asset_loader;
 
 use core::f32;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
     io::{self, BufReader, Cursor, Read},
     mem,
@@ -21,10 +21,12 @@ use bevy::{
         },
         AssetLoader, AssetPath, AsyncReadExt, LoadContext,
     },
-    color::palettes::css::WHITE,
+    color::palettes::css::{AQUA, GRAY, LIME, ORANGE, RED, WHITE, YELLOW},
+    core_pipeline::Skybox,
     math::VectorSpace,
     prelude::*,
     render::{
+        camera::ClearColorConfig,
         mesh::{MeshVertexAttribute, PrimitiveTopology},
         render_asset::RenderAssetUsages,
         render_resource::Texture,
@@ -32,6 +34,7 @@ use bevy::{
         texture::{
             GpuImage, ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor,
         },
+        view::RenderLayers,
     },
     tasks::futures_lite::{io::Take, AsyncRead, AsyncSeek, AsyncSeekExt},
 };
@@ -52,15 +55,21 @@ use smooth_bevy_cameras::{
     LookTransform, LookTransformBundle, LookTransformPlugin, Smoother,
 };
 use source::{
-    bsp::gamelump::{load_gamelump, GameLump},
-    meshes::build_meshes,
+    compile_output::{LeakTrail, PortalFile},
+    meshes::{build_meshes, build_meshes_for_faces, MeshBuildOptions},
+    nav::{
+        path::{NavPath, NavTraverse, PathCosts},
+        NavMesh,
+    },
     prelude::*,
+    skybox::{self, Skybox3D, SkyboxCubemap},
     studio::vvd::Fixup,
+    vmf::{csg::build_vmf_meshes, Vmf},
 };
 use vmt_asset_loader::VMTAssetLoader;
 use vpk_asset_reader::VPKAssetReader;
 use vtf_asset_loader::VTFAssetLoader;
-use wgpu::{Extent3d, TextureViewDescriptor};
+use wgpu::{Extent3d, TextureViewDescriptor, TextureViewDimension};
 
 #[derive(Resource)]
 pub struct GameDataArc {
@@ -73,6 +82,35 @@ struct SourceObject {
     // open: bool,
 }
 
+/// Draws the 3D skybox layer behind the world, following the main camera
+#[derive(Component)]
+struct SkyLayerCamera;
+
+const SKY_LAYER: usize = 1;
+
+/// The map's nav mesh, and a path across it picked with N (start) and M (goal) from the camera's position
+#[derive(Resource)]
+struct NavDebug {
+    mesh: NavMesh,
+    start: Option<Vec3>,
+    goal: Option<Vec3>,
+    path: Option<NavPath>,
+}
+
+/// Vis portals and the leak trail written when the map was compiled. P toggles the portals.
+#[derive(Resource)]
+struct CompileOverlays {
+    portals: Option<PortalFile>,
+    leak: Option<LeakTrail>,
+    show_portals: bool,
+}
+
+/// Source units are inches with z up, the scene is in metres with y up
+fn source_to_world() -> Transform {
+    Transform::from_rotation(Quat::from_rotation_x(-90_f32.to_radians()))
+        .with_scale(Vec3::ONE * 0.01)
+}
+
 fn main() {
     App::new()
         .register_asset_source(
@@ -97,6 +135,12 @@ fn main() {
         // or after the `EguiSet::BeginFrame` system (which belongs to the `CoreSet::PreUpdate` set).
         // .add_systems(Update, ui_example_system)
         .add_systems(Startup, load)
+        .add_systems(Update, (pick_nav_path, draw_nav_gizmos).chain())
+        .add_systems(Update, draw_compile_overlays)
+        .add_systems(
+            PostUpdate,
+            follow_main_camera.before(bevy::transform::TransformSystem::TransformPropagate),
+        )
         .run();
 }
 
@@ -131,11 +175,26 @@ fn builder_to_mesh(
     //     Mesh::ATTRIBUTE_COLOR,
     //     builder.verts().iter().map(|v| v.color.to_array()).collect::<Vec<_>>(),
     // );
-    mesh.insert_indices(bevy::render::mesh::Indices::U16(builder.tris().to_vec()));
+    mesh.insert_indices(bevy::render::mesh::Indices::U32(builder.tris().to_vec()));
 
     meshes.add(mesh)
 }
 
+/// The scene is rotated from Source's Z up to Y up, which matches the `(x, z, y)` sampling of the cubemap
+/// once Bevy's skybox flips Z for its left handed lookups.
+fn cubemap_to_image(sky: SkyboxCubemap) -> Image {
+    Image {
+        texture_descriptor: sky.descriptor(),
+        texture_view_descriptor: Some(TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..default()
+        }),
+        data: sky.data,
+        asset_usage: RenderAssetUsages::RENDER_WORLD,
+        ..default()
+    }
+}
+
 fn builder_to_mesh2(
     verts: &[UVAlphaVertex],
     indices: &[u16],
@@ -287,6 +346,7 @@ fn load(
     mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
     // mut vmt_materials: ResMut<Assets<VMTAsset>>,
 ) {
     let ini = Ini::load_from_file("conf.ini").unwrap();
@@ -294,14 +354,26 @@ fn load(
 
     // println!("{:?}",game_data.dirs()[0].files);
 
-    let (header, mut buffer) = BSPHeader::load(&game_data.starter_map()).unwrap();
+    // `source-explorer path/to/map.vmf` previews an uncompiled map instead of the starter map
+    if let Some(vmf_path) = std::env::args().nth(1).filter(|arg| arg.ends_with(".vmf")) {
+        load_vmf(
+            Path::new(&vmf_path),
+            &game_data,
+            &mut commands,
+            &asset_server,
+            &mut meshes,
+        );
+        return;
+    }
+
+    let bsp = Bsp::load(&game_data.starter_map()).unwrap();
 
-    let faces = header.get_lump::<BSPFace>(&mut buffer);
-    let surf_edges = header.get_lump::<BSPSurfEdge>(&mut buffer);
-    let edges = header.get_lump::<BSPEdge>(&mut buffer);
-    let verts = header.get_lump::<Vec3>(&mut buffer);
-    let tex_info = header.get_lump::<BSPTexInfo>(&mut buffer);
-    let tex_data = header.get_lump::<BSPTexData>(&mut buffer);
+    let faces = bsp.faces().unwrap();
+    let surf_edges = bsp.surf_edges().unwrap();
+    let edges = bsp.edges().unwrap();
+    let verts = bsp.vertices().unwrap();
+    let tex_info = bsp.tex_info().unwrap();
+    let tex_data = bsp.tex_data().unwrap();
 
     //let mut annotated_verts = bytemuck::zeroed_slice_box::<UVVertex>(verts.len());
 
@@ -311,16 +383,55 @@ fn load(
 
     //let mut tris = Vec::<u16>::new();
     // for now, filter by texture of first face
-    let infos = header.get_lump::<BSPDispInfo>(&mut buffer);
-    let disp_verts = header.get_lump::<BSPDispVert>(&mut buffer);
-    let lighting = header.get_lump::<ColorRGBExp32>(&mut buffer);
+    let infos = bsp.disp_infos().unwrap();
+    let disp_verts = bsp.disp_verts().unwrap();
+    let lighting = bsp.lighting().unwrap();
 
-    let gamelump = load_gamelump(header.get_lump_header(LumpType::GameLump), &mut buffer).unwrap();
+    let gamelump = bsp.game_lump().unwrap();
 
     let mut lighting_cols: Vec<Vec4> = lighting.iter().map(|&x| x.into()).collect();
 
-    let pak_header = header.get_lump_header(LumpType::PakFile);
-    let pak_vpk: VPKDirectory = pak_header.read_binary(&mut buffer).unwrap();
+    let pak_vpk = bsp.pak().unwrap();
+
+    match NavMesh::load_for_map(&bsp) {
+        Ok(mesh) => commands.insert_resource(NavDebug {
+            mesh,
+            start: None,
+            goal: None,
+            path: None,
+        }),
+        Err(e) => log::info!("No nav mesh: {e}"),
+    }
+
+    let portals = PortalFile::load_for_map(&bsp)
+        .inspect_err(|e| log::info!("No portal file: {e}"))
+        .ok();
+    let leak = LeakTrail::load_for_map(&bsp).ok();
+    if leak.is_some() {
+        log::warn!("Map has a leak, drawing its pointfile");
+    }
+    commands.insert_resource(CompileOverlays {
+        portals,
+        leak,
+        show_portals: true,
+    });
+
+    let sky_3d = Skybox3D::find(&bsp).unwrap_or_else(|e| {
+        log::warn!("Failed to find 3D skybox: {e}");
+        None
+    });
+
+    let sky = bsp
+        .entities()
+        .ok()
+        .and_then(skybox::sky_name)
+        .and_then(|name| match SkyboxCubemap::load(&game_data, name, true) {
+            Ok(sky) => Some(images.add(cubemap_to_image(sky))),
+            Err(e) => {
+                log::warn!("Failed to load sky {name}: {e}");
+                None
+            }
+        });
 
     //let pak: VPKDirectory = VPKDirectory::read(&mut buffer, files, "".into()).unwrap();
 
@@ -335,17 +446,25 @@ fn load(
 
     let textured_tris = build_meshes(
         faces,
-        &verts,
-        &disp_verts,
-        &tex_info,
-        &tex_data,
-        &infos,
-        &edges,
-        &surf_edges,
+        verts,
+        disp_verts,
+        tex_info,
+        tex_data,
+        infos,
+        edges,
+        surf_edges,
     );
 
-    let tex_data_string_table = header.get_lump::<BSPTexDataStringTable>(&mut buffer);
-    let tex_data_string_data = header.get_lump_header(LumpType::TexDataStringData);
+    // Skybox faces are also in the world, so the material names found for the world cover them
+    let sky_tris = sky_3d
+        .as_ref()
+        .and_then(|sky| {
+            build_meshes_for_faces(&bsp, &sky.faces, &MeshBuildOptions::default())
+                .inspect_err(|e| log::warn!("Failed to build 3D skybox: {e}"))
+                .ok()
+        })
+        .map(|built| built.meshes)
+        .unwrap_or_default();
 
     let material_name_map: Arc<HashMap<_, _>> = Arc::new(
         textured_tris
@@ -353,8 +472,9 @@ fn load(
             .map(|(tex, _tris)| {
                 (
                     *tex,
-                    tex_data_string_table[tex_data[*tex as usize].name_string_table_id as usize]
-                        .get_filename(&mut buffer, tex_data_string_data),
+                    bsp.texture_name(*tex as usize)
+                        .unwrap_or_default()
+                        .to_owned(),
                 )
             })
             .collect(),
@@ -362,13 +482,28 @@ fn load(
 
     let scene = commands
         .spawn((SpatialBundle {
-            transform: Transform::from_rotation(Quat::from_rotation_x(-90_f32.to_radians()))
-                .with_scale(Vec3::ONE * 0.01),
+            transform: source_to_world(),
             ..default()
         },))
         .id();
 
-    for prop in &gamelump.props {
+    // Skybox geometry is drawn a second time, scaled up around the world on its own layer
+    let sky_root = sky_3d.as_ref().map(|sky| {
+        let root = commands
+            .spawn(SpatialBundle {
+                transform: Transform::from_matrix(sky.transform()),
+                ..default()
+            })
+            .id();
+        commands.entity(scene).push_children(&[root]);
+        root
+    });
+    let sky_props: HashSet<usize> = sky_3d
+        .iter()
+        .flat_map(|sky| sky.props.iter().copied())
+        .collect();
+
+    for (i_prop, prop) in gamelump.props.iter().enumerate() {
         let path = gamelump.static_prop_names[prop.prop_type as usize].as_str();
 
         // println!("{}", path);
@@ -390,6 +525,22 @@ fn load(
 
         let transform = Transform::from_translation(prop.m_origin.into()).with_rotation(rot);
 
+        if let Some(sky_root) = sky_root.filter(|_| sky_props.contains(&i_prop)) {
+            let obj = commands
+                .spawn((
+                    SourceObject { egui: None },
+                    MaterialMeshBundle::<StandardMaterial> {
+                        mesh: mesh.clone(),
+                        material: material.clone(),
+                        transform,
+                        ..default()
+                    },
+                    RenderLayers::layer(SKY_LAYER),
+                ))
+                .id();
+            commands.entity(sky_root).push_children(&[obj]);
+        }
+
         let obj = commands
             .spawn((
                 SourceObject { egui: None },
@@ -404,7 +555,12 @@ fn load(
         commands.entity(scene).push_children(&[obj]);
     }
 
-    for (material, builder) in textured_tris {
+    let layers = textured_tris
+        .into_iter()
+        .map(|tris| (tris, None))
+        .chain(sky_tris.into_iter().map(|tris| (tris, sky_root)));
+
+    for ((material, builder), sky_root) in layers {
         let mesh = builder_to_mesh(&builder, &mut meshes);
 
         let Some(mat_name) = material_name_map.get(&material) else {
@@ -441,18 +597,22 @@ fn load(
             }
         };
         if let Some(material) = material {
-            let obj = commands
-                .spawn((
-                    SourceObject { egui: None },
-                    MaterialMeshBundle::<StandardMaterial> {
-                        mesh,
-                        material,
-                        ..default()
-                    },
-                ))
-                .id();
+            let mut obj = commands.spawn((
+                SourceObject { egui: None },
+                MaterialMeshBundle::<StandardMaterial> {
+                    mesh,
+                    material,
+                    ..default()
+                },
+            ));
+            if sky_root.is_some() {
+                obj.insert(RenderLayers::layer(SKY_LAYER));
+            }
+            let obj = obj.id();
 
-            commands.entity(scene).push_children(&[obj]);
+            commands
+                .entity(sky_root.unwrap_or(scene))
+                .push_children(&[obj]);
         }
 
         // vmt_materials.get(vmt.id()).unwrap();
@@ -593,18 +753,39 @@ fn load(
         brightness: 100.0,
     });
 
-    // camera
-    commands.spawn((
-        Camera3dBundle {
-            transform: Transform::from_xyz(-2.5, 4.5, 9.0).looking_at(Vec3::ZERO, Vec3::Y),
-            ..default()
-        },
-        UnrealCameraController::default(),
-        LookTransformBundle {
-            transform: LookTransform::new(vec3(-2.5, 4.5, 9.0), Vec3::ZERO, Vec3::Y),
-            smoother: Smoother::new(00.1), // Value between 0.0 and 1.0, higher is smoother.
+    let camera_transform = Transform::from_xyz(-2.5, 4.5, 9.0).looking_at(Vec3::ZERO, Vec3::Y);
+
+    // With a 3D skybox, a first camera clears to the 2D sky and draws the skybox layer, then the main camera
+    // draws the world over it with its own depth buffer
+    let sky_camera = sky_3d.is_some().then(|| {
+        commands
+            .spawn((
+                Camera3dBundle {
+                    transform: camera_transform,
+                    ..default()
+                },
+                RenderLayers::layer(SKY_LAYER),
+                SkyLayerCamera,
+            ))
+            .id()
+    });
+
+    let camera = spawn_camera(
+        &mut commands,
+        if sky_camera.is_some() {
+            ClearColorConfig::None
+        } else {
+            ClearColorConfig::Default
         },
-    ));
+    );
+    if let Some(image) = sky {
+        commands
+            .entity(sky_camera.unwrap_or(camera))
+            .insert(Skybox {
+                image,
+                brightness: 1000.0,
+            });
+    }
 
     // println!("{:?c}", textured_tris.len());
 
@@ -613,6 +794,187 @@ fn load(
     // });
 }
 
+fn spawn_camera(commands: &mut Commands, clear_color: ClearColorConfig) -> Entity {
+    commands
+        .spawn((
+            Camera3dBundle {
+                camera: Camera {
+                    order: 1,
+                    clear_color,
+                    ..default()
+                },
+                transform: Transform::from_xyz(-2.5, 4.5, 9.0).looking_at(Vec3::ZERO, Vec3::Y),
+                ..default()
+            },
+            UnrealCameraController::default(),
+            LookTransformBundle {
+                transform: LookTransform::new(vec3(-2.5, 4.5, 9.0), Vec3::ZERO, Vec3::Y),
+                smoother: Smoother::new(00.1), // Value between 0.0 and 1.0, higher is smoother.
+            },
+        ))
+        .id()
+}
+
+/// Preview a map's brushes before it is compiled. There is no lighting, props or sky until `vbsp` has run.
+fn load_vmf(
+    path: &Path,
+    game_data: &GameData,
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    meshes: &mut ResMut<Assets<Mesh>>,
+) {
+    let vmf = match Vmf::load(path) {
+        Ok(vmf) => vmf,
+        Err(e) => {
+            log::error!("Failed to load {}: {e}", path.display());
+            return;
+        }
+    };
+
+    let built = build_vmf_meshes(&vmf, &MeshBuildOptions::default(), |material| {
+        game_data.material_size(material)
+    });
+    if !built.degenerate.is_empty() {
+        log::warn!(
+            "{} brush sides were clipped away entirely: {:?}",
+            built.degenerate.len(),
+            built.degenerate
+        );
+    }
+
+    let scene = commands
+        .spawn(SpatialBundle {
+            transform: source_to_world(),
+            ..default()
+        })
+        .id();
+
+    for (key, builder) in &built.meshes {
+        let Some(mat_name) = built.material(*key) else {
+            continue;
+        };
+        let obj = commands
+            .spawn((
+                SourceObject { egui: None },
+                MaterialMeshBundle::<StandardMaterial> {
+                    mesh: builder_to_mesh(builder, meshes),
+                    material: asset_server.load(format!("vpk://materials/{mat_name}.vmt")),
+                    ..default()
+                },
+            ))
+            .id();
+        commands.entity(scene).push_children(&[obj]);
+    }
+
+    commands.insert_resource(AmbientLight {
+        color: WHITE.into(),
+        brightness: 100.0,
+    });
+    spawn_camera(commands, ClearColorConfig::Default);
+}
+
+fn follow_main_camera(
+    main: Query<&Transform, (With<UnrealCameraController>, Without<SkyLayerCamera>)>,
+    mut sky: Query<&mut Transform, With<SkyLayerCamera>>,
+) {
+    let Ok(main) = main.get_single() else {
+        return;
+    };
+    for mut transform in &mut sky {
+        *transform = *main;
+    }
+}
+
+fn pick_nav_path(
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Query<&Transform, With<UnrealCameraController>>,
+    nav: Option<ResMut<NavDebug>>,
+) {
+    let (Some(mut nav), Ok(camera)) = (nav, camera.get_single()) else {
+        return;
+    };
+    let position = source_to_world()
+        .compute_matrix()
+        .inverse()
+        .transform_point3(camera.translation);
+
+    if keys.just_pressed(KeyCode::KeyN) {
+        nav.start = Some(position);
+    } else if keys.just_pressed(KeyCode::KeyM) {
+        nav.goal = Some(position);
+    } else {
+        return;
+    }
+
+    if let (Some(start), Some(goal)) = (nav.start, nav.goal) {
+        nav.path = nav.mesh.find_path(start, goal, &PathCosts::default());
+        match &nav.path {
+            Some(path) => log::info!(
+                "Nav path through {} areas, cost {}",
+                path.areas().len(),
+                path.cost
+            ),
+            None => log::info!("No nav path"),
+        }
+    }
+}
+
+fn draw_nav_gizmos(nav: Option<Res<NavDebug>>, mut gizmos: Gizmos) {
+    let Some(nav) = nav else {
+        return;
+    };
+    let to_world = source_to_world();
+
+    for area in &nav.mesh.areas {
+        let corners = area.corners().map(|c| to_world.transform_point(c));
+        gizmos.linestrip(corners.into_iter().chain([corners[0]]), GRAY);
+    }
+
+    let Some(path) = &nav.path else {
+        return;
+    };
+    for pair in path.points.windows(2) {
+        let colour = match pair[1].traverse {
+            NavTraverse::Walk => LIME,
+            NavTraverse::Crouch => YELLOW,
+            NavTraverse::Jump => ORANGE,
+            NavTraverse::Ladder => RED,
+        };
+        gizmos.line(
+            to_world.transform_point(pair[0].position),
+            to_world.transform_point(pair[1].position),
+            colour,
+        );
+    }
+}
+
+fn draw_compile_overlays(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut overlays: ResMut<CompileOverlays>,
+    mut gizmos: Gizmos,
+) {
+    if keys.just_pressed(KeyCode::KeyP) {
+        overlays.show_portals = !overlays.show_portals;
+    }
+    let to_world = source_to_world();
+
+    if let Some(portals) = overlays.portals.as_ref().filter(|_| overlays.show_portals) {
+        for (a, b) in portals.portals.iter().flat_map(|portal| portal.edges()) {
+            gizmos.line(
+                to_world.transform_point(a),
+                to_world.transform_point(b),
+                AQUA,
+            );
+        }
+    }
+    if let Some(leak) = &overlays.leak {
+        gizmos.linestrip(
+            leak.points.iter().map(|&p| to_world.transform_point(p)),
+            RED,
+        );
+    }
+}
+
 // fn ui_example_system(
 //     mut contexts: EguiContexts,
 //     mut objects: Query<(&mut SourceObject, &Handle<StandardMaterial>)>,
@@ -1,7 +1,4 @@
-use std::{
-    io::{self, BufReader, Cursor},
-    path::PathBuf,
-};
+use std::path::PathBuf;
 
 use bevy::{
     asset::{
@@ -11,10 +8,7 @@ use bevy::{
     prelude::*,
     reflect::TypePath,
 };
-use source::{
-    binaries::BinaryData,
-    vmt::{VMTError, VMT},
-};
+use source::{error::SourceError, vmt::VMT};
 use thiserror::Error;
 
 #[derive(Default)]
@@ -29,20 +23,25 @@ pub struct VMTAssetLoader;
 impl AssetLoader for VMTAssetLoader {
     type Asset = StandardMaterial;
     type Settings = ();
-    type Error = VMTError;
+    type Error = SourceError;
     async fn load<'a>(
         &'a self,
         reader: &'a mut Reader<'_>,
         _settings: &'a (),
         load_context: &'a mut LoadContext<'_>,
     ) -> Result<Self::Asset, Self::Error> {
+        let path = load_context.path().to_owned();
         let mut bytes = Vec::new();
 
-		reader.read_to_end(&mut bytes).await.unwrap();
+		reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(|e| SourceError::io("VMT", 0, e).in_file(&path))?;
 
-        let data = String::from_utf8(bytes).unwrap();
+        let data = String::from_utf8(bytes)
+            .map_err(|e| SourceError::invalid("VMT", 0, e.to_string()).in_file(&path))?;
  
-        let vmt = VMT::from_string(data)?;
+        let vmt = VMT::from_string(data).map_err(|e| SourceError::from(e).in_file(&path))?;
 
         // vmt.load_dependants(load_context.loader().load(path));
 
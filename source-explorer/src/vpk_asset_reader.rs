@@ -1,9 +1,9 @@
-use std::io;
+use std::{io, path::Path, sync::Arc};
 
-use bevy::{asset::{io::{AssetReader, AssetReaderError, ErasedAssetReader, Reader, SliceReader}, AsyncReadExt}, tasks::futures_lite::{io::Take, AsyncRead, AsyncSeek, AsyncSeekExt}};
+use bevy::{asset::{io::{AssetReader, AssetReaderError, ErasedAssetReader, Reader, SliceReader}, AsyncReadExt}, tasks::futures_lite::{io::{Chain, Take}, AsyncRead, AsyncSeek, AsyncSeekExt}};
 use common::vpath::VGlobalPath;
 use ini::Ini;
-use source::prelude::GameData;
+use source::prelude::{GameData, VPKDirectory};
 
 
 pub struct VPKAssetReader {
@@ -23,9 +23,10 @@ impl VPKAssetReader {
     }
 }
 
-struct OuterTake<'a>(Take<Box<Reader<'a>>>);
+/// A file's preload data followed by the rest of it from its archive
+struct ChainedReader<'a>(Chain<SliceReader<'a>, Take<Box<Reader<'a>>>>);
 
-impl<'a> AsyncRead for OuterTake<'a> {
+impl<'a> AsyncRead for ChainedReader<'a> {
     fn poll_read(
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
@@ -36,7 +37,7 @@ impl<'a> AsyncRead for OuterTake<'a> {
     }
 }
 
-impl<'a> AsyncSeek for OuterTake<'a> {
+impl<'a> AsyncSeek for ChainedReader<'a> {
     fn poll_seek(
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
@@ -49,6 +50,38 @@ impl<'a> AsyncSeek for OuterTake<'a> {
     }
 }
 
+/// Read a file from the first of `dirs` that has it, opening its archive with `archives`
+async fn read_vpk_file<'a>(
+    dirs: &'a [Arc<VPKDirectory>],
+    archives: &'a dyn ErasedAssetReader,
+    path: &'a Path,
+) -> Result<Box<Reader<'a>>, AssetReaderError> {
+    let p = path.to_str().unwrap();
+
+    for dir in dirs {
+        let Ok(file_data) = dir.file_data(&VGlobalPath::new(p)) else {
+            continue;
+        };
+
+        let preload = file_data.preload().map_or(&[][..], Vec::as_slice);
+        if file_data.len() == 0 {
+            //TODO: delete preload data after
+            log::debug!("Reading {p} from preload data");
+            return Ok(Box::new(SliceReader::new(preload)));
+        }
+
+        let (archive_path, offset) = dir.archive_location(file_data);
+        log::debug!("Reading {p} from {}", archive_path.display());
+
+        let mut file = archives.read(archive_path).await?;
+        file.seek(io::SeekFrom::Start(offset)).await?;
+        let file = SliceReader::new(preload).chain(file.take(file_data.len() as _));
+
+        return Ok(Box::new(ChainedReader(file)));
+    }
+    Err(AssetReaderError::NotFound(path.to_owned()))
+}
+
 impl AssetReader for VPKAssetReader {
     async fn read<'a>(
         &'a self,
@@ -56,41 +89,7 @@ impl AssetReader for VPKAssetReader {
     ) -> Result<Box<Reader<'a>>, AssetReaderError> {
         // self.fallback_io.read(path).await
 
-        let p = path.to_str().unwrap();
-
-        println!("Loading {p} reader");
-        for dir in self.game_data.dirs() {
-            let Ok(file_data) = dir.file_data(&VGlobalPath::new(p)) else {
-                continue;
-            };
-
-            if let Some(preload) = file_data.preload() {
-                // Load from preload data
-                //TODO: delete preload data after
-
-                println!("Loaded preload reader");
-
-                return Ok(Box::new(SliceReader::new(preload)));
-            } else {
-                // Attempt to load
-
-                // println!("Loading file reader");
-
-                let index = file_data.archive();
-
-                // open file
-                let mut file =
-                    self.fallback_io.read(&dir.pak_archive(index)).await? as Box<Reader<'a>>;
-                file.seek(io::SeekFrom::Start(file_data.offset() as _))
-                    .await?;
-                file = Box::new(OuterTake(file.take(file_data.len() as _))) as Box<Reader<'a>>;
-
-                // println!("Loaded file reader in {:?}", dir.pak_archive(index));
-
-                return Ok(file);
-            }
-        }
-        return Err(AssetReaderError::NotFound(path.to_owned()));
+        read_vpk_file(self.game_data.dirs(), &*self.fallback_io, path).await
     }
 
     async fn read_meta<'a>(
@@ -115,4 +114,72 @@ impl AssetReader for VPKAssetReader {
     ) -> Result<bool, AssetReaderError> {
         Err(AssetReaderError::NotFound(path.to_owned()))
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod vpk_asset_reader_tests {
+    use std::path::PathBuf;
+
+    use bevy::{asset::io::memory::MemoryAssetReader, tasks::block_on};
+    use common::vfile::VFileSystem;
+    use source::vpk::EMBEDDED_ARCHIVE;
+
+    use super::*;
+
+    #[test]
+    fn chained_reader() {
+        let archive = b"--world!--";
+        block_on(async {
+            let mut archive = Box::new(SliceReader::new(archive)) as Box<Reader>;
+            archive.seek(io::SeekFrom::Start(2)).await.unwrap();
+            let mut reader = ChainedReader(SliceReader::new(b"hello ").chain(archive.take(6)));
+
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await.unwrap();
+            assert_eq!(bytes, b"hello world!");
+        });
+    }
+
+    /// A version 1 directory holding `dir/file.txt`, with `hello ` preloaded and `world!` stored after the tree
+    fn embedded_dir() -> Vec<u8> {
+        let mut tree = b"txt\0dir\0file\0".to_vec();
+        tree.extend_from_slice(&0u32.to_le_bytes()); // crc
+        tree.extend_from_slice(&6u16.to_le_bytes()); // preload bytes
+        tree.extend_from_slice(&EMBEDDED_ARCHIVE.to_le_bytes());
+        tree.extend_from_slice(&2u32.to_le_bytes()); // offset
+        tree.extend_from_slice(&6u32.to_le_bytes()); // length
+        tree.extend_from_slice(&0xffffu16.to_le_bytes());
+        tree.extend_from_slice(b"hello \0\0\0");
+
+        let mut data = 0x55aa1234u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&(tree.len() as u32).to_le_bytes());
+        data.extend(tree);
+        data.extend_from_slice(b"--world!--");
+        data
+    }
+
+    #[test]
+    fn embedded_file() {
+        let files = VFileSystem::from_iter([("addon_dir.vpk".to_owned(), embedded_dir())]);
+        let dirs = [Arc::new(
+            VPKDirectory::load(files, PathBuf::from("addon_dir.vpk")).unwrap(),
+        )];
+        let archives = MemoryAssetReader::default();
+        archives
+            .root
+            .insert_asset(Path::new("addon_dir.vpk"), embedded_dir());
+
+        block_on(async {
+            let mut reader = read_vpk_file(&dirs, &archives, Path::new("dir/file.txt"))
+                .await
+                .unwrap();
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await.unwrap();
+            assert_eq!(bytes, b"hello world!");
+
+            assert!(read_vpk_file(&dirs, &archives, Path::new("dir/missing.txt"))
+                .await
+                .is_err());
+        });
+    }
+}
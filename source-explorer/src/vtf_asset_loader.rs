@@ -1,4 +1,4 @@
-use std::io::{self, BufReader, Cursor};
+use std::io::{BufReader, Cursor};
 
 use bevy::{
     asset::{io::Reader, Asset, AssetLoader, AsyncReadExt, LoadContext},
@@ -10,6 +10,7 @@ use bevy::{
     },
 };
 use source::binaries::BinaryData;
+use source::error::SourceError;
 use source::vtf::VTF;
 
 #[derive(Default)]
@@ -18,26 +19,30 @@ pub struct VTFAssetLoader;
 impl AssetLoader for VTFAssetLoader {
     type Asset = Image;
     type Settings = ();
-    type Error = io::Error;
+    type Error = SourceError;
     async fn load<'a>(
         &'a self,
         reader: &'a mut Reader<'_>,
         _settings: &'a (),
-        _load_context: &'a mut LoadContext<'_>,
+        load_context: &'a mut LoadContext<'_>,
     ) -> Result<Self::Asset, Self::Error> {
+        let path = load_context.path().to_owned();
         let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(|e| SourceError::io("VTF", 0, e).in_file(&path))?;
 		let len = bytes.len();
-        reader.read_to_end(&mut bytes).await?;
 
         let mut c = Cursor::new(bytes);
-        let vtf = VTF::read(&mut BufReader::new(&mut c), Some(len))?;
+        let vtf = VTF::read(&mut BufReader::new(&mut c), Some(len)).map_err(|e| e.in_file(&path))?;
 
         if vtf.high_res_data().len() > 0 {
             let image = vtf_to_image(&vtf);
 
             Ok(image)
         } else {
-            Err(io::Error::new(io::ErrorKind::InvalidData, "failed to load image"))
+            Err(SourceError::invalid("VTF", 0, "No high res image data").in_file(&path))
         }
     }
 
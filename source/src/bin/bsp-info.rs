@@ -0,0 +1,52 @@
+//! Print what is in a map file: `bsp-info [--json] <map.bsp>`
+//!
+//! Lumps that cannot be read are shown with their error in place of their contents, and make the exit status a failure.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use source::{bsp::info::MapInfo, prelude::*};
+
+const USAGE: &str = "Usage: bsp-info [--json] <map.bsp>";
+
+fn main() -> ExitCode {
+    let mut json = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("Unexpected argument {arg}\n{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let info = match Bsp::load(&path) {
+        Ok(bsp) => MapInfo::new(&bsp),
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if json {
+        println!("{:#}", info.to_json());
+    } else {
+        print!("{info}");
+    }
+
+    // Sections that could not be read are shown with their errors, but still fail
+    if info.is_complete() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,244 @@
+//! List, extract, verify and create VPK archives
+//!
+//! ```text
+//! vpk list <pak_dir.vpk> [glob...]
+//! vpk extract <pak_dir.vpk> <output dir> [glob...]
+//! vpk verify <pak_dir.vpk>
+//! vpk create [--v1] [--no-md5] [--preload <bytes>] [--archive-size <MiB>] <input dir> <pak_dir.vpk>
+//! ```
+//!
+//! `verify` also checks the signature of signed VPKs when built with the `vpk-signature` feature.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    process::ExitCode,
+};
+
+use common::vfile::VFileSystem;
+use glob::{MatchOptions, Pattern};
+use source::{
+    prelude::*,
+    vpk::{
+        writer::{VPKWriteOptions, VPKWriter},
+        EMBEDDED_ARCHIVE,
+    },
+};
+
+const USAGE: &str = "Usage:
+  vpk list <pak_dir.vpk> [glob...]
+  vpk extract <pak_dir.vpk> <output dir> [glob...]
+  vpk verify <pak_dir.vpk>
+  vpk create [--v1] [--no-md5] [--preload <bytes>] [--archive-size <MiB>] <input dir> <pak_dir.vpk>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["list", vpk, ref globs @ ..] => list(Path::new(vpk), globs),
+        ["extract", vpk, out, ref globs @ ..] => extract(Path::new(vpk), Path::new(out), globs),
+        ["verify", vpk] => verify(Path::new(vpk)),
+        ["create", ref args @ ..] => create(args),
+        ["-h" | "--help"] => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn load(path: &Path) -> SourceResult<VPKDirectory> {
+    VPKDirectory::load(VFileSystem::default(), path.to_path_buf())
+}
+
+/// Globs to select files by path. Naming a directory selects everything in it.
+fn patterns(globs: &[&str]) -> Result<Vec<Pattern>, SourceError> {
+    let mut patterns = Vec::new();
+    for glob in globs {
+        let glob = glob.to_ascii_lowercase().replace('\\', "/");
+        let dir_glob = format!("{}/**", glob.trim_end_matches('/'));
+        for glob in [&glob, &dir_glob] {
+            patterns.push(
+                Pattern::new(glob)
+                    .map_err(|e| SourceError::invalid("glob", e.pos as u64, e.msg))?,
+            );
+        }
+    }
+    Ok(patterns)
+}
+
+fn selected(patterns: &[Pattern], path: &str) -> bool {
+    let options = MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    patterns.is_empty() || patterns.iter().any(|p| p.matches_with(path, options))
+}
+
+fn list(vpk_path: &Path, globs: &[&str]) -> SourceResult<bool> {
+    let vpk = load(vpk_path)?;
+    let patterns = patterns(globs)?;
+
+    println!("{:>12} {:>7} path", "size", "archive");
+    for (path, file) in vpk.entries() {
+        if !selected(&patterns, &path) {
+            continue;
+        }
+        let size = file.preload().map_or(0, Vec::len) + file.len() as usize;
+        let archive = match file.archive() {
+            _ if file.len() == 0 => "-".to_owned(),
+            EMBEDDED_ARCHIVE => "dir".to_owned(),
+            index => format!("{index:03}"),
+        };
+        println!("{size:>12} {archive:>7} {path}");
+    }
+    Ok(true)
+}
+
+fn extract(vpk_path: &Path, out: &Path, globs: &[&str]) -> SourceResult<bool> {
+    let vpk = load(vpk_path)?;
+    let patterns = patterns(globs)?;
+
+    let mut ok = true;
+    for (path, file) in vpk.entries() {
+        if !selected(&patterns, &path) {
+            continue;
+        }
+        let Some(out_path) = extract_path(out, &path) else {
+            eprintln!("{path}: outside of the output directory");
+            ok = false;
+            continue;
+        };
+        let written = vpk.entry_bytes(file).and_then(|bytes| {
+            let io_error = |e| SourceError::io("extract", 0, e).in_file(&out_path);
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent).map_err(io_error)?;
+            }
+            fs::write(&out_path, bytes).map_err(io_error)
+        });
+        match written {
+            Ok(()) => println!("{path}"),
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+/// Where to write the file at `path` in a VPK, or `None` if it would be outside `out`, such as `../x` or `/etc/x`
+fn extract_path(out: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| out.join(path))
+}
+
+fn verify(vpk_path: &Path) -> SourceResult<bool> {
+    let report = load(vpk_path)?.verify()?;
+    for mismatch in &report.mismatches {
+        eprintln!("{mismatch}");
+    }
+    println!(
+        "{} checked, {} problems",
+        report.checked,
+        report.mismatches.len()
+    );
+    Ok(report.is_ok())
+}
+
+fn create(args: &[&str]) -> SourceResult<bool> {
+    let mut options = VPKWriteOptions::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let mut number = |name: &str| {
+            args.next()
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| {
+                    SourceError::invalid("arguments", 0, format!("{name} needs a number"))
+                })
+        };
+        match arg {
+            "--v1" => options.version = 1,
+            "--no-md5" => options.md5 = false,
+            "--preload" => options.preload_bytes = number(arg)?.min(u16::MAX as u64) as u16,
+            "--archive-size" => {
+                options.max_archive_size = number(arg)?
+                    .checked_mul(1024 * 1024)
+                    .filter(|&size| size > 0 && size <= u32::MAX as u64)
+                    .ok_or_else(|| {
+                        SourceError::invalid("arguments", 0, "--archive-size must be 1 to 4095 MiB")
+                    })?
+            }
+            _ => paths.push(Path::new(arg)),
+        }
+    }
+    let [input, vpk_path] = paths[..] else {
+        eprintln!("{USAGE}");
+        return Ok(false);
+    };
+
+    let mut writer = VPKWriter::new();
+    writer.add_dir(input)?;
+
+    let vpk_path = if vpk_path.to_string_lossy().ends_with("_dir.vpk") {
+        vpk_path.to_path_buf()
+    } else {
+        // Archives are found by replacing `_dir` in the directory's name
+        let stem = vpk_path.file_stem().unwrap_or_default().to_string_lossy();
+        vpk_path.with_file_name(format!("{stem}_dir.vpk"))
+    };
+    writer.write(&vpk_path, &options)?;
+
+    println!("Packed {} files into {}", writer.len(), vpk_path.display());
+    Ok(true)
+}
+
+#[cfg(test)]
+mod vpk_tests {
+    use source::vpk::writer::VPKWriter;
+
+    use super::*;
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn extract_outside() {
+        let dir = std::env::temp_dir().join(format!("vpk_extract_{}", std::process::id()));
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let vpk_path = dir.join("test_dir.vpk");
+
+        let mut writer = VPKWriter::new();
+        writer.add_file("materials/inside.vmt", b"inside".to_vec());
+        writer.add_file("../outside.vmt", b"outside".to_vec());
+        let absolute = dir.join("absolute.vmt");
+        writer.add_file(&absolute.to_string_lossy(), b"outside".to_vec());
+        writer.write(&vpk_path, &Default::default()).unwrap();
+
+        let ok = extract(&vpk_path, &out, &[]);
+        let inside = fs::read(out.join("materials/inside.vmt"));
+        let outside = dir.join("outside.vmt").exists() || absolute.exists();
+        let entries = load(&vpk_path).unwrap().entries().len();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Both entries outside are reported, and only the one inside is written
+        assert_eq!(entries, 3);
+        assert!(!ok.unwrap());
+        assert_eq!(inside.unwrap(), b"inside");
+        assert!(!outside);
+    }
+}
@@ -0,0 +1,106 @@
+//! Shared, read only views of whole files, so structures can be borrowed from them without copying
+
+use std::{fmt::Debug, mem, sync::Arc};
+
+#[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+use std::{fs::File, path::Path};
+
+use crate::error::{structure_name, SourceError, SourceResult};
+
+/// Cheaply cloneable bytes of a file, either owned in memory or mapped from disk
+#[derive(Clone)]
+pub struct SharedBytes(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl SharedBytes {
+    pub fn new(bytes: impl AsRef<[u8]> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(bytes))
+    }
+
+    /// Map a file into memory
+    #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+    pub fn map(path: &Path) -> SourceResult<Self> {
+        let file =
+            File::open(path).map_err(|e| SourceError::io("mapped file", 0, e).in_file(path))?;
+
+        // Safety: game files are treated as read only. If another process truncates the file while it is mapped,
+        // reads will fault, which is the accepted tradeoff for not copying every lump.
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .map_err(|e| SourceError::io("mapped file", 0, e).in_file(path))?;
+
+        Ok(Self::new(map))
+    }
+
+    /// Borrow `len` bytes at `offset`
+    pub fn slice(&self, offset: usize, len: usize) -> SourceResult<&[u8]> {
+        slice(self.as_ref(), offset, len, "bytes")
+    }
+}
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        (*self.0).as_ref()
+    }
+}
+
+impl Debug for SharedBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SharedBytes({} bytes)", self.as_ref().len())
+    }
+}
+
+pub(crate) fn slice<'a>(
+    bytes: &'a [u8],
+    offset: usize,
+    len: usize,
+    structure: &'static str,
+) -> SourceResult<&'a [u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| {
+            SourceError::invalid(
+                structure,
+                offset as u64,
+                format!("{len} bytes past the end of {} byte file", bytes.len()),
+            )
+        })
+}
+
+/// Cast `len` bytes at `offset` into a slice of `T` without copying.
+///
+/// Returns `None` if the bytes are not aligned for `T`, in which case they must be copied out instead.
+pub fn view<T: bytemuck::AnyBitPattern>(
+    bytes: &[u8],
+    offset: usize,
+    len: usize,
+) -> SourceResult<Option<&[T]>> {
+    let item_size = mem::size_of::<T>();
+    let data = slice(bytes, offset, len, structure_name::<T>())?;
+
+    if item_size == 0 || !len.is_multiple_of(item_size) {
+        return Err(SourceError::invalid(
+            structure_name::<T>(),
+            offset as u64,
+            format!("Length {len} is not a multiple of the structure size {item_size}"),
+        ));
+    }
+
+    Ok(bytemuck::try_cast_slice(data).ok())
+}
+
+#[cfg(test)]
+mod mapped_tests {
+    use super::*;
+
+    #[test]
+    fn views() {
+        let words = [1u32, 2, 3];
+        let bytes: &[u8] = bytemuck::cast_slice(&words);
+
+        assert_eq!(view::<u32>(bytes, 4, 8).unwrap(), Some(&[2, 3][..]));
+        assert!(view::<u32>(bytes, 4, 6).is_err());
+        assert!(view::<u32>(bytes, 8, 8).is_err());
+        // Misaligned views have to fall back to a copy
+        assert_eq!(view::<u32>(bytes, 1, 8).unwrap(), None);
+    }
+}
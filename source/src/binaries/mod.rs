@@ -1,12 +1,16 @@
 use std::{
     fmt::Debug,
-    io::{self, BufRead, BufReader, Read, Seek},
+    io::{BufRead, BufReader, Read, Seek},
     marker::PhantomData,
     mem, slice,
 };
 
+use crate::error::{structure_name, ResultExt, SourceError, SourceResult};
+
+pub mod mapped;
+
 pub trait BinaryData {
-    fn read<R: Read + Seek>(buffer: &mut BufReader<R>, _max_size: Option<usize>) -> io::Result<Self>
+    fn read<R: Read + Seek>(buffer: &mut BufReader<R>, _max_size: Option<usize>) -> SourceResult<Self>
     where
         Self: Sized,
     {
@@ -16,7 +20,9 @@ pub trait BinaryData {
         unsafe {
             let slice = slice::from_raw_parts_mut(&mut data as *mut _ as *mut u8, size);
             // `read_exact()` comes from `Read` impl for `&[u8]`
-            buffer.read_exact(slice)?;
+            buffer
+                .read_exact(slice)
+                .at(structure_name::<Self>(), buffer)?;
         }
         Ok(data)
     }
@@ -25,7 +31,7 @@ pub trait BinaryData {
         buffer: &mut BufReader<R>,
         count: usize,
         _max_size: Option<usize>,
-    ) -> io::Result<Box<[Self]>>
+    ) -> SourceResult<Box<[Self]>>
     where
         Self: Sized + bytemuck::Zeroable,
     {
@@ -36,7 +42,9 @@ pub trait BinaryData {
                 let size = count * mem::size_of::<Self>();
                 let slice = slice::from_raw_parts_mut(&mut header[0] as *mut _ as *mut u8, size);
                 // `read_exact()` comes from `Read` impl for `&[u8]`
-                buffer.read_exact(slice)?;
+                buffer
+                    .read_exact(slice)
+                    .at(structure_name::<Self>(), buffer)?;
             }
         }
 
@@ -58,9 +66,9 @@ impl BinOffset {
         buffer: &mut BufReader<R>,
         start: i64,
         pos: &mut i64,
-    ) -> io::Result<()> {
+    ) -> SourceResult<()> {
         let p = self.index as i64 + start;
-        buffer.seek_relative(p - *pos)?;
+        buffer.seek_relative(p - *pos).at("BinOffset", buffer)?;
         *pos = p;
         Ok(())
     }
@@ -69,19 +77,19 @@ impl BinOffset {
         buffer: &mut BufReader<R>,
         start: i64,
         pos: &mut i64,
-    ) -> io::Result<String> {
+    ) -> SourceResult<String> {
         self.seek_start(buffer, start, pos)?;
         //let mut b = [0, 0, 0, 0];
         //buffer.read_exact(&mut b)?;
         //println!("{b:?}");
         let mut data = Default::default();
 
-        *pos += buffer.read_until(0, &mut data)? as i64;
+        *pos += buffer.read_until(0, &mut data).at("string", buffer)? as i64;
 
         // Remove trailing 0
         data.pop();
 
-        Ok(String::from_utf8(data).unwrap())
+        String::from_utf8(data).map_err(|e| SourceError::invalid("string", *pos as u64, e.to_string()))
     }
     //TODO: choose name
     pub fn read_array_f<T: Sized + bytemuck::Zeroable, R: Read + Seek>(
@@ -90,7 +98,7 @@ impl BinOffset {
         start: i64,
         pos: &mut i64,
         count: usize,
-    ) -> io::Result<Box<[T]>> {
+    ) -> SourceResult<Box<[T]>> {
         self.seek_start(buffer, start, pos)?;
 
         //let mut b = [0, 0, 0, 0];
@@ -108,7 +116,7 @@ impl BinOffset {
         start: i64,
         pos: &mut i64,
         count: u32,
-    ) -> io::Result<Vec<(i64, T)>> {
+    ) -> SourceResult<Vec<(i64, T)>> {
         self.seek_start(buffer, start, pos)?;
 
         //let mut b = [0, 0, 0, 0];
@@ -142,7 +150,7 @@ impl<T: Sized + BinaryData> BinArray<T> {
         buffer: &mut BufReader<R>,
         start: i64,
         pos: &mut i64,
-    ) -> io::Result<Vec<(i64, T)>> {
+    ) -> SourceResult<Vec<(i64, T)>> {
         self.offset.read_array(buffer, start, pos, self.count)
     }
 }
@@ -152,7 +160,7 @@ impl<T: Sized + bytemuck::Zeroable> BinArray<T> {
         buffer: &mut BufReader<R>,
         start: i64,
         pos: &mut i64,
-    ) -> io::Result<Box<[T]>> {
+    ) -> SourceResult<Box<[T]>> {
         self.offset
             .read_array_f(buffer, start, pos, self.count as usize)
     }
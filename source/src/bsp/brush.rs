@@ -0,0 +1,86 @@
+//! Brushes are the convex solids a map was built from. The compiler keeps them for collision, as the faces
+//! alone cannot say what is inside the map.
+
+use flagset::FlagSet;
+
+use super::{
+    consts::{Contents, LumpType, MAX_MAP_BRUSHES, MAX_MAP_BRUSHSIDES, MAX_MAP_LEAFBRUSHES},
+    Lump,
+};
+
+///Brush
+///
+///The brush array (Lump 18) holds every brush in the map. Each brush is the space behind all of its sides.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BSPBrush {
+    /// Index into the brush side lump
+    pub first_side: i32,
+    pub num_sides: i32,
+    pub contents: i32,
+}
+
+impl BSPBrush {
+    /// The known bits of `contents`
+    pub fn contents(&self) -> FlagSet<Contents> {
+        FlagSet::new_truncated(self.contents)
+    }
+}
+
+impl Lump for BSPBrush {
+    fn max() -> usize {
+        MAX_MAP_BRUSHES
+    }
+    fn lump_type() -> LumpType {
+        LumpType::Brushes
+    }
+}
+
+///Brush side
+///
+///The brush side array (Lump 19) holds the planes bounding each brush, facing out of it.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BSPBrushSide {
+    /// Index into the plane lump
+    pub plane_num: u16,
+    /// Index into the tex info lump, or -1
+    pub tex_info: i16,
+    pub disp_info: i16,
+    /// Bevels are extra planes added so boxes slide smoothly along the brush. They are not real faces.
+    pub bevel: u8,
+    pub thin: u8,
+}
+
+impl BSPBrushSide {
+    pub fn is_bevel(&self) -> bool {
+        self.bevel != 0
+    }
+}
+
+impl Lump for BSPBrushSide {
+    fn max() -> usize {
+        MAX_MAP_BRUSHSIDES
+    }
+    fn lump_type() -> LumpType {
+        LumpType::BrushSides
+    }
+}
+
+///Leaf brush
+///
+///The leaf brush lump (Lump 17) lists the brushes touching each leaf, as indices into the brush lump.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BSPLeafBrush {
+    pub brush: u16,
+}
+
+impl Lump for BSPLeafBrush {
+    fn max() -> usize {
+        MAX_MAP_LEAFBRUSHES
+    }
+    fn lump_type() -> LumpType {
+        LumpType::LeafBrushes
+    }
+}
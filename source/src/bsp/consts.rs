@@ -1,4 +1,4 @@
-use flagset::flags;
+use flagset::{flags, FlagSet};
 use num_derive::FromPrimitive;
 
 pub const HEADER_LUMPS: usize = 64;
@@ -64,7 +64,7 @@ pub const MAX_MAP_PRIMINDICES: usize = 65536;
 
 pub const TEXTURE_NAME_LENGTH: usize = 128;
 
-#[derive(Copy, Clone, FromPrimitive, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, FromPrimitive, Debug)]
 pub enum LumpType {
     Entities = 0,
     Places = 1,
@@ -75,36 +75,62 @@ pub enum LumpType {
     TexInfo = 6,
     Faces = 7,
     Lighting = 8,
+    Occlusion = 9,
     Leafs = 10,
+    FaceIds = 11,
     Edges = 12,
     SurfEdges = 13,
     Models = 14,
     WorldLights = 15,
     LeafFaces = 16,
+    LeafBrushes = 17,
+    Brushes = 18,
+    BrushSides = 19,
+    Areas = 20,
+    AreaPortals = 21,
+    Portals = 22,
+    Clusters = 23,
+    PortalVerts = 24,
+    ClusterPortals = 25,
     DispInfo = 26,
     OriginalFaces = 27,
+    PhysDisp = 28,
+    PhysCollide = 29,
     VertNormals = 30,
     VertNormalIndices = 31,
+    DispLightmapAlphas = 32,
     DispVerts = 33,
+    DispLightmapSamplePositions = 34,
     GameLump = 35,
     LeafWaterData = 36,
     Primitives = 37,
+    PrimVerts = 38,
     PrimIndices = 39,
     PakFile = 40,
+    ClipPortalVerts = 41,
     Cubemaps = 42,
     TexDataStringData = 43,
     TexDataStringTable = 44,
     Overlays = 45,
+    LeafMinDistToWater = 46,
+    FaceMacroTextureInfo = 47,
+    DispTris = 48,
+    PhysCollideSurface = 49,
+    WaterOverlays = 50,
     LeafAmbientIndexHdr = 51,
     LeafAmbientIndex = 52,
     LightingHdr = 53,
     WorldLightsHdr = 54,
     LeafAmbientLightingHdr = 55,
     LeafAmbientLighting = 56,
+    XZipPakFile = 57,
     FacesHdr = 58,
+    MapFlags = 59,
+    OverlayFades = 60,
 }
 flags! {
-    enum Contents: i32 {
+    /// `BSPLeaf::contents` and `BSPBrush::contents`, from bspflags.h
+    pub enum Contents: i32 {
         EMPTY = 0,             //N.o contents
         SOLID = 0x1,           //an eye is never valid in a solid
         WINDOW = 0x2,          //translucent, but not watery (glass)
@@ -139,3 +165,47 @@ flags! {
         HITBOX = 0x40000000,      // 	use accurate hitboxes on trace
     }
 }
+
+impl Contents {
+    /// `MASK_SOLID`, everything that is normally solid
+    pub fn mask_solid() -> FlagSet<Self> {
+        Self::SOLID | Self::MOVEABLE | Self::WINDOW | Self::MONSTER | Self::GRATE
+    }
+
+    /// `MASK_PLAYERSOLID`, everything that blocks player movement
+    pub fn mask_player_solid() -> FlagSet<Self> {
+        Self::mask_solid() | Self::PLAYERCLIP
+    }
+
+    /// `MASK_SHOT`, everything that blocks bullets
+    pub fn mask_shot() -> FlagSet<Self> {
+        Self::SOLID | Self::MOVEABLE | Self::MONSTER | Self::WINDOW | Self::DEBRIS | Self::HITBOX
+    }
+
+    /// `MASK_OPAQUE`, everything that blocks line of sight
+    pub fn mask_opaque() -> FlagSet<Self> {
+        Self::SOLID | Self::MOVEABLE | Self::OPAQUE
+    }
+}
+
+flags! {
+    /// `BSPTexInfo::flags`, from bspflags.h
+    pub enum SurfaceFlags: i32 {
+        LIGHT = 0x1,         // value will hold the light strength
+        SKY2D = 0x2,         // don't draw, indicates we should skylight + draw 2d sky but not draw the 3D skybox
+        SKY = 0x4,           // don't draw, but add to skybox
+        WARP = 0x8,          // turbulent water warp
+        TRANS = 0x10,        // texture is translucent
+        NOPORTAL = 0x20,     // the surface can not have a portal placed on it
+        TRIGGER = 0x40,      // FIXME: This is an xbox hack to work around elimination of trigger surfaces, which breaks occluders
+        NODRAW = 0x80,       // don't bother referencing the texture
+        HINT = 0x100,        // make a primary bsp splitter
+        SKIP = 0x200,        // completely ignore, allowing non-closed brushes
+        NOLIGHT = 0x400,     // Don't calculate light
+        BUMPLIGHT = 0x800,   // calculate three lightmaps for the surface for bumpmapping
+        NOSHADOWS = 0x1000,  // Don't receive shadows
+        NODECALS = 0x2000,   // Don't receive decals
+        NOCHOP = 0x4000,     // Don't subdivide patches on this surface
+        HITBOX = 0x8000,     // surface is part of a hitbox
+    }
+}
@@ -1,10 +1,10 @@
 use crate::bsp::consts::MAX_DISP_CORNER_NEIGHBORS;
-use bytemuck::{Pod, Zeroable};
+use bytemuck::{AnyBitPattern, Pod, Zeroable};
 use flagset::flags;
 use glam::Vec3;
 
 use super::{
-    consts::{LumpType, MAX_MAP_DISPINFO},
+    consts::{LumpType, MAX_MAP_DISPINFO, MAX_MAP_DISP_VERTS},
     Lump,
 };
 
@@ -26,6 +26,9 @@ pub struct BSPDispInfo {
     pub allowed_verts: [u32; 10],  // active verticies
 }
 
+// Every field is plain data, but padding keeps this (and CDispCornerNeighbours) from being Pod
+unsafe impl AnyBitPattern for BSPDispInfo {}
+
 impl Lump for BSPDispInfo {
     fn max() -> usize {
         MAX_MAP_DISPINFO as usize
@@ -47,7 +50,7 @@ pub struct BSPDispVert {
 
 impl Lump for BSPDispVert {
     fn max() -> usize {
-        MAX_MAP_DISPINFO as usize
+        MAX_MAP_DISP_VERTS
     }
 
     fn lump_type() -> super::consts::LumpType {
@@ -148,8 +151,8 @@ pub struct CDispSubNeighbour {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::AnyBitPattern)]
 pub struct CDispCornerNeighbours {
-    neighbours: [u16; MAX_DISP_CORNER_NEIGHBORS], // indices of neighbours.
-    n_neighbours: u8,
+    pub neighbours: [u16; MAX_DISP_CORNER_NEIGHBORS], // indices of neighbours.
+    pub n_neighbours: u8,
 }
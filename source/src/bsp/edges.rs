@@ -13,6 +13,16 @@ pub struct BSPEdge {
     v0: u16, // vertex indices
     v1: u16, // vertex indices
 }
+impl BSPEdge {
+    pub fn new(v0: u16, v1: u16) -> Self {
+        Self { v0, v1 }
+    }
+
+    pub fn verts(&self) -> (u16, u16) {
+        (self.v0, self.v1)
+    }
+}
+
 impl Lump for BSPEdge {
     fn max() -> usize {
         MAX_MAP_EDGES
@@ -52,14 +62,31 @@ impl Lump for BSPSurfEdge {
 }
 
 impl BSPSurfEdge {
-    pub fn get_edge(&self, edges: &[BSPEdge]) -> (u16, u16) {
-        let id = self.index.abs() as usize;
-        let rev = self.index.signum();
-        let edge = edges[id];
-        match rev {
-            0 | 1 => (edge.v0, edge.v1),
-            -1 => (edge.v1, edge.v0),
-            _ => panic!("Invalid edge"),
+    pub fn new(index: i32) -> Self {
+        Self { index }
+    }
+
+    /// Index into the edge lump this surfedge references
+    pub fn edge_index(&self) -> usize {
+        self.index.unsigned_abs() as usize
+    }
+
+    /// True if the edge is traced from its second vertex to its first
+    pub fn is_reversed(&self) -> bool {
+        self.index < 0
+    }
+
+    /// Vertex indices of the edge in traced order, or `None` if the edge index is out of range
+    pub fn try_get_edge(&self, edges: &[BSPEdge]) -> Option<(u16, u16)> {
+        let edge = edges.get(self.edge_index())?;
+        if self.is_reversed() {
+            Some((edge.v1, edge.v0))
+        } else {
+            Some((edge.v0, edge.v1))
         }
     }
+
+    pub fn get_edge(&self, edges: &[BSPEdge]) -> (u16, u16) {
+        self.try_get_edge(edges).expect("Invalid edge")
+    }
 }
@@ -0,0 +1,183 @@
+//! The entity lump is plain text: a `{ "key" "value" ... }` block for every entity in the map
+
+use glam::{EulerRot, Mat4, Quat, Vec3};
+
+use crate::{
+    error::SourceResult,
+    vmf::keyvalues::{Token, Tokens},
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BSPEntity {
+    /// Properties in file order. Keys can repeat, such as for entity outputs
+    pub properties: Vec<(String, String)>,
+}
+
+impl BSPEntity {
+    /// Value of the first property called `key`, ignoring case as the engine does
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    pub fn target_name(&self) -> Option<&str> {
+        self.get("targetname")
+    }
+
+    pub fn origin(&self) -> Option<Vec3> {
+        self.get("origin").and_then(parse_vec3)
+    }
+
+    /// Pitch, yaw and roll in degrees. Falls back to the yaw only `angle` key
+    pub fn angles(&self) -> Option<Vec3> {
+        self.get("angles").and_then(parse_vec3).or_else(|| {
+            let yaw = self.get("angle")?.trim().parse().ok()?;
+            Some(Vec3::new(0.0, yaw, 0.0))
+        })
+    }
+
+    /// Index into the models lump of the brush model (`*N`) this entity uses
+    pub fn brush_model(&self) -> Option<usize> {
+        self.get("model")?.strip_prefix('*')?.parse().ok()
+    }
+
+    /// Where the entity places its model in the world
+    pub fn transform(&self) -> Mat4 {
+        angles_transform(
+            self.origin().unwrap_or_default(),
+            self.angles().unwrap_or_default(),
+        )
+    }
+}
+
+/// Place a model at `origin`, turned by pitch, yaw and roll in degrees
+pub fn angles_transform(origin: Vec3, angles: Vec3) -> Mat4 {
+    let rotation = Quat::from_euler(
+        EulerRot::ZYX,
+        angles.y.to_radians(),
+        angles.x.to_radians(),
+        angles.z.to_radians(),
+    );
+
+    Mat4::from_rotation_translation(rotation, origin)
+}
+
+fn parse_vec3(value: &str) -> Option<Vec3> {
+    let mut parts = value.split_whitespace().map(str::parse);
+    let v = Vec3::new(
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+    parts.next().is_none().then_some(v)
+}
+
+/// Parse the text of the entity lump. Offsets in errors are relative to the start of the lump.
+pub fn parse_entities(text: &str) -> SourceResult<Vec<BSPEntity>> {
+    let mut tokens = Tokens::new(text, "Entities").quoted_only();
+    let mut entities = Vec::new();
+
+    while let Some((start, token)) = tokens.next()? {
+        let Token::Open = token else {
+            return Err(tokens.invalid(start, "Expected '{' to start an entity"));
+        };
+
+        let mut entity = BSPEntity::default();
+        loop {
+            match tokens.next()? {
+                Some((_, Token::Close)) => break,
+                Some((offset, Token::String(key))) => match tokens.next()? {
+                    Some((_, Token::String(value))) => {
+                        entity.properties.push((key.to_owned(), value.to_owned()))
+                    }
+                    _ => return Err(tokens.invalid(offset, format!("Key {key:?} has no value"))),
+                },
+                Some((offset, Token::Open)) => {
+                    return Err(tokens.invalid(offset, "Entities cannot be nested"))
+                }
+                None => return Err(tokens.invalid(start, "Unterminated entity")),
+            }
+        }
+        entities.push(entity);
+    }
+
+    Ok(entities)
+}
+
+#[cfg(test)]
+mod entities_tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::error::SourceError;
+
+    const LUMP: &str = r#"{
+"classname" "worldspawn"
+"skyname" "sky_day01_01"
+}
+{
+"model" "*1"
+"origin" "128 0 -64"
+"angles" "0 90 0"
+"OnOpen" "relay,Trigger,,0,-1"
+"onopen" "relay2,Trigger,,0,-1"
+"ClassName" "func_door"
+}
+"#;
+
+    #[test]
+    fn parse() {
+        let entities = parse_entities(&format!("{LUMP}\0")).unwrap();
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].classname(), Some("worldspawn"));
+        assert_eq!(entities[0].brush_model(), None);
+
+        let door = &entities[1];
+        assert_eq!(door.classname(), Some("func_door"));
+        assert_eq!(door.brush_model(), Some(1));
+        assert_eq!(door.origin(), Some(vec3(128.0, 0.0, -64.0)));
+        assert_eq!(door.properties.len(), 6);
+        assert_eq!(door.get("onopen"), Some("relay,Trigger,,0,-1"));
+    }
+
+    #[test]
+    fn transform() {
+        let door = &parse_entities(LUMP).unwrap()[1];
+
+        // A yaw of 90 degrees turns +x to +y
+        let p = door.transform().transform_point3(Vec3::X);
+        assert!(p.abs_diff_eq(vec3(128.0, 1.0, -64.0), 1e-4));
+
+        let pitched = BSPEntity {
+            properties: vec![("angles".to_owned(), "90 0 0".to_owned())],
+        };
+        // Positive pitch looks down
+        let p = pitched.transform().transform_point3(Vec3::X);
+        assert!(p.abs_diff_eq(-Vec3::Z, 1e-4));
+    }
+
+    #[test]
+    fn errors() {
+        for bad in [
+            "{ \"classname\" }",
+            "{ \"classname\" \"worldspawn\"",
+            "{ { } }",
+            "\"key\" \"value\"",
+            "{ \"unterminated }",
+            "{ classname worldspawn }",
+        ] {
+            assert!(
+                matches!(parse_entities(bad), Err(SourceError::Invalid { .. })),
+                "{bad}"
+            );
+        }
+        assert!(parse_entities("").unwrap().is_empty());
+    }
+}
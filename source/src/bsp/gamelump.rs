@@ -1,14 +1,17 @@
 use std::{
     collections::HashMap,
-    io::{self, BufReader, Read, Seek},
+    io::{BufReader, Read, Seek},
 };
 
 use fixedstr::zstr;
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 
-use crate::binaries::BinaryData;
+use crate::{
+    binaries::BinaryData,
+    error::{ResultExt, SourceError, SourceResult},
+};
 
-use super::lump::BSPLump;
+use super::{entities::angles_transform, lump::BSPLump};
 
 #[derive(Debug, bytemuck::Zeroable)]
 #[repr(C, packed)]
@@ -27,14 +30,22 @@ pub struct StaticPropLumpV5 {
     pub fl_forced_fade_scale: f32, //	int				m_Lighting;			// index into the GAMELUMP_STATIC_PROP_LIGHTING lump
 }
 
-#[derive(Debug, bytemuck::Zeroable)]
+impl StaticPropLumpV5 {
+    /// Where the prop places its model in the world, turned the same way as an entity's angles
+    pub fn transform(&self) -> Mat4 {
+        angles_transform(self.m_origin, self.angles)
+    }
+}
+
+/// Entry of the game lump's directory, such as `prps` for static props
+#[derive(Debug, Clone, Copy, bytemuck::Zeroable)]
 #[repr(C, packed)]
-struct BSPGameLump {
-    id: [u8; 4],  // gamelump ID
-    flags: u16,   // flags
-    version: u16, // gamelump version
-    fileofs: i32, // offset to this gamelump
-    filelen: i32, // length
+pub struct BSPGameLump {
+    pub id: [u8; 4],  // gamelump ID
+    pub flags: u16,   // flags
+    pub version: u16, // gamelump version
+    pub fileofs: i32, // offset to this gamelump
+    pub filelen: i32, // length
 }
 
 #[derive(Debug)]
@@ -45,33 +56,58 @@ pub struct PropDictEntry {
 
 impl BinaryData for PropDictEntry {}
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct GameLump {
     pub static_prop_names: Vec<String>,
+    /// Leafs touched by each prop, indexed by the prop's `first_leaf` and `leaf_count`
+    pub static_prop_leafs: Vec<u16>,
     pub props: Vec<StaticPropLumpV5>,
 }
 
-pub fn load_gamelump(
+/// Read the directory of the game lump, in file order
+pub fn load_gamelump_directory(
     lump: &BSPLump,
     buffer: &mut BufReader<impl Read + Seek>,
-) -> io::Result<GameLump> {
-    buffer.seek(std::io::SeekFrom::Start(lump.file_ofs as u64))?;
+) -> SourceResult<Vec<BSPGameLump>> {
+    if lump.file_len <= 0 {
+        return Ok(Vec::new());
+    }
+
+    buffer
+        .seek(std::io::SeekFrom::Start(lump.file_ofs as u64))
+        .at("GameLump", buffer)?;
 
     let lump_count = i32::read(buffer, None)?;
 
-    let mut lumps = HashMap::new();
+    let mut lumps = Vec::new();
     for _i in 0..lump_count {
-        let e = BSPGameLump::read(buffer, None)?;
-
-        lumps.insert(e.id.clone(), e);
+        lumps.push(BSPGameLump::read(buffer, None)?);
     }
+    Ok(lumps)
+}
 
-    let static_props_lump = lumps.get(b"prps").unwrap();
-    //TODO: Support more versions
-    assert!(static_props_lump.version == 5);
-
-    buffer.seek(std::io::SeekFrom::Start(static_props_lump.fileofs as u64))?;
+/// Find the static prop lump in the game lump and seek to its start
+fn seek_static_props(
+    lump: &BSPLump,
+    buffer: &mut BufReader<impl Read + Seek>,
+) -> SourceResult<Option<BSPGameLump>> {
+    let lumps: HashMap<_, _> = load_gamelump_directory(lump, buffer)?
+        .into_iter()
+        .map(|e| (e.id, e))
+        .collect();
+
+    // Maps without any static props have no prop lump
+    let Some(&static_props_lump) = lumps.get(b"prps") else {
+        return Ok(None);
+    };
+    buffer
+        .seek(std::io::SeekFrom::Start(static_props_lump.fileofs as u64))
+        .at("StaticPropLump", buffer)?;
+    Ok(Some(static_props_lump))
+}
 
+/// Read the model names at the start of the static prop lump, which are laid out the same in every version
+fn read_static_prop_names(buffer: &mut BufReader<impl Read + Seek>) -> SourceResult<Vec<String>> {
     let dict_entries = i32::read(buffer, None)?;
 
     let mut static_prop_names = Vec::new();
@@ -81,23 +117,66 @@ pub fn load_gamelump(
 
         static_prop_names.push(e.name.to_ascii_lowercase());
     }
+    Ok(static_prop_names)
+}
+
+/// Read just the model names used by static props, which unlike [`load_gamelump`] works for any version of props
+pub fn load_static_prop_names(
+    lump: &BSPLump,
+    buffer: &mut BufReader<impl Read + Seek>,
+) -> SourceResult<Vec<String>> {
+    match seek_static_props(lump, buffer)? {
+        Some(_) => read_static_prop_names(buffer),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub fn load_gamelump(
+    lump: &BSPLump,
+    buffer: &mut BufReader<impl Read + Seek>,
+) -> SourceResult<GameLump> {
+    let Some(static_props_lump) = seek_static_props(lump, buffer)? else {
+        return Ok(GameLump::default());
+    };
+    //TODO: Support more versions
+    let version = static_props_lump.version;
+    if version != 5 {
+        return Err(SourceError::UnsupportedVersion {
+            structure: "StaticPropLump",
+            version: version.to_string(),
+        });
+    }
+
+    let static_prop_names = read_static_prop_names(buffer)?;
     let leafs = i32::read(buffer, None)?;
+    let mut static_prop_leafs = Vec::new();
     for _i in 0..leafs {
-        u16::read(buffer, None)?;
+        static_prop_leafs.push(u16::read(buffer, None)?);
     }
 
     let prop_lumps = i32::read(buffer, None)?;
     let mut props = Vec::new();
-    for _p in 0..prop_lumps {
+    for p in 0..prop_lumps {
         let prop = StaticPropLumpV5::read(buffer, None)?;
 
-        assert!((prop.prop_type as usize) < static_prop_names.len());
+        if prop.prop_type as usize >= static_prop_names.len() {
+            return Err(SourceError::invalid(
+                "StaticPropLumpV5",
+                buffer.stream_position().unwrap_or(0),
+                format!(
+                    "Prop {p} uses model {} of {}",
+                    { prop.prop_type },
+                    static_prop_names.len()
+                ),
+            ));
+        }
 
         props.push(prop);
     }
 
     Ok(GameLump {
         static_prop_names,
+        static_prop_leafs,
         props,
     })
 }
@@ -1,7 +1,7 @@
 use crate::bsp::consts::HEADER_LUMPS;
 use std::{
     fmt,
-    io::{self, BufReader, Cursor, Read, Seek},
+    io::{BufReader, Cursor, Read, Seek},
     mem,
     path::Path,
     slice,
@@ -13,11 +13,15 @@ use std::fs::File;
 use bytemuck::Zeroable;
 use common::vfile::VFileSystem;
 
+use crate::error::{ResultExt, SourceError, SourceResult};
+
 use super::{
     lump::{BSPLump, Lump},
     LumpType,
 };
 
+pub const BSP_IDENT: [u8; 4] = *b"VBSP";
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct BSPHeader {
@@ -54,21 +58,26 @@ impl BSPHeader {
     pub fn load_file<'a>(
         path: &Path,
         data: &'a VFileSystem,
-    ) -> io::Result<(Self, BufReader<Cursor<&'a [u8]>>)> {
-        let mut buffer = data.get(path).unwrap();
+    ) -> SourceResult<(Self, BufReader<Cursor<&'a [u8]>>)> {
+        let mut buffer = data
+            .get(path)
+            .ok_or_else(|| SourceError::NotFound(path.display().to_string()))?;
 
-        Ok((Self::load_buf(&mut buffer)?, buffer))
+        let header = Self::load_buf(&mut buffer).map_err(|e| e.in_file(path))?;
+        Ok((header, buffer))
     }
 
     #[cfg(target_arch = "x86_64")]
-    pub fn load(path: &Path) -> io::Result<(Self, BufReader<File>)> {
-        let file = File::open(path)?;
+    pub fn load(path: &Path) -> SourceResult<(Self, BufReader<File>)> {
+        let file = File::open(path)
+            .map_err(|e| SourceError::io("BSPHeader", 0, e).in_file(path))?;
         let mut buffer = BufReader::new(file);
 
-        Ok((Self::load_buf(&mut buffer)?, buffer))
+        let header = Self::load_buf(&mut buffer).map_err(|e| e.in_file(path))?;
+        Ok((header, buffer))
     }
 
-    pub fn load_buf<F: Read + Seek>(buffer: &mut BufReader<F>) -> io::Result<Self> {
+    pub fn load_buf<F: Read + Seek>(buffer: &mut BufReader<F>) -> SourceResult<Self> {
         let mut header = Self::zeroed();
 
         let header_size = mem::size_of::<Self>();
@@ -76,9 +85,16 @@ impl BSPHeader {
             let header_slice =
                 slice::from_raw_parts_mut(&mut header as *mut _ as *mut u8, header_size);
             // `read_exact()` comes from `Read` impl for `&[u8]`
-            buffer.read_exact(header_slice).unwrap();
+            buffer.read_exact(header_slice).at("BSPHeader", buffer)?;
         }
         //buffer.read_exact(&mut header.ident).unwrap();
+        if !header.has_valid_ident() {
+            return Err(SourceError::invalid(
+                "BSPHeader",
+                0,
+                format!("Unknown ident {:?}", header.ident),
+            ));
+        }
         Ok(header)
     }
     pub fn get_lump_header(&self, lump: LumpType) -> &BSPLump {
@@ -87,21 +103,16 @@ impl BSPHeader {
     pub fn get_lump<T: Lump + bytemuck::Zeroable>(
         &self,
         buffer: &mut BufReader<impl Seek + Read>,
-    ) -> Box<[T]> {
-        self.get_lump_header(T::lump_type()).decode(buffer).unwrap()
+    ) -> SourceResult<Box<[T]>> {
+        self.get_lump_header(T::lump_type()).decode(buffer)
+    }
+    /// Check the magic number
+    /// This way around means little endian, PSBV is big endian
+    pub fn has_valid_ident(&self) -> bool {
+        self.ident == BSP_IDENT
     }
     pub fn validate(&self) {
-        // Check the magic number
-        // This way around means little endian, PSBV is big endian
-        let text = "VBSP";
-        let magic_number: [u8; 4] = text
-            .chars()
-            .map(|c| c as u8)
-            .collect::<Vec<u8>>()
-            .try_into()
-            .unwrap();
-
-        assert_eq!(self.ident, magic_number);
+        assert_eq!(self.ident, BSP_IDENT);
 
         //let mut i8s = [0, 0, 0, 0];
         //buffer.read_exact(&mut i8s).unwrap();
@@ -0,0 +1,354 @@
+//! A summary of what is in a map file, for the `bsp-info` tool
+
+use std::{
+    fmt,
+    io::{Read, Seek},
+};
+
+use num_traits::FromPrimitive;
+use serde_json::{json, Value};
+
+use super::{
+    consts::{HEADER_LUMPS, MAX_MAP_ENTITIES},
+    validate::lump_limit,
+    Bsp, LumpType,
+};
+use crate::error::SourceResult;
+
+/// A section of [`MapInfo`] read from the map, or why it could not be, so the rest can still be shown
+pub type Section<T> = Result<Vec<T>, String>;
+
+/// An entry of the header's lump directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LumpInfo {
+    pub index: usize,
+    /// `None` for indices the engine does not use
+    pub lump_type: Option<LumpType>,
+    pub offset: i32,
+    pub length: i32,
+    pub version: i32,
+    /// Number of elements, for lumps of fixed size elements and the entity lump
+    pub elements: Option<usize>,
+    /// Engine limit on `elements`
+    pub max_elements: Option<usize>,
+}
+
+/// An entry of the game lump's directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameLumpInfo {
+    /// Four character id, such as `sprp` for static props
+    pub id: String,
+    pub flags: u16,
+    pub version: u16,
+    pub offset: i32,
+    pub length: i32,
+}
+
+/// The header, lump directories, pakfile contents and dependencies of a map
+///
+/// Everything after the header's lump directory is read from lumps that may be broken, so each keeps its own error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapInfo {
+    pub version: i32,
+    pub map_revision: i32,
+    pub lumps: Vec<LumpInfo>,
+    pub game_lumps: Section<GameLumpInfo>,
+    /// Paths and sizes of the files packed into the map, sorted by path
+    pub pak_files: Section<(String, usize)>,
+    /// Materials used by faces, such as `brick/brickwall001a`
+    pub materials: Section<String>,
+    /// Models of static props and entities, such as `models/props_c17/oildrum001.mdl`
+    pub models: Section<String>,
+}
+
+impl MapInfo {
+    pub fn new<R: Read + Seek>(bsp: &Bsp<R>) -> Self {
+        let header = bsp.header();
+
+        let lumps = (0..HEADER_LUMPS)
+            .map(|index| {
+                let lump = header.lumps[index];
+                let lump_type = LumpType::from_usize(index);
+                let (elements, max_elements) = match lump_type {
+                    Some(LumpType::Entities) => (
+                        bsp.entities().ok().map(|entities| entities.len()),
+                        Some(MAX_MAP_ENTITIES),
+                    ),
+                    Some(lump_type) => match lump_limit(lump_type, lump.version) {
+                        Some(limit) => (
+                            Some(lump.file_len.max(0) as usize / limit.element_size),
+                            Some(limit.max_elements),
+                        ),
+                        None => (None, None),
+                    },
+                    None => (None, None),
+                };
+                LumpInfo {
+                    index,
+                    lump_type,
+                    offset: lump.file_ofs,
+                    length: lump.file_len,
+                    version: lump.version,
+                    elements,
+                    max_elements,
+                }
+            })
+            .collect();
+
+        let game_lumps = section(|| {
+            Ok(bsp
+                .game_lump_directory()?
+                .into_iter()
+                .map(|lump| GameLumpInfo {
+                    // Stored as a little endian integer, so `sprp` is read as `prps`
+                    id: lump.id.iter().rev().map(|&c| c as char).collect(),
+                    flags: lump.flags,
+                    version: lump.version,
+                    offset: lump.fileofs,
+                    length: lump.filelen,
+                })
+                .collect())
+        });
+
+        let pak_files = section(|| {
+            if header.get_lump_header(LumpType::PakFile).file_len <= 0 {
+                return Ok(Vec::new());
+            }
+            Ok(bsp
+                .pak()?
+                .entries()
+                .into_iter()
+                .map(|(path, file)| {
+                    let size = file.preload().map_or(0, Vec::len) + file.len() as usize;
+                    (path, size)
+                })
+                .collect())
+        });
+
+        let materials = section(|| {
+            let mut materials = bsp.texture_names()?.to_vec();
+            materials.sort();
+            materials.dedup();
+            Ok(materials)
+        });
+
+        let models = section(|| {
+            let mut models: Vec<String> = bsp.static_prop_names()?;
+            models.extend(
+                bsp.entities()?
+                    .iter()
+                    .filter_map(|entity| entity.get("model"))
+                    .filter(|model| !model.starts_with('*'))
+                    .map(|model| model.to_ascii_lowercase().replace('\\', "/")),
+            );
+            models.sort();
+            models.dedup();
+            Ok(models)
+        });
+
+        Self {
+            version: header.version,
+            map_revision: header.map_revision,
+            lumps,
+            game_lumps,
+            pak_files,
+            materials,
+            models,
+        }
+    }
+
+    /// Whether every section could be read
+    pub fn is_complete(&self) -> bool {
+        self.game_lumps.is_ok()
+            && self.pak_files.is_ok()
+            && self.materials.is_ok()
+            && self.models.is_ok()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "version": self.version,
+            "map_revision": self.map_revision,
+            "lumps": self.lumps.iter().map(|lump| json!({
+                "index": lump.index,
+                "type": lump.lump_type.map(|lump_type| format!("{lump_type:?}")),
+                "offset": lump.offset,
+                "length": lump.length,
+                "version": lump.version,
+                "elements": lump.elements,
+                "max_elements": lump.max_elements,
+            })).collect::<Vec<_>>(),
+            "game_lumps": section_json(&self.game_lumps, |lump| json!({
+                "id": lump.id,
+                "flags": lump.flags,
+                "version": lump.version,
+                "offset": lump.offset,
+                "length": lump.length,
+            })),
+            "pak_files": section_json(&self.pak_files, |(path, size)| json!({
+                "path": path,
+                "size": size,
+            })),
+            "materials": section_json(&self.materials, |material| json!(material)),
+            "models": section_json(&self.models, |model| json!(model)),
+        })
+    }
+}
+
+fn section<T>(read: impl FnOnce() -> SourceResult<Vec<T>>) -> Section<T> {
+    read().map_err(|e| e.to_string())
+}
+
+/// An array of the section's items, or an object holding its error
+fn section_json<T>(section: &Section<T>, item: impl Fn(&T) -> Value) -> Value {
+    match section {
+        Ok(items) => items.iter().map(item).collect(),
+        Err(e) => json!({ "error": e }),
+    }
+}
+
+/// The heading of a section and its items, or its error
+fn write_section<T>(
+    f: &mut fmt::Formatter<'_>,
+    heading: &str,
+    section: &Section<T>,
+    mut item: impl FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+) -> fmt::Result {
+    match section {
+        Ok(items) => {
+            writeln!(f, "\n{heading} ({}):", items.len())?;
+            items.iter().try_for_each(|i| item(f, i))
+        }
+        Err(e) => writeln!(f, "\n{heading}: unreadable, {e}"),
+    }
+}
+
+impl fmt::Display for MapInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Version {}, revision {}",
+            self.version, self.map_revision
+        )?;
+
+        writeln!(f, "\nLumps:")?;
+        writeln!(
+            f,
+            "{:>3} {:<28} {:>10} {:>10} {:>4} {:>17}",
+            "#", "type", "offset", "length", "ver", "elements"
+        )?;
+        for lump in &self.lumps {
+            let lump_type = match lump.lump_type {
+                Some(lump_type) => format!("{lump_type:?}"),
+                None => "Unused".to_owned(),
+            };
+            let elements = match (lump.elements, lump.max_elements) {
+                (Some(elements), Some(max)) => format!("{elements}/{max}"),
+                (Some(elements), None) => elements.to_string(),
+                _ => String::new(),
+            };
+            writeln!(
+                f,
+                "{:>3} {:<28} {:>10} {:>10} {:>4} {:>17}",
+                lump.index, lump_type, lump.offset, lump.length, lump.version, elements
+            )?;
+        }
+
+        write_section(f, "Game lumps", &self.game_lumps, |f, lump| {
+            writeln!(
+                f,
+                "  {} version {}, flags {:#x}, {} bytes at {}",
+                lump.id, lump.version, lump.flags, lump.length, lump.offset
+            )
+        })?;
+        write_section(f, "Pakfile", &self.pak_files, |f, (path, size)| {
+            writeln!(f, "  {path} ({size} bytes)")
+        })?;
+        write_section(f, "Materials", &self.materials, |f, material| {
+            writeln!(f, "  {material}")
+        })?;
+        write_section(f, "Models", &self.models, |f, model| {
+            writeln!(f, "  {model}")
+        })
+    }
+}
+
+#[cfg(test)]
+mod info_tests {
+    use super::*;
+    use crate::bsp::test_map::TestMap;
+
+    #[test]
+    fn quad() {
+        let entities = "{\n\"classname\" \"worldspawn\"\n}\n\
+            {\n\"classname\" \"prop_dynamic\"\n\"model\" \"Models\\Crate.mdl\"\n}\n\0";
+        let bsp = Bsp::new(
+            TestMap::quad()
+                .with_bytes(LumpType::Entities, entities.as_bytes().to_vec())
+                .reader(),
+        )
+        .unwrap();
+        let info = MapInfo::new(&bsp);
+
+        assert_eq!(info.lumps.len(), HEADER_LUMPS);
+        let faces = &info.lumps[LumpType::Faces as usize];
+        assert_eq!(faces.lump_type, Some(LumpType::Faces));
+        assert_eq!(faces.elements, Some(1));
+        assert_eq!(faces.max_elements, Some(65536));
+        assert_eq!(info.lumps[LumpType::Entities as usize].elements, Some(2));
+        assert_eq!(info.lumps[63].lump_type, None);
+
+        assert!(info.is_complete());
+        assert_eq!(info.game_lumps, Ok(Vec::new()));
+        assert_eq!(info.pak_files, Ok(Vec::new()));
+        assert_eq!(
+            info.materials.as_deref(),
+            Ok(&["dev/dev_measuregeneric01".to_owned()][..])
+        );
+        assert_eq!(
+            info.models.as_deref(),
+            Ok(&["models/crate.mdl".to_owned()][..])
+        );
+
+        let json = info.to_json();
+        assert_eq!(json["lumps"][7]["type"], "Faces");
+        assert_eq!(json["materials"][0], "dev/dev_measuregeneric01");
+        assert!(info.to_string().contains("Faces"));
+    }
+
+    #[test]
+    fn broken_lump() {
+        // The material's name is missing from the string table
+        let bsp = Bsp::new(
+            TestMap::quad()
+                .with_bytes(LumpType::TexDataStringTable, Vec::new())
+                .reader(),
+        )
+        .unwrap();
+        let info = MapInfo::new(&bsp);
+
+        assert!(!info.is_complete());
+        assert!(info.materials.is_err());
+        assert_eq!(info.lumps.len(), HEADER_LUMPS);
+        assert_eq!(info.models, Ok(Vec::new()));
+
+        assert!(info.to_json()["materials"]["error"].is_string());
+        let text = info.to_string();
+        assert!(text.contains("Faces"));
+        assert!(text.contains("Materials: unreadable"));
+    }
+
+    #[test]
+    fn newer_props() {
+        // Props in a version that can't be read yet still have their models listed
+        let map = TestMap::quad().with_static_props(10, &["Models/Crate.mdl"], &[0; 76], 1);
+        let info = MapInfo::new(&Bsp::new(map.reader()).unwrap());
+
+        assert!(info.is_complete());
+        assert_eq!(info.game_lumps.as_ref().unwrap()[0].id, "sprp");
+        assert_eq!(
+            info.models.as_deref(),
+            Ok(&["models/crate.mdl".to_owned()][..])
+        );
+    }
+}
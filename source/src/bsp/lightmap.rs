@@ -1,7 +1,9 @@
+use std::collections::HashMap;
+
 use common::vbuffer::VBuffer;
-use glam::{vec3, vec4, Vec3, Vec4};
+use glam::{uvec2, vec3, vec4, UVec2, Vec2, Vec3, Vec4};
 
-use super::{consts::MAX_MAP_LIGHTING, Lump, LumpType};
+use super::{consts::MAX_MAP_LIGHTING, face::BSPFace, Lump, LumpType};
 
 pub struct LightingData {
     pub buffer: VBuffer,
@@ -40,3 +42,140 @@ impl Lump for ColorRGBExp32 {
         LumpType::Lighting
     }
 }
+
+/// Every face's lightmap packed into one image, for exporting a map with its lighting
+pub struct LightmapAtlas {
+    pub width: u32,
+    pub height: u32,
+    /// sRGB RGBA8 pixels, row by row
+    pub pixels: Vec<u8>,
+    /// Corner of each face's luxels, keyed by the index of its first sample in the lighting lump
+    corners: HashMap<i32, UVec2>,
+}
+
+impl LightmapAtlas {
+    /// Pack the lightmaps of `faces`, one row of lightmaps after another, tallest first. Faces without lighting
+    /// share a white luxel in the corner.
+    pub fn build(faces: &[BSPFace], lighting: &[ColorRGBExp32]) -> Self {
+        let mut lightmaps: Vec<(i32, UVec2)> = faces
+            .iter()
+            .filter(|face| face.light_ofs >= 0)
+            .filter_map(|face| {
+                let size = (face.lightmap_texture_size_in_luxels + 1).as_uvec2();
+                let first = face.light_ofs / 4;
+                let end = first as usize + (size.x * size.y) as usize;
+                (end <= lighting.len()).then_some((first, size))
+            })
+            .collect();
+        lightmaps.sort_by_key(|&(first, size)| (std::cmp::Reverse(size.y), first));
+        lightmaps.dedup_by_key(|&mut (first, _)| first);
+
+        let area: u32 = lightmaps.iter().map(|(_, size)| size.x * size.y).sum();
+        let widest = lightmaps.iter().map(|(_, size)| size.x).max().unwrap_or(1);
+        let width = ((area as f32).sqrt().ceil() as u32)
+            .next_power_of_two()
+            .max(widest);
+
+        let mut corners = HashMap::new();
+        let mut cursor = uvec2(1, 0);
+        let mut row_height = 1;
+        for &(first, size) in &lightmaps {
+            if cursor.x + size.x > width {
+                cursor = uvec2(0, cursor.y + row_height);
+                row_height = 0;
+            }
+            corners.insert(first, cursor);
+            cursor.x += size.x;
+            row_height = row_height.max(size.y);
+        }
+        let height = cursor.y + row_height;
+
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        pixels[..4].copy_from_slice(&[255; 4]);
+        for &(first, size) in &lightmaps {
+            let corner = corners[&first];
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let color: Vec3 = lighting[(first as u32 + y * size.x + x) as usize].into();
+                    let i = (((corner.y + y) * width + corner.x + x) * 4) as usize;
+                    pixels[i..i + 3].copy_from_slice(&color.to_array().map(linear_to_srgb));
+                    pixels[i + 3] = 255;
+                }
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            corners,
+        }
+    }
+
+    /// Atlas uv of a vertex from the mesh builder, from its light index and luxel coordinates
+    pub fn uv(&self, light_index: i32, lightmap_uv: Vec2) -> Vec2 {
+        // Unlit faces use the white luxel
+        let (corner, luxel) = match self.corners.get(&light_index) {
+            Some(&corner) => (corner, lightmap_uv),
+            None => (UVec2::ZERO, Vec2::ZERO),
+        };
+        // Luxel coordinates are at the centres of luxels
+        (corner.as_vec2() + luxel + 0.5) / uvec2(self.width, self.height).as_vec2()
+    }
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let srgb = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod lightmap_tests {
+    use bytemuck::Zeroable;
+    use glam::{ivec2, vec2, IVec2};
+
+    use super::*;
+
+    fn face(light_ofs: i32, size: IVec2) -> BSPFace {
+        let mut face = BSPFace::zeroed();
+        face.light_ofs = light_ofs;
+        face.lightmap_texture_size_in_luxels = size - 1;
+        face
+    }
+
+    #[test]
+    fn atlas() {
+        let grey = ColorRGBExp32 {
+            r: 128,
+            g: 128,
+            b: 128,
+            exponent: 0,
+        };
+        // A 2x2 lightmap and a 3x1 one
+        let lighting = vec![grey; 7];
+        // Two faces sharing a lightmap, one unlit, and one with its lighting out of range
+        let faces = [
+            face(0, ivec2(2, 2)),
+            face(0, ivec2(2, 2)),
+            face(-1, IVec2::ONE),
+            face(16, ivec2(3, 1)),
+            face(64, ivec2(4, 4)),
+        ];
+        let atlas = LightmapAtlas::build(&faces, &lighting);
+
+        assert_eq!((atlas.width, atlas.height), (4, 3));
+        assert_eq!(atlas.pixels.len(), 4 * 3 * 4);
+        assert_eq!(&atlas.pixels[..4], &[255; 4]);
+        // Taller lightmaps first, after the white luxel
+        assert_eq!(&atlas.pixels[4..8], &[188, 188, 188, 255]);
+        assert_eq!(atlas.uv(0, Vec2::ZERO), vec2(1.5 / 4.0, 0.5 / 3.0));
+        assert_eq!(atlas.uv(4, vec2(2.0, 0.0)), vec2(2.5 / 4.0, 2.5 / 3.0));
+        assert_eq!(atlas.uv(-1, vec2(5.0, 5.0)), vec2(0.5 / 4.0, 0.5 / 3.0));
+        assert_eq!(atlas.uv(16, Vec2::ONE), atlas.uv(-1, Vec2::ZERO));
+    }
+}
@@ -4,7 +4,10 @@ use std::{
     mem, slice,
 };
 
-use crate::binaries::BinaryData;
+use crate::{
+    binaries::{mapped, BinaryData},
+    error::{structure_name, ResultExt, SourceError, SourceResult},
+};
 
 use super::consts::LumpType;
 
@@ -30,16 +33,19 @@ impl BSPLump {
     pub fn decode<T: bytemuck::Zeroable, R: Seek + Read>(
         &self,
         buffer: &mut BufReader<R>,
-    ) -> io::Result<Box<[T]>> {
+    ) -> SourceResult<Box<[T]>> {
         let item_size = mem::size_of::<T>();
 
-        assert_eq!(
-            self.file_len as usize % item_size,
-            0,
-            "Structure given does not fit nicely into lump data"
-        );
+        let (_, file_len) = self.checked_range::<T>()?;
+        if !file_len.is_multiple_of(item_size) {
+            return Err(SourceError::invalid(
+                structure_name::<T>(),
+                self.file_ofs as u64,
+                format!("Lump length {file_len} is not a multiple of the structure size {item_size}"),
+            ));
+        }
 
-        let len = self.file_len as usize / item_size;
+        let len = file_len / item_size;
 
         let mut table = bytemuck::zeroed_slice_box(len);
 
@@ -47,27 +53,58 @@ impl BSPLump {
             unsafe {
                 let header_slice =
                     slice::from_raw_parts_mut(&mut table[0] as *mut _ as *mut u8, len * item_size);
-                buffer.seek(io::SeekFrom::Start(self.file_ofs as u64))?;
+                buffer
+                    .seek(io::SeekFrom::Start(self.file_ofs as u64))
+                    .at(structure_name::<T>(), buffer)?;
                 // `read_exact()` comes from `Read` impl for `&[u8]`
-                buffer.read_exact(header_slice)?;
+                buffer
+                    .read_exact(header_slice)
+                    .at(structure_name::<T>(), buffer)?;
             }
         }
 
         Ok(table)
     }
 
+    /// Borrow the lump straight from the bytes of the whole file.
+    ///
+    /// Returns `None` if the lump is not aligned for `T` and has to be decoded instead.
+    pub fn view<'a, T: bytemuck::AnyBitPattern>(
+        &self,
+        file: &'a [u8],
+    ) -> SourceResult<Option<&'a [T]>> {
+        let (file_ofs, file_len) = self.checked_range::<T>()?;
+        mapped::view(file, file_ofs, file_len)
+    }
+
+    fn checked_range<T>(&self) -> SourceResult<(usize, usize)> {
+        let file_len = self.file_len;
+        match (usize::try_from(self.file_ofs), usize::try_from(file_len)) {
+            (Ok(file_ofs), Ok(file_len)) => Ok((file_ofs, file_len)),
+            _ => Err(SourceError::invalid(
+                structure_name::<T>(),
+                self.file_ofs as u64,
+                format!("Lump has negative offset or length {file_len}"),
+            )),
+        }
+    }
+
     pub fn read_binary<T: BinaryData>(
         &self,
         buffer: &mut BufReader<impl Read + Seek>,
-    ) -> io::Result<T> {
-        buffer.seek(std::io::SeekFrom::Start(self.file_ofs as u64))?;
-        Ok(T::read(buffer, Some(self.file_len as usize))?)
+    ) -> SourceResult<T> {
+        buffer
+            .seek(std::io::SeekFrom::Start(self.file_ofs as u64))
+            .at(structure_name::<T>(), buffer)?;
+        T::read(buffer, Some(self.file_len.max(0) as usize))
     }
 
-    pub fn read_bytes(&self, buffer: &mut BufReader<impl Read + Seek>) -> io::Result<Box<[u8]>> {
-        buffer.seek(std::io::SeekFrom::Start(self.file_ofs as u64))?;
-        let mut bytes = bytemuck::zeroed_slice_box(self.file_len as usize);
-        buffer.read_exact(&mut bytes)?;
+    pub fn read_bytes(&self, buffer: &mut BufReader<impl Read + Seek>) -> SourceResult<Box<[u8]>> {
+        buffer
+            .seek(std::io::SeekFrom::Start(self.file_ofs as u64))
+            .at("lump", buffer)?;
+        let mut bytes = bytemuck::zeroed_slice_box(self.file_len.max(0) as usize);
+        buffer.read_exact(&mut bytes).at("lump", buffer)?;
         Ok(bytes)
     }
 }
@@ -0,0 +1,640 @@
+use std::{
+    any::Any,
+    collections::BTreeSet,
+    io::{BufReader, Cursor, Read, Seek},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
+};
+
+#[cfg(target_arch = "x86_64")]
+use std::fs::File;
+
+use common::vfile::VFileSystem;
+use glam::Vec3;
+
+use crate::{
+    binaries::mapped::SharedBytes,
+    error::{structure_name, SourceError, SourceResult},
+    vpk::VPKDirectory,
+};
+
+use super::{
+    brush::{BSPBrush, BSPBrushSide, BSPLeafBrush},
+    consts::HEADER_LUMPS,
+    displacement::{BSPDispInfo, BSPDispVert},
+    edges::{BSPEdge, BSPSurfEdge},
+    entities::{parse_entities, BSPEntity},
+    face::BSPFace,
+    gamelump::{
+        load_gamelump, load_gamelump_directory, load_static_prop_names, BSPGameLump, GameLump,
+    },
+    header::BSPHeader,
+    lightmap::ColorRGBExp32,
+    model::BSPModel,
+    plane::BSPPlane,
+    textures::{BSPTexData, BSPTexDataStringTable, BSPTexInfo},
+    tree::{BSPLeaf, BSPLeafFace, BSPNode},
+    Lump, LumpType,
+};
+
+type LumpCell = OnceLock<SourceResult<Box<dyn Any + Send + Sync>>>;
+
+/// A loaded map file.
+///
+/// Lumps are decoded the first time they are asked for and cached, so accessors are cheap to call repeatedly
+/// and can be shared between threads. Maps opened from bytes already in memory (or mapped with the `mmap` feature)
+/// borrow their lumps from those bytes instead of copying them.
+pub struct Bsp<R> {
+    path: Option<PathBuf>,
+    header: BSPHeader,
+    bytes: Option<SharedBytes>,
+    buffer: Mutex<BufReader<R>>,
+    lumps: [LumpCell; HEADER_LUMPS],
+    texture_names: OnceLock<SourceResult<Box<[String]>>>,
+    entities: OnceLock<SourceResult<Box<[BSPEntity]>>>,
+    pak: OnceLock<SourceResult<Arc<VPKDirectory>>>,
+    game_lump: OnceLock<SourceResult<Arc<GameLump>>>,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Bsp<File> {
+    pub fn load(path: &Path) -> SourceResult<Self> {
+        let (header, buffer) = BSPHeader::load(path)?;
+        Ok(Self::from_parts(header, buffer, Some(path.to_owned())))
+    }
+}
+
+impl<'a> Bsp<Cursor<&'a [u8]>> {
+    pub fn load_file(path: &Path, data: &'a VFileSystem) -> SourceResult<Self> {
+        let (header, buffer) = BSPHeader::load_file(path, data)?;
+        Ok(Self::from_parts(header, buffer, Some(path.to_owned())))
+    }
+}
+
+impl Bsp<Cursor<SharedBytes>> {
+    /// Read a map from bytes that lumps can be borrowed from directly
+    pub fn from_bytes(bytes: SharedBytes) -> SourceResult<Self> {
+        let mut bsp = Self::new(BufReader::new(Cursor::new(bytes.clone())))?;
+        bsp.bytes = Some(bytes);
+        Ok(bsp)
+    }
+
+    /// Map a file into memory, so lumps are read straight from the page cache
+    #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+    pub fn load_mapped(path: &Path) -> SourceResult<Self> {
+        let mut bsp = Self::from_bytes(SharedBytes::map(path)?).map_err(|e| e.in_file(path))?;
+        bsp.path = Some(path.to_owned());
+        Ok(bsp)
+    }
+}
+
+impl<R: Read + Seek> Bsp<R> {
+    /// Read a map from any seekable source, such as an in-memory buffer
+    pub fn new(mut buffer: BufReader<R>) -> SourceResult<Self> {
+        let header = BSPHeader::load_buf(&mut buffer)?;
+        Ok(Self::from_parts(header, buffer, None))
+    }
+
+    fn from_parts(header: BSPHeader, buffer: BufReader<R>, path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            header,
+            bytes: None,
+            buffer: Mutex::new(buffer),
+            lumps: std::array::from_fn(|_| OnceLock::new()),
+            texture_names: OnceLock::new(),
+            entities: OnceLock::new(),
+            pak: OnceLock::new(),
+            game_lump: OnceLock::new(),
+        }
+    }
+
+    pub fn header(&self) -> &BSPHeader {
+        &self.header
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Borrow the lump holding `T` from the mapped file, or decode it and return the cached copy
+    pub fn lump<T: Lump + bytemuck::AnyBitPattern + Send + Sync>(&self) -> SourceResult<&[T]> {
+        if let Some(bytes) = &self.bytes {
+            let lump = self.header.get_lump_header(T::lump_type());
+            if let Some(view) = lump.view(bytes.as_ref()).map_err(|e| self.context(e))? {
+                return Ok(view);
+            }
+        }
+
+        self.cached_lump(|| self.header.get_lump::<T>(&mut self.buffer()))
+    }
+
+    /// The cached copy of the lump holding `T`, filling it with `decode` the first time
+    fn cached_lump<T: Lump + Send + Sync + 'static>(
+        &self,
+        decode: impl FnOnce() -> SourceResult<Box<[T]>>,
+    ) -> SourceResult<&[T]> {
+        let lump = self.lumps[T::lump_type() as usize].get_or_init(|| {
+            decode()
+                .map(|lump| Box::new(lump) as Box<dyn Any + Send + Sync>)
+                .map_err(|e| self.context(e))
+        });
+
+        match lump {
+            Ok(lump) => lump
+                .downcast_ref::<Box<[T]>>()
+                .map(|lump| &lump[..])
+                .ok_or_else(|| {
+                    SourceError::invalid(
+                        structure_name::<T>(),
+                        self.header.get_lump_header(T::lump_type()).file_ofs as u64,
+                        "Lump was already loaded as a different type",
+                    )
+                }),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    pub fn planes(&self) -> SourceResult<&[BSPPlane]> {
+        self.lump()
+    }
+
+    pub fn vertices(&self) -> SourceResult<&[Vec3]> {
+        self.lump()
+    }
+
+    pub fn edges(&self) -> SourceResult<&[BSPEdge]> {
+        self.lump()
+    }
+
+    pub fn surf_edges(&self) -> SourceResult<&[BSPSurfEdge]> {
+        self.lump()
+    }
+
+    pub fn faces(&self) -> SourceResult<&[BSPFace]> {
+        self.lump()
+    }
+
+    pub fn models(&self) -> SourceResult<&[BSPModel]> {
+        self.lump()
+    }
+
+    pub fn tex_info(&self) -> SourceResult<&[BSPTexInfo]> {
+        self.lump()
+    }
+
+    pub fn tex_data(&self) -> SourceResult<&[BSPTexData]> {
+        self.lump()
+    }
+
+    pub fn tex_data_string_table(&self) -> SourceResult<&[BSPTexDataStringTable]> {
+        self.lump()
+    }
+
+    pub fn disp_infos(&self) -> SourceResult<&[BSPDispInfo]> {
+        self.lump()
+    }
+
+    pub fn disp_verts(&self) -> SourceResult<&[BSPDispVert]> {
+        self.lump()
+    }
+
+    pub fn nodes(&self) -> SourceResult<&[BSPNode]> {
+        self.lump()
+    }
+
+    /// Leafs of the tree. Older maps store version 0 leafs, which are converted to the current layout
+    pub fn leafs(&self) -> SourceResult<&[BSPLeaf]> {
+        let lump = self.header.get_lump_header(LumpType::Leafs);
+        if lump.version != 0 {
+            return self.lump();
+        }
+
+        self.cached_lump(|| {
+            let bytes = lump.read_bytes(&mut self.buffer())?;
+            if !bytes.len().is_multiple_of(BSPLeaf::V0_SIZE) {
+                return Err(SourceError::invalid(
+                    "BSPLeaf",
+                    lump.file_ofs as u64,
+                    format!(
+                        "Version 0 lump length {} is not a multiple of {}",
+                        bytes.len(),
+                        BSPLeaf::V0_SIZE
+                    ),
+                ));
+            }
+
+            Ok(bytes
+                .chunks_exact(BSPLeaf::V0_SIZE)
+                .map(|leaf| BSPLeaf::from_v0(leaf.try_into().unwrap()))
+                .collect())
+        })
+    }
+
+    pub fn leaf_faces(&self) -> SourceResult<&[BSPLeafFace]> {
+        self.lump()
+    }
+
+    pub fn leaf_brushes(&self) -> SourceResult<&[BSPLeafBrush]> {
+        self.lump()
+    }
+
+    pub fn brushes(&self) -> SourceResult<&[BSPBrush]> {
+        self.lump()
+    }
+
+    pub fn brush_sides(&self) -> SourceResult<&[BSPBrushSide]> {
+        self.lump()
+    }
+
+    pub fn lighting(&self) -> SourceResult<&[ColorRGBExp32]> {
+        self.lump()
+    }
+
+    /// Positions of the corners of a face, in the order its edges are traced
+    pub fn face_vertices(&self, face: &BSPFace) -> SourceResult<Vec<Vec3>> {
+        let verts = self.vertices()?;
+        let edges = self.edges()?;
+        let surf_edges = self.surf_edges()?;
+
+        let first_edge = face.first_edge;
+        let num_edges = face.num_edges;
+
+        (0..num_edges.max(0) as usize)
+            .map(|i| {
+                let (v, _) = usize::try_from(first_edge)
+                    .ok()
+                    .and_then(|first| surf_edges.get(first + i))
+                    .and_then(|surf_edge| surf_edge.try_get_edge(edges))
+                    .ok_or_else(|| {
+                        SourceError::invalid(
+                            "BSPFace",
+                            0,
+                            format!("Surf edge {} out of range", first_edge as i64 + i as i64),
+                        )
+                    })?;
+
+                verts.get(v as usize).copied().ok_or_else(|| {
+                    SourceError::invalid("BSPEdge", 0, format!("Vertex {v} out of range"))
+                })
+            })
+            .collect::<SourceResult<_>>()
+            .map_err(|e| self.context(e))
+    }
+
+    /// Index of the leaf containing `point`, walking down the tree from the world's head node
+    pub fn find_leaf(&self, point: Vec3) -> SourceResult<usize> {
+        let nodes = self.nodes()?;
+        let planes = self.planes()?;
+        let mut node = self.models()?.first().map_or(0, BSPModel::head_node);
+
+        // Every step goes down the tree, so taking more steps than there are nodes means it loops
+        for _ in 0..=nodes.len() {
+            if node < 0 {
+                return Ok((-1 - node) as usize);
+            }
+
+            let n = nodes.get(node as usize).ok_or_else(|| {
+                self.context(SourceError::invalid(
+                    "BSPNode",
+                    0,
+                    format!("Node {node} out of range"),
+                ))
+            })?;
+            let plane_num = n.plane_num;
+            let plane = planes.get(plane_num as usize).ok_or_else(|| {
+                self.context(SourceError::invalid(
+                    "BSPNode",
+                    0,
+                    format!("Plane {plane_num} of node {node} out of range"),
+                ))
+            })?;
+
+            let (normal, dist) = (plane.normal, plane.dist);
+            let children = n.children;
+            node = children[if normal.dot(point) >= dist { 0 } else { 1 }];
+        }
+
+        Err(self.context(SourceError::invalid("BSPNode", 0, "Node tree loops")))
+    }
+
+    /// Indices of the brushes in every leaf under a model's head node, in order. Model 0 is the world.
+    pub fn model_brushes(&self, model: usize) -> SourceResult<Vec<usize>> {
+        let nodes = self.nodes()?;
+        let leafs = self.leafs()?;
+        let leaf_brushes = self.leaf_brushes()?;
+        let head_node = self
+            .models()?
+            .get(model)
+            .ok_or_else(|| SourceError::NotFound(format!("model {model}")))?
+            .head_node();
+
+        let mut brushes = BTreeSet::new();
+        // Leafs can be shared, and a broken tree could loop, so visit each node once
+        let mut visited = vec![false; nodes.len()];
+        let mut stack = vec![head_node];
+
+        while let Some(node) = stack.pop() {
+            if node < 0 {
+                let leaf = (-1 - node) as usize;
+                let range = leafs.get(leaf).and_then(|leaf| {
+                    let first = leaf.first_leaf_brush as usize;
+                    leaf_brushes.get(first..first + leaf.num_leaf_brushes as usize)
+                });
+                let range = range.ok_or_else(|| {
+                    self.context(SourceError::invalid(
+                        "BSPLeaf",
+                        0,
+                        format!("Leaf {leaf} or its brushes out of range"),
+                    ))
+                })?;
+                brushes.extend(range.iter().map(|leaf_brush| leaf_brush.brush as usize));
+                continue;
+            }
+
+            match visited.get_mut(node as usize) {
+                Some(true) => (),
+                Some(seen) => {
+                    *seen = true;
+                    stack.extend(nodes[node as usize].children);
+                }
+                None => {
+                    return Err(self.context(SourceError::invalid(
+                        "BSPNode",
+                        0,
+                        format!("Node {node} out of range"),
+                    )))
+                }
+            }
+        }
+
+        Ok(brushes.into_iter().collect())
+    }
+
+    /// Lower case material names for every entry in the tex data lump
+    pub fn texture_names(&self) -> SourceResult<&[String]> {
+        self.texture_names
+            .get_or_init(|| {
+                let tex_data = self.tex_data()?;
+                let string_table = self.tex_data_string_table()?;
+                let string_data = self.header.get_lump_header(LumpType::TexDataStringData);
+
+                let mut buffer = self.buffer();
+                tex_data
+                    .iter()
+                    .map(|data| {
+                        let id = data.name_string_table_id;
+                        string_table
+                            .get(id as usize)
+                            .ok_or_else(|| {
+                                SourceError::invalid(
+                                    "BSPTexData",
+                                    0,
+                                    format!("String table entry {id} out of range"),
+                                )
+                            })?
+                            .get_filename(&mut buffer, string_data)
+                    })
+                    .collect::<SourceResult<_>>()
+                    .map_err(|e| self.context(e))
+            })
+            .as_ref()
+            .map(|names| &names[..])
+            .map_err(Clone::clone)
+    }
+
+    /// Material name of the tex data entry at `tex_data`
+    pub fn texture_name(&self, tex_data: usize) -> SourceResult<&str> {
+        self.texture_names()?
+            .get(tex_data)
+            .map(String::as_str)
+            .ok_or_else(|| self.context(SourceError::NotFound(format!("Tex data {tex_data}"))))
+    }
+
+    pub fn entities(&self) -> SourceResult<&[BSPEntity]> {
+        self.entities
+            .get_or_init(|| {
+                let bytes = self
+                    .header
+                    .get_lump_header(LumpType::Entities)
+                    .read_bytes(&mut self.buffer())
+                    .map_err(|e| self.context(e))?;
+
+                parse_entities(&String::from_utf8_lossy(&bytes))
+                    .map(Vec::into_boxed_slice)
+                    .map_err(|e| self.context(e))
+            })
+            .as_ref()
+            .map(|entities| &entities[..])
+            .map_err(Clone::clone)
+    }
+
+    /// Files embedded in the map
+    pub fn pak(&self) -> SourceResult<&Arc<VPKDirectory>> {
+        self.pak
+            .get_or_init(|| {
+                self.header
+                    .get_lump_header(LumpType::PakFile)
+                    .read_binary(&mut self.buffer())
+                    .map(Arc::new)
+                    .map_err(|e| self.context(e))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    pub fn game_lump(&self) -> SourceResult<&Arc<GameLump>> {
+        self.game_lump
+            .get_or_init(|| {
+                load_gamelump(
+                    self.header.get_lump_header(LumpType::GameLump),
+                    &mut self.buffer(),
+                )
+                .map(Arc::new)
+                .map_err(|e| self.context(e))
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// The game lump for placing static props, or `None` with a warning if its props are in a version
+    /// that can't be read yet, so the rest of the map can still be used
+    pub fn static_prop_lump(&self) -> SourceResult<Option<&Arc<GameLump>>> {
+        match self.game_lump() {
+            Ok(game_lump) => Ok(Some(game_lump)),
+            Err(e) if matches!(e.root(), SourceError::UnsupportedVersion { .. }) => {
+                log::warn!("Skipping static props: {e}");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Model names used by static props, which can be read even when [`Bsp::game_lump`] can't read the props
+    pub fn static_prop_names(&self) -> SourceResult<Vec<String>> {
+        load_static_prop_names(
+            self.header.get_lump_header(LumpType::GameLump),
+            &mut self.buffer(),
+        )
+        .map_err(|e| self.context(e))
+    }
+
+    /// Every entry of the game lump, including those not understood by [`Bsp::game_lump`]
+    pub fn game_lump_directory(&self) -> SourceResult<Vec<BSPGameLump>> {
+        load_gamelump_directory(
+            self.header.get_lump_header(LumpType::GameLump),
+            &mut self.buffer(),
+        )
+        .map_err(|e| self.context(e))
+    }
+
+    fn buffer(&self) -> MutexGuard<'_, BufReader<R>> {
+        // The reader is always seeked before use, so a panic mid-read leaves nothing to clean up
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(super) fn context(&self, e: SourceError) -> SourceError {
+        match &self.path {
+            Some(path) => e.in_file(path),
+            None => e,
+        }
+    }
+}
+
+#[cfg(test)]
+mod map_tests {
+    use bytemuck::Zeroable;
+    use glam::vec3;
+
+    use crate::bsp::test_map::TestMap;
+
+    use super::*;
+
+    #[test]
+    fn cached_lumps() {
+        let bsp = Bsp::new(TestMap::quad().reader()).unwrap();
+
+        let faces = bsp.faces().unwrap();
+        assert_eq!(faces.len(), 1);
+        assert!(std::ptr::eq(faces, bsp.faces().unwrap()));
+        assert_eq!({ bsp.planes().unwrap()[0].normal }, Vec3::Z);
+    }
+
+    #[test]
+    fn face_vertices() {
+        let bsp = Bsp::new(TestMap::quad().reader()).unwrap();
+        let face = bsp.faces().unwrap()[0];
+
+        assert_eq!(
+            bsp.face_vertices(&face).unwrap(),
+            [
+                vec3(0.0, 0.0, 0.0),
+                vec3(0.0, 64.0, 0.0),
+                vec3(64.0, 64.0, 0.0),
+                vec3(64.0, 0.0, 0.0),
+            ]
+        );
+
+        let mut broken = face;
+        broken.first_edge = 3;
+        assert!(bsp.face_vertices(&broken).is_err());
+    }
+
+    #[test]
+    fn find_leaf() {
+        let map = TestMap::two_areas();
+        let bsp = Bsp::new(map.reader()).unwrap();
+
+        assert_eq!(bsp.find_leaf(vec3(32.0, 32.0, 0.0)).unwrap(), 0);
+        assert_eq!(bsp.find_leaf(vec3(1024.0, 0.0, 16.0)).unwrap(), 1);
+        assert_eq!(bsp.leafs().unwrap()[1].area(), 2);
+
+        // Version 0 leafs have ambient lighting at the end, which is dropped
+        let v0 = map
+            .lump_data::<BSPLeaf>(LumpType::Leafs)
+            .iter()
+            .flat_map(|leaf| {
+                let mut bytes = bytemuck::bytes_of(leaf)[..30].to_vec();
+                bytes.resize(BSPLeaf::V0_SIZE, 0xFF);
+                bytes
+            })
+            .collect();
+        let bsp = Bsp::new(map.clone().with_bytes(LumpType::Leafs, v0).reader()).unwrap();
+        let leafs = bsp.leafs().unwrap();
+        assert_eq!(leafs.len(), 2);
+        assert_eq!(({ leafs[1].first_leaf_face }, leafs[1].area()), (1, 2));
+
+        let mut node = BSPNode::zeroed();
+        node.children = [0, 0];
+        let bsp = Bsp::new(map.with_lump(LumpType::Nodes, &[node]).reader()).unwrap();
+        assert!(bsp.find_leaf(Vec3::ZERO).is_err());
+    }
+
+    #[test]
+    fn texture_names() {
+        let bsp = Bsp::new(TestMap::quad().reader()).unwrap();
+
+        assert_eq!(bsp.texture_name(0).unwrap(), "dev/dev_measuregeneric01");
+        assert!(bsp.texture_name(1).is_err());
+    }
+
+    #[test]
+    fn entities() {
+        let bsp = Bsp::new(TestMap::quad().reader()).unwrap();
+
+        let entities = bsp.entities().unwrap();
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].classname(), Some("worldspawn"));
+    }
+
+    #[test]
+    fn missing_optional_lumps() {
+        let bsp = Bsp::new(TestMap::quad().reader()).unwrap();
+
+        assert!(bsp.game_lump().unwrap().props.is_empty());
+        assert!(bsp.pak().unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn borrowed_lumps() {
+        let bytes = SharedBytes::new(TestMap::quad().build());
+        let bsp = Bsp::from_bytes(bytes.clone()).unwrap();
+
+        let verts = bsp.vertices().unwrap();
+        assert_eq!(verts.len(), 4);
+        assert!(bytes
+            .as_ref()
+            .as_ptr_range()
+            .contains(&verts.as_ptr().cast()));
+        assert_eq!(bsp.texture_name(0).unwrap(), "dev/dev_measuregeneric01");
+    }
+
+    #[test]
+    #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+    fn mapped_file() {
+        let path = std::env::temp_dir().join(format!("bsp_map_tests_{}.bsp", std::process::id()));
+        std::fs::write(&path, TestMap::quad().build()).unwrap();
+
+        let bsp = Bsp::load_mapped(&path).unwrap();
+        assert_eq!(bsp.path(), Some(path.as_path()));
+        assert_eq!(bsp.faces().unwrap().len(), 1);
+
+        drop(bsp);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_lump() {
+        let bsp = Bsp::new(
+            TestMap::quad()
+                .with_bytes(LumpType::Faces, vec![0; 5])
+                .reader(),
+        )
+        .unwrap();
+
+        assert!(bsp.faces().is_err());
+        // Errors are cached too, and other lumps are unaffected
+        assert!(bsp.faces().is_err());
+        assert_eq!(bsp.vertices().unwrap().len(), 4);
+    }
+}
@@ -1,18 +1,28 @@
+pub mod brush;
 pub mod consts;
 pub mod displacement;
 pub mod edges;
+pub mod entities;
 pub mod face;
 pub mod gamelump;
 pub mod header;
+pub mod info;
 pub mod lightmap;
 pub mod lump;
+pub mod map;
 pub mod model;
 pub mod plane;
+#[cfg(test)]
+pub(crate) mod test_map;
 pub mod textures;
+pub mod trace;
+pub mod tree;
+pub mod validate;
 pub mod vert;
 
 pub use consts::LumpType;
 pub use lump::Lump;
+pub use map::Bsp;
 
 // https://developer.valvesoftware.com/wiki/BSP_(Source)
 //
@@ -48,21 +58,20 @@ mod bsp_tests {
 
     use stream_unzip::ZipReader;
 
-    use bytemuck::Zeroable;
+    use bytemuck::AnyBitPattern;
     use glam::Vec3;
 
     use crate::bsp::consts::{num_disp_power_verts, MAX_MAP_TEXDATA_STRING_DATA};
 
     use super::{
         consts::LumpType,
-        displacement::{BSPDispInfo, BSPDispVert},
         edges::{BSPEdge, BSPSurfEdge},
         face::BSPFace,
         header::BSPHeader,
         model::BSPModel,
         plane::BSPPlane,
         textures::{BSPTexData, BSPTexDataStringTable, BSPTexInfo},
-        Lump,
+        Bsp, Lump,
     };
 
     const PATH : &str = "D:\\Program Files (x86)\\Steam\\steamapps\\common\\Half-Life 2\\hl2\\maps\\d1_trainstation_02.bsp";
@@ -117,10 +126,10 @@ mod bsp_tests {
 
     #[test]
     fn displacements() {
-        let (header, mut buffer) = BSPHeader::load(Path::new(PATH)).unwrap();
-        let infos = header.get_lump::<BSPDispInfo>(&mut buffer);
-        let verts = header.get_lump::<BSPDispVert>(&mut buffer);
-        let faces = header.get_lump::<BSPFace>(&mut buffer);
+        let bsp = Bsp::load(Path::new(PATH)).unwrap();
+        let infos = bsp.disp_infos().unwrap();
+        let verts = bsp.disp_verts().unwrap();
+        let faces = bsp.faces().unwrap();
 
         //ensure every vertex is accounted for
         let mut vert_marks = vec![0; verts.len()];
@@ -199,11 +208,9 @@ mod bsp_tests {
 
     #[test]
     fn textures() {
-        let (header, mut buffer) = BSPHeader::load(Path::new(PATH)).unwrap();
-        let tex_info = header.get_lump::<BSPTexInfo>(&mut buffer);
-        let tex_data = header.get_lump::<BSPTexData>(&mut buffer);
-        let tex_data_string_table = header.get_lump::<BSPTexDataStringTable>(&mut buffer);
-        let tex_data_string_data = header.get_lump_header(LumpType::TexDataStringData);
+        let bsp = Bsp::load(Path::new(PATH)).unwrap();
+        let tex_info = bsp.tex_info().unwrap();
+        let tex_data = bsp.tex_data().unwrap();
 
         // test data relation
         for info in tex_info.iter() {
@@ -211,22 +218,16 @@ mod bsp_tests {
             println!("{:?}", data);
         }
         // test data itself
-        for data in tex_data.iter() {
-            let string = tex_data_string_table[data.name_string_table_id as usize]
-                .get_filename(&mut buffer, tex_data_string_data);
-
+        for string in bsp.texture_names().unwrap() {
             println!("{}", string);
         }
     }
 
-    fn test_lump<T: Lump + Clone + Zeroable>() -> Box<[T]> {
-        let (header, mut buffer) = BSPHeader::load(Path::new(PATH)).unwrap();
-        let lump = header
-            .get_lump_header(T::lump_type())
-            .decode(&mut buffer)
-            .unwrap();
+    fn test_lump<T: Lump + AnyBitPattern + Send + Sync>() -> Vec<T> {
+        let bsp = Bsp::load(Path::new(PATH)).unwrap();
+        let lump = bsp.lump::<T>().unwrap();
 
         assert!(lump.len() < BSPPlane::max());
-        return lump;
+        lump.to_vec()
     }
 }
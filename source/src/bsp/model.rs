@@ -24,6 +24,22 @@ impl BSPModel {
     pub fn mins(&self) -> Vec3 {
         self.mins
     }
+
+    pub fn origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    pub fn head_node(&self) -> i32 {
+        self.headnode
+    }
+
+    pub fn first_face(&self) -> i32 {
+        self.firstface
+    }
+
+    pub fn num_faces(&self) -> i32 {
+        self.numfaces
+    }
 }
 
 impl Lump for BSPModel {
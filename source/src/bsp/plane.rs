@@ -24,7 +24,7 @@ use super::{consts::MAX_MAP_PLANES, Lump};
 ///
 /// There can be up to 65536 planes in a map (`MAX_MAP_PLANES`).
 #[repr(C, packed)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct BSPPlane {
     pub normal: Vec3, // normal vector
     pub dist: f32,    // distance from origin
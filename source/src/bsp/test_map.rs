@@ -0,0 +1,349 @@
+//! Builds small BSP files in memory for tests that cannot rely on game files being installed
+
+use std::{
+    io::{BufReader, Cursor},
+    mem,
+};
+
+use bytemuck::Zeroable;
+use glam::{ivec2, vec3, Vec3};
+
+use super::{
+    brush::{BSPBrush, BSPBrushSide, BSPLeafBrush},
+    consts::{Contents, HEADER_LUMPS},
+    edges::{BSPEdge, BSPSurfEdge},
+    face::BSPFace,
+    gamelump::BSPGameLump,
+    header::{BSPHeader, BSP_IDENT},
+    lump::BSPLump,
+    plane::BSPPlane,
+    textures::{BSPTexData, BSPTexDataStringTable, BSPTexInfo},
+    tree::{BSPLeaf, BSPLeafFace, BSPNode},
+    LumpType,
+};
+
+#[derive(Clone, Default)]
+pub(crate) struct TestMap {
+    lumps: Vec<(LumpType, i32, Vec<u8>)>,
+}
+
+impl TestMap {
+    /// A map containing a single 64x64 unlit quad on the z = 0 plane, facing up
+    pub fn quad() -> Self {
+        let verts = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(0.0, 64.0, 0.0),
+            vec3(64.0, 64.0, 0.0),
+            vec3(64.0, 0.0, 0.0),
+        ];
+        let edges = [
+            BSPEdge::new(0, 0),
+            BSPEdge::new(0, 1),
+            BSPEdge::new(1, 2),
+            BSPEdge::new(2, 3),
+            BSPEdge::new(3, 0),
+        ];
+        let surf_edges = [1, 2, 3, 4].map(BSPSurfEdge::new);
+        let planes = [BSPPlane {
+            normal: Vec3::Z,
+            dist: 0.0,
+            axis: 2,
+        }];
+        let tex_info = [BSPTexInfo {
+            tex_s: [1.0, 0.0, 0.0, 0.0],
+            tex_t: [0.0, 1.0, 0.0, 0.0],
+            lightmap_s: [1.0 / 16.0, 0.0, 0.0, 0.0],
+            lightmap_t: [0.0, 1.0 / 16.0, 0.0, 0.0],
+            flags: 0,
+            tex_data: 0,
+        }];
+        let tex_data = [BSPTexData {
+            reflectivity: Vec3::splat(0.5),
+            name_string_table_id: 0,
+            width: 64,
+            height: 64,
+            view_width: 64,
+            view_height: 64,
+        }];
+        let faces = [BSPFace {
+            plane_num: 0,
+            side: 0,
+            on_node: 0,
+            first_edge: 0,
+            num_edges: 4,
+            tex_info: 0,
+            disp_info: -1,
+            surface_fog_volume_id: -1,
+            styles: [-1; 4],
+            light_ofs: -1,
+            area: 64.0 * 64.0,
+            lightmap_texture_mins_in_luxels: ivec2(0, 0),
+            lightmap_texture_size_in_luxels: ivec2(4, 4),
+            orig_face: 0,
+            num_prims: 0,
+            first_prim_id: 0,
+            smoothing_groups: 0,
+        }];
+
+        Self::default()
+            .with_bytes(
+                LumpType::Entities,
+                b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec(),
+            )
+            .with_lump(LumpType::Vertexes, &verts)
+            .with_lump(LumpType::Edges, &edges)
+            .with_lump(LumpType::SurfEdges, &surf_edges)
+            .with_lump(LumpType::Places, &planes)
+            .with_lump(LumpType::TexInfo, &tex_info)
+            .with_lump(LumpType::TexData, &tex_data)
+            .with_lump(
+                LumpType::TexDataStringTable,
+                &[BSPTexDataStringTable { index: 0 }],
+            )
+            .with_bytes(
+                LumpType::TexDataStringData,
+                b"DEV/DEV_MEASUREGENERIC01\0".to_vec(),
+            )
+            .with_lump(LumpType::Faces, &faces)
+            .with_bytes(LumpType::Nodes, vec![0; 32])
+            .with_bytes(
+                LumpType::Models,
+                Self::model_bytes(Vec3::ZERO, vec3(64.0, 64.0, 0.0), 0, 0, 1),
+            )
+    }
+
+    /// The quad map split into two leafs by the plane x = 512. The quad is in leaf 0 and area 1, and a copy of it
+    /// moved along to x = 1024 is in leaf 1 and area 2, along with a `sky_camera` with a scale of 32.
+    pub fn two_areas() -> Self {
+        let map = Self::quad();
+        let offset = vec3(1024.0, 0.0, 0.0);
+
+        let mut verts: Vec<Vec3> = map.lump_data(LumpType::Vertexes);
+        verts.extend(verts.clone().into_iter().map(|v| v + offset));
+        let mut edges: Vec<BSPEdge> = map.lump_data(LumpType::Edges);
+        edges.extend((4..8).map(|v| BSPEdge::new(v, 4 + (v + 1) % 4)));
+        let surf_edges = (1..9).map(BSPSurfEdge::new).collect::<Vec<_>>();
+
+        let mut faces = map.faces();
+        faces.push(BSPFace {
+            first_edge: 4,
+            ..faces[0]
+        });
+
+        let mut planes: Vec<BSPPlane> = map.lump_data(LumpType::Places);
+        planes.push(BSPPlane {
+            normal: Vec3::X,
+            dist: 512.0,
+            axis: 0,
+        });
+
+        let mut node = BSPNode::zeroed();
+        node.plane_num = 1;
+        node.children = [-2, -1];
+
+        let leafs = [1, 2].map(|area| {
+            let mut leaf = BSPLeaf::zeroed().with_area(area);
+            leaf.cluster = area as i16 - 1;
+            leaf.first_leaf_face = area - 1;
+            leaf.num_leaf_faces = 1;
+            leaf
+        });
+
+        map.with_bytes(
+            LumpType::Entities,
+            br#"{ "classname" "worldspawn" }
+{ "classname" "sky_camera" "origin" "1024 0 16" "scale" "32" }
+"#
+            .to_vec(),
+        )
+        .with_lump(LumpType::Vertexes, &verts)
+        .with_lump(LumpType::Edges, &edges)
+        .with_lump(LumpType::SurfEdges, &surf_edges)
+        .with_lump(LumpType::Faces, &faces)
+        .with_lump(LumpType::Places, &planes)
+        .with_lump(LumpType::Nodes, &[node])
+        .with_versioned_bytes(LumpType::Leafs, 1, bytemuck::cast_slice(&leafs).to_vec())
+        .with_lump(
+            LumpType::LeafFaces,
+            &[0, 1].map(|face| BSPLeafFace { face }),
+        )
+        .with_bytes(
+            LumpType::Models,
+            Self::model_bytes(Vec3::ZERO, vec3(1088.0, 64.0, 0.0), 0, 0, 2),
+        )
+    }
+
+    /// The quad map above a solid brush filling -64 to 128 along x and y, and -64 to 0 along z.
+    /// Only the top of the brush has a texture.
+    pub fn ground() -> Self {
+        let map = Self::quad();
+
+        let mut planes: Vec<BSPPlane> = map.lump_data(LumpType::Places);
+        let sides: Vec<BSPBrushSide> = [
+            (Vec3::Z, 0.0),
+            (-Vec3::Z, 64.0),
+            (Vec3::X, 128.0),
+            (-Vec3::X, 64.0),
+            (Vec3::Y, 128.0),
+            (-Vec3::Y, 64.0),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (normal, dist))| {
+            planes.push(BSPPlane {
+                normal,
+                dist,
+                axis: (normal.abs() * vec3(1.0, 2.0, 3.0)).max_element() as i32 - 1,
+            });
+            BSPBrushSide {
+                plane_num: planes.len() as u16 - 1,
+                tex_info: if i == 0 { 0 } else { -1 },
+                ..BSPBrushSide::zeroed()
+            }
+        })
+        .collect();
+
+        let brush = BSPBrush {
+            first_side: 0,
+            num_sides: 6,
+            contents: Contents::SOLID as i32,
+        };
+
+        // Split by the z = 0 plane, with the brush below it
+        let mut node = BSPNode::zeroed();
+        node.children = [-2, -1];
+        let mut solid = BSPLeaf::zeroed();
+        solid.contents = Contents::SOLID as i32;
+        solid.num_leaf_brushes = 1;
+
+        map.with_lump(LumpType::Places, &planes)
+            .with_lump(LumpType::Nodes, &[node])
+            .with_versioned_bytes(
+                LumpType::Leafs,
+                1,
+                bytemuck::cast_slice(&[solid, BSPLeaf::zeroed()]).to_vec(),
+            )
+            .with_lump(LumpType::LeafBrushes, &[BSPLeafBrush { brush: 0 }])
+            .with_lump(LumpType::Brushes, &[brush])
+            .with_lump(LumpType::BrushSides, &sides)
+    }
+
+    /// Replace the contents of a lump
+    pub fn with_lump<T: bytemuck::Pod>(self, lump_type: LumpType, data: &[T]) -> Self {
+        self.with_bytes(lump_type, bytemuck::cast_slice(data).to_vec())
+    }
+
+    pub fn with_bytes(self, lump_type: LumpType, bytes: Vec<u8>) -> Self {
+        self.with_versioned_bytes(lump_type, 0, bytes)
+    }
+
+    pub fn with_versioned_bytes(
+        mut self,
+        lump_type: LumpType,
+        version: i32,
+        bytes: Vec<u8>,
+    ) -> Self {
+        self.lumps.retain(|(l, ..)| *l != lump_type);
+        self.lumps.push((lump_type, version, bytes));
+        self
+    }
+
+    /// The faces currently in the map
+    pub fn faces(&self) -> Vec<BSPFace> {
+        self.lump_data(LumpType::Faces)
+    }
+
+    /// The current contents of a lump
+    pub fn lump_data<T: bytemuck::Pod>(&self, lump_type: LumpType) -> Vec<T> {
+        self.lumps
+            .iter()
+            .find(|(l, ..)| *l == lump_type)
+            .map(|(_, _, bytes)| bytemuck::pod_collect_to_vec(bytes))
+            .unwrap_or_default()
+    }
+
+    /// `BSPModel` keeps its fields private, so write it out by hand
+    pub fn model_bytes(
+        mins: Vec3,
+        maxs: Vec3,
+        head_node: i32,
+        first_face: i32,
+        num_faces: i32,
+    ) -> Vec<u8> {
+        let mut bytes = bytemuck::cast_slice(&[mins, maxs, Vec3::ZERO]).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&[head_node, first_face, num_faces]));
+        bytes
+    }
+
+    /// Add a game lump holding a static prop lump of `version`, with the given model names and `props` already
+    /// written out in that version's layout
+    pub fn with_static_props(
+        self,
+        version: u16,
+        names: &[&str],
+        props: &[u8],
+        prop_count: i32,
+    ) -> Self {
+        // Game lump entries point into the file, so the game lump goes last where its offset is known
+        let map = self.with_bytes(LumpType::GameLump, Vec::new());
+        let start = map.build().len().next_multiple_of(4);
+
+        // One directory entry, written out by hand as `BSPGameLump` isn't `Pod`
+        let mut bytes = 1i32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"prps");
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&version.to_le_bytes());
+        let data_start = start + 4 + mem::size_of::<BSPGameLump>();
+        bytes.extend_from_slice(&(data_start as i32).to_le_bytes());
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&(names.len() as i32).to_le_bytes());
+        for name in names {
+            let mut entry = [0; 128];
+            entry[..name.len()].copy_from_slice(name.as_bytes());
+            bytes.extend_from_slice(&entry);
+        }
+        // No leafs
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&prop_count.to_le_bytes());
+        bytes.extend_from_slice(props);
+        map.with_bytes(LumpType::GameLump, bytes)
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut header = BSPHeader {
+            ident: BSP_IDENT,
+            version: 20,
+            lumps: [BSPLump::default(); HEADER_LUMPS],
+            map_revision: 1,
+        };
+
+        let mut data = Vec::new();
+        let data_start = mem::size_of::<BSPHeader>();
+
+        for (lump_type, version, bytes) in &self.lumps {
+            // Lumps are 4 byte aligned within the file
+            while !(data_start + data.len()).is_multiple_of(4) {
+                data.push(0);
+            }
+            header.lumps[*lump_type as usize] = BSPLump {
+                file_ofs: (data_start + data.len()) as i32,
+                file_len: bytes.len() as i32,
+                version: *version,
+                four_cc: [0; 4],
+            };
+            data.extend_from_slice(bytes);
+        }
+
+        let mut file = bytemuck::bytes_of(&header).to_vec();
+        file.extend(data);
+        file
+    }
+
+    pub fn reader(&self) -> BufReader<Cursor<Vec<u8>>> {
+        Self::reader_for(self.build())
+    }
+
+    pub fn reader_for(data: Vec<u8>) -> BufReader<Cursor<Vec<u8>>> {
+        BufReader::new(Cursor::new(data))
+    }
+}
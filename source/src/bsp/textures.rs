@@ -1,9 +1,14 @@
 use std::io::{BufRead, BufReader, Read, Seek};
 
+use flagset::FlagSet;
 use glam::{Vec3, Vec4};
 
+use crate::error::{ResultExt, SourceError, SourceResult};
+
 use super::{
-    consts::{LumpType, MAX_MAP_TEXDATA, MAX_MAP_TEXINFO},
+    consts::{
+        LumpType, SurfaceFlags, MAX_MAP_TEXDATA, MAX_MAP_TEXDATA_STRING_TABLE, MAX_MAP_TEXINFO,
+    },
     lump::{BSPLump, Lump},
 };
 
@@ -68,6 +73,12 @@ pub struct BSPTexInfo {
     pub flags: i32,           // miptex flags overrides
     pub tex_data: i32,        // Pointer to texture name, size, etc.
 }
+impl BSPTexInfo {
+    /// The known bits of `flags`
+    pub fn surface_flags(&self) -> FlagSet<SurfaceFlags> {
+        FlagSet::new_truncated(self.flags)
+    }
+}
 impl Lump for BSPTexInfo {
     fn max() -> usize {
         MAX_MAP_TEXINFO
@@ -128,30 +139,42 @@ impl BSPTexDataStringTable {
         &self,
         buffer: &mut BufReader<impl Read + Seek>,
         tex_data_string_data: &BSPLump,
-    ) -> String {
+    ) -> SourceResult<String> {
         let index = self.index;
 
+        if index < 0 || index >= tex_data_string_data.file_len {
+            return Err(SourceError::invalid(
+                "BSPTexDataStringTable",
+                tex_data_string_data.file_ofs as u64,
+                format!("String offset {index} outside of string data"),
+            ));
+        }
+
         let seek_index = index + tex_data_string_data.file_ofs;
 
         buffer
             .seek(std::io::SeekFrom::Start(seek_index as u64))
-            .unwrap();
+            .at("TexDataStringData", buffer)?;
 
         let mut string_buf = Vec::new();
 
-        buffer.read_until(0, &mut string_buf).unwrap();
+        buffer
+            .read_until(0, &mut string_buf)
+            .at("TexDataStringData", buffer)?;
 
         // remove trailing \0
         string_buf.pop();
 
-        let mut str = unsafe { String::from_utf8_unchecked(string_buf) };
+        let mut str = String::from_utf8(string_buf).map_err(|e| {
+            SourceError::invalid("TexDataStringData", seek_index as u64, e.to_string())
+        })?;
         str.make_ascii_lowercase();
-        str
+        Ok(str)
     }
 }
 impl Lump for BSPTexDataStringTable {
     fn max() -> usize {
-        0
+        MAX_MAP_TEXDATA_STRING_TABLE
     }
     fn lump_type() -> LumpType {
         LumpType::TexDataStringTable
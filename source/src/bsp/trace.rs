@@ -0,0 +1,483 @@
+//! Tracing rays and boxes through the BSP tree against the map's brushes, as the engine's `CM_BoxTrace` does
+
+use std::io::{Read, Seek};
+
+use flagset::FlagSet;
+use glam::Vec3;
+
+use super::{
+    brush::{BSPBrush, BSPBrushSide, BSPLeafBrush},
+    consts::{Contents, SurfaceFlags},
+    plane::BSPPlane,
+    textures::BSPTexInfo,
+    tree::{BSPLeaf, BSPNode},
+    Bsp,
+};
+use crate::error::{SourceError, SourceResult};
+
+/// How far in front of a plane a trace stops, so the next trace from its end does not start inside the brush
+pub const DIST_EPSILON: f32 = 0.03125;
+/// Deepest node tree walked before giving up, far deeper than compiled maps' trees
+const MAX_NODE_DEPTH: usize = 1024;
+
+/// The result of a trace
+#[derive(Debug, Clone, Copy)]
+pub struct Trace {
+    /// How far along the trace it got before hitting something, from 0 to 1
+    pub fraction: f32,
+    /// Where the trace stopped
+    pub end: Vec3,
+    /// The trace started inside a brush
+    pub start_solid: bool,
+    /// The trace never left a brush, so `fraction` is 0
+    pub all_solid: bool,
+    /// The brush plane that was hit, facing out of the brush
+    pub plane: Option<BSPPlane>,
+    /// Contents of the brush that was hit
+    pub contents: FlagSet<Contents>,
+    /// Flags of the surface that was hit, from its tex info
+    pub surface_flags: FlagSet<SurfaceFlags>,
+    /// Index into the tex info lump of the surface that was hit
+    pub tex_info: Option<usize>,
+}
+
+impl Trace {
+    pub fn hit(&self) -> bool {
+        self.fraction < 1.0 || self.start_solid
+    }
+}
+
+impl<R: Read + Seek> Bsp<R> {
+    /// Trace a line through the world, stopping at the first brush with contents in `contents_mask`.
+    ///
+    /// Only brushes are collided with. Displacements and brush entities are not traced against.
+    pub fn trace_ray(
+        &self,
+        start: Vec3,
+        end: Vec3,
+        contents_mask: impl Into<FlagSet<Contents>>,
+    ) -> SourceResult<Trace> {
+        self.trace_hull(start, end, Vec3::ZERO, Vec3::ZERO, contents_mask)
+    }
+
+    /// Sweep the box `mins` to `maxs`, relative to `start`, through the world to `end`.
+    /// The box is expanded against brushes by their bevel planes, as the engine does for player movement.
+    pub fn trace_hull(
+        &self,
+        start: Vec3,
+        end: Vec3,
+        mins: Vec3,
+        maxs: Vec3,
+        contents_mask: impl Into<FlagSet<Contents>>,
+    ) -> SourceResult<Trace> {
+        // Trace the centre of the box, so it extends evenly either side
+        let offset = (mins + maxs) * 0.5;
+        let extents = (maxs - mins) * 0.5;
+
+        let brushes = self.brushes()?;
+        let nodes = self.nodes()?;
+        let mut tracer = Tracer {
+            nodes,
+            leafs: self.leafs()?,
+            planes: self.planes()?,
+            brushes,
+            brush_sides: self.brush_sides()?,
+            leaf_brushes: self.leaf_brushes()?,
+            tex_info: self.tex_info()?,
+            mask: contents_mask.into(),
+            start: start + offset,
+            end: end + offset,
+            extents,
+            is_point: extents == Vec3::ZERO,
+            checked: vec![false; brushes.len()],
+            visited: vec![false; nodes.len()],
+            trace: Trace {
+                fraction: 1.0,
+                end,
+                start_solid: false,
+                all_solid: false,
+                plane: None,
+                contents: FlagSet::default(),
+                surface_flags: FlagSet::default(),
+                tex_info: None,
+            },
+        };
+
+        let head_node = self.models()?.first().map_or(0, |model| model.head_node());
+        let (trace_start, trace_end) = (tracer.start, tracer.end);
+        tracer
+            .check_node(head_node, 0.0, 1.0, trace_start, trace_end, 0)
+            .map_err(|e| self.context(e))?;
+
+        let mut trace = tracer.trace;
+        if trace.fraction < 1.0 {
+            trace.end = start + (end - start) * trace.fraction;
+        }
+        Ok(trace)
+    }
+}
+
+/// The lumps and state of one trace
+struct Tracer<'a> {
+    nodes: &'a [BSPNode],
+    leafs: &'a [BSPLeaf],
+    planes: &'a [BSPPlane],
+    brushes: &'a [BSPBrush],
+    brush_sides: &'a [BSPBrushSide],
+    leaf_brushes: &'a [BSPLeafBrush],
+    tex_info: &'a [BSPTexInfo],
+    mask: FlagSet<Contents>,
+    start: Vec3,
+    end: Vec3,
+    /// Half the size of the box being traced
+    extents: Vec3,
+    is_point: bool,
+    /// Brushes can be in many leafs, but only need testing once
+    checked: Vec<bool>,
+    /// Each node of a tree has one parent, so is walked at most once
+    visited: Vec<bool>,
+    trace: Trace,
+}
+
+impl Tracer<'_> {
+    fn plane(&self, plane_num: usize, structure: &'static str) -> SourceResult<BSPPlane> {
+        self.planes.get(plane_num).copied().ok_or_else(|| {
+            SourceError::invalid(structure, 0, format!("Plane {plane_num} out of range"))
+        })
+    }
+
+    /// Walk the part of the trace from `p1` to `p2`, fractions `p1f` to `p2f` of the way along, down through `node`
+    fn check_node(
+        &mut self,
+        node: i32,
+        p1f: f32,
+        p2f: f32,
+        p1: Vec3,
+        p2: Vec3,
+        depth: usize,
+    ) -> SourceResult<()> {
+        // Something closer than this part of the trace was already hit
+        if self.trace.fraction <= p1f {
+            return Ok(());
+        }
+        if node < 0 {
+            return self.check_leaf((-1 - node) as usize);
+        }
+        if depth > MAX_NODE_DEPTH {
+            return Err(SourceError::invalid(
+                "BSPNode",
+                0,
+                format!("Node tree is deeper than {MAX_NODE_DEPTH}"),
+            ));
+        }
+
+        let n = self.nodes.get(node as usize).ok_or_else(|| {
+            SourceError::invalid("BSPNode", 0, format!("Node {node} out of range"))
+        })?;
+        // Reaching a node twice means it has two parents, so the tree loops or branches back on itself
+        if std::mem::replace(&mut self.visited[node as usize], true) {
+            return Err(SourceError::invalid(
+                "BSPNode",
+                0,
+                format!("Node {node} is reached twice, so the tree loops"),
+            ));
+        }
+        let children = n.children;
+        let plane = self.plane(n.plane_num as usize, "BSPNode")?;
+        let (normal, dist, axis) = (plane.normal, plane.dist, plane.axis);
+
+        let (t1, t2, offset) = if (0..3).contains(&axis) {
+            let axis = axis as usize;
+            let sign = normal[axis];
+            (
+                p1[axis] * sign - dist,
+                p2[axis] * sign - dist,
+                self.extents[axis],
+            )
+        } else {
+            (
+                normal.dot(p1) - dist,
+                normal.dot(p2) - dist,
+                (self.extents * normal).abs().element_sum(),
+            )
+        };
+
+        // Entirely on one side of the plane
+        if t1 >= offset && t2 >= offset {
+            return self.check_node(children[0], p1f, p2f, p1, p2, depth + 1);
+        }
+        if t1 < -offset && t2 < -offset {
+            return self.check_node(children[1], p1f, p2f, p1, p2, depth + 1);
+        }
+
+        // Split the trace where it crosses the plane, with the halves overlapping by the box size
+        let (side, frac, frac2) = if t1 < t2 {
+            let inv = 1.0 / (t1 - t2);
+            (
+                1,
+                (t1 - offset + DIST_EPSILON) * inv,
+                (t1 + offset + DIST_EPSILON) * inv,
+            )
+        } else if t1 > t2 {
+            let inv = 1.0 / (t1 - t2);
+            (
+                0,
+                (t1 + offset + DIST_EPSILON) * inv,
+                (t1 - offset - DIST_EPSILON) * inv,
+            )
+        } else {
+            (0, 1.0, 0.0)
+        };
+
+        let frac = frac.clamp(0.0, 1.0);
+        let mid = p1 + (p2 - p1) * frac;
+        let midf = p1f + (p2f - p1f) * frac;
+        self.check_node(children[side], p1f, midf, p1, mid, depth + 1)?;
+
+        let frac2 = frac2.clamp(0.0, 1.0);
+        let mid = p1 + (p2 - p1) * frac2;
+        let midf = p1f + (p2f - p1f) * frac2;
+        self.check_node(children[side ^ 1], midf, p2f, mid, p2, depth + 1)
+    }
+
+    fn check_leaf(&mut self, leaf: usize) -> SourceResult<()> {
+        let l = self.leafs.get(leaf).ok_or_else(|| {
+            SourceError::invalid("BSPLeaf", 0, format!("Leaf {leaf} out of range"))
+        })?;
+        if (l.contents() & self.mask).is_empty() {
+            return Ok(());
+        }
+
+        let first = l.first_leaf_brush as usize;
+        let leaf_brushes = self
+            .leaf_brushes
+            .get(first..first + l.num_leaf_brushes as usize)
+            .ok_or_else(|| {
+                SourceError::invalid("BSPLeaf", 0, format!("Leaf {leaf} brushes out of range"))
+            })?;
+
+        for leaf_brush in leaf_brushes {
+            let i_brush = leaf_brush.brush as usize;
+            let brush = *self.brushes.get(i_brush).ok_or_else(|| {
+                SourceError::invalid("BSPLeafBrush", 0, format!("Brush {i_brush} out of range"))
+            })?;
+            if std::mem::replace(&mut self.checked[i_brush], true) {
+                continue;
+            }
+            if (brush.contents() & self.mask).is_empty() {
+                continue;
+            }
+
+            self.clip_to_brush(i_brush, &brush)?;
+            if self.trace.fraction == 0.0 {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn clip_to_brush(&mut self, i_brush: usize, brush: &BSPBrush) -> SourceResult<()> {
+        let sides = usize::try_from(brush.first_side)
+            .ok()
+            .zip(usize::try_from(brush.num_sides).ok())
+            .and_then(|(first, num)| self.brush_sides.get(first..first + num))
+            .ok_or_else(|| {
+                SourceError::invalid("BSPBrush", 0, format!("Brush {i_brush} sides out of range"))
+            })?;
+
+        let mut enter_frac = -1.0;
+        let mut leave_frac = 1.0;
+        let mut lead: Option<(BSPPlane, &BSPBrushSide)> = None;
+        let mut starts_out = false;
+        let mut gets_out = false;
+
+        for side in sides {
+            // Bevels only matter for boxes
+            if self.is_point && side.is_bevel() {
+                continue;
+            }
+
+            let plane = self.plane(side.plane_num as usize, "BSPBrushSide")?;
+            let normal = plane.normal;
+            // Push the plane out by the corner of the box furthest behind it
+            let dist = plane.dist + (self.extents * normal).abs().element_sum();
+
+            let d1 = normal.dot(self.start) - dist;
+            let d2 = normal.dot(self.end) - dist;
+
+            if d2 > 0.0 {
+                gets_out = true;
+            }
+            if d1 > 0.0 {
+                starts_out = true;
+            }
+
+            // Entirely in front of this side, so the trace misses the brush
+            if d1 > 0.0 && d2 >= d1 {
+                return Ok(());
+            }
+            // Entirely behind this side
+            if d1 <= 0.0 && d2 <= 0.0 {
+                continue;
+            }
+
+            if d1 > d2 {
+                // Entering the brush
+                let f = (d1 - DIST_EPSILON) / (d1 - d2);
+                if f > enter_frac {
+                    enter_frac = f;
+                    lead = Some((plane, side));
+                }
+            } else {
+                // Leaving the brush
+                let f = (d1 + DIST_EPSILON) / (d1 - d2);
+                if f < leave_frac {
+                    leave_frac = f;
+                }
+            }
+        }
+
+        if !starts_out {
+            self.trace.start_solid = true;
+            if !gets_out {
+                self.trace.all_solid = true;
+                self.trace.fraction = 0.0;
+                self.trace.contents = brush.contents();
+            }
+            return Ok(());
+        }
+
+        if enter_frac < leave_frac && enter_frac > -1.0 && enter_frac < self.trace.fraction {
+            let Some((plane, side)) = lead else {
+                return Ok(());
+            };
+            let tex_info = usize::try_from(side.tex_info).ok();
+
+            self.trace.fraction = enter_frac.max(0.0);
+            self.trace.plane = Some(plane);
+            self.trace.contents = brush.contents();
+            self.trace.tex_info = tex_info;
+            self.trace.surface_flags = tex_info
+                .and_then(|i| self.tex_info.get(i))
+                .map(BSPTexInfo::surface_flags)
+                .unwrap_or_default();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use glam::vec3;
+
+    use crate::bsp::{test_map::TestMap, LumpType};
+
+    use super::*;
+
+    fn ground() -> Bsp<std::io::Cursor<Vec<u8>>> {
+        Bsp::new(TestMap::ground().reader()).unwrap()
+    }
+
+    #[test]
+    fn ray() {
+        let bsp = ground();
+        let down = (vec3(32.0, 32.0, 64.0), vec3(32.0, 32.0, -64.0));
+
+        let trace = bsp
+            .trace_ray(down.0, down.1, Contents::mask_solid())
+            .unwrap();
+        assert!(trace.hit());
+        assert!((trace.fraction - 0.5).abs() < 0.001, "{}", trace.fraction);
+        assert!(trace.end.z > 0.0 && trace.end.z < 0.1);
+        assert_eq!({ trace.plane.unwrap().normal }, Vec3::Z);
+        assert_eq!(trace.contents, Contents::SOLID);
+        assert_eq!(trace.tex_info, Some(0));
+        assert!(!trace.start_solid);
+
+        // Water does not stop at solid brushes
+        let trace = bsp.trace_ray(down.0, down.1, Contents::WATER).unwrap();
+        assert!(!trace.hit());
+        assert_eq!(trace.end, down.1);
+
+        // Past the side of the brush
+        let trace = bsp
+            .trace_ray(
+                vec3(256.0, 0.0, 64.0),
+                vec3(256.0, 0.0, -64.0),
+                Contents::SOLID,
+            )
+            .unwrap();
+        assert_eq!(trace.fraction, 1.0);
+
+        // Into the side, where there is no texture
+        let trace = bsp
+            .trace_ray(
+                vec3(256.0, 0.0, -32.0),
+                vec3(0.0, 0.0, -32.0),
+                Contents::SOLID,
+            )
+            .unwrap();
+        assert!((trace.fraction - 0.5).abs() < 0.001);
+        assert_eq!({ trace.plane.unwrap().normal }, Vec3::X);
+        assert_eq!(trace.tex_info, None);
+    }
+
+    #[test]
+    fn start_solid() {
+        let bsp = ground();
+
+        let trace = bsp
+            .trace_ray(vec3(0.0, 0.0, -8.0), vec3(0.0, 0.0, 64.0), Contents::SOLID)
+            .unwrap();
+        assert!(trace.start_solid && !trace.all_solid);
+
+        let trace = bsp
+            .trace_ray(vec3(0.0, 0.0, -8.0), vec3(8.0, 0.0, -8.0), Contents::SOLID)
+            .unwrap();
+        assert!(trace.all_solid);
+        assert_eq!(trace.fraction, 0.0);
+        assert_eq!(trace.end, vec3(0.0, 0.0, -8.0));
+    }
+
+    #[test]
+    fn hull() {
+        let bsp = ground();
+        let (mins, maxs) = (vec3(-16.0, -16.0, -8.0), vec3(16.0, 16.0, 56.0));
+
+        // The bottom of the box lands on the ground with its origin 8 units up
+        let trace = bsp
+            .trace_hull(
+                vec3(32.0, 32.0, 64.0),
+                vec3(32.0, 32.0, -64.0),
+                mins,
+                maxs,
+                Contents::mask_player_solid(),
+            )
+            .unwrap();
+        assert!((trace.end.z - 8.0).abs() < 0.1, "{}", trace.end);
+        assert_eq!({ trace.plane.unwrap().normal }, Vec3::Z);
+
+        // The box clips the edge of the brush when the ray would miss it
+        let (start, end) = (vec3(140.0, 0.0, 64.0), vec3(140.0, 0.0, -64.0));
+        assert!(!bsp.trace_ray(start, end, Contents::SOLID).unwrap().hit());
+        assert!(bsp
+            .trace_hull(start, end, mins, maxs, Contents::SOLID)
+            .unwrap()
+            .hit());
+    }
+
+    #[test]
+    fn looping_tree() {
+        let mut nodes = ground().nodes().unwrap().to_vec();
+        nodes[0].children = [0, 0];
+        let bsp = Bsp::new(
+            TestMap::ground()
+                .with_lump(LumpType::Nodes, &nodes)
+                .reader(),
+        )
+        .unwrap();
+
+        let trace = bsp.trace_ray(vec3(0.0, 0.0, 64.0), vec3(0.0, 0.0, -64.0), Contents::SOLID);
+        assert!(matches!(trace, Err(SourceError::Invalid { .. })));
+    }
+}
@@ -0,0 +1,145 @@
+//! The BSP tree itself: nodes split space by planes until reaching a leaf, a convex region of the map
+
+use flagset::FlagSet;
+
+use super::{
+    consts::{Contents, LumpType, MAX_MAP_LEAFFACES, MAX_MAP_LEAFS, MAX_MAP_NODES},
+    Lump,
+};
+
+///Node
+///
+///The node array (Lump 5) holds the branches of the tree. Every node splits its space by a plane into two children,
+/// which are either another node or, if negative, the leaf `-1 - child`.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BSPNode {
+    /// Index into the plane lump
+    pub plane_num: i32,
+    /// Children in front of and behind the plane. Negative numbers are leafs, `-1 - child`
+    pub children: [i32; 2],
+    /// Bounding box, for frustum culling
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    /// Faces on the node's plane, for drawing front to back
+    pub first_face: u16,
+    pub num_faces: u16,
+    /// If all leafs below this node are in the same area, that area, otherwise -1
+    pub area: i16,
+    padding: i16,
+}
+
+impl Lump for BSPNode {
+    fn max() -> usize {
+        MAX_MAP_NODES
+    }
+    fn lump_type() -> LumpType {
+        LumpType::Nodes
+    }
+}
+
+///Leaf
+///
+///The leaf array (Lump 10) is the end of every branch of the tree. Leafs know what they are filled with, which
+/// visibility cluster and area they belong to, and which faces and brushes are inside them.
+///
+///This is the version 1 layout. Version 0 leafs, from older maps, have ambient lighting in the middle and are
+/// converted by [`crate::bsp::Bsp::leafs`].
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BSPLeaf {
+    /// OR of the contents of every brush in the leaf
+    pub contents: i32,
+    /// Visibility cluster, or -1 for leafs that can never be seen
+    pub cluster: i16,
+    /// 9 bits of area, then 7 bits of flags
+    area_flags: i16,
+    pub mins: [i16; 3],
+    pub maxs: [i16; 3],
+    /// Index into the leaf face lump
+    pub first_leaf_face: u16,
+    pub num_leaf_faces: u16,
+    /// Index into the leaf brush lump
+    pub first_leaf_brush: u16,
+    pub num_leaf_brushes: u16,
+    pub leaf_water_data_id: i16,
+    padding: i16,
+}
+
+impl BSPLeaf {
+    /// Size of a version 0 leaf, which has 24 bytes of ambient lighting before the padding
+    pub const V0_SIZE: usize = 56;
+
+    /// Convert a version 0 leaf by dropping its ambient lighting
+    pub fn from_v0(bytes: &[u8; Self::V0_SIZE]) -> Self {
+        let mut v1 = [0; std::mem::size_of::<Self>()];
+        v1[..30].copy_from_slice(&bytes[..30]);
+        bytemuck::cast(v1)
+    }
+
+    /// The known bits of `contents`
+    pub fn contents(&self) -> FlagSet<Contents> {
+        FlagSet::new_truncated(self.contents)
+    }
+
+    /// The area this leaf is in. Areas are regions of the map separated by area portals, and the 3D skybox is one
+    pub fn area(&self) -> u16 {
+        self.area_flags as u16 & 0x1FF
+    }
+
+    pub fn flags(&self) -> u16 {
+        self.area_flags as u16 >> 9
+    }
+
+    pub fn with_area(mut self, area: u16) -> Self {
+        self.area_flags = ((self.flags() << 9) | (area & 0x1FF)) as i16;
+        self
+    }
+}
+
+impl Lump for BSPLeaf {
+    fn max() -> usize {
+        MAX_MAP_LEAFS
+    }
+    fn lump_type() -> LumpType {
+        LumpType::Leafs
+    }
+}
+
+///Leaf face
+///
+///The leaf face lump (Lump 16) lists the faces in each leaf, as indices into the face lump.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BSPLeafFace {
+    pub face: u16,
+}
+
+impl Lump for BSPLeafFace {
+    fn max() -> usize {
+        MAX_MAP_LEAFFACES
+    }
+    fn lump_type() -> LumpType {
+        LumpType::LeafFaces
+    }
+}
+
+#[cfg(test)]
+mod tree_tests {
+    use super::*;
+
+    #[test]
+    fn leaf_layout() {
+        assert_eq!(std::mem::size_of::<BSPNode>(), 32);
+        assert_eq!(std::mem::size_of::<BSPLeaf>(), 32);
+
+        let leaf = BSPLeaf::from_v0(&[0xFF; BSPLeaf::V0_SIZE]);
+        assert_eq!(leaf.area(), 0x1FF);
+        assert_eq!(leaf.flags(), 0x7F);
+        assert_eq!({ leaf.leaf_water_data_id }, -1);
+        assert_eq!({ leaf.padding }, 0);
+
+        let leaf = leaf.with_area(3);
+        assert_eq!((leaf.area(), leaf.flags()), (3, 0x7F));
+    }
+}
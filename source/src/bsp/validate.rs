@@ -0,0 +1,958 @@
+use std::{
+    fmt,
+    io::{BufReader, Read, Seek, SeekFrom},
+    mem,
+};
+
+use glam::Vec3;
+use num_traits::FromPrimitive;
+
+use crate::{binaries::BinaryData, error::SourceError};
+
+use super::{
+    consts::*,
+    displacement::{BSPDispInfo, BSPDispVert},
+    edges::{BSPEdge, BSPSurfEdge},
+    face::BSPFace,
+    header::BSPHeader,
+    lump::BSPLump,
+    model::BSPModel,
+    plane::BSPPlane,
+    textures::{BSPTexData, BSPTexDataStringTable, BSPTexInfo},
+    Lump, LumpType,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The engine will load the map, but something looks wrong
+    Warning,
+    /// The map breaks an engine limit or references data that does not exist
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    /// Lump the issue was found in, or `None` for the file header
+    pub lump: Option<LumpType>,
+    /// Index of the offending element within `lump`
+    pub element: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning")?,
+            Severity::Error => write!(f, "error")?,
+        }
+        match (self.lump, self.element) {
+            (Some(lump), Some(element)) => write!(f, " [{lump:?} #{element}]")?,
+            (Some(lump), None) => write!(f, " [{lump:?}]")?,
+            (None, _) => write!(f, " [header]")?,
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Result of [`validate_map`], listing every problem found in the file
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// True if no errors were found. Warnings are allowed.
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|i| i.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|i| i.severity == Severity::Warning)
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        lump: Option<LumpType>,
+        element: Option<usize>,
+        message: String,
+    ) {
+        self.issues.push(ValidationIssue {
+            severity,
+            lump,
+            element,
+            message,
+        })
+    }
+
+    fn error(&mut self, lump: LumpType, element: Option<usize>, message: String) {
+        self.push(Severity::Error, Some(lump), element, message)
+    }
+
+    fn warning(&mut self, lump: LumpType, element: Option<usize>, message: String) {
+        self.push(Severity::Warning, Some(lump), element, message)
+    }
+}
+
+/// Size of a single element of a lump, and the engine limit on how many of them a map may contain
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LumpLimit {
+    pub element_size: usize,
+    pub max_elements: usize,
+}
+
+impl LumpLimit {
+    fn of<T: Lump>() -> Self {
+        Self {
+            element_size: mem::size_of::<T>(),
+            max_elements: T::max(),
+        }
+    }
+    fn new(element_size: usize, max_elements: usize) -> Self {
+        Self {
+            element_size,
+            max_elements,
+        }
+    }
+}
+
+/// Engine limit for a lump, for lumps made up of fixed size elements.
+/// `version` is the lump version from the header, as some lumps changed size between versions.
+pub fn lump_limit(lump_type: LumpType, version: i32) -> Option<LumpLimit> {
+    Some(match lump_type {
+        LumpType::Places => LumpLimit::of::<BSPPlane>(),
+        LumpType::TexData => LumpLimit::of::<BSPTexData>(),
+        LumpType::Vertexes => LumpLimit::of::<Vec3>(),
+        LumpType::Visibility => LumpLimit::new(1, MAX_MAP_VISIBILITY),
+        LumpType::Nodes => LumpLimit::new(32, MAX_MAP_NODES),
+        LumpType::TexInfo => LumpLimit::of::<BSPTexInfo>(),
+        LumpType::Faces | LumpType::FacesHdr | LumpType::OriginalFaces => {
+            LumpLimit::of::<BSPFace>()
+        }
+        LumpType::Lighting | LumpType::LightingHdr => LumpLimit::new(4, MAX_MAP_LIGHTING / 4),
+        LumpType::Leafs if version == 0 => LumpLimit::new(56, MAX_MAP_LEAFS),
+        LumpType::Leafs => LumpLimit::new(32, MAX_MAP_LEAFS),
+        LumpType::Edges => LumpLimit::of::<BSPEdge>(),
+        LumpType::SurfEdges => LumpLimit::of::<BSPSurfEdge>(),
+        LumpType::Models => LumpLimit::of::<BSPModel>(),
+        LumpType::WorldLights | LumpType::WorldLightsHdr => LumpLimit::new(88, MAX_MAP_WORLDLIGHTS),
+        LumpType::LeafFaces => LumpLimit::new(2, MAX_MAP_LEAFFACES),
+        LumpType::LeafBrushes => LumpLimit::new(2, MAX_MAP_LEAFBRUSHES),
+        LumpType::Brushes => LumpLimit::new(12, MAX_MAP_BRUSHES),
+        LumpType::BrushSides => LumpLimit::new(8, MAX_MAP_BRUSHSIDES),
+        LumpType::Areas => LumpLimit::new(8, MAX_MAP_AREAS),
+        LumpType::AreaPortals => LumpLimit::new(12, MAX_MAP_AREAPORTALS),
+        LumpType::Portals => LumpLimit::new(16, MAX_MAP_PORTALS),
+        LumpType::Clusters => LumpLimit::new(8, MAX_MAP_CLUSTERS),
+        LumpType::PortalVerts => LumpLimit::new(2, MAX_MAP_PORTALVERTS),
+        LumpType::ClusterPortals => LumpLimit::new(2, MAX_MAP_PORTALS),
+        LumpType::DispInfo => LumpLimit::of::<BSPDispInfo>(),
+        LumpType::VertNormals => LumpLimit::new(12, MAX_MAP_VERTNORMALS),
+        LumpType::VertNormalIndices => LumpLimit::new(2, MAX_MAP_VERTNORMALINDICES),
+        LumpType::DispVerts => LumpLimit::of::<BSPDispVert>(),
+        LumpType::DispTris => LumpLimit::new(2, MAX_MAP_DISPINFO * MAX_DISPTRIS),
+        LumpType::LeafWaterData => LumpLimit::new(12, MAX_MAP_LEAFWATERDATA),
+        LumpType::Primitives => LumpLimit::new(10, MAX_MAP_PRIMITIVES),
+        LumpType::PrimVerts => LumpLimit::new(12, MAX_MAP_PRIMVERTS),
+        LumpType::PrimIndices => LumpLimit::new(2, MAX_MAP_PRIMINDICES),
+        LumpType::ClipPortalVerts => LumpLimit::new(12, MAX_MAP_PORTALVERTS),
+        LumpType::Cubemaps => LumpLimit::new(16, MAX_MAP_CUBEMAPSAMPLES),
+        LumpType::TexDataStringData => LumpLimit::new(1, MAX_MAP_TEXDATA_STRING_DATA as usize),
+        LumpType::TexDataStringTable => LumpLimit::of::<BSPTexDataStringTable>(),
+        LumpType::Overlays => LumpLimit::new(352, MAX_MAP_OVERLAYS),
+        LumpType::WaterOverlays => LumpLimit::new(1120, MAX_MAP_WATEROVERLAYS),
+        _ => return None,
+    })
+}
+
+/// Lumps that [`validate_map`] decodes and cross references. A lump of these types that
+/// cannot be decoded is an error, while the layout of any other lump is only a warning.
+const DECODED_LUMPS: [LumpType; 13] = [
+    LumpType::Places,
+    LumpType::TexData,
+    LumpType::Vertexes,
+    LumpType::TexInfo,
+    LumpType::Faces,
+    LumpType::Lighting,
+    LumpType::Edges,
+    LumpType::SurfEdges,
+    LumpType::Models,
+    LumpType::DispInfo,
+    LumpType::DispVerts,
+    LumpType::TexDataStringData,
+    LumpType::TexDataStringTable,
+];
+
+/// Check a map for broken lump tables, engine limits and out of range indices between lumps.
+///
+/// Unlike the loaders, this never panics on bad data, so it is safe to run over untrusted maps.
+/// Every issue found is collected into the returned report.
+pub fn validate_map<R: Read + Seek>(buffer: &mut BufReader<R>) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let file_len = match buffer.seek(SeekFrom::End(0)) {
+        Ok(len) => len,
+        Err(e) => {
+            report.push(
+                Severity::Error,
+                None,
+                None,
+                format!("Failed to read file length: {e}"),
+            );
+            return report;
+        }
+    };
+
+    // Read the raw header so a bad ident is reported as an issue rather than a load failure
+    let header = match buffer
+        .seek(SeekFrom::Start(0))
+        .map_err(|e| SourceError::io("BSPHeader", 0, e))
+        .and_then(|_| <BSPHeader as BinaryData>::read(buffer, None))
+    {
+        Ok(header) => header,
+        Err(e) => {
+            report.push(
+                Severity::Error,
+                None,
+                None,
+                format!("Failed to read header: {e}"),
+            );
+            return report;
+        }
+    };
+
+    if !header.has_valid_ident() {
+        report.push(
+            Severity::Error,
+            None,
+            None,
+            format!("Invalid ident {:?}, expected VBSP", header.ident),
+        );
+        // Nothing else in the file can be trusted
+        return report;
+    }
+
+    let version = header.version;
+    if !(19..=21).contains(&version) {
+        report.push(
+            Severity::Warning,
+            None,
+            None,
+            format!("Untested BSP version {version}"),
+        );
+    }
+
+    let mut validator = Validator {
+        header: &header,
+        file_len,
+        usable: [false; HEADER_LUMPS],
+        report,
+    };
+
+    validator.check_lump_table();
+    validator.check_entities(buffer);
+    validator.check_visibility(buffer);
+    validator.check_cross_references(buffer);
+
+    validator.report
+}
+
+struct Validator<'a> {
+    header: &'a BSPHeader,
+    file_len: u64,
+    /// Lumps whose data lies within the file and fits their element size
+    usable: [bool; HEADER_LUMPS],
+    report: ValidationReport,
+}
+
+impl<'a> Validator<'a> {
+    fn lump(&self, lump_type: LumpType) -> BSPLump {
+        *self.header.get_lump_header(lump_type)
+    }
+
+    fn check_lump_table(&mut self) {
+        for i in 0..HEADER_LUMPS {
+            let lump = self.header.lumps[i];
+            let (file_ofs, file_len, version) = (lump.file_ofs, lump.file_len, lump.version);
+
+            let Some(lump_type) = LumpType::from_usize(i) else {
+                continue;
+            };
+
+            if file_ofs < 0 || file_len < 0 {
+                self.report.error(
+                    lump_type,
+                    None,
+                    format!("Negative offset {file_ofs} or length {file_len}"),
+                );
+                continue;
+            }
+            if file_ofs as u64 + file_len as u64 > self.file_len {
+                self.report.error(
+                    lump_type,
+                    None,
+                    format!(
+                        "Lump data {file_ofs}..{} lies outside of the file ({} bytes)",
+                        file_ofs as u64 + file_len as u64,
+                        self.file_len
+                    ),
+                );
+                continue;
+            }
+
+            if let Some(limit) = lump_limit(lump_type, version) {
+                let file_len = file_len as usize;
+                if !file_len.is_multiple_of(limit.element_size) {
+                    let message = format!(
+                        "Length {file_len} is not a multiple of the element size {} (lump version {version})",
+                        limit.element_size
+                    );
+                    if DECODED_LUMPS.contains(&lump_type) {
+                        self.report.error(lump_type, None, message);
+                    } else {
+                        self.report.warning(lump_type, None, message);
+                    }
+                    continue;
+                }
+                let count = file_len / limit.element_size;
+                if count > limit.max_elements {
+                    self.report.error(
+                        lump_type,
+                        None,
+                        format!(
+                            "{count} elements exceeds the engine limit of {}",
+                            limit.max_elements
+                        ),
+                    );
+                }
+            }
+
+            self.usable[i] = true;
+        }
+    }
+
+    fn bytes<R: Read + Seek>(
+        &mut self,
+        lump_type: LumpType,
+        buffer: &mut BufReader<R>,
+    ) -> Box<[u8]> {
+        if !self.usable[lump_type as usize] {
+            return Box::new([]);
+        }
+        match self.lump(lump_type).read_bytes(buffer) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.report
+                    .error(lump_type, None, format!("Failed to read lump: {e}"));
+                self.usable[lump_type as usize] = false;
+                Box::new([])
+            }
+        }
+    }
+
+    fn decode<T: Lump + bytemuck::Zeroable, R: Read + Seek>(
+        &mut self,
+        buffer: &mut BufReader<R>,
+    ) -> Box<[T]> {
+        let lump_type = T::lump_type();
+        let lump = self.lump(lump_type);
+        // Lump types with versioned layouts may not match the structure we decode them with
+        if !self.usable[lump_type as usize]
+            || !(lump.file_len as usize).is_multiple_of(mem::size_of::<T>())
+        {
+            return Box::new([]);
+        }
+        match lump.decode(buffer) {
+            Ok(data) => data,
+            Err(e) => {
+                self.report
+                    .error(lump_type, None, format!("Failed to decode lump: {e}"));
+                self.usable[lump_type as usize] = false;
+                Box::new([])
+            }
+        }
+    }
+
+    /// Number of elements in a lump we do not decode, or `None` if the lump is unusable
+    fn count(&self, lump_type: LumpType) -> Option<usize> {
+        if !self.usable[lump_type as usize] {
+            return None;
+        }
+        let lump = self.lump(lump_type);
+        let limit = lump_limit(lump_type, lump.version)?;
+        Some(lump.file_len as usize / limit.element_size)
+    }
+
+    fn check_entities<R: Read + Seek>(&mut self, buffer: &mut BufReader<R>) {
+        let entities = self.bytes(LumpType::Entities, buffer);
+
+        let mut count = 0;
+        let mut depth = 0;
+        let mut in_string = false;
+        for &c in entities.iter() {
+            match c {
+                b'"' => in_string = !in_string,
+                b'{' if !in_string => {
+                    depth += 1;
+                    count += 1;
+                }
+                b'}' if !in_string => depth -= 1,
+                _ => (),
+            }
+            if !(0..=1).contains(&depth) {
+                self.report.error(
+                    LumpType::Entities,
+                    Some(count),
+                    "Unbalanced braces in entity lump".to_owned(),
+                );
+                return;
+            }
+        }
+        if depth != 0 || in_string {
+            self.report.error(
+                LumpType::Entities,
+                Some(count),
+                "Entity lump ends inside an entity".to_owned(),
+            );
+        }
+        if count > MAX_MAP_ENTITIES {
+            self.report.error(
+                LumpType::Entities,
+                None,
+                format!("{count} entities exceeds the engine limit of {MAX_MAP_ENTITIES}"),
+            );
+        }
+    }
+
+    fn check_visibility<R: Read + Seek>(&mut self, buffer: &mut BufReader<R>) {
+        let vis = self.bytes(LumpType::Visibility, buffer);
+        if vis.len() < 4 {
+            return;
+        }
+        let num_clusters = i32::from_le_bytes([vis[0], vis[1], vis[2], vis[3]]);
+        if num_clusters < 0 || num_clusters as usize > MAX_MAP_CLUSTERS {
+            self.report.error(
+                LumpType::Visibility,
+                None,
+                format!("{num_clusters} clusters exceeds the engine limit of {MAX_MAP_CLUSTERS}"),
+            );
+        } else if vis.len() < 4 + num_clusters as usize * 8 {
+            self.report.error(
+                LumpType::Visibility,
+                None,
+                format!("Lump too small to hold offsets for {num_clusters} clusters"),
+            );
+        }
+    }
+
+    fn check_cross_references<R: Read + Seek>(&mut self, buffer: &mut BufReader<R>) {
+        let planes = self.decode::<BSPPlane, R>(buffer);
+        let verts = self.decode::<Vec3, R>(buffer);
+        let edges = self.decode::<BSPEdge, R>(buffer);
+        let surf_edges = self.decode::<BSPSurfEdge, R>(buffer);
+        let faces = self.decode::<BSPFace, R>(buffer);
+        let tex_info = self.decode::<BSPTexInfo, R>(buffer);
+        let tex_data = self.decode::<BSPTexData, R>(buffer);
+        let string_table = self.decode::<BSPTexDataStringTable, R>(buffer);
+        let string_data = self.bytes(LumpType::TexDataStringData, buffer);
+        let models = self.decode::<BSPModel, R>(buffer);
+        let disp_infos = self.decode::<BSPDispInfo, R>(buffer);
+        let disp_verts = self.decode::<BSPDispVert, R>(buffer);
+
+        let lighting_len = match self.lump(LumpType::Lighting).file_len {
+            0 => self.lump(LumpType::LightingHdr).file_len,
+            len => len,
+        } as usize;
+
+        for (i, plane) in planes.iter().enumerate() {
+            let (normal, axis) = (plane.normal, plane.axis);
+            if !(0..=5).contains(&axis) {
+                self.report
+                    .warning(LumpType::Places, Some(i), format!("Invalid axis {axis}"));
+            }
+            if (normal.length_squared() - 1.0).abs() > 0.01 {
+                self.report.warning(
+                    LumpType::Places,
+                    Some(i),
+                    format!("Normal {normal} is not unit length"),
+                );
+            }
+        }
+
+        for (i, edge) in edges.iter().enumerate() {
+            let (v0, v1) = edge.verts();
+            for v in [v0, v1] {
+                if v as usize >= verts.len() {
+                    self.report.error(
+                        LumpType::Edges,
+                        Some(i),
+                        format!("Vertex {v} out of range of {} vertices", verts.len()),
+                    );
+                }
+            }
+        }
+
+        for (i, surf_edge) in surf_edges.iter().enumerate() {
+            if surf_edge.edge_index() >= edges.len() {
+                self.report.error(
+                    LumpType::SurfEdges,
+                    Some(i),
+                    format!(
+                        "Edge {} out of range of {} edges",
+                        surf_edge.edge_index(),
+                        edges.len()
+                    ),
+                );
+            }
+        }
+
+        let orig_faces = self.count(LumpType::OriginalFaces).unwrap_or(0);
+
+        for (i, face) in faces.iter().enumerate() {
+            let (first_edge, num_edges) = (face.first_edge, face.num_edges);
+            let (tex_info_id, disp_info, light_ofs, orig_face) = (
+                face.tex_info,
+                face.disp_info,
+                face.light_ofs,
+                face.orig_face,
+            );
+
+            if face.plane_num as usize >= planes.len() {
+                self.report.error(
+                    LumpType::Faces,
+                    Some(i),
+                    format!(
+                        "Plane {} out of range of {} planes",
+                        { face.plane_num },
+                        planes.len()
+                    ),
+                );
+            }
+            if num_edges < 3 {
+                self.report.warning(
+                    LumpType::Faces,
+                    Some(i),
+                    format!("Degenerate face with {num_edges} edges"),
+                );
+            }
+            if first_edge < 0 || first_edge as usize + num_edges.max(0) as usize > surf_edges.len()
+            {
+                self.report.error(
+                    LumpType::Faces,
+                    Some(i),
+                    format!(
+                        "Surfedges {first_edge}..{} out of range of {} surfedges",
+                        first_edge as i64 + num_edges as i64,
+                        surf_edges.len()
+                    ),
+                );
+            }
+
+            if tex_info_id != -1 && (tex_info_id < 0 || tex_info_id as usize >= tex_info.len()) {
+                self.report.error(
+                    LumpType::Faces,
+                    Some(i),
+                    format!(
+                        "Texinfo {tex_info_id} out of range of {} texinfos",
+                        tex_info.len()
+                    ),
+                );
+            }
+
+            if disp_info != -1 {
+                if disp_info < 0 || disp_info as usize >= disp_infos.len() {
+                    self.report.error(
+                        LumpType::Faces,
+                        Some(i),
+                        format!(
+                            "Dispinfo {disp_info} out of range of {} dispinfos",
+                            disp_infos.len()
+                        ),
+                    );
+                }
+                if num_edges != 4 {
+                    self.report.error(
+                        LumpType::Faces,
+                        Some(i),
+                        format!("Displacement face has {num_edges} edges instead of 4"),
+                    );
+                }
+            }
+
+            if light_ofs != -1 {
+                let styles = face.styles.iter().filter(|&&s| s != -1).count().max(1);
+                let size = face.lightmap_texture_size_in_luxels;
+                let bump = tex_info
+                    .get(tex_info_id.max(0) as usize)
+                    .is_some_and(|t| t.surface_flags().contains(SurfaceFlags::BUMPLIGHT));
+                // Sizes come from the file, so may be large enough to overflow
+                let bytes = (size.x.max(0) as usize + 1)
+                    .checked_mul(size.y.max(0) as usize + 1)
+                    .and_then(|luxels| luxels.checked_mul(styles * if bump { 4 } else { 1 } * 4));
+                let end = usize::try_from(light_ofs)
+                    .ok()
+                    .zip(bytes)
+                    .and_then(|(light_ofs, bytes)| light_ofs.checked_add(bytes));
+
+                match (bytes, end) {
+                    (None, _) => self.report.error(
+                        LumpType::Faces,
+                        Some(i),
+                        format!(
+                            "Lightmap of {}x{} luxels overflows",
+                            size.x as i64 + 1,
+                            size.y as i64 + 1
+                        ),
+                    ),
+                    (Some(bytes), end) if end.is_none_or(|end| end > lighting_len) => {
+                        self.report.error(
+                            LumpType::Faces,
+                            Some(i),
+                            format!(
+                                "Lightmap {light_ofs}..{} out of range of {lighting_len} lighting bytes",
+                                (light_ofs as i64).saturating_add(bytes as i64)
+                            ),
+                        )
+                    }
+                    _ => (),
+                }
+            }
+
+            if orig_face < 0 || (orig_faces > 0 && orig_face as usize >= orig_faces) {
+                self.report.warning(
+                    LumpType::Faces,
+                    Some(i),
+                    format!("Original face {orig_face} out of range of {orig_faces} faces"),
+                );
+            }
+        }
+
+        for (i, info) in tex_info.iter().enumerate() {
+            let data = info.tex_data;
+            if data != -1 && (data < 0 || data as usize >= tex_data.len()) {
+                self.report.error(
+                    LumpType::TexInfo,
+                    Some(i),
+                    format!("Texdata {data} out of range of {} texdatas", tex_data.len()),
+                );
+            }
+        }
+
+        for (i, data) in tex_data.iter().enumerate() {
+            let id = data.name_string_table_id;
+            if id < 0 || id as usize >= string_table.len() {
+                self.report.error(
+                    LumpType::TexData,
+                    Some(i),
+                    format!(
+                        "Name {id} out of range of {} string table entries",
+                        string_table.len()
+                    ),
+                );
+            }
+        }
+
+        for (i, entry) in string_table.iter().enumerate() {
+            let index = entry.index;
+            let Some(name) = (index >= 0)
+                .then(|| string_data.get(index as usize..))
+                .flatten()
+            else {
+                self.report.error(
+                    LumpType::TexDataStringTable,
+                    Some(i),
+                    format!(
+                        "Offset {index} out of range of {} bytes of string data",
+                        string_data.len()
+                    ),
+                );
+                continue;
+            };
+            match name.iter().position(|&c| c == 0) {
+                None => self.report.error(
+                    LumpType::TexDataStringTable,
+                    Some(i),
+                    format!("String at offset {index} is not null terminated"),
+                ),
+                Some(len) if len >= TEXTURE_NAME_LENGTH => self.report.warning(
+                    LumpType::TexDataStringTable,
+                    Some(i),
+                    format!(
+                        "Texture name of {len} characters is longer than {TEXTURE_NAME_LENGTH}"
+                    ),
+                ),
+                _ => (),
+            }
+        }
+
+        let nodes = self.count(LumpType::Nodes);
+
+        if models.is_empty() && self.usable[LumpType::Models as usize] {
+            self.report
+                .error(LumpType::Models, None, "Map has no world model".to_owned());
+        }
+
+        for (i, model) in models.iter().enumerate() {
+            let (first_face, num_faces) = (model.first_face(), model.num_faces());
+            if first_face < 0
+                || num_faces < 0
+                || first_face as usize + num_faces as usize > faces.len()
+            {
+                self.report.error(
+                    LumpType::Models,
+                    Some(i),
+                    format!(
+                        "Faces {first_face}..{} out of range of {} faces",
+                        first_face as i64 + num_faces as i64,
+                        faces.len()
+                    ),
+                );
+            }
+            let head_node = model.head_node();
+            if let Some(nodes) = nodes {
+                if head_node < 0 || head_node as usize >= nodes {
+                    self.report.error(
+                        LumpType::Models,
+                        Some(i),
+                        format!("Head node {head_node} out of range of {nodes} nodes"),
+                    );
+                }
+            }
+        }
+
+        let disp_tris = self.count(LumpType::DispTris);
+
+        for (i, info) in disp_infos.iter().enumerate() {
+            let power = info.power as usize;
+            if !(MIN_MAP_DISP_POWER..=MAX_MAP_DISP_POWER).contains(&power) {
+                self.report.error(
+                    LumpType::DispInfo,
+                    Some(i),
+                    format!("Power {power} outside of {MIN_MAP_DISP_POWER}..={MAX_MAP_DISP_POWER}"),
+                );
+                continue;
+            }
+
+            match faces.get(info.map_face as usize) {
+                None => self.report.error(
+                    LumpType::DispInfo,
+                    Some(i),
+                    format!(
+                        "Face {} out of range of {} faces",
+                        info.map_face,
+                        faces.len()
+                    ),
+                ),
+                Some(face) if face.disp_info as isize != i as isize => self.report.warning(
+                    LumpType::DispInfo,
+                    Some(i),
+                    format!("Face {} references dispinfo {} instead", info.map_face, {
+                        face.disp_info
+                    }),
+                ),
+                _ => (),
+            }
+
+            let start = info.disp_vert_start;
+            let count = num_disp_power_verts(power);
+            if start < 0 || start as usize + count > disp_verts.len() {
+                self.report.error(
+                    LumpType::DispInfo,
+                    Some(i),
+                    format!(
+                        "Displacement verts {start}..{} out of range of {} verts",
+                        start as i64 + count as i64,
+                        disp_verts.len()
+                    ),
+                );
+            }
+
+            if let Some(disp_tris) = disp_tris {
+                let start = info.disp_tri_start;
+                let count = num_disp_power_tris(power);
+                if start < 0 || start as usize + count > disp_tris {
+                    self.report.error(
+                        LumpType::DispInfo,
+                        Some(i),
+                        format!(
+                            "Displacement tris {start}..{} out of range of {disp_tris} tris",
+                            start as i64 + count as i64,
+                        ),
+                    );
+                }
+            }
+
+            for edge in &info.edge_neighbours {
+                for sub in &edge.sub_neighbours {
+                    if sub.i_neighbour != 0xFFFF && sub.i_neighbour as usize >= disp_infos.len() {
+                        self.report.error(
+                            LumpType::DispInfo,
+                            Some(i),
+                            format!(
+                                "Edge neighbour {} out of range of {} dispinfos",
+                                sub.i_neighbour,
+                                disp_infos.len()
+                            ),
+                        );
+                    }
+                }
+            }
+            for corner in &info.corner_neighbours {
+                let n = corner.n_neighbours as usize;
+                if n > MAX_DISP_CORNER_NEIGHBORS {
+                    self.report.error(
+                        LumpType::DispInfo,
+                        Some(i),
+                        format!("{n} corner neighbours exceeds {MAX_DISP_CORNER_NEIGHBORS}"),
+                    );
+                    continue;
+                }
+                for &neighbour in &corner.neighbours[..n] {
+                    if neighbour as usize >= disp_infos.len() {
+                        self.report.error(
+                            LumpType::DispInfo,
+                            Some(i),
+                            format!(
+                                "Corner neighbour {neighbour} out of range of {} dispinfos",
+                                disp_infos.len()
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use glam::Vec3;
+
+    use crate::bsp::{
+        edges::BSPSurfEdge, face::BSPFace, test_map::TestMap, textures::BSPTexInfo, LumpType,
+    };
+
+    use super::{validate_map, Severity};
+
+    #[test]
+    fn valid_map() {
+        let report = validate_map(&mut TestMap::quad().reader());
+        assert!(report.issues.is_empty(), "{:#?}", report.issues);
+    }
+
+    #[test]
+    fn bad_ident() {
+        let mut data = TestMap::quad().build();
+        data[0..4].copy_from_slice(b"PSBV");
+
+        let report = validate_map(&mut TestMap::reader_for(data));
+        assert!(!report.is_ok());
+        assert_eq!(report.issues[0].lump, None);
+    }
+
+    #[test]
+    fn truncated_file() {
+        let data = TestMap::quad().build();
+
+        let report = validate_map(&mut TestMap::reader_for(data[..100].to_vec()));
+        assert!(!report.is_ok());
+
+        let report = validate_map(&mut TestMap::reader_for(data[..data.len() - 8].to_vec()));
+        assert!(report
+            .errors()
+            .any(|i| i.message.contains("outside of the file")));
+    }
+
+    #[test]
+    fn misaligned_lump() {
+        let report = validate_map(
+            &mut TestMap::quad()
+                .with_bytes(LumpType::Vertexes, vec![0; 13])
+                .reader(),
+        );
+        let error = report.errors().next().unwrap();
+        assert_eq!(error.lump, Some(LumpType::Vertexes));
+        // Edges now reference missing vertices
+        assert!(report.errors().any(|i| i.lump == Some(LumpType::Edges)));
+    }
+
+    #[test]
+    fn out_of_range_indices() {
+        let map = TestMap::quad();
+        let mut faces = map.faces();
+        faces[0].tex_info = 5;
+        faces.push(BSPFace {
+            first_edge: 2,
+            ..faces[0]
+        });
+
+        let report = validate_map(
+            &mut map
+                .with_lump(LumpType::Faces, &faces)
+                .with_lump(
+                    LumpType::SurfEdges,
+                    &[BSPSurfEdge::new(1), BSPSurfEdge::new(-9)],
+                )
+                .reader(),
+        );
+
+        let face_errors: Vec<_> = report
+            .errors()
+            .filter(|i| i.lump == Some(LumpType::Faces))
+            .map(|i| i.element)
+            .collect();
+        assert_eq!(face_errors, [Some(0), Some(0), Some(1), Some(1)]);
+
+        let surf_edge = report
+            .errors()
+            .find(|i| i.lump == Some(LumpType::SurfEdges))
+            .unwrap();
+        assert_eq!(surf_edge.element, Some(1));
+    }
+
+    #[test]
+    fn limits() {
+        let verts = vec![Vec3::ZERO; 65537];
+        let tex_info = vec![bytemuck::Zeroable::zeroed(); 12289];
+
+        let report = validate_map(
+            &mut TestMap::quad()
+                .with_lump(LumpType::Vertexes, &verts)
+                .with_lump::<BSPTexInfo>(LumpType::TexInfo, &tex_info)
+                .with_bytes(LumpType::PortalVerts, vec![0; 2 * 128001])
+                .reader(),
+        );
+
+        let mut limited: Vec<_> = report
+            .issues
+            .iter()
+            .filter(|i| i.severity == Severity::Error && i.element.is_none())
+            .map(|i| i.lump.unwrap())
+            .collect();
+        limited.sort_by_key(|&l| l as usize);
+        assert_eq!(
+            limited,
+            [LumpType::Vertexes, LumpType::TexInfo, LumpType::PortalVerts]
+        );
+    }
+
+    #[test]
+    fn lightmap_overflow() {
+        let map = TestMap::quad();
+        let mut faces = map.faces();
+        faces[0].light_ofs = 0;
+        faces[0].lightmap_texture_size_in_luxels = glam::IVec2::splat(i32::MAX);
+
+        let report = validate_map(&mut map.with_lump(LumpType::Faces, &faces).reader());
+        assert!(report
+            .errors()
+            .any(|i| i.lump == Some(LumpType::Faces) && i.message.contains("overflows")));
+    }
+}
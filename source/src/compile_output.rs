@@ -0,0 +1,290 @@
+//! Debug files the compile tools write next to a map.
+//!
+//! `vbsp` writes `mapname.prt`, the portals between visibility clusters that `vvis` works through, and when the
+//! map is not sealed, `mapname.lin`, a line from an entity out into the void.
+
+use std::{
+    io::{Read, Seek},
+    path::Path,
+};
+
+use glam::{vec3, Vec3};
+
+use crate::{
+    bsp::Bsp,
+    error::{SourceError, SourceResult},
+};
+
+/// First line of a portal file
+pub const PORTAL_FILE_ID: &str = "PRT1";
+
+/// A portal between two visibility clusters, through which one can see into the other
+#[derive(Debug, Clone, PartialEq)]
+pub struct VisPortal {
+    pub clusters: [u32; 2],
+    /// Corners of the convex polygon
+    pub points: Vec<Vec3>,
+}
+
+impl VisPortal {
+    /// Every edge of the polygon, closing back to the first point
+    pub fn edges(&self) -> impl Iterator<Item = (Vec3, Vec3)> + '_ {
+        self.points
+            .iter()
+            .zip(self.points.iter().cycle().skip(1))
+            .map(|(&a, &b)| (a, b))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortalFile {
+    pub num_clusters: u32,
+    pub portals: Vec<VisPortal>,
+}
+
+impl PortalFile {
+    pub fn parse(text: &str) -> SourceResult<Self> {
+        let mut lines = Lines::new(text);
+
+        let (offset, id) = lines.next_line("PortalFile")?;
+        if id != PORTAL_FILE_ID {
+            return Err(SourceError::invalid(
+                "PortalFile",
+                offset,
+                format!("expected {PORTAL_FILE_ID}, found {id:?}"),
+            ));
+        }
+        let num_clusters = lines.number("PortalFile")?;
+        let num_portals: usize = lines.number("PortalFile")?;
+
+        let mut portals = Vec::with_capacity(num_portals.min(text.len()));
+        for _ in 0..num_portals {
+            let (offset, line) = lines.next_line("VisPortal")?;
+            let invalid = |reason: &str| SourceError::invalid("VisPortal", offset, reason);
+
+            // `4 0 1 (x y z ) (x y z ) ...`, where the brackets only group the numbers
+            let mut values = line
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .filter(|v| !v.is_empty());
+            let mut int = || -> SourceResult<u32> {
+                values
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| invalid("expected a whole number"))
+            };
+            let num_points = int()? as usize;
+            let clusters = [int()?, int()?];
+            for cluster in clusters {
+                if cluster >= num_clusters {
+                    return Err(invalid(&format!("cluster {cluster} out of {num_clusters}")));
+                }
+            }
+
+            let floats = values
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(&e.to_string()))?;
+            if floats.len() != num_points * 3 {
+                return Err(invalid(&format!(
+                    "expected {num_points} points, found {} numbers",
+                    floats.len()
+                )));
+            }
+            let points = floats.chunks(3).map(|p| vec3(p[0], p[1], p[2])).collect();
+
+            portals.push(VisPortal { clusters, points });
+        }
+
+        Ok(Self {
+            num_clusters,
+            portals,
+        })
+    }
+
+    pub fn load(path: &Path) -> SourceResult<Self> {
+        Self::parse(&read_text("PortalFile", path)?).map_err(|e| e.in_file(path))
+    }
+
+    /// Load `mapname.prt` from next to the BSP
+    pub fn load_for_map<R: Read + Seek>(bsp: &Bsp<R>) -> SourceResult<Self> {
+        Self::load(&beside_map(bsp, "prt")?)
+    }
+
+    /// Portals leading out of `cluster`
+    pub fn portals_of(&self, cluster: u32) -> impl Iterator<Item = &VisPortal> {
+        self.portals
+            .iter()
+            .filter(move |portal| portal.clusters.contains(&cluster))
+    }
+}
+
+/// The line `vbsp` found from an entity to outside the map, which the map needs sealing along
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LeakTrail {
+    pub points: Vec<Vec3>,
+}
+
+impl LeakTrail {
+    pub fn parse(text: &str) -> SourceResult<Self> {
+        let mut points = Vec::new();
+
+        for (offset, line) in Lines::new(text) {
+            let point = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| SourceError::invalid("LeakTrail", offset, e.to_string()))?;
+            let &[x, y, z] = point.as_slice() else {
+                return Err(SourceError::invalid(
+                    "LeakTrail",
+                    offset,
+                    format!("expected 3 numbers, found {}", point.len()),
+                ));
+            };
+            points.push(vec3(x, y, z));
+        }
+
+        Ok(Self { points })
+    }
+
+    pub fn load(path: &Path) -> SourceResult<Self> {
+        Self::parse(&read_text("LeakTrail", path)?).map_err(|e| e.in_file(path))
+    }
+
+    /// Load `mapname.lin` from next to the BSP
+    pub fn load_for_map<R: Read + Seek>(bsp: &Bsp<R>) -> SourceResult<Self> {
+        Self::load(&beside_map(bsp, "lin")?)
+    }
+
+    /// Each line of the trail, from the entity outwards
+    pub fn segments(&self) -> impl Iterator<Item = (Vec3, Vec3)> + '_ {
+        self.points.windows(2).map(|w| (w[0], w[1]))
+    }
+}
+
+fn read_text(structure: &'static str, path: &Path) -> SourceResult<String> {
+    std::fs::read_to_string(path).map_err(|e| SourceError::io(structure, 0, e).in_file(path))
+}
+
+fn beside_map<R: Read + Seek>(bsp: &Bsp<R>, extension: &str) -> SourceResult<std::path::PathBuf> {
+    let path = bsp
+        .path()
+        .ok_or_else(|| SourceError::NotFound(format!("path of map for .{extension} file")))?
+        .with_extension(extension);
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(SourceError::NotFound(path.display().to_string()))
+    }
+}
+
+/// Non empty lines, with the byte offset of each for errors
+struct Lines<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn next_line(&mut self, structure: &'static str) -> SourceResult<(u64, &'a str)> {
+        let end = self.text.len() as u64;
+        self.next()
+            .ok_or_else(|| SourceError::invalid(structure, end, "unexpected end of file"))
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, structure: &'static str) -> SourceResult<T> {
+        let (offset, line) = self.next_line(structure)?;
+        line.parse().map_err(|_| {
+            SourceError::invalid(
+                structure,
+                offset,
+                format!("expected a number, found {line:?}"),
+            )
+        })
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = (u64, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos < self.text.len() {
+            let start = self.pos;
+            let rest = &self.text[start..];
+            let len = rest.find('\n').map_or(rest.len(), |i| i + 1);
+            self.pos += len;
+
+            let line = rest[..len].trim();
+            if !line.is_empty() {
+                return Some((start as u64, line));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod compile_output_tests {
+    use super::*;
+
+    #[test]
+    fn portals() {
+        let text = "PRT1\r\n3\r\n2\r\n\
+            4 0 1 (0 0 0 ) (0 64 0 ) (0 64 64 ) (0 0 64 ) \r\n\
+            3 1 2 (128 0 0 ) (128 64 0 ) (128.5 64 -64.25 ) \r\n";
+        let prt = PortalFile::parse(text).unwrap();
+
+        assert_eq!(prt.num_clusters, 3);
+        assert_eq!(prt.portals.len(), 2);
+        assert_eq!(prt.portals[1].clusters, [1, 2]);
+        assert_eq!(prt.portals[1].points[2], vec3(128.5, 64.0, -64.25));
+        assert_eq!(prt.portals_of(1).count(), 2);
+        assert_eq!(prt.portals_of(2).count(), 1);
+
+        let edges: Vec<_> = prt.portals[0].edges().collect();
+        assert_eq!(edges.len(), 4);
+        assert_eq!(edges[3], (vec3(0.0, 0.0, 64.0), Vec3::ZERO));
+    }
+
+    #[test]
+    fn bad_portals() {
+        let err = |text| PortalFile::parse(text).unwrap_err();
+
+        assert!(matches!(
+            err("PRT2\n1\n0\n"),
+            SourceError::Invalid { offset: 0, .. }
+        ));
+        // Missing a coordinate
+        assert!(matches!(
+            err("PRT1\n2\n1\n2 0 1 (0 0 0 ) (0 0 )\n"),
+            SourceError::Invalid { offset: 9, .. }
+        ));
+        assert!(matches!(
+            err("PRT1\n2\n1\n1 0 2 (0 0 0 )\n"),
+            SourceError::Invalid { .. }
+        ));
+        assert!(matches!(
+            err("PRT1\n2\n2\n1 0 1 (0 0 0 )\n"),
+            SourceError::Invalid { offset: 24, .. }
+        ));
+    }
+
+    #[test]
+    fn leak() {
+        let lin =
+            LeakTrail::parse("0.000000 0.000000 64.000000\n512 0 64\n512 -4096.5 64\n").unwrap();
+
+        assert_eq!(lin.points.len(), 3);
+        assert_eq!(lin.points[2], vec3(512.0, -4096.5, 64.0));
+        assert_eq!(lin.segments().count(), 2);
+
+        assert!(LeakTrail::parse("").unwrap().points.is_empty());
+        assert!(matches!(
+            LeakTrail::parse("0 0 0\n1 1\n"),
+            Err(SourceError::Invalid { offset: 6, .. })
+        ));
+    }
+}
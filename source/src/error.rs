@@ -0,0 +1,139 @@
+use std::{
+    any, io,
+    io::Seek,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use thiserror::Error;
+
+use crate::vmt::VMTError;
+
+/// Error produced while reading any of the Source engine file formats in this crate.
+///
+/// Cloneable so that failed loads can be cached alongside successful ones.
+#[derive(Error, Debug, Clone)]
+pub enum SourceError {
+    #[error("Failed reading {structure} at offset {offset}: {source}")]
+    Io {
+        structure: &'static str,
+        offset: u64,
+        #[source]
+        source: Arc<io::Error>,
+    },
+    #[error("Invalid {structure} at offset {offset}: {reason}")]
+    Invalid {
+        structure: &'static str,
+        offset: u64,
+        reason: String,
+    },
+    #[error("Unsupported {structure} version {version}")]
+    UnsupportedVersion {
+        structure: &'static str,
+        version: String,
+    },
+    #[error("Could not find {0}")]
+    NotFound(String),
+    #[error("In {path}: {source}")]
+    File {
+        path: PathBuf,
+        #[source]
+        source: Box<SourceError>,
+    },
+    #[error(transparent)]
+    VMT(#[from] VMTError),
+}
+
+pub type SourceResult<T> = Result<T, SourceError>;
+
+impl SourceError {
+    pub fn io(structure: &'static str, offset: u64, source: io::Error) -> Self {
+        Self::Io {
+            structure,
+            offset,
+            source: Arc::new(source),
+        }
+    }
+
+    pub fn invalid(structure: &'static str, offset: u64, reason: impl Into<String>) -> Self {
+        Self::Invalid {
+            structure,
+            offset,
+            reason: reason.into(),
+        }
+    }
+
+    /// Record the file this error came from. Errors already attached to a file keep the innermost path.
+    pub fn in_file(self, path: impl AsRef<Path>) -> Self {
+        match self {
+            Self::File { .. } => self,
+            source => Self::File {
+                path: path.as_ref().to_owned(),
+                source: Box::new(source),
+            },
+        }
+    }
+
+    /// The underlying error, without any file context
+    pub fn root(&self) -> &SourceError {
+        match self {
+            Self::File { source, .. } => source.root(),
+            e => e,
+        }
+    }
+}
+
+/// Attach the structure being read and the position of the reader to io errors
+pub trait ResultExt<T> {
+    fn at<S: Seek>(self, structure: &'static str, buffer: &mut S) -> SourceResult<T>;
+}
+
+impl<T> ResultExt<T> for io::Result<T> {
+    fn at<S: Seek>(self, structure: &'static str, buffer: &mut S) -> SourceResult<T> {
+        self.map_err(|e| SourceError::io(structure, buffer.stream_position().unwrap_or(0), e))
+    }
+}
+
+/// Name of a type without its module path, for error messages
+pub fn structure_name<T: ?Sized>() -> &'static str {
+    let name = any::type_name::<T>();
+    let base = name.split('<').next().unwrap_or(name);
+    match base.rfind("::") {
+        Some(i) => &name[i + 2..],
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use std::io::{BufReader, Cursor};
+
+    use crate::binaries::BinaryData;
+
+    use super::*;
+
+    #[test]
+    fn short_read() {
+        let mut buffer = BufReader::new(Cursor::new(vec![0u8; 6]));
+        let err = <[u32; 2]>::read(&mut buffer, None).unwrap_err();
+
+        assert!(matches!(err, SourceError::Io { .. }));
+        assert!(err.to_string().contains("[u32; 2]"));
+    }
+
+    #[test]
+    fn file_context() {
+        let err = SourceError::NotFound("a.vtf".to_owned())
+            .in_file("a_dir.vpk")
+            .in_file("ignored");
+
+        assert_eq!(err.to_string(), "In a_dir.vpk: Could not find a.vtf");
+        assert!(matches!(err.root(), SourceError::NotFound(_)));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(structure_name::<crate::vtf::VTF>(), "VTF");
+        assert_eq!(structure_name::<Vec<u8>>(), "Vec<u8>");
+    }
+}
@@ -0,0 +1,636 @@
+//! glTF 2.0 export of compiled maps
+//!
+//! The world and each brush model become a node with a mesh per material, and static props become nodes sharing
+//! one mesh per model. Source is Z up and measured in inches, while glTF is Y up and measured in metres, so
+//! positions are turned and scaled as they are written, and node transforms changed to match. Lightmaps are packed
+//! into one atlas image, sampled with a second uv set on the world meshes.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Seek},
+    path::Path,
+};
+
+use common::vertex::UVVertex;
+use glam::{Mat3, Mat4, Vec2, Vec3};
+use serde_json::{json, Value};
+
+use super::{encode_png, material_png, model_name, static_props, write_file, ExportAssets};
+use crate::{
+    bsp::{gamelump::GameLump, lightmap::LightmapAtlas},
+    meshes::{build_model_meshes, MeshBuildOptions, MeshBuilder},
+    prelude::*,
+    studio::mesh::PropMesh,
+};
+
+const INCHES_TO_METRES: f32 = 0.0254;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON: &[u8; 4] = b"JSON";
+const GLB_BIN: &[u8; 4] = b"BIN\0";
+
+/// What to include in an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GltfOptions {
+    /// Which faces of the world and brush models to export
+    pub meshes: MeshBuildOptions,
+    /// Decode base textures to PNGs. Materials without them are left plain white.
+    pub textures: bool,
+    /// Add the lightmap atlas, and a second uv set for it on world meshes
+    pub lightmaps: bool,
+    pub static_props: bool,
+}
+
+impl Default for GltfOptions {
+    fn default() -> Self {
+        Self {
+            meshes: MeshBuildOptions::default(),
+            textures: true,
+            lightmaps: true,
+            static_props: true,
+        }
+    }
+}
+
+/// A glTF document and the binary buffer its meshes and images are in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gltf {
+    pub json: Value,
+    pub buffer: Vec<u8>,
+}
+
+impl Gltf {
+    /// Export a map, finding its materials and prop models in `assets`
+    pub fn from_map<R: Read + Seek>(
+        bsp: &Bsp<R>,
+        assets: &impl ExportAssets,
+        options: &GltfOptions,
+    ) -> SourceResult<Self> {
+        let mut builder = Builder::new(assets, options);
+
+        if options.lightmaps {
+            let atlas = LightmapAtlas::build(bsp.faces()?, bsp.lighting()?);
+            let png = encode_png(atlas.width, atlas.height, &atlas.pixels)?;
+            let texture = builder.texture("lightmap", &png);
+            builder.lightmap = Some((atlas, texture));
+        }
+
+        let entities = bsp.entities()?;
+        for model in build_model_meshes(bsp, &options.meshes)? {
+            if model.meshes.meshes.is_empty() {
+                continue;
+            }
+            let name = model_name(entities, &model);
+
+            let mut meshes: Vec<_> = model.meshes.meshes.iter().collect();
+            meshes.sort_by_key(|(&tex_data, _)| tex_data);
+            let primitives = meshes
+                .into_iter()
+                .map(|(&tex_data, mesh)| {
+                    let material = builder.material(bsp.texture_name(tex_data as usize)?, true);
+                    Ok(builder.face_primitive(mesh, material))
+                })
+                .collect::<SourceResult<Vec<_>>>()?;
+
+            let mesh = builder.mesh(&name, primitives);
+            builder.node(json!({ "name": name, "mesh": mesh }), model.transform);
+        }
+
+        if options.static_props {
+            if let Some(game_lump) = bsp.static_prop_lump()? {
+                builder.static_props(game_lump);
+            }
+        }
+
+        Ok(builder.finish())
+    }
+
+    /// The document and its buffer as one binary `.glb`
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut json = self.json.to_string().into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = self.buffer.clone();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        for (kind, chunk) in [(GLB_JSON, &json), (GLB_BIN, &bin)] {
+            glb.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            glb.extend_from_slice(kind);
+            glb.extend_from_slice(chunk);
+        }
+        glb
+    }
+
+    /// Save as a `.glb` if `path` has that extension, otherwise as a `.gltf` with its buffer in a `.bin` beside it
+    pub fn save(&self, path: &Path) -> SourceResult<()> {
+        let write = |path: &Path, contents: &[u8]| write_file("glTF", path, contents);
+
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("glb"))
+        {
+            return write(path, &self.to_glb());
+        }
+
+        let bin_path = path.with_extension("bin");
+        let mut json = self.json.clone();
+        if let Some(name) = bin_path.file_name() {
+            json["buffers"][0]["uri"] = name.to_string_lossy().into();
+        }
+        write(&bin_path, &self.buffer)?;
+        write(path, json.to_string().as_bytes())
+    }
+}
+
+/// Source's space to glTF's: Z up to Y up, and inches to metres
+fn to_gltf() -> Mat4 {
+    Mat4::from_scale(Vec3::splat(INCHES_TO_METRES))
+        * Mat4::from_mat3(Mat3::from_cols(Vec3::X, -Vec3::Z, Vec3::Y))
+}
+
+/// The arrays of a glTF document as they are filled in
+struct Builder<'a, A> {
+    assets: &'a A,
+    options: &'a GltfOptions,
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    images: Vec<Value>,
+    textures: Vec<Value>,
+    materials: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+    /// Nodes at the root of the scene
+    roots: Vec<usize>,
+    /// Material index by name, and whether it is for lightmapped faces
+    material_indices: HashMap<(String, bool), usize>,
+    /// The lightmap atlas and its texture index
+    lightmap: Option<(LightmapAtlas, usize)>,
+}
+
+impl<'a, A: ExportAssets> Builder<'a, A> {
+    fn new(assets: &'a A, options: &'a GltfOptions) -> Self {
+        Self {
+            assets,
+            options,
+            buffer: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            images: Vec::new(),
+            textures: Vec::new(),
+            materials: Vec::new(),
+            meshes: Vec::new(),
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            material_indices: HashMap::new(),
+            lightmap: None,
+        }
+    }
+
+    /// Append data to the buffer, aligned for any component type
+    fn buffer_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = target.into();
+        }
+        self.buffer.extend_from_slice(bytes);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn accessor(&mut self, bytes: &[u8], count: usize, kind: &str, component: u32) -> usize {
+        let target = if component == UNSIGNED_INT {
+            ELEMENT_ARRAY_BUFFER
+        } else {
+            ARRAY_BUFFER
+        };
+        let view = self.buffer_view(bytes, Some(target));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": component,
+            "count": count,
+            "type": kind,
+        }));
+        self.accessors.len() - 1
+    }
+
+    /// Positions need their bounds
+    fn positions(&mut self, positions: &[Vec3]) -> usize {
+        let accessor = self.accessor(
+            bytemuck::cast_slice(positions),
+            positions.len(),
+            "VEC3",
+            FLOAT,
+        );
+        let min = positions
+            .iter()
+            .copied()
+            .reduce(Vec3::min)
+            .unwrap_or_default();
+        let max = positions
+            .iter()
+            .copied()
+            .reduce(Vec3::max)
+            .unwrap_or_default();
+        self.accessors[accessor]["min"] = json!(min.to_array());
+        self.accessors[accessor]["max"] = json!(max.to_array());
+        accessor
+    }
+
+    fn vec3s(&mut self, data: &[Vec3]) -> usize {
+        self.accessor(bytemuck::cast_slice(data), data.len(), "VEC3", FLOAT)
+    }
+
+    fn vec2s(&mut self, data: &[Vec2]) -> usize {
+        self.accessor(bytemuck::cast_slice(data), data.len(), "VEC2", FLOAT)
+    }
+
+    fn indices(&mut self, indices: &[u32]) -> usize {
+        self.accessor(
+            bytemuck::cast_slice(indices),
+            indices.len(),
+            "SCALAR",
+            UNSIGNED_INT,
+        )
+    }
+
+    /// A texture showing a PNG from the buffer
+    fn texture(&mut self, name: &str, png: &[u8]) -> usize {
+        let view = self.buffer_view(png, None);
+        self.images.push(json!({
+            "name": name,
+            "bufferView": view,
+            "mimeType": "image/png",
+        }));
+        self.textures
+            .push(json!({ "source": self.images.len() - 1 }));
+        self.textures.len() - 1
+    }
+
+    /// The material called `name`, created the first time it is used. Lightmapped materials point at the atlas.
+    fn material(&mut self, name: &str, lightmapped: bool) -> usize {
+        let key = (name.to_owned(), lightmapped);
+        if let Some(&material) = self.material_indices.get(&key) {
+            return material;
+        }
+
+        let mut material = json!({
+            "name": name,
+            "pbrMetallicRoughness": { "metallicFactor": 0.0 },
+        });
+        if self.options.textures {
+            if let Some(png) = material_png(self.assets, name) {
+                let texture = self.texture(name, &png);
+                material["pbrMetallicRoughness"]["baseColorTexture"] = json!({ "index": texture });
+            }
+        }
+        // glTF has no lightmap slot, so the atlas is left for importers that look for it
+        if let (true, Some((_, lightmap))) = (lightmapped, &self.lightmap) {
+            material["extras"] = json!({ "lightmap": { "index": lightmap, "texCoord": 1 } });
+        }
+
+        self.materials.push(material);
+        self.material_indices.insert(key, self.materials.len() - 1);
+        self.materials.len() - 1
+    }
+
+    /// A primitive for a mesh of map faces, in the space of its brush model
+    fn face_primitive(&mut self, mesh: &MeshBuilder<UVVertex>, material: usize) -> Value {
+        let to_gltf = to_gltf();
+        let verts = mesh.verts();
+
+        let positions: Vec<Vec3> = verts
+            .iter()
+            .map(|v| to_gltf.transform_point3(v.position))
+            .collect();
+        let uvs: Vec<Vec2> = verts.iter().map(|v| v.uv).collect();
+
+        let mut attributes = json!({
+            "POSITION": self.positions(&positions),
+            "TEXCOORD_0": self.vec2s(&uvs),
+        });
+        if let Some((atlas, _)) = &self.lightmap {
+            let lightmap_uvs: Vec<Vec2> = verts
+                .iter()
+                .map(|v| atlas.uv(v.color.x, v.lightmap_uv))
+                .collect();
+            attributes["TEXCOORD_1"] = self.vec2s(&lightmap_uvs).into();
+        }
+
+        json!({
+            "attributes": attributes,
+            "indices": self.indices(mesh.tris()),
+            "material": material,
+        })
+    }
+
+    fn mesh(&mut self, name: &str, primitives: Vec<Value>) -> usize {
+        self.meshes
+            .push(json!({ "name": name, "primitives": primitives }));
+        self.meshes.len() - 1
+    }
+
+    /// Add a node at the root of the scene, placed by a transform in Source's space
+    fn node(&mut self, mut node: Value, transform: Mat4) -> usize {
+        let to_gltf = to_gltf();
+        let transform = to_gltf * transform * to_gltf.inverse();
+        if !transform.abs_diff_eq(Mat4::IDENTITY, 1e-6) {
+            node["matrix"] = json!(transform.to_cols_array());
+        }
+
+        self.nodes.push(node);
+        self.roots.push(self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    /// A mesh for a prop model, with a primitive per material
+    fn prop_mesh(&mut self, name: &str, prop: &PropMesh) -> usize {
+        let to_gltf = to_gltf();
+        let rotation = Mat3::from_cols(Vec3::X, -Vec3::Z, Vec3::Y);
+        let positions: Vec<Vec3> = prop
+            .positions
+            .iter()
+            .map(|&p| to_gltf.transform_point3(p))
+            .collect();
+        let normals: Vec<Vec3> = prop.normals.iter().map(|&n| rotation * n).collect();
+
+        let position = self.positions(&positions);
+        let normal = self.vec3s(&normals);
+        let uv = self.vec2s(&prop.uvs);
+
+        let primitives = prop
+            .parts
+            .iter()
+            .map(|part| {
+                json!({
+                    "attributes": { "POSITION": position, "NORMAL": normal, "TEXCOORD_0": uv },
+                    "indices": self.indices(&part.indices),
+                    "material": self.material(&part.material, false),
+                })
+            })
+            .collect();
+        self.mesh(name, primitives)
+    }
+
+    /// A node for each static prop, sharing a mesh between props of the same model. Props whose model cannot be
+    /// loaded keep an empty node, so they can still be found.
+    fn static_props(&mut self, game_lump: &GameLump) {
+        let mut meshes = HashMap::new();
+        for prop in static_props(game_lump, self.assets) {
+            let mut node = json!({ "name": prop.model });
+            if let Some(mesh) = &prop.mesh {
+                let mesh = *meshes
+                    .entry(prop.prop_type)
+                    .or_insert_with(|| self.prop_mesh(prop.model, mesh));
+                node["mesh"] = mesh.into();
+            }
+            self.node(node, prop.transform);
+        }
+    }
+
+    fn finish(self) -> Gltf {
+        let mut json = json!({
+            "asset": { "version": "2.0", "generator": "bsp-rs" },
+            "scene": 0,
+            "scenes": [{ "nodes": self.roots }],
+            "nodes": self.nodes,
+            "meshes": self.meshes,
+            "materials": self.materials,
+            "accessors": self.accessors,
+            "bufferViews": self.buffer_views,
+            "buffers": [{ "byteLength": self.buffer.len() }],
+        });
+        // Empty arrays are not allowed
+        if !self.images.is_empty() {
+            json["images"] = self.images.into();
+            json["textures"] = self.textures.into();
+        }
+        for key in ["nodes", "meshes", "materials", "accessors", "bufferViews"] {
+            if json[key].as_array().is_some_and(Vec::is_empty) {
+                json.as_object_mut().unwrap().remove(key);
+            }
+        }
+
+        Gltf {
+            json,
+            buffer: self.buffer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod gltf_tests {
+    use bytemuck::Zeroable;
+    use glam::{ivec2, vec2, vec3};
+
+    use super::*;
+    use crate::{
+        bsp::{
+            displacement::{BSPDispInfo, BSPDispVert},
+            test_map::TestMap,
+        },
+        export::export_tests::{prop_lump, TestAssets},
+    };
+
+    const MATERIAL: &str = "dev/dev_measuregeneric01";
+
+    /// The quad map, lit with a 5x5 lightmap
+    fn lit_quad() -> Bsp<std::io::Cursor<Vec<u8>>> {
+        let map = TestMap::quad();
+        let mut faces = map.faces();
+        faces[0].light_ofs = 0;
+        let lighting = [ColorRGBExp32::zeroed(); 25];
+
+        Bsp::new(
+            map.with_lump(LumpType::Faces, &faces)
+                .with_lump(LumpType::Lighting, &lighting)
+                .reader(),
+        )
+        .unwrap()
+    }
+
+    fn close(value: &Value, expected: &[f64]) -> bool {
+        let values = value.as_array().unwrap();
+        values.len() == expected.len()
+            && values
+                .iter()
+                .zip(expected)
+                .all(|(v, e)| (v.as_f64().unwrap() - e).abs() < 1e-5)
+    }
+
+    fn accessor<'a>(gltf: &'a Gltf, index: &Value) -> &'a Value {
+        &gltf.json["accessors"][index.as_u64().unwrap() as usize]
+    }
+
+    #[test]
+    fn world() {
+        let assets = TestAssets::default().with_material(MATERIAL, [0, 0, 255, 255]);
+        let gltf = Gltf::from_map(&lit_quad(), &assets, &GltfOptions::default()).unwrap();
+        let json = &gltf.json;
+
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 1);
+        assert_eq!(json["nodes"][0]["name"], "worldspawn");
+        assert_eq!(json["nodes"][0].get("matrix"), None);
+
+        let primitive = &json["meshes"][0]["primitives"][0];
+        let material = &json["materials"][primitive["material"].as_u64().unwrap() as usize];
+        assert_eq!(material["name"], MATERIAL);
+        assert_eq!(material["extras"]["lightmap"]["texCoord"], 1);
+        // The lightmap and the base texture
+        assert_eq!(json["images"].as_array().unwrap().len(), 2);
+        assert_eq!(json["images"][0]["name"], "lightmap");
+
+        // 64 inches along y is 1.6256 metres along -z
+        let positions = accessor(&gltf, &primitive["attributes"]["POSITION"]);
+        assert_eq!(positions["count"], 4);
+        assert!(close(&positions["min"], &[0.0, 0.0, -1.6256]));
+        assert!(close(&positions["max"], &[1.6256, 0.0, 0.0]));
+        assert!(primitive["attributes"].get("TEXCOORD_1").is_some());
+        assert_eq!(accessor(&gltf, &primitive["indices"])["count"], 9);
+
+        let mut end = 0;
+        for view in json["bufferViews"].as_array().unwrap() {
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            assert_eq!(offset % 4, 0);
+            assert!(offset >= end);
+            end = offset + view["byteLength"].as_u64().unwrap() as usize;
+        }
+        assert_eq!(end, gltf.buffer.len());
+        assert_eq!(json["buffers"][0]["byteLength"], gltf.buffer.len());
+    }
+
+    #[test]
+    fn displacement() {
+        let map = TestMap::quad();
+        let mut faces = map.faces();
+        faces[0].light_ofs = 0;
+        faces[0].disp_info = 0;
+        faces[0].lightmap_texture_mins_in_luxels = ivec2(4, 4);
+        let mut tex_info: Vec<BSPTexInfo> = map.lump_data(LumpType::TexInfo);
+        tex_info[0].lightmap_s[3] = 4.0;
+        tex_info[0].lightmap_t[3] = 4.0;
+        // A zeroed 3x3 displacement of face 0, written by hand as padding keeps the struct from being Pod
+        let mut info = vec![0; std::mem::size_of::<BSPDispInfo>()];
+        info[20..24].copy_from_slice(&1u32.to_le_bytes());
+        let bsp = Bsp::new(
+            map.with_lump(LumpType::Faces, &faces)
+                .with_lump(LumpType::TexInfo, &tex_info)
+                .with_bytes(LumpType::DispInfo, info)
+                .with_lump(LumpType::DispVerts, &[BSPDispVert::zeroed(); 9])
+                .with_lump(LumpType::Lighting, &[ColorRGBExp32::zeroed(); 25])
+                .reader(),
+        )
+        .unwrap();
+
+        let assets = TestAssets::default().with_material(MATERIAL, [0, 0, 255, 255]);
+        let gltf = Gltf::from_map(&bsp, &assets, &GltfOptions::default()).unwrap();
+        let primitive = &gltf.json["meshes"][0]["primitives"][0];
+        let uvs = accessor(&gltf, &primitive["attributes"]["TEXCOORD_1"]);
+        assert_eq!(uvs["count"], 9);
+        let view = &gltf.json["bufferViews"][uvs["bufferView"].as_u64().unwrap() as usize];
+        let offset = view["byteOffset"].as_u64().unwrap() as usize;
+        let len = view["byteLength"].as_u64().unwrap() as usize;
+        let uvs: Vec<Vec2> = bytemuck::pod_collect_to_vec(&gltf.buffer[offset..offset + len]);
+
+        // The 5x5 lightmap is at (1, 0) in an 8x5 atlas, and the grid covers all of it
+        let (min, max) = uvs.iter().fold((Vec2::MAX, Vec2::MIN), |(min, max), &uv| {
+            (min.min(uv), max.max(uv))
+        });
+        assert!(min.abs_diff_eq(vec2(1.5 / 8.0, 0.5 / 5.0), 1e-6));
+        assert!(max.abs_diff_eq(vec2(5.5 / 8.0, 4.5 / 5.0), 1e-6));
+    }
+
+    #[test]
+    fn plain() {
+        let options = GltfOptions {
+            textures: false,
+            lightmaps: false,
+            ..Default::default()
+        };
+        let assets = TestAssets::default().with_material(MATERIAL, [0, 0, 255, 255]);
+        let gltf = Gltf::from_map(&lit_quad(), &assets, &options).unwrap();
+
+        assert_eq!(gltf.json.get("images"), None);
+        assert_eq!(gltf.json["materials"][0].get("extras"), None);
+        assert!(gltf.json["meshes"][0]["primitives"][0]["attributes"]
+            .get("TEXCOORD_1")
+            .is_none());
+    }
+
+    #[test]
+    fn static_props() {
+        let assets = TestAssets::default().with_crate();
+        let game_lump = prop_lump(&[
+            (0, vec3(0.0, 0.0, 50.0), Vec3::ZERO),
+            (0, vec3(100.0, 0.0, 50.0), Vec3::ZERO),
+            (1, vec3(0.0, 0.0, 50.0), Vec3::ZERO),
+        ]);
+
+        let options = GltfOptions::default();
+        let mut builder = Builder::new(&assets, &options);
+        builder.static_props(&game_lump);
+        let gltf = builder.finish();
+        let nodes = gltf.json["nodes"].as_array().unwrap();
+
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(gltf.json["meshes"].as_array().unwrap().len(), 1);
+        assert_eq!(nodes[0]["mesh"], 0);
+        assert_eq!(nodes[1]["mesh"], 0);
+        assert_eq!(nodes[2]["name"], "models/missing.mdl");
+        assert_eq!(nodes[2].get("mesh"), None);
+
+        // 100 inches along x and 50 up
+        let translation = json!(nodes[1]["matrix"].as_array().unwrap()[12..15]);
+        assert!(close(&translation, &[2.54, 1.27, 0.0]));
+    }
+
+    #[test]
+    fn unsupported_props() {
+        let map = TestMap::quad().with_static_props(6, &["models/crate.mdl"], &[], 0);
+        let assets = TestAssets::default().with_crate();
+        let gltf = Gltf::from_map(
+            &Bsp::new(map.reader()).unwrap(),
+            &assets,
+            &GltfOptions::default(),
+        )
+        .unwrap();
+
+        // Just the world
+        assert_eq!(gltf.json["nodes"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn glb() {
+        let assets = TestAssets::default();
+        let gltf = Gltf::from_map(&lit_quad(), &assets, &GltfOptions::default()).unwrap();
+        let glb = gltf.to_glb();
+
+        let u32_at = |i: usize| u32::from_le_bytes(glb[i..i + 4].try_into().unwrap()) as usize;
+        assert_eq!(&glb[..4], GLB_MAGIC);
+        assert_eq!(u32_at(8), glb.len());
+
+        let json_len = u32_at(12);
+        assert_eq!(&glb[16..20], GLB_JSON);
+        let json: Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+        assert_eq!(json, gltf.json);
+
+        let bin = 20 + json_len;
+        assert_eq!(&glb[bin + 4..bin + 8], GLB_BIN);
+        assert_eq!(&glb[bin + 8..bin + 8 + gltf.buffer.len()], &gltf.buffer[..]);
+        assert_eq!(glb.len() % 4, 0);
+    }
+}
@@ -0,0 +1,286 @@
+//! Writing maps out in formats other tools can open, such as for artists to look at a map in Blender
+
+pub mod gltf;
+pub mod obj;
+
+use std::{collections::HashMap, fs, path::Path, rc::Rc, sync::Arc};
+
+use common::vpath::{VLocalPath, VPath};
+use glam::Mat4;
+
+use crate::{
+    bsp::gamelump::GameLump, meshes::BrushModelMeshes, prelude::*, studio::mesh::PropMesh,
+};
+
+/// Where exporters find the materials and models a map uses
+pub trait ExportAssets {
+    fn vmt(&self, path: &dyn VPath) -> Option<Arc<VMT>>;
+    fn vtf(&self, path: &dyn VPath) -> Option<Arc<VTF>>;
+    /// Triangles of a static prop's model, such as `models/props_c17/oildrum001.mdl`
+    fn prop(&self, model: &str) -> Option<PropMesh>;
+}
+
+impl ExportAssets for GameData {
+    fn vmt(&self, path: &dyn VPath) -> Option<Arc<VMT>> {
+        self.load_vmt(path).cloned()
+    }
+
+    fn vtf(&self, path: &dyn VPath) -> Option<Arc<VTF>> {
+        self.load_vtf(path).cloned()
+    }
+
+    fn prop(&self, model: &str) -> Option<PropMesh> {
+        PropMesh::load(self, model)
+            .inspect_err(|e| log::warn!("Skipping model of static prop: {e}"))
+            .ok()
+    }
+}
+
+/// The game's files along with those packed into a map, which take priority, such as materials patched by cubemaps
+pub struct MapAssets<'a> {
+    pub game: &'a GameData,
+    pub pak: &'a VPKDirectory,
+}
+
+impl ExportAssets for MapAssets<'_> {
+    fn vmt(&self, path: &dyn VPath) -> Option<Arc<VMT>> {
+        match self.pak.load_vmt(path) {
+            Ok(vmt) => Some(vmt.clone()),
+            Err(_) => self.game.vmt(path),
+        }
+    }
+
+    fn vtf(&self, path: &dyn VPath) -> Option<Arc<VTF>> {
+        match self.pak.load_vtf(path) {
+            Ok(vtf) => Some(vtf.clone()),
+            Err(_) => self.game.vtf(path),
+        }
+    }
+
+    fn prop(&self, model: &str) -> Option<PropMesh> {
+        self.game.prop(model)
+    }
+}
+
+/// The base texture of a material, such as `BRICK/BRICKWALL001A`, as a PNG. `None` if the material or its texture
+/// is missing, or the texture is in a format that cannot be decoded.
+pub fn material_png(assets: &impl ExportAssets, material: &str) -> Option<Vec<u8>> {
+    let material = material.to_ascii_lowercase().replace('\\', "/");
+    let vmt = assets.vmt(&VLocalPath::new("materials", &material, "vmt"))?;
+    let texture = vmt.get_basetex()?.to_ascii_lowercase().replace('\\', "/");
+    let vtf = assets.vtf(&VLocalPath::new("materials", &texture, "vtf"))?;
+
+    let (width, height) = vtf.mip_size(0);
+    vtf.high_res_rgba8(0)
+        .and_then(|rgba| encode_png(width as u32, height as u32, &rgba))
+        .inspect_err(|e| log::warn!("Skipping texture {texture}: {e}"))
+        .ok()
+}
+
+/// Name of a brush model's object, from its entity's target name or else its class name, such as `func_door *1`.
+/// The world is named after its entity alone.
+pub(crate) fn model_name(entities: &[BSPEntity], model: &BrushModelMeshes) -> String {
+    let entity = model.entity.map(|i| &entities[i]);
+    match entity.and_then(|e| e.target_name().or(e.classname())) {
+        Some(name) if model.model == 0 => name.to_owned(),
+        Some(name) => format!("{name} *{}", model.model),
+        None => format!("*{}", model.model),
+    }
+}
+
+/// A static prop, and its model's triangles if they could be loaded
+pub(crate) struct StaticProp<'a> {
+    /// Index into [`GameLump::props`]
+    pub index: usize,
+    /// Index into [`GameLump::static_prop_names`], the same for every prop of a model
+    pub prop_type: u16,
+    pub model: &'a str,
+    pub transform: Mat4,
+    pub mesh: Option<Rc<PropMesh>>,
+}
+
+/// Every static prop with a model name, loading each model once however many props use it
+pub(crate) fn static_props<'a>(
+    game_lump: &'a GameLump,
+    assets: &impl ExportAssets,
+) -> Vec<StaticProp<'a>> {
+    let mut meshes = HashMap::new();
+    game_lump
+        .props
+        .iter()
+        .enumerate()
+        .filter_map(|(index, prop)| {
+            let prop_type = prop.prop_type;
+            let model = game_lump.static_prop_names.get(prop_type as usize)?;
+            let mesh = meshes
+                .entry(prop_type)
+                .or_insert_with(|| assets.prop(model).map(Rc::new))
+                .clone();
+            Some(StaticProp {
+                index,
+                prop_type,
+                model,
+                transform: prop.transform(),
+                mesh,
+            })
+        })
+        .collect()
+}
+
+/// Write one file of an export, such as `OBJ` or `glTF`
+pub(crate) fn write_file(format: &'static str, path: &Path, contents: &[u8]) -> SourceResult<()> {
+    fs::write(path, contents).map_err(|e| SourceError::io(format, 0, e).in_file(path))
+}
+
+/// Encode tightly packed RGBA8 pixels as a PNG
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> SourceResult<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba))
+        .map_err(|e| SourceError::invalid("PNG", 0, e.to_string()))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+pub(crate) mod export_tests {
+    use bytemuck::Zeroable;
+    use glam::{vec3, Vec2, Vec3};
+
+    use super::*;
+    use crate::{
+        bsp::gamelump::StaticPropLumpV5,
+        studio::mesh::PropMeshPart,
+        vtf::{consts::ImageFormat, test_vtf::vtf},
+    };
+
+    /// Materials and models keyed by path, such as `materials/dev/dev_measuregeneric01.vmt`
+    #[derive(Default)]
+    pub struct TestAssets {
+        pub vmts: HashMap<String, Arc<VMT>>,
+        pub vtfs: HashMap<String, Arc<VTF>>,
+        pub props: HashMap<String, PropMesh>,
+    }
+
+    fn key(path: &dyn VPath) -> String {
+        format!("{}/{}.{}", path.dir(), path.filename(), path.ext())
+    }
+
+    impl ExportAssets for TestAssets {
+        fn vmt(&self, path: &dyn VPath) -> Option<Arc<VMT>> {
+            self.vmts.get(&key(path)).cloned()
+        }
+
+        fn vtf(&self, path: &dyn VPath) -> Option<Arc<VTF>> {
+            self.vtfs.get(&key(path)).cloned()
+        }
+
+        fn prop(&self, model: &str) -> Option<PropMesh> {
+            self.props.get(model).cloned()
+        }
+    }
+
+    impl TestAssets {
+        /// A material with a 4x4 base texture of one colour
+        pub fn with_material(mut self, material: &str, color: [u8; 4]) -> Self {
+            let mut vmt = VMT::new(String::new(), "LightmappedGeneric".to_owned());
+            vmt.data
+                .insert("$basetexture".to_owned(), format!("{material}_color"));
+
+            self.vmts
+                .insert(format!("materials/{material}.vmt"), Arc::new(vmt));
+            self.vtfs.insert(
+                format!("materials/{material}_color.vtf"),
+                vtf(4, 4, ImageFormat::RGBA8888, &color.repeat(16)),
+            );
+            self
+        }
+
+        /// A triangle as `models/crate.mdl`, with a material on each side
+        pub fn with_crate(mut self) -> Self {
+            self.props.insert(
+                "models/crate.mdl".to_owned(),
+                PropMesh {
+                    positions: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+                    normals: vec![Vec3::X; 3],
+                    uvs: vec![Vec2::ZERO; 3],
+                    parts: vec![
+                        PropMeshPart {
+                            material: "models/crate".to_owned(),
+                            indices: vec![0, 1, 2],
+                        },
+                        PropMeshPart {
+                            material: "models/crate_back".to_owned(),
+                            indices: vec![0, 2, 1],
+                        },
+                    ],
+                },
+            );
+            self
+        }
+    }
+
+    /// Static props of `models/crate.mdl` (type 0), or `models/missing.mdl` (type 1) which has no model, each at an
+    /// origin and turned by angles
+    pub fn prop_lump(props: &[(u16, Vec3, Vec3)]) -> GameLump {
+        let mut game_lump = GameLump {
+            static_prop_names: vec![
+                "models/crate.mdl".to_owned(),
+                "models/missing.mdl".to_owned(),
+            ],
+            ..Default::default()
+        };
+        for &(prop_type, origin, angles) in props {
+            let mut prop = StaticPropLumpV5::zeroed();
+            prop.prop_type = prop_type;
+            prop.m_origin = origin;
+            prop.angles = angles;
+            game_lump.props.push(prop);
+        }
+        game_lump
+    }
+
+    #[test]
+    fn shared_models() {
+        let assets = TestAssets::default().with_crate();
+        let mut game_lump = prop_lump(&[
+            (0, Vec3::ZERO, Vec3::ZERO),
+            (1, Vec3::ZERO, Vec3::ZERO),
+            (0, vec3(0.0, 0.0, 10.0), Vec3::ZERO),
+        ]);
+        game_lump.props.push(StaticPropLumpV5::zeroed());
+        game_lump.props.last_mut().unwrap().prop_type = 2;
+
+        let props = static_props(&game_lump, &assets);
+        // The prop with no model name is left out
+        assert_eq!(props.len(), 3);
+        assert_eq!(props[1].model, "models/missing.mdl");
+        assert!(props[1].mesh.is_none());
+        assert_eq!(props[2].index, 2);
+        assert_eq!(props[2].transform.w_axis, vec3(0.0, 0.0, 10.0).extend(1.0));
+        assert!(Rc::ptr_eq(
+            props[0].mesh.as_ref().unwrap(),
+            props[2].mesh.as_ref().unwrap()
+        ));
+    }
+
+    #[test]
+    fn material_pngs() {
+        let assets =
+            TestAssets::default().with_material("dev/dev_measuregeneric01", [255, 0, 0, 255]);
+
+        let png = material_png(&assets, "DEV\\DEV_MEASUREGENERIC01").unwrap();
+        let decoder = png::Decoder::new(&png[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+
+        assert_eq!((info.width, info.height), (4, 4));
+        assert_eq!(&pixels[..4], &[255, 0, 0, 255]);
+        assert_eq!(material_png(&assets, "missing"), None);
+    }
+}
@@ -0,0 +1,489 @@
+//! Wavefront OBJ and MTL export of compiled maps, for tools that take nothing else
+//!
+//! OBJ has no units or up axis, so positions stay in Source's Z up space, multiplied by [`ObjOptions::scale`].
+
+use std::{
+    fmt::Write,
+    fs,
+    io::{Read, Seek},
+    path::Path,
+};
+
+use glam::{Mat3, Vec2, Vec3};
+
+use super::{material_png, model_name, static_props, write_file, ExportAssets};
+use crate::{
+    bsp::gamelump::GameLump,
+    meshes::{build_model_meshes, MeshBuildOptions},
+    prelude::*,
+};
+
+/// Directory beside the `.obj` that textures are saved in
+const TEXTURE_DIR: &str = "textures";
+
+/// How the faces of an export are split up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjGrouping {
+    /// A group for each material, with every brush model and prop using it
+    Material,
+    /// An object for each brush model and static prop, changing material within it
+    BrushModel,
+}
+
+/// What to include in an export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjOptions {
+    /// Which faces of the world and brush models to export. Displacements are included unless excluded by flags.
+    pub meshes: MeshBuildOptions,
+    pub grouping: ObjGrouping,
+    /// Multiplier for positions, such as 0.0254 for metres, as map units are inches
+    pub scale: f32,
+    /// Add static props, using the triangles of their models
+    pub static_props: bool,
+    /// Decode base textures to PNGs for the materials to use
+    pub textures: bool,
+}
+
+impl Default for ObjOptions {
+    fn default() -> Self {
+        Self {
+            meshes: MeshBuildOptions::default(),
+            grouping: ObjGrouping::Material,
+            scale: 1.0,
+            static_props: false,
+            textures: true,
+        }
+    }
+}
+
+/// The text of an OBJ and its MTL, and the textures the MTL refers to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Obj {
+    /// OBJ text, without the `mtllib` line naming the MTL, which depends on where it is saved
+    pub obj: String,
+    pub mtl: String,
+    /// PNGs by path relative to the MTL
+    pub textures: Vec<(String, Vec<u8>)>,
+}
+
+/// Vertices of a brush model's mesh or a prop, already placed in the world
+struct Vertices {
+    positions: Vec<Vec3>,
+    /// With `v` going up the texture, as OBJ expects
+    uvs: Vec<Vec2>,
+    /// Only props have normals. Faces of the map are flat, so tools can work them out.
+    normals: Vec<Vec3>,
+}
+
+/// Triangles of one material in one object
+struct Piece {
+    object: String,
+    material: String,
+    /// Index into [`Pieces::vertices`], shared by every part of a prop
+    vertices: usize,
+    indices: Vec<u32>,
+}
+
+/// Everything to export, with vertices kept apart so they are written once however many pieces use them
+#[derive(Default)]
+struct Pieces {
+    vertices: Vec<Vertices>,
+    pieces: Vec<Piece>,
+}
+
+impl Pieces {
+    /// Add vertices and a piece for each material's triangles over them
+    fn add(
+        &mut self,
+        object: &str,
+        vertices: Vertices,
+        parts: impl IntoIterator<Item = (String, Vec<u32>)>,
+    ) {
+        self.vertices.push(vertices);
+        let index = self.vertices.len() - 1;
+        self.pieces
+            .extend(parts.into_iter().map(|(material, indices)| Piece {
+                object: object.to_owned(),
+                material,
+                vertices: index,
+                indices,
+            }));
+    }
+}
+
+impl Obj {
+    /// Export a map, finding its materials and prop models in `assets`
+    pub fn from_map<R: Read + Seek>(
+        bsp: &Bsp<R>,
+        assets: &impl ExportAssets,
+        options: &ObjOptions,
+    ) -> SourceResult<Self> {
+        let mut pieces = Pieces::default();
+        map_pieces(&mut pieces, bsp, &options.meshes)?;
+        if options.static_props {
+            if let Some(game_lump) = bsp.static_prop_lump()? {
+                prop_pieces(&mut pieces, game_lump, assets);
+            }
+        }
+        Ok(Self::from_pieces(pieces, assets, options))
+    }
+
+    /// Save the OBJ at `path`, with its MTL beside it and textures in a directory beside that
+    pub fn save(&self, path: &Path) -> SourceResult<()> {
+        let write = |path: &Path, contents: &[u8]| write_file("OBJ", path, contents);
+
+        let mtl_path = path.with_extension("mtl");
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if !self.textures.is_empty() {
+            let texture_dir = dir.join(TEXTURE_DIR);
+            fs::create_dir_all(&texture_dir)
+                .map_err(|e| SourceError::io("OBJ", 0, e).in_file(&texture_dir))?;
+        }
+        for (name, png) in &self.textures {
+            write(&dir.join(name), png)?;
+        }
+        write(&mtl_path, self.mtl.as_bytes())?;
+
+        let mtl_name = mtl_path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        write(path, format!("mtllib {mtl_name}\n{}", self.obj).as_bytes())
+    }
+
+    fn from_pieces(
+        Pieces {
+            vertices,
+            mut pieces,
+        }: Pieces,
+        assets: &impl ExportAssets,
+        options: &ObjOptions,
+    ) -> Self {
+        if options.grouping == ObjGrouping::Material {
+            // Stable, so objects stay in order within each material
+            pieces.sort_by(|a, b| a.material.cmp(&b.material));
+        }
+
+        let mut obj = String::new();
+        // Only props write normals, so they are counted apart from positions and uvs
+        let (mut next_vertex, mut next_normal) = (1, 1);
+        // Index of the first vertex and normal of each list written so far
+        let mut first_vertices = vec![None; vertices.len()];
+        let mut group = None;
+        let mut material = None;
+        for piece in &pieces {
+            let piece_group = match options.grouping {
+                ObjGrouping::Material => &piece.material,
+                ObjGrouping::BrushModel => &piece.object,
+            };
+            if group != Some(piece_group) {
+                let keyword = match options.grouping {
+                    ObjGrouping::Material => "g",
+                    ObjGrouping::BrushModel => "o",
+                };
+                writeln!(obj, "{keyword} {}", obj_name(piece_group)).unwrap();
+                group = Some(piece_group);
+                material = None;
+            }
+            if material != Some(&piece.material) {
+                writeln!(obj, "usemtl {}", obj_name(&piece.material)).unwrap();
+                material = Some(&piece.material);
+            }
+
+            let verts = &vertices[piece.vertices];
+            let (first_vertex, first_normal) =
+                *first_vertices[piece.vertices].get_or_insert_with(|| {
+                    let first = (next_vertex, next_normal);
+                    for p in &verts.positions {
+                        let p = *p * options.scale;
+                        writeln!(obj, "v {} {} {}", p.x, p.y, p.z).unwrap();
+                    }
+                    for uv in &verts.uvs {
+                        writeln!(obj, "vt {} {}", uv.x, uv.y).unwrap();
+                    }
+                    for n in &verts.normals {
+                        writeln!(obj, "vn {} {} {}", n.x, n.y, n.z).unwrap();
+                    }
+                    next_vertex += verts.positions.len();
+                    next_normal += verts.normals.len();
+                    first
+                });
+
+            // Positions, uvs and normals of a list all line up, so each corner uses one offset into each
+            let has_normals = !verts.normals.is_empty();
+            for tri in piece.indices.chunks_exact(3) {
+                obj.push('f');
+                for &i in tri {
+                    let (v, n) = (first_vertex + i as usize, first_normal + i as usize);
+                    if has_normals {
+                        write!(obj, " {v}/{v}/{n}").unwrap();
+                    } else {
+                        write!(obj, " {v}/{v}").unwrap();
+                    }
+                }
+                obj.push('\n');
+            }
+        }
+
+        let mut materials: Vec<&str> = pieces.iter().map(|piece| piece.material.as_str()).collect();
+        materials.sort_unstable();
+        materials.dedup();
+
+        let mut mtl = String::new();
+        let mut textures = Vec::new();
+        for material in materials {
+            writeln!(mtl, "newmtl {}\nKd 1 1 1", obj_name(material)).unwrap();
+            if let Some(png) = options
+                .textures
+                .then(|| material_png(assets, material))
+                .flatten()
+            {
+                let path = format!("{TEXTURE_DIR}/{}.png", obj_name(material).replace('/', "_"));
+                writeln!(mtl, "map_Kd {path}").unwrap();
+                textures.push((path, png));
+            }
+            mtl.push('\n');
+        }
+
+        Self { obj, mtl, textures }
+    }
+}
+
+/// Names in OBJ and MTL files end at whitespace
+fn obj_name(name: &str) -> String {
+    name.to_ascii_lowercase().replace(char::is_whitespace, "_")
+}
+
+/// A piece for each material of each brush model, moved to where its entity places it
+fn map_pieces<R: Read + Seek>(
+    pieces: &mut Pieces,
+    bsp: &Bsp<R>,
+    options: &MeshBuildOptions,
+) -> SourceResult<()> {
+    let entities = bsp.entities()?;
+
+    for model in build_model_meshes(bsp, options)? {
+        let object = model_name(entities, &model);
+
+        let mut meshes: Vec<_> = model.meshes.meshes.iter().collect();
+        meshes.sort_by_key(|(&tex_data, _)| tex_data);
+        for (&tex_data, mesh) in meshes {
+            let vertices = Vertices {
+                positions: mesh
+                    .verts()
+                    .iter()
+                    .map(|v| model.transform.transform_point3(v.position))
+                    .collect(),
+                uvs: mesh.verts().iter().map(|v| flip_v(v.uv)).collect(),
+                normals: Vec::new(),
+            };
+            let material = bsp.texture_name(tex_data as usize)?.to_owned();
+            pieces.add(&object, vertices, [(material, mesh.tris().to_vec())]);
+        }
+    }
+    Ok(())
+}
+
+/// A piece for each material of each static prop whose model can be loaded, all sharing the prop's vertices
+fn prop_pieces(pieces: &mut Pieces, game_lump: &GameLump, assets: &impl ExportAssets) {
+    for prop in static_props(game_lump, assets) {
+        let Some(mesh) = &prop.mesh else {
+            continue;
+        };
+
+        let rotation = Mat3::from_mat4(prop.transform);
+        let vertices = Vertices {
+            positions: mesh
+                .positions
+                .iter()
+                .map(|&p| prop.transform.transform_point3(p))
+                .collect(),
+            uvs: mesh.uvs.iter().map(|&uv| flip_v(uv)).collect(),
+            normals: mesh.normals.iter().map(|&n| rotation * n).collect(),
+        };
+        let parts = mesh
+            .parts
+            .iter()
+            .map(|part| (part.material.clone(), part.indices.clone()));
+        pieces.add(
+            &format!("prop {} {}", prop.index, prop.model),
+            vertices,
+            parts,
+        );
+    }
+}
+
+/// Textures go down in Source and up in OBJ
+fn flip_v(uv: Vec2) -> Vec2 {
+    Vec2::new(uv.x, 1.0 - uv.y)
+}
+
+#[cfg(test)]
+mod obj_tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::{
+        bsp::test_map::TestMap,
+        export::export_tests::{prop_lump, TestAssets},
+    };
+
+    const MATERIAL: &str = "dev/dev_measuregeneric01";
+
+    /// The quad as the world, and a copy of it as a `func_door` 32 units up
+    fn door_map() -> Bsp<std::io::Cursor<Vec<u8>>> {
+        let map = TestMap::quad();
+        let mut faces = map.faces();
+        faces.push(faces[0]);
+
+        let mut models = TestMap::model_bytes(Vec3::ZERO, vec3(64.0, 64.0, 0.0), 0, 0, 1);
+        models.extend(TestMap::model_bytes(
+            Vec3::ZERO,
+            vec3(64.0, 64.0, 0.0),
+            0,
+            1,
+            1,
+        ));
+        let entities = "{\n\"classname\" \"worldspawn\"\n}\n\
+            {\n\"classname\" \"func_door\"\n\"model\" \"*1\"\n\"origin\" \"0 0 32\"\n}\n\0";
+
+        Bsp::new(
+            map.with_lump(LumpType::Faces, &faces)
+                .with_bytes(LumpType::Models, models)
+                .with_bytes(LumpType::Entities, entities.as_bytes().to_vec())
+                .reader(),
+        )
+        .unwrap()
+    }
+
+    fn lines<'a>(text: &'a str, keyword: &str) -> Vec<&'a str> {
+        text.lines()
+            .filter(|line| line.split(' ').next() == Some(keyword))
+            .collect()
+    }
+
+    #[test]
+    fn materials() {
+        let assets = TestAssets::default().with_material(MATERIAL, [0, 255, 0, 255]);
+        let options = ObjOptions {
+            scale: 0.5,
+            ..Default::default()
+        };
+        let obj = Obj::from_map(&door_map(), &assets, &options).unwrap();
+
+        // Both models share the material, so are in one group
+        assert_eq!(lines(&obj.obj, "g"), [format!("g {MATERIAL}")]);
+        assert_eq!(lines(&obj.obj, "usemtl").len(), 1);
+        assert_eq!(lines(&obj.obj, "v").len(), 8);
+        assert!(obj.obj.contains("v 32 32 0\n"));
+        assert!(obj.obj.contains("v 32 32 16\n"));
+        // The quad's texture covers it once, upside down in OBJ's uvs
+        assert!(obj.obj.contains("vt 1 0\n"));
+
+        let faces = lines(&obj.obj, "f");
+        assert_eq!(faces.len(), 6);
+        assert!(faces[3].split(' ').skip(1).all(|corner| {
+            let index: usize = corner.split('/').next().unwrap().parse().unwrap();
+            (5..=8).contains(&index)
+        }));
+
+        let texture = format!("{TEXTURE_DIR}/dev_dev_measuregeneric01.png");
+        assert_eq!(
+            obj.mtl,
+            format!("newmtl {MATERIAL}\nKd 1 1 1\nmap_Kd {texture}\n\n")
+        );
+        assert_eq!(obj.textures.len(), 1);
+        assert_eq!(obj.textures[0].0, texture);
+    }
+
+    #[test]
+    fn brush_models() {
+        let options = ObjOptions {
+            grouping: ObjGrouping::BrushModel,
+            textures: false,
+            ..Default::default()
+        };
+        let obj = Obj::from_map(&door_map(), &TestAssets::default(), &options).unwrap();
+
+        assert_eq!(lines(&obj.obj, "o"), ["o worldspawn", "o func_door_*1"]);
+        assert_eq!(lines(&obj.obj, "usemtl").len(), 2);
+        assert_eq!(obj.mtl, format!("newmtl {MATERIAL}\nKd 1 1 1\n\n"));
+        assert!(obj.textures.is_empty());
+    }
+
+    #[test]
+    fn static_props() {
+        let assets = TestAssets::default().with_crate();
+        let turned = vec3(0.0, 90.0, 0.0);
+        let game_lump = prop_lump(&[(0, vec3(0.0, 0.0, 10.0), turned), (1, Vec3::ZERO, turned)]);
+
+        let mut pieces = Pieces::default();
+        prop_pieces(&mut pieces, &game_lump, &assets);
+        assert_eq!(pieces.pieces.len(), 2);
+        assert_eq!(pieces.pieces[0].object, "prop 0 models/crate.mdl");
+        assert_eq!(pieces.vertices.len(), 1);
+        let vertices = &pieces.vertices[0];
+        assert!(vertices.positions[1].abs_diff_eq(vec3(0.0, 1.0, 10.0), 1e-5));
+        assert!(vertices.normals[0].abs_diff_eq(Vec3::Y, 1e-5));
+        assert_eq!(vertices.uvs[0], Vec2::Y);
+
+        let options = ObjOptions {
+            grouping: ObjGrouping::BrushModel,
+            ..Default::default()
+        };
+        let obj = Obj::from_pieces(pieces, &assets, &options);
+        assert_eq!(lines(&obj.obj, "o"), ["o prop_0_models/crate.mdl"]);
+        // Both sides use the prop's one set of vertices
+        assert_eq!(lines(&obj.obj, "v").len(), 3);
+        assert_eq!(
+            lines(&obj.obj, "usemtl"),
+            ["usemtl models/crate", "usemtl models/crate_back"]
+        );
+        assert_eq!(
+            lines(&obj.obj, "f"),
+            ["f 1/1/1 2/2/2 3/3/3", "f 1/1/1 3/3/3 2/2/2"]
+        );
+    }
+
+    #[test]
+    fn world_and_props() {
+        let assets = TestAssets::default().with_crate();
+        let game_lump = prop_lump(&[(0, Vec3::ZERO, Vec3::ZERO)]);
+
+        let mut pieces = Pieces::default();
+        map_pieces(&mut pieces, &door_map(), &MeshBuildOptions::default()).unwrap();
+        prop_pieces(&mut pieces, &game_lump, &assets);
+        let obj = Obj::from_pieces(pieces, &assets, &ObjOptions::default());
+
+        let positions = lines(&obj.obj, "v").len();
+        let normals = lines(&obj.obj, "vn").len();
+        assert_eq!(normals, 3);
+        // The prop's vertices come after the map's, but its normals are the first written
+        let (a, b, c) = (positions - 2, positions - 1, positions);
+        let faces = lines(&obj.obj, "f");
+        assert_eq!(
+            faces[faces.len() - 2..],
+            [
+                format!("f {a}/{a}/1 {b}/{b}/2 {c}/{c}/3"),
+                format!("f {a}/{a}/1 {c}/{c}/3 {b}/{b}/2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_props() {
+        let map = TestMap::quad().with_static_props(6, &["models/crate.mdl"], &[], 0);
+        let bsp = Bsp::new(map.reader()).unwrap();
+        let assets = TestAssets::default().with_crate();
+
+        let options = ObjOptions {
+            static_props: true,
+            ..Default::default()
+        };
+
+        // The map is exported without its props
+        let obj = Obj::from_map(&bsp, &assets, &options).unwrap();
+        assert_eq!(lines(&obj.obj, "v").len(), 4);
+        assert!(lines(&obj.obj, "vn").is_empty());
+    }
+}
@@ -1,7 +1,10 @@
-use common::{vfile::VFileSystem, vpath::VPath};
+use common::{
+    vfile::VFileSystem,
+    vpath::{VLocalPath, VPath},
+};
+use glam::{uvec2, UVec2};
 use ini::Ini;
 use std::{
-    io::{self, BufReader, Cursor},
     path::{Path, PathBuf},
     sync::{Arc, OnceLock},
     time::Instant,
@@ -37,7 +40,16 @@ impl GameData {
         None
     }
 
-    pub fn load<'a, T: BinaryData + 'a, F: Fn(&'a VPKFile) -> &'a OnceLock<io::Result<Arc<T>>>>(
+    /// Size of the base texture of `material`, for turning texture projections into uvs
+    pub fn material_size(&self, material: &str) -> Option<UVec2> {
+        let material = material.to_ascii_lowercase().replace('\\', "/");
+        let vmt = self.load_vmt(&VLocalPath::new("materials", &material, "vmt"))?;
+        let texture = vmt.get_basetex()?.replace('\\', "/");
+        let vtf = self.load_vtf(&VLocalPath::new("materials", &texture, "vtf"))?;
+        Some(uvec2(vtf.width(), vtf.height()))
+    }
+
+    pub fn load<'a, T: BinaryData + 'a, F: Fn(&'a VPKFile) -> &'a OnceLock<SourceResult<Arc<T>>>>(
         &'a self,
         path: &dyn VPath,
         get_cell: F,
@@ -71,9 +83,10 @@ impl GameData {
         for vpk in game.get_all("vpk") {
             println!("{vpk}");
 
-            dirs.push(Arc::new(
-                VPKDirectory::load(Default::default(), path.join(vpk)).unwrap(),
-            ));
+            match VPKDirectory::load(Default::default(), path.join(vpk)) {
+                Ok(dir) => dirs.push(Arc::new(dir)),
+                Err(e) => log::error!("Skipping VPK: {}", e),
+            }
         }
 
         println!("Took {:?}", now.elapsed());
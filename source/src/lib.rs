@@ -1,5 +1,7 @@
 pub  mod binaries;
 pub mod bsp;
+pub mod error;
+pub mod export;
 pub mod game_data;
 pub mod prelude;
 pub mod studio;
@@ -7,3 +9,7 @@ pub mod vmt;
 pub mod vpk;
 pub mod vtf;
 pub mod meshes;
+pub mod nav;
+pub mod compile_output;
+pub mod vmf;
+pub mod skybox;
@@ -1,46 +1,63 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::{Read, Seek},
+};
 
 use common::vertex::{UVAlphaVertex, UVVertex, Vertex};
-use glam::{ivec3, vec2, IVec3, Vec3, Vec4};
+use flagset::FlagSet;
+use glam::{ivec3, vec2, IVec3, Mat4, Vec3, Vec4};
 
-use crate::prelude::*;
+use crate::{bsp::consts::SurfaceFlags, prelude::*};
 
 
+/// Vertices and 32 bit triangle indices for one mesh.
+///
+/// Vertices are shared within a face: adding the same map vertex again before the next
+/// [`MeshBuilder::start_face`] returns the index of the existing copy.
 #[derive(Default)]
 pub struct MeshBuilder<V: Vertex + Default> {
-    tris: Vec<u16>,
-    //tri_map: HashMap<u16, u16>,
+    tris: Vec<u32>,
+    /// Map vertex index to index in `verts`, for the current face
+    tri_map: HashMap<u32, u32>,
     verts: Vec<V>,
 }
 impl<V: Vertex + Default> MeshBuilder<V> {
-    pub fn push_tri(&mut self) {
-        for i in 0..3u16 {
-            self.tris.push(self.verts.len() as u16 + i - 3);
-        }
+    /// Stop sharing vertices with the previous face, as its uvs will be different
+    pub fn start_face(&mut self) {
+        self.tri_map.clear();
     }
-    pub fn add_tri(&mut self, tri: [u16; 3]) {
-        for i in 0..3 {
-            self.tris.push(tri[i]);
-        }
+
+    pub fn add_tri(&mut self, tri: [u32; 3]) {
+        self.tris.extend_from_slice(&tri);
     }
 
-    pub fn tris_to_lines(&self) -> Vec<u16> {
-        let mut lines: Vec<u16> = Default::default();
+    pub fn tris_to_lines(&self) -> Vec<u32> {
+        let mut lines: Vec<u32> = Default::default();
 
-        for i in (0..self.tris.len()).step_by(3) {
-            lines.push(self.tris[i]);
-            lines.push(self.tris[i + 1]);
+        for tri in self.tris.chunks_exact(3) {
+            lines.push(tri[0]);
+            lines.push(tri[1]);
 
-            lines.push(self.tris[i + 1]);
-            lines.push(self.tris[i + 2]);
+            lines.push(tri[1]);
+            lines.push(tri[2]);
 
-            lines.push(self.tris[i + 2]);
-            lines.push(self.tris[i + 3]);
+            lines.push(tri[2]);
+            lines.push(tri[0]);
         }
 
         lines
     }
-	pub fn tris(&self) -> &[u16] {
+
+    /// Index of map vertex `index` on the current face, creating it if this is its first use
+    fn shared_vert(&mut self, index: u32, vertex: impl FnOnce() -> V) -> u32 {
+        let verts = &mut self.verts;
+        *self.tri_map.entry(index).or_insert_with(|| {
+            verts.push(vertex());
+            (verts.len() - 1) as u32
+        })
+    }
+
+	pub fn tris(&self) -> &[u32] {
 		&self.tris
 	}
 	
@@ -49,20 +66,18 @@ impl<V: Vertex + Default> MeshBuilder<V> {
 		}
 }
 impl MeshBuilder<UVAlphaVertex> {
-    pub fn add_vert_a(&mut self, _index: u16, vertex: Vec3, s: Vec4, t: Vec4, alpha: f32) {
-        //if !self.tri_map.contains_key(&index) {
-        // if not contained, add in and generate uvs
-        let u = s.dot(Vec4::from((vertex, 1.0)));
-        let v = t.dot(Vec4::from((vertex, 1.0)));
-
-        //self.tri_map.insert(index, self.verts.len() as u16);
-
-        self.verts.push(UVAlphaVertex {
-            position: vertex,
-            uv: vec2(u, v),
-            alpha,
-        });
-        //}
+    pub fn add_vert_a(&mut self, index: u32, vertex: Vec3, s: Vec4, t: Vec4, alpha: f32) -> u32 {
+        self.shared_vert(index, || {
+            // if not contained, add in and generate uvs
+            let u = s.dot(Vec4::from((vertex, 1.0)));
+            let v = t.dot(Vec4::from((vertex, 1.0)));
+
+            UVAlphaVertex {
+                position: vertex,
+                uv: vec2(u, v),
+                alpha,
+            }
+        })
     }
 	
 
@@ -71,7 +86,7 @@ impl MeshBuilder<UVAlphaVertex> {
 impl MeshBuilder<UVVertex> {
     pub fn add_vert(
         &mut self,
-        _index: u16,
+        index: u32,
         vertex: Vec3,
         tex_s: Vec4,
         tex_t: Vec4,
@@ -79,31 +94,253 @@ impl MeshBuilder<UVVertex> {
         lightmap_t: Vec4,
         alpha: f32,
         color: IVec3,
-    ) {
-        //if !self.tri_map.contains_key(&index) {
-        // if not contained, add in and generate uvs
-        let tex_u = tex_s.dot(Vec4::from((vertex, 1.0)));
-        let tex_v = tex_t.dot(Vec4::from((vertex, 1.0)));
- 
-
-        let env_u = lightmap_s.dot(Vec4::from((vertex, 1.0)));
-        let env_v = lightmap_t.dot(Vec4::from((vertex, 1.0)));
-
-        //self.tri_map.insert(index, self.verts.len() as u16);
-
-        self.verts.push(UVVertex {
-            position: vertex,
-            uv: vec2(tex_u, tex_v),
-            lightmap_uv: vec2(env_u, env_v),
-            alpha,
-            color,
-        });
-        //}
+    ) -> u32 {
+        self.shared_vert(index, || {
+            // if not contained, add in and generate uvs
+            let tex_u = tex_s.dot(Vec4::from((vertex, 1.0)));
+            let tex_v = tex_t.dot(Vec4::from((vertex, 1.0)));
+
+            let env_u = lightmap_s.dot(Vec4::from((vertex, 1.0)));
+            let env_v = lightmap_t.dot(Vec4::from((vertex, 1.0)));
+
+            UVVertex {
+                position: vertex,
+                uv: vec2(tex_u, tex_v),
+                lightmap_uv: vec2(env_u, env_v),
+                alpha,
+                color,
+            }
+        })
+    }
+
+    /// Move every vertex by `transform`, such as to place a brush model or the 3D skybox in the world
+    pub fn transform(&mut self, transform: Mat4) {
+        for vert in &mut self.verts {
+            vert.position = transform.transform_point3(vert.position);
+        }
+    }
+}
+
+/// Which faces to turn into meshes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshBuildOptions {
+    /// Faces with any of these surface flags are skipped
+    pub exclude: FlagSet<SurfaceFlags>,
+    /// Build faces without lightmap data. Their vertices get a light index of -1, to be drawn fully lit
+    pub include_unlit: bool,
+}
+
+impl Default for MeshBuildOptions {
+    /// Everything the engine would draw with the world
+    fn default() -> Self {
+        Self {
+            exclude: SurfaceFlags::SKY
+                | SurfaceFlags::SKY2D
+                | SurfaceFlags::NODRAW
+                | SurfaceFlags::TRIGGER
+                | SurfaceFlags::HINT
+                | SurfaceFlags::SKIP,
+            include_unlit: true,
+        }
+    }
+}
+
+impl MeshBuildOptions {
+    /// Build every face, whatever its flags
+    pub fn all() -> Self {
+        Self {
+            exclude: FlagSet::default(),
+            include_unlit: true,
+        }
+    }
+
+    pub fn sky(self, include: bool) -> Self {
+        self.with(SurfaceFlags::SKY | SurfaceFlags::SKY2D, include)
+    }
+
+    pub fn nodraw(self, include: bool) -> Self {
+        self.with(SurfaceFlags::NODRAW.into(), include)
+    }
+
+    pub fn trigger(self, include: bool) -> Self {
+        self.with(SurfaceFlags::TRIGGER.into(), include)
+    }
+
+    pub fn hint_skip(self, include: bool) -> Self {
+        self.with(SurfaceFlags::HINT | SurfaceFlags::SKIP, include)
+    }
+
+    pub fn translucent(self, include: bool) -> Self {
+        self.with(SurfaceFlags::TRANS.into(), include)
+    }
+
+    pub fn warp(self, include: bool) -> Self {
+        self.with(SurfaceFlags::WARP.into(), include)
+    }
+
+    pub fn unlit(mut self, include: bool) -> Self {
+        self.include_unlit = include;
+        self
+    }
+
+    fn with(mut self, flags: FlagSet<SurfaceFlags>, include: bool) -> Self {
+        if include {
+            self.exclude -= flags;
+        } else {
+            self.exclude |= flags;
+        }
+        self
+    }
+}
+
+/// Why a face was left out of the meshes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The face has a surface flag in [`MeshBuildOptions::exclude`]
+    Flag(SurfaceFlags),
+    /// The face has no lightmap data, and [`MeshBuildOptions::include_unlit`] is off
+    Unlit,
+    /// The face has no tex info, as with faces of tool textures such as clip
+    NoTexInfo,
+    /// The face's lighting offset is not a multiple of 4 bytes, the size of a lightmap sample
+    MisalignedLighting,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkippedFace {
+    /// Index into the faces lump
+    pub face: usize,
+    pub reason: SkipReason,
+}
+
+/// Meshes for a set of faces
+#[derive(Default)]
+pub struct FaceMeshes {
+    /// Meshes keyed by tex data index
+    pub meshes: HashMap<i32, MeshBuilder<UVVertex>>,
+    /// Faces that were left out, and why
+    pub skipped: Vec<SkippedFace>,
+}
+
+/// The faces of one brush model, and the entity that places it in the world
+pub struct BrushModelMeshes {
+    /// Index into the models lump. Model 0 is the world
+    pub model: usize,
+    /// Index into [`Bsp::entities`] of the entity using this model, such as `worldspawn` or a `func_door`
+    pub entity: Option<usize>,
+    /// Model to world transform, from the entity's origin and angles
+    pub transform: Mat4,
+    pub meshes: FaceMeshes,
+}
+
+/// The lumps faces are built from
+struct FaceLumps<'a> {
+    faces: &'a [BSPFace],
+    verts: &'a [Vec3],
+    disp_verts: &'a [BSPDispVert],
+    tex_info: &'a [BSPTexInfo],
+    tex_data: &'a [BSPTexData],
+    infos: &'a [BSPDispInfo],
+    edges: &'a [BSPEdge],
+    surf_edges: &'a [BSPSurfEdge],
+}
+
+impl<'a> FaceLumps<'a> {
+    fn load<R: Read + Seek>(bsp: &'a Bsp<R>) -> SourceResult<Self> {
+        Ok(Self {
+            faces: bsp.faces()?,
+            verts: bsp.vertices()?,
+            disp_verts: bsp.disp_verts()?,
+            tex_info: bsp.tex_info()?,
+            tex_data: bsp.tex_data()?,
+            infos: bsp.disp_infos()?,
+            edges: bsp.edges()?,
+            surf_edges: bsp.surf_edges()?,
+        })
     }
 }
 
+/// Build meshes for every face in the map, keeping only the surfaces `options` asks for
+pub fn build_meshes_with_options<R: Read + Seek>(
+    bsp: &Bsp<R>,
+    options: &MeshBuildOptions,
+) -> SourceResult<FaceMeshes> {
+    let lumps = FaceLumps::load(bsp)?;
+    Ok(build_face_meshes(&lumps, 0..lumps.faces.len(), options))
+}
+
+/// Build meshes for a chosen set of faces, such as those in the leafs of the 3D skybox
+pub fn build_meshes_for_faces<R: Read + Seek>(
+    bsp: &Bsp<R>,
+    faces: &[usize],
+    options: &MeshBuildOptions,
+) -> SourceResult<FaceMeshes> {
+    let lumps = FaceLumps::load(bsp)?;
+    if let Some(&face) = faces.iter().find(|&&face| face >= lumps.faces.len()) {
+        return Err(SourceError::NotFound(format!("Face {face}")));
+    }
+    Ok(build_face_meshes(&lumps, faces.iter().copied(), options))
+}
+
+/// Build meshes for every brush model separately, so brush entities such as doors can be moved independently of the world
+pub fn build_model_meshes<R: Read + Seek>(
+    bsp: &Bsp<R>,
+    options: &MeshBuildOptions,
+) -> SourceResult<Vec<BrushModelMeshes>> {
+    let lumps = FaceLumps::load(bsp)?;
+    let faces = lumps.faces;
+    let models = bsp.models()?;
+    let entities = bsp.entities()?;
+
+    // Link models to the first entity that references them
+    let mut model_entities = vec![None; models.len()];
+    for (i_entity, entity) in entities.iter().enumerate() {
+        let model = if entity.classname() == Some("worldspawn") {
+            Some(0)
+        } else {
+            entity.brush_model()
+        };
+
+        if let Some(slot) = model.and_then(|model| model_entities.get_mut(model)) {
+            slot.get_or_insert(i_entity);
+        }
+    }
+
+    models
+        .iter()
+        .enumerate()
+        .map(|(i_model, model)| {
+            let first_face = model.first_face();
+            let num_faces = model.num_faces();
+
+            let face_range = usize::try_from(first_face)
+                .ok()
+                .zip(usize::try_from(num_faces).ok())
+                .map(|(first, num)| first..first + num)
+                .filter(|range| range.end <= faces.len())
+                .ok_or_else(|| {
+                    SourceError::invalid(
+                        "BSPModel",
+                        0,
+                        format!("Model {i_model} faces {first_face}+{num_faces} out of range"),
+                    )
+                })?;
+
+            let entity = model_entities[i_model];
+
+            Ok(BrushModelMeshes {
+                model: i_model,
+                entity,
+                transform: entity.map_or(Mat4::IDENTITY, |e| entities[e].transform()),
+                meshes: build_face_meshes(&lumps, face_range, options),
+            })
+        })
+        .collect()
+}
+
+/// Build meshes for every face, with the default [`MeshBuildOptions`]
 pub fn build_meshes(
-    faces: Box<[BSPFace]>,
+    faces: &[BSPFace],
     verts: &[Vec3],
     disp_verts: &[BSPDispVert],
     tex_info: &[BSPTexInfo],
@@ -112,10 +349,74 @@ pub fn build_meshes(
     edges: &[BSPEdge],
     surf_edges: &[BSPSurfEdge],
 ) -> HashMap<i32, MeshBuilder<UVVertex>> {
+    let lumps = FaceLumps {
+        faces,
+        verts,
+        disp_verts,
+        tex_info,
+        tex_data,
+        infos,
+        edges,
+        surf_edges,
+    };
+    build_face_meshes(&lumps, 0..faces.len(), &MeshBuildOptions::default()).meshes
+}
+
+/// Build meshes for the faces at `face_indices`, which must all be in range of the faces lump
+fn build_face_meshes(
+    lumps: &FaceLumps,
+    face_indices: impl IntoIterator<Item = usize>,
+    options: &MeshBuildOptions,
+) -> FaceMeshes {
+    let FaceLumps {
+        faces,
+        verts,
+        disp_verts,
+        tex_info,
+        tex_data,
+        infos,
+        edges,
+        surf_edges,
+    } = *lumps;
+
     let mut textured_tris = HashMap::<i32, MeshBuilder<UVVertex>>::new();
+    let mut skipped = Vec::new();
+
+    for i_face in face_indices {
+        let face = &faces[i_face];
+        let Some(&tex) = usize::try_from(face.tex_info)
+            .ok()
+            .and_then(|i| tex_info.get(i))
+        else {
+            skipped.push(SkippedFace {
+                face: i_face,
+                reason: SkipReason::NoTexInfo,
+            });
+            continue;
+        };
+
+        if let Some(flag) = (tex.surface_flags() & options.exclude).into_iter().next() {
+            skipped.push(SkippedFace {
+                face: i_face,
+                reason: SkipReason::Flag(flag),
+            });
+            continue;
+        }
+        if face.light_ofs == -1 && !options.include_unlit {
+            skipped.push(SkippedFace {
+                face: i_face,
+                reason: SkipReason::Unlit,
+            });
+            continue;
+        }
+        if face.light_ofs != -1 && face.light_ofs % 4 != 0 {
+            skipped.push(SkippedFace {
+                face: i_face,
+                reason: SkipReason::MisalignedLighting,
+            });
+            continue;
+        }
 
-    for (i_face, face) in faces.iter().enumerate() {
-        let tex = tex_info[face.tex_info as usize];
         let i_texdata = tex.tex_data;
         let data = tex_data[i_texdata as usize];
 
@@ -139,13 +440,12 @@ pub fn build_meshes(
         let lightmap_s = tex.lightmap_s;
         let lightmap_t = tex.lightmap_t;
 
-        if face.light_ofs == -1 {
-            continue;
-        }
         // light_ofs is a byte offset, and these are 4 byte structures
-        assert_eq!(face.light_ofs % 4, 0);
-
-        let light_base_index = face.light_ofs as usize / 4;
+        let light_base_index = if face.light_ofs == -1 {
+            -1
+        } else {
+            face.light_ofs / 4
+        };
 
         // Ensure we have the data
         //let Some(lighting) = lighting.get(light_base_index) else {
@@ -156,11 +456,13 @@ pub fn build_meshes(
         let lightmap_texture_size_in_luxels = face.lightmap_texture_size_in_luxels + 1;
 
         let light_data = ivec3(
-            light_base_index as i32,
+            light_base_index,
             lightmap_texture_size_in_luxels.x,
             0,
         );
 
+        builder.start_face();
+
         if face.disp_info != -1 {
             // This is a displacement
 
@@ -183,7 +485,7 @@ pub fn build_meshes(
 
             let get_i = |x: usize, y: usize| -> usize { x + disp_side_len * y };
 
-            let old_vert_count = builder.verts.len() as u16;
+            let old_vert_count = builder.verts.len() as u32;
 
             for y in 0..disp_side_len {
                 let dy = y as f32 / (disp_side_len as f32 - 1.0);
@@ -200,19 +502,23 @@ pub fn build_meshes(
 
                     let pos = vert.vec + Vec3::lerp(v0, v1, dx);
 
+                    // The face's lightmap is stretched over the grid rather than projected, so the
+                    // lightmap vectors are constants giving each vertex its luxel
+                    let luxel = vec2(dx, dy) * (lightmap_texture_size_in_luxels - 1).as_vec2();
+
                     builder.add_vert(
-                        i as u16,
+                        i as u32,
                         pos,
                         tex_s,
                         tex_t,
-                        lightmap_s.into(),
-                        lightmap_t.into(),
+                        Vec4::W * luxel.x,
+                        Vec4::W * luxel.y,
                         vert.alpha,
                         light_data,
                     );
                 }
             }
-            let disp_side_len = disp_side_len as u16;
+            let disp_side_len = disp_side_len as u32;
 
             // Build grid index buffer.
             for y in 0..(disp_side_len - 1) {
@@ -228,36 +534,212 @@ pub fn build_meshes(
             let root_edge_index = face.first_edge as usize;
             let root_edge = surf_edges[root_edge_index].get_edge(edges);
 
+            // The lightmapVecs float array performs a similar mapping of the lightmap samples of the
+            // texture onto the world. It is the same formula but with lightmapVecs instead of textureVecs,
+            // and then subtracting the [0] and [1] values of LightmapTextureMinsInLuxels for u and v respectively.
+            // LightmapTextureMinsInLuxels is referenced in dface_t;
+            let lightmap_s =
+                Vec4::from(lightmap_s) - Vec4::W * lightmap_texture_mins_in_luxels.x as f32;
+            let lightmap_t =
+                Vec4::from(lightmap_t) - Vec4::W * lightmap_texture_mins_in_luxels.y as f32;
+
             for i in 1..(face.num_edges as usize) {
                 let edge = surf_edges[root_edge_index + i].get_edge(edges);
 
-                let tri = [edge.0, root_edge.0, edge.1];
-                for i in tri {
-                    let l = builder.verts.len();
+                // Fan triangles share the root vertex and each edge with their neighbours
+                let tri = [edge.0, root_edge.0, edge.1].map(|i| {
                     builder.add_vert(
-                        i,
+                        i as u32,
                         verts[i as usize],
                         tex_s,
                         tex_t,
-                        lightmap_s.into(),
-                        lightmap_t.into(),
+                        lightmap_s,
+                        lightmap_t,
                         1.0,
                         light_data,
-                    );
-                    let v = &mut builder.verts[l];
-
-                    // The lightmapVecs float array performs a similar mapping of the lightmap samples of the
-                    // texture onto the world. It is the same formula but with lightmapVecs instead of textureVecs,
-                    // and then subtracting the [0] and [1] values of LightmapTextureMinsInLuxels for u and v respectively.
-                    // LightmapTextureMinsInLuxels is referenced in dface_t;
-
-                    v.lightmap_uv -= lightmap_texture_mins_in_luxels.as_vec2();
-                    //v.lightmap_uv /= lightmap_texture_size_in_luxels.as_vec2();
-                }
-                builder.push_tri();
+                    )
+                });
+                builder.add_tri(tri);
             }
         }
     }
-    textured_tris
+    FaceMeshes {
+        meshes: textured_tris,
+        skipped,
+    }
 }
 
+
+#[cfg(test)]
+mod meshes_tests {
+    use glam::vec3;
+
+    use crate::bsp::test_map::TestMap;
+
+    use super::*;
+
+    #[test]
+    fn shared_face_verts() {
+        let map = TestMap::quad();
+        let mut faces = map.faces();
+        faces[0].light_ofs = 0;
+        let bsp = Bsp::new(map.with_lump(LumpType::Faces, &faces).reader()).unwrap();
+
+        let meshes = build_meshes(
+            bsp.faces().unwrap(),
+            bsp.vertices().unwrap(),
+            &[],
+            bsp.tex_info().unwrap(),
+            bsp.tex_data().unwrap(),
+            &[],
+            bsp.edges().unwrap(),
+            bsp.surf_edges().unwrap(),
+        );
+        let builder = &meshes[&0];
+
+        // One vertex per corner, rather than three per triangle
+        assert_eq!(builder.verts().len(), 4);
+        assert_eq!(builder.tris().len(), 9);
+        assert!(builder.tris().iter().all(|&i| i < 4));
+    }
+
+    #[test]
+    fn model_meshes() {
+        let map = TestMap::quad();
+        let mut faces = map.faces();
+        faces[0].light_ofs = 0;
+        faces.push(faces[0]);
+
+        let mut models = TestMap::model_bytes(Vec3::ZERO, vec3(64.0, 64.0, 0.0), 0, 0, 1);
+        models.extend(TestMap::model_bytes(
+            Vec3::ZERO,
+            vec3(64.0, 64.0, 0.0),
+            0,
+            1,
+            1,
+        ));
+        // An unused model
+        models.extend(TestMap::model_bytes(Vec3::ZERO, Vec3::ZERO, 0, 2, 0));
+
+        let entities = "{\n\"classname\" \"worldspawn\"\n}\n\
+            {\n\"classname\" \"func_door\"\n\"model\" \"*1\"\n\"origin\" \"0 0 32\"\n}\n\0";
+
+        let bsp = Bsp::new(
+            map.with_lump(LumpType::Faces, &faces)
+                .with_bytes(LumpType::Models, models)
+                .with_bytes(LumpType::Entities, entities.as_bytes().to_vec())
+                .reader(),
+        )
+        .unwrap();
+
+        let meshes = build_model_meshes(&bsp, &MeshBuildOptions::default()).unwrap();
+        assert_eq!(meshes.len(), 3);
+
+        assert_eq!(meshes[0].entity, Some(0));
+        assert_eq!(meshes[0].transform, Mat4::IDENTITY);
+        assert_eq!(meshes[0].meshes.meshes[&0].verts().len(), 4);
+
+        assert_eq!(meshes[1].entity, Some(1));
+        assert_eq!(
+            meshes[1].transform,
+            Mat4::from_translation(vec3(0.0, 0.0, 32.0))
+        );
+        assert_eq!(meshes[1].meshes.meshes[&0].verts().len(), 4);
+
+        assert_eq!(meshes[2].entity, None);
+        assert!(meshes[2].meshes.meshes.is_empty());
+    }
+
+    #[test]
+    fn surface_options() {
+        let map = TestMap::quad();
+        let bsp = Bsp::new(map.reader()).unwrap();
+
+        let mut tex_info = bsp.tex_info().unwrap().to_vec();
+        tex_info.push(tex_info[0]);
+        tex_info[1].flags = FlagSet::from(SurfaceFlags::NODRAW).bits();
+
+        let quad = map.faces()[0];
+        let mut faces = [quad; 5];
+        faces[0].light_ofs = 0;
+        faces[1].light_ofs = 0;
+        faces[1].tex_info = 1;
+        faces[2].tex_info = -1;
+        faces[4].light_ofs = 6;
+
+        let bsp = Bsp::new(
+            map.with_lump(LumpType::TexInfo, &tex_info)
+                .with_lump(LumpType::Faces, &faces)
+                .reader(),
+        )
+        .unwrap();
+
+        let built = build_meshes_with_options(&bsp, &MeshBuildOptions::default()).unwrap();
+        assert_eq!(
+            built.skipped,
+            [
+                SkippedFace {
+                    face: 1,
+                    reason: SkipReason::Flag(SurfaceFlags::NODRAW)
+                },
+                SkippedFace {
+                    face: 2,
+                    reason: SkipReason::NoTexInfo
+                },
+                SkippedFace {
+                    face: 4,
+                    reason: SkipReason::MisalignedLighting
+                },
+            ]
+        );
+        // The lit face and the unlit one
+        assert_eq!(built.meshes[&0].verts().len(), 8);
+        assert_eq!(built.meshes[&0].verts()[4].color.x, -1);
+
+        let options = MeshBuildOptions::default().nodraw(true).unlit(false);
+        let built = build_meshes_with_options(&bsp, &options).unwrap();
+        assert_eq!(
+            built.skipped.iter().map(|s| s.reason).collect::<Vec<_>>(),
+            [
+                SkipReason::NoTexInfo,
+                SkipReason::Unlit,
+                SkipReason::MisalignedLighting
+            ]
+        );
+        assert_eq!(built.meshes[&0].verts().len(), 8);
+    }
+
+    #[test]
+    fn chosen_faces() {
+        let bsp = Bsp::new(TestMap::two_areas().reader()).unwrap();
+        let options = MeshBuildOptions::all();
+
+        let mut built = build_meshes_for_faces(&bsp, &[1], &options).unwrap();
+        let builder = built.meshes.get_mut(&0).unwrap();
+        assert_eq!(builder.verts().len(), 4);
+        assert_eq!(builder.verts()[0].position, vec3(1024.0, 64.0, 0.0));
+
+        builder.transform(Mat4::from_translation(vec3(-1024.0, 0.0, 8.0)));
+        assert_eq!(builder.verts()[0].position, vec3(0.0, 64.0, 8.0));
+
+        assert!(matches!(
+            build_meshes_for_faces(&bsp, &[2], &options),
+            Err(SourceError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn large_indices() {
+        let mut builder = MeshBuilder::<UVAlphaVertex>::default();
+        for i in 0..70_000 {
+            builder.add_vert_a(i, Vec3::ZERO, Vec4::X, Vec4::Y, 1.0);
+        }
+        builder.add_tri([0, 69_998, 69_999]);
+
+        assert_eq!(builder.verts().len(), 70_000);
+        assert_eq!(
+            builder.tris_to_lines(),
+            [0, 69_998, 69_998, 69_999, 69_999, 0]
+        );
+    }
+}
@@ -0,0 +1,944 @@
+//! Navigation meshes, the `.nav` files bots and NPCs walk on.
+//!
+//! A nav mesh is a graph of rectangular areas lying on the walkable surfaces of a map, joined by connections in
+//! the four compass directions, with ladders between areas that are joined vertically.
+//!
+//! https://developer.valvesoftware.com/wiki/NAV_(file_format)
+
+pub mod path;
+
+use std::{
+    collections::HashMap,
+    io::{Read, Seek},
+    path::Path,
+};
+
+use common::vpath::VLocalPath;
+use flagset::{flags, FlagSet};
+use glam::{vec3, Vec3};
+
+use crate::{
+    bsp::Bsp,
+    error::{SourceError, SourceResult},
+};
+
+pub const NAV_MAGIC: u32 = 0xFEEDFACE;
+/// Oldest and newest versions that can be read
+pub const MIN_NAV_VERSION: u32 = 3;
+pub const MAX_NAV_VERSION: u32 = 16;
+/// Teams with their own earliest occupy times
+pub const MAX_NAV_TEAMS: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NavDirection {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl NavDirection {
+    pub const ALL: [Self; 4] = [Self::North, Self::East, Self::South, Self::West];
+
+    fn from_index(index: u32) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    pub fn opposite(self) -> Self {
+        Self::ALL[(self as usize + 2) % 4]
+    }
+}
+
+flags! {
+    /// `NavAttributeType`, from nav.h
+    pub enum NavAttributes: u32 {
+        CROUCH = 0x1,
+        JUMP = 0x2,
+        PRECISE = 0x4,
+        NoJump = 0x8,
+        STOP = 0x10,
+        RUN = 0x20,
+        WALK = 0x40,
+        AVOID = 0x80,
+        TRANSIENT = 0x100,
+        DontHide = 0x200,
+        STAND = 0x400,
+        NoHostages = 0x800,
+        STAIRS = 0x1000,
+        NoMerge = 0x2000,
+        ObstacleTop = 0x4000,
+        CLIFF = 0x8000,
+    }
+}
+
+flags! {
+    pub enum HidingSpotFlags: u8 {
+        InCover = 0x1,
+        GoodSniperSpot = 0x2,
+        IdealSniperSpot = 0x4,
+        EXPOSED = 0x8,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HidingSpot {
+    pub id: u32,
+    pub position: Vec3,
+    pub flags: FlagSet<HidingSpotFlags>,
+}
+
+/// A spot along an encounter path, where a bot walking it can see enemies hiding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpotOrder {
+    /// ID of a hiding spot
+    pub spot: u32,
+    /// How far along the path the spot comes into view, from 0 to 1
+    pub t: f32,
+}
+
+/// A route through an area, from one neighbour to another, and the hiding spots seen along it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncounterPath {
+    /// ID of the area the path enters from
+    pub from: u32,
+    pub from_direction: NavDirection,
+    /// ID of the area the path leaves to
+    pub to: u32,
+    pub to_direction: NavDirection,
+    pub spots: Vec<SpotOrder>,
+}
+
+/// A way of reaching an area, stored by versions before 15
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApproachArea {
+    pub here: u32,
+    pub prev: u32,
+    pub prev_to_here: u8,
+    pub next: u32,
+    pub here_to_next: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    NotVisible,
+    PotentiallyVisible,
+    CompletelyVisible,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisibleArea {
+    pub area: u32,
+    pub visibility: Visibility,
+}
+
+/// One walkable rectangle of the mesh. It is flat along x and y, but each corner can be at a different height.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavArea {
+    pub id: u32,
+    pub attributes: FlagSet<NavAttributes>,
+    /// The corner with the smallest x and y
+    pub north_west: Vec3,
+    /// The corner with the largest x and y
+    pub south_east: Vec3,
+    pub north_east_z: f32,
+    pub south_west_z: f32,
+    /// IDs of the areas reachable from each side, in [`NavDirection::ALL`] order
+    pub connections: [Vec<u32>; 4],
+    pub hiding_spots: Vec<HidingSpot>,
+    pub approach_areas: Vec<ApproachArea>,
+    pub encounter_paths: Vec<EncounterPath>,
+    /// Index into [`NavMesh::places`] plus one, or 0 for no place
+    pub place: u16,
+    /// IDs of the ladders going up and down from this area
+    pub ladders_up: Vec<u32>,
+    pub ladders_down: Vec<u32>,
+    pub earliest_occupy_times: [f32; MAX_NAV_TEAMS],
+    /// Light at each corner, in [`NavArea::corners`] order
+    pub light_intensity: [f32; 4],
+    pub visible_areas: Vec<VisibleArea>,
+    /// ID of the area this one shares its visibility with, or 0
+    pub inherit_visibility_from: u32,
+}
+
+impl NavArea {
+    /// North west, north east, south east then south west
+    pub fn corners(&self) -> [Vec3; 4] {
+        let (nw, se) = (self.north_west, self.south_east);
+        [
+            nw,
+            vec3(se.x, nw.y, self.north_east_z),
+            se,
+            vec3(nw.x, se.y, self.south_west_z),
+        ]
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.corners().iter().sum::<Vec3>() / 4.0
+    }
+
+    /// Whether `(x, y)` is over the area, ignoring height
+    pub fn contains_xy(&self, x: f32, y: f32) -> bool {
+        (self.north_west.x..=self.south_east.x).contains(&x)
+            && (self.north_west.y..=self.south_east.y).contains(&y)
+    }
+
+    /// Height of the area at `(x, y)`, interpolated between the corners
+    pub fn z_at(&self, x: f32, y: f32) -> f32 {
+        let size = (self.south_east - self.north_west).truncate();
+        let u = if size.x > 0.0 {
+            ((x - self.north_west.x) / size.x).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let v = if size.y > 0.0 {
+            ((y - self.north_west.y) / size.y).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let north = self.north_west.z + (self.north_east_z - self.north_west.z) * u;
+        let south = self.south_west_z + (self.south_east.z - self.south_west_z) * u;
+        north + (south - north) * v
+    }
+
+    /// Every connection, with the side of this area it leaves from
+    pub fn connections(&self) -> impl Iterator<Item = (NavDirection, u32)> + '_ {
+        NavDirection::ALL
+            .iter()
+            .zip(&self.connections)
+            .flat_map(|(&dir, ids)| ids.iter().map(move |&id| (dir, id)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavLadder {
+    pub id: u32,
+    pub width: f32,
+    pub top: Vec3,
+    pub bottom: Vec3,
+    pub length: f32,
+    /// The direction a player faces to climb it
+    pub direction: NavDirection,
+    /// IDs of the areas at the top in front, to the left, to the right and behind, and at the bottom. 0 if none.
+    pub top_forward_area: u32,
+    pub top_left_area: u32,
+    pub top_right_area: u32,
+    pub top_behind_area: u32,
+    pub bottom_area: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavMesh {
+    pub version: u32,
+    /// Version of the game specific data, from version 10
+    pub sub_version: u32,
+    /// Size of the BSP the mesh was built for, so the game can tell when it is out of date
+    pub bsp_size: u32,
+    pub is_analyzed: bool,
+    /// Names of the places areas can be in, such as `BombsiteA`
+    pub places: Vec<String>,
+    pub has_unnamed_areas: bool,
+    pub areas: Vec<NavArea>,
+    pub ladders: Vec<NavLadder>,
+    /// Indices into `areas` by ID
+    area_indices: HashMap<u32, usize>,
+}
+
+impl NavMesh {
+    /// Parse a `.nav` file. Game specific data after the ladders is ignored.
+    ///
+    /// Games with a non zero sub version can store their own data in every area, which only the game knows the size of.
+    /// Those files must be read with [`NavMesh::read_with_area_data`].
+    pub fn read(data: &[u8]) -> SourceResult<Self> {
+        Self::read_inner(data, None)
+    }
+
+    /// Parse a `.nav` file, skipping `bytes` of game specific data at the end of every area
+    pub fn read_with_area_data(data: &[u8], bytes: usize) -> SourceResult<Self> {
+        Self::read_inner(data, Some(bytes))
+    }
+
+    fn read_inner(data: &[u8], area_data: Option<usize>) -> SourceResult<Self> {
+        let mut r = NavReader { data, pos: 0 };
+
+        let magic = r.u32()?;
+        if magic != NAV_MAGIC {
+            return Err(SourceError::invalid(
+                "NavMesh",
+                0,
+                format!("Bad magic {magic:#X}"),
+            ));
+        }
+
+        let version = r.u32()?;
+        if !(MIN_NAV_VERSION..=MAX_NAV_VERSION).contains(&version) {
+            return Err(SourceError::UnsupportedVersion {
+                structure: "NavMesh",
+                version: version.to_string(),
+            });
+        }
+
+        let sub_version = if version >= 10 { r.u32()? } else { 0 };
+        let area_data = match area_data {
+            Some(bytes) => bytes,
+            None if sub_version == 0 => 0,
+            None => {
+                return Err(SourceError::UnsupportedVersion {
+                    structure: "NavMesh",
+                    version: format!("{version} with game data version {sub_version}"),
+                })
+            }
+        };
+        let bsp_size = if version >= 4 { r.u32()? } else { 0 };
+        let is_analyzed = version >= 14 && r.u8()? != 0;
+
+        let mut places = Vec::new();
+        let mut has_unnamed_areas = false;
+        if version >= 5 {
+            for _ in 0..r.u16()? {
+                let len = r.u16()? as usize;
+                let name = r.bytes(len)?;
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                places.push(String::from_utf8_lossy(name).into_owned());
+            }
+            has_unnamed_areas = version > 11 && r.u8()? != 0;
+        }
+
+        let areas = (0..r.u32()?)
+            .map(|_| {
+                let area = r.area(version)?;
+                r.bytes(area_data)?;
+                Ok(area)
+            })
+            .collect::<SourceResult<Vec<_>>>()?;
+
+        let ladders = if version >= 6 {
+            (0..r.u32()?)
+                .map(|_| r.ladder(version))
+                .collect::<SourceResult<Vec<_>>>()?
+        } else {
+            Vec::new()
+        };
+
+        let mut mesh = Self {
+            version,
+            sub_version,
+            bsp_size,
+            is_analyzed,
+            places,
+            has_unnamed_areas,
+            areas,
+            ladders,
+            area_indices: HashMap::new(),
+        };
+        mesh.index_areas();
+        Ok(mesh)
+    }
+
+    /// A mesh of the newest version, for building meshes in code
+    pub fn from_areas(areas: Vec<NavArea>, ladders: Vec<NavLadder>) -> Self {
+        let mut mesh = Self {
+            version: MAX_NAV_VERSION,
+            sub_version: 0,
+            bsp_size: 0,
+            is_analyzed: false,
+            places: Vec::new(),
+            has_unnamed_areas: false,
+            areas,
+            ladders,
+            area_indices: HashMap::new(),
+        };
+        mesh.index_areas();
+        mesh
+    }
+
+    /// Rebuild the lookup from area IDs, after changing `areas`
+    pub fn index_areas(&mut self) {
+        self.area_indices = self
+            .areas
+            .iter()
+            .enumerate()
+            .map(|(i, area)| (area.id, i))
+            .collect();
+    }
+
+    pub fn load(path: &Path) -> SourceResult<Self> {
+        let data =
+            std::fs::read(path).map_err(|e| SourceError::io("NavMesh", 0, e).in_file(path))?;
+        Self::read(&data).map_err(|e| e.in_file(path))
+    }
+
+    /// Load the nav mesh for a map, from `mapname.nav` next to the BSP or else from its pakfile
+    pub fn load_for_map<R: Read + Seek>(bsp: &Bsp<R>) -> SourceResult<Self> {
+        let name = bsp
+            .path()
+            .and_then(Path::file_stem)
+            .map(|stem| stem.to_string_lossy());
+
+        if let Some(path) = bsp.path().map(|path| path.with_extension("nav")) {
+            if path.is_file() {
+                return Self::load(&path);
+            }
+        }
+
+        let pak = bsp.pak()?;
+        // Without a file name, use whatever nav mesh the pakfile has
+        let name = match name {
+            Some(name) => name.into_owned(),
+            None => pak
+                .files
+                .get("nav")
+                .and_then(|dirs| dirs.get("maps"))
+                .and_then(|files| files.keys().next())
+                .cloned()
+                .ok_or_else(|| SourceError::NotFound("maps/*.nav".to_owned()))?,
+        };
+
+        let bytes = pak.file_bytes(&VLocalPath::new("maps", &name, "nav"))?;
+        Self::read(&bytes)
+    }
+
+    /// Index into `areas` of the area with this ID
+    pub fn area_index(&self, id: u32) -> Option<usize> {
+        match self.area_indices.get(&id) {
+            Some(&i) if self.areas.get(i).is_some_and(|area| area.id == id) => Some(i),
+            // Areas have changed since they were indexed
+            _ => self.areas.iter().position(|area| area.id == id),
+        }
+    }
+
+    pub fn area(&self, id: u32) -> Option<&NavArea> {
+        self.area_index(id).map(|i| &self.areas[i])
+    }
+
+    pub fn ladder(&self, id: u32) -> Option<&NavLadder> {
+        self.ladders.iter().find(|ladder| ladder.id == id)
+    }
+
+    pub fn place_name(&self, area: &NavArea) -> Option<&str> {
+        self.places
+            .get((area.place as usize).checked_sub(1)?)
+            .map(String::as_str)
+    }
+
+    /// Indices of the areas connected to the area at `index`. Connections to missing areas are skipped.
+    pub fn neighbours(&self, index: usize) -> impl Iterator<Item = (NavDirection, usize)> + '_ {
+        self.areas[index]
+            .connections()
+            .filter_map(|(dir, id)| Some((dir, self.area_index(id)?)))
+    }
+}
+
+/// Reads little endian values, tracking the offset for errors
+struct NavReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NavReader<'a> {
+    fn bytes(&mut self, len: usize) -> SourceResult<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or_else(|| {
+            SourceError::invalid("NavMesh", self.pos as u64, "Unexpected end of file")
+        })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> SourceResult<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> SourceResult<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> SourceResult<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> SourceResult<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> SourceResult<f32> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn vec3(&mut self) -> SourceResult<Vec3> {
+        Ok(vec3(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn ids(&mut self, count: usize) -> SourceResult<Vec<u32>> {
+        (0..count).map(|_| self.u32()).collect()
+    }
+
+    fn direction(&mut self, index: u32) -> SourceResult<NavDirection> {
+        NavDirection::from_index(index).ok_or_else(|| {
+            SourceError::invalid(
+                "NavMesh",
+                self.pos as u64,
+                format!("Invalid direction {index}"),
+            )
+        })
+    }
+
+    fn area(&mut self, version: u32) -> SourceResult<NavArea> {
+        let id = self.u32()?;
+        let attributes = match version {
+            ..=8 => self.u8()? as u32,
+            9..=12 => self.u16()? as u32,
+            _ => self.u32()?,
+        };
+        let north_west = self.vec3()?;
+        let south_east = self.vec3()?;
+        let north_east_z = self.f32()?;
+        let south_west_z = self.f32()?;
+
+        let mut connections: [Vec<u32>; 4] = Default::default();
+        for ids in &mut connections {
+            let count = self.u32()? as usize;
+            *ids = self.ids(count)?;
+        }
+
+        let hiding_spots = (0..self.u8()?)
+            .map(|_| {
+                Ok(HidingSpot {
+                    id: self.u32()?,
+                    position: self.vec3()?,
+                    flags: FlagSet::new_truncated(self.u8()?),
+                })
+            })
+            .collect::<SourceResult<_>>()?;
+
+        let approach_areas = if version < 15 {
+            (0..self.u8()?)
+                .map(|_| {
+                    Ok(ApproachArea {
+                        here: self.u32()?,
+                        prev: self.u32()?,
+                        prev_to_here: self.u8()?,
+                        next: self.u32()?,
+                        here_to_next: self.u8()?,
+                    })
+                })
+                .collect::<SourceResult<_>>()?
+        } else {
+            Vec::new()
+        };
+
+        let encounter_paths = (0..self.u32()?)
+            .map(|_| {
+                let from = self.u32()?;
+                let from_direction = self.u8()?;
+                let from_direction = self.direction(from_direction as u32)?;
+                let to = self.u32()?;
+                let to_direction = self.u8()?;
+                let to_direction = self.direction(to_direction as u32)?;
+                let spots = (0..self.u8()?)
+                    .map(|_| {
+                        Ok(SpotOrder {
+                            spot: self.u32()?,
+                            t: self.u8()? as f32 / 255.0,
+                        })
+                    })
+                    .collect::<SourceResult<_>>()?;
+                Ok(EncounterPath {
+                    from,
+                    from_direction,
+                    to,
+                    to_direction,
+                    spots,
+                })
+            })
+            .collect::<SourceResult<_>>()?;
+
+        let place = if version >= 5 { self.u16()? } else { 0 };
+
+        let (mut ladders_up, mut ladders_down) = (Vec::new(), Vec::new());
+        if version >= 7 {
+            let count = self.u32()? as usize;
+            ladders_up = self.ids(count)?;
+            let count = self.u32()? as usize;
+            ladders_down = self.ids(count)?;
+        }
+
+        let mut earliest_occupy_times = [0.0; MAX_NAV_TEAMS];
+        if version >= 8 {
+            for time in &mut earliest_occupy_times {
+                *time = self.f32()?;
+            }
+        }
+
+        let mut light_intensity = [1.0; 4];
+        if version >= 11 {
+            for light in &mut light_intensity {
+                *light = self.f32()?;
+            }
+        }
+
+        let (mut visible_areas, mut inherit_visibility_from) = (Vec::new(), 0);
+        if version >= 16 {
+            visible_areas = (0..self.u32()?)
+                .map(|_| {
+                    let area = self.u32()?;
+                    let visibility = match self.u8()? {
+                        0 => Visibility::NotVisible,
+                        1 => Visibility::PotentiallyVisible,
+                        _ => Visibility::CompletelyVisible,
+                    };
+                    Ok(VisibleArea { area, visibility })
+                })
+                .collect::<SourceResult<_>>()?;
+            inherit_visibility_from = self.u32()?;
+        }
+
+        Ok(NavArea {
+            id,
+            attributes: FlagSet::new_truncated(attributes),
+            north_west,
+            south_east,
+            north_east_z,
+            south_west_z,
+            connections,
+            hiding_spots,
+            approach_areas,
+            encounter_paths,
+            place,
+            ladders_up,
+            ladders_down,
+            earliest_occupy_times,
+            light_intensity,
+            visible_areas,
+            inherit_visibility_from,
+        })
+    }
+
+    fn ladder(&mut self, version: u32) -> SourceResult<NavLadder> {
+        let id = self.u32()?;
+        let width = self.f32()?;
+        let top = self.vec3()?;
+        let bottom = self.vec3()?;
+        let length = self.f32()?;
+        let direction = self.u32()?;
+        let direction = self.direction(direction)?;
+        if version == 6 {
+            // Whether the ladder is dangling, dropped in later versions
+            self.u8()?;
+        }
+
+        Ok(NavLadder {
+            id,
+            width,
+            top,
+            bottom,
+            length,
+            direction,
+            top_forward_area: self.u32()?,
+            top_left_area: self.u32()?,
+            top_right_area: self.u32()?,
+            top_behind_area: self.u32()?,
+            bottom_area: self.u32()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod nav_tests {
+    use super::*;
+
+    /// Writes a mesh out in the layout of its version, the reverse of [`NavReader`]
+    fn write(mesh: &NavMesh, area_data: &[u8]) -> Vec<u8> {
+        let version = mesh.version;
+        let mut out = Vec::new();
+        let u32 = |out: &mut Vec<u8>, v: u32| out.extend_from_slice(&v.to_le_bytes());
+        let f32 = |out: &mut Vec<u8>, v: f32| out.extend_from_slice(&v.to_le_bytes());
+        let vec3 = |out: &mut Vec<u8>, v: Vec3| v.to_array().iter().for_each(|&f| f32(out, f));
+        let ids = |out: &mut Vec<u8>, ids: &[u32]| {
+            u32(out, ids.len() as u32);
+            ids.iter().for_each(|&id| u32(out, id));
+        };
+
+        u32(&mut out, NAV_MAGIC);
+        u32(&mut out, version);
+        if version >= 10 {
+            u32(&mut out, mesh.sub_version);
+        }
+        if version >= 4 {
+            u32(&mut out, mesh.bsp_size);
+        }
+        if version >= 14 {
+            out.push(mesh.is_analyzed as u8);
+        }
+        if version >= 5 {
+            out.extend_from_slice(&(mesh.places.len() as u16).to_le_bytes());
+            for place in &mesh.places {
+                out.extend_from_slice(&(place.len() as u16 + 1).to_le_bytes());
+                out.extend_from_slice(place.as_bytes());
+                out.push(0);
+            }
+            if version > 11 {
+                out.push(mesh.has_unnamed_areas as u8);
+            }
+        }
+
+        u32(&mut out, mesh.areas.len() as u32);
+        for area in &mesh.areas {
+            u32(&mut out, area.id);
+            let attributes = area.attributes.bits();
+            match version {
+                ..=8 => out.push(attributes as u8),
+                9..=12 => out.extend_from_slice(&(attributes as u16).to_le_bytes()),
+                _ => u32(&mut out, attributes),
+            }
+            vec3(&mut out, area.north_west);
+            vec3(&mut out, area.south_east);
+            f32(&mut out, area.north_east_z);
+            f32(&mut out, area.south_west_z);
+            for connections in &area.connections {
+                ids(&mut out, connections);
+            }
+
+            out.push(area.hiding_spots.len() as u8);
+            for spot in &area.hiding_spots {
+                u32(&mut out, spot.id);
+                vec3(&mut out, spot.position);
+                out.push(spot.flags.bits());
+            }
+            if version < 15 {
+                out.push(area.approach_areas.len() as u8);
+                for approach in &area.approach_areas {
+                    u32(&mut out, approach.here);
+                    u32(&mut out, approach.prev);
+                    out.push(approach.prev_to_here);
+                    u32(&mut out, approach.next);
+                    out.push(approach.here_to_next);
+                }
+            }
+
+            u32(&mut out, area.encounter_paths.len() as u32);
+            for path in &area.encounter_paths {
+                u32(&mut out, path.from);
+                out.push(path.from_direction as u8);
+                u32(&mut out, path.to);
+                out.push(path.to_direction as u8);
+                out.push(path.spots.len() as u8);
+                for spot in &path.spots {
+                    u32(&mut out, spot.spot);
+                    out.push((spot.t * 255.0).round() as u8);
+                }
+            }
+
+            if version >= 5 {
+                out.extend_from_slice(&area.place.to_le_bytes());
+            }
+            if version >= 7 {
+                ids(&mut out, &area.ladders_up);
+                ids(&mut out, &area.ladders_down);
+            }
+            if version >= 8 {
+                area.earliest_occupy_times
+                    .iter()
+                    .for_each(|&t| f32(&mut out, t));
+            }
+            if version >= 11 {
+                area.light_intensity.iter().for_each(|&l| f32(&mut out, l));
+            }
+            if version >= 16 {
+                u32(&mut out, area.visible_areas.len() as u32);
+                for visible in &area.visible_areas {
+                    u32(&mut out, visible.area);
+                    out.push(visible.visibility as u8);
+                }
+                u32(&mut out, area.inherit_visibility_from);
+            }
+            out.extend_from_slice(area_data);
+        }
+
+        if version >= 6 {
+            u32(&mut out, mesh.ladders.len() as u32);
+            for ladder in &mesh.ladders {
+                u32(&mut out, ladder.id);
+                f32(&mut out, ladder.width);
+                vec3(&mut out, ladder.top);
+                vec3(&mut out, ladder.bottom);
+                f32(&mut out, ladder.length);
+                u32(&mut out, ladder.direction as u32);
+                if version == 6 {
+                    out.push(0);
+                }
+                for id in [
+                    ladder.top_forward_area,
+                    ladder.top_left_area,
+                    ladder.top_right_area,
+                    ladder.top_behind_area,
+                    ladder.bottom_area,
+                ] {
+                    u32(&mut out, id);
+                }
+            }
+        }
+        out
+    }
+
+    pub(crate) fn area(id: u32, north_west: Vec3, south_east: Vec3) -> NavArea {
+        NavArea {
+            id,
+            attributes: FlagSet::default(),
+            north_west,
+            south_east,
+            north_east_z: north_west.z,
+            south_west_z: south_east.z,
+            connections: Default::default(),
+            hiding_spots: Vec::new(),
+            approach_areas: Vec::new(),
+            encounter_paths: Vec::new(),
+            place: 0,
+            ladders_up: Vec::new(),
+            ladders_down: Vec::new(),
+            earliest_occupy_times: [0.0; MAX_NAV_TEAMS],
+            light_intensity: [1.0; 4],
+            visible_areas: Vec::new(),
+            inherit_visibility_from: 0,
+        }
+    }
+
+    /// Two areas side by side along x joined both ways, and a ladder up from the second
+    fn mesh(version: u32) -> NavMesh {
+        let mut first = area(1, Vec3::ZERO, vec3(100.0, 100.0, 0.0));
+        first.attributes = NavAttributes::CROUCH | NavAttributes::JUMP;
+        first.connections[NavDirection::East as usize] = vec![2];
+        first.hiding_spots.push(HidingSpot {
+            id: 7,
+            position: vec3(10.0, 10.0, 0.0),
+            flags: HidingSpotFlags::InCover | HidingSpotFlags::EXPOSED,
+        });
+        first.encounter_paths.push(EncounterPath {
+            from: 2,
+            from_direction: NavDirection::East,
+            to: 2,
+            to_direction: NavDirection::East,
+            spots: vec![SpotOrder { spot: 7, t: 1.0 }],
+        });
+        first.place = 1;
+
+        let mut second = area(2, vec3(100.0, 0.0, 0.0), vec3(200.0, 100.0, 16.0));
+        second.connections[NavDirection::West as usize] = vec![1];
+
+        if version >= 7 {
+            second.ladders_up = vec![3];
+        }
+        if version >= 8 {
+            second.earliest_occupy_times = [2.5, 4.0];
+        }
+        if version >= 11 {
+            second.light_intensity = [0.5, 0.25, 1.0, 0.0];
+        }
+        if version >= 16 {
+            first.visible_areas.push(VisibleArea {
+                area: 2,
+                visibility: Visibility::CompletelyVisible,
+            });
+            second.inherit_visibility_from = 1;
+        } else if version < 15 {
+            second.approach_areas.push(ApproachArea {
+                here: 2,
+                prev: 1,
+                prev_to_here: 1,
+                next: 0,
+                here_to_next: 0,
+            });
+        }
+
+        let ladder = NavLadder {
+            id: 3,
+            width: 32.0,
+            top: vec3(200.0, 50.0, 128.0),
+            bottom: vec3(200.0, 50.0, 16.0),
+            length: 112.0,
+            direction: NavDirection::West,
+            top_forward_area: 0,
+            top_left_area: 0,
+            top_right_area: 0,
+            top_behind_area: 0,
+            bottom_area: 2,
+        };
+
+        let mut mesh = NavMesh::from_areas(vec![first, second], vec![ladder]);
+        mesh.version = version;
+        mesh.bsp_size = 1234;
+        mesh.is_analyzed = version >= 14;
+        mesh.places = vec!["Courtyard".to_owned()];
+        mesh.has_unnamed_areas = version > 11;
+        mesh
+    }
+
+    #[test]
+    fn read() {
+        for version in [6, 9, 12, 15, MAX_NAV_VERSION] {
+            let mesh = mesh(version);
+            let read = NavMesh::read(&write(&mesh, &[])).unwrap();
+            assert_eq!(read, mesh, "version {version}");
+        }
+
+        let mesh = NavMesh::read(&write(&mesh(MAX_NAV_VERSION), &[])).unwrap();
+        assert_eq!(mesh.place_name(&mesh.areas[0]), Some("Courtyard"));
+        assert_eq!(mesh.place_name(&mesh.areas[1]), None);
+        assert_eq!(mesh.area(2).unwrap().south_east.z, 16.0);
+        assert_eq!(mesh.ladder(3).unwrap().bottom_area, 2);
+        assert_eq!(
+            mesh.neighbours(0).collect::<Vec<_>>(),
+            [(NavDirection::East, 1)]
+        );
+    }
+
+    #[test]
+    fn area_shape() {
+        let mut area = area(1, Vec3::ZERO, vec3(100.0, 50.0, 10.0));
+        area.north_east_z = 10.0;
+        area.south_west_z = 0.0;
+
+        assert_eq!(area.corners()[1], vec3(100.0, 0.0, 10.0));
+        assert_eq!(area.center(), vec3(50.0, 25.0, 5.0));
+        assert!(area.contains_xy(100.0, 0.0));
+        assert!(!area.contains_xy(50.0, 51.0));
+        assert_eq!(area.z_at(50.0, 25.0), 5.0);
+        assert_eq!(area.z_at(100.0, 50.0), 10.0);
+    }
+
+    #[test]
+    fn game_data() {
+        let mut mesh = mesh(MAX_NAV_VERSION);
+        mesh.sub_version = 1;
+        let bytes = write(&mesh, &[0xAB; 4]);
+
+        assert!(matches!(
+            NavMesh::read(&bytes),
+            Err(SourceError::UnsupportedVersion { .. })
+        ));
+        assert_eq!(NavMesh::read_with_area_data(&bytes, 4).unwrap(), mesh);
+    }
+
+    #[test]
+    fn errors() {
+        let bytes = write(&mesh(MAX_NAV_VERSION), &[]);
+
+        let mut bad = bytes.clone();
+        bad[0] = 0;
+        assert!(matches!(
+            NavMesh::read(&bad),
+            Err(SourceError::Invalid { offset: 0, .. })
+        ));
+
+        let mut bad = bytes.clone();
+        bad[4] = MAX_NAV_VERSION as u8 + 1;
+        assert!(matches!(
+            NavMesh::read(&bad),
+            Err(SourceError::UnsupportedVersion { .. })
+        ));
+
+        assert!(matches!(
+            NavMesh::read(&bytes[..bytes.len() - 1]),
+            Err(SourceError::Invalid { .. })
+        ));
+    }
+}
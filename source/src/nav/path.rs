@@ -0,0 +1,657 @@
+//! Routes across a nav mesh, found with A* over its areas.
+//!
+//! Paths come back as plain points, so they can be checked in tests or drawn without the game.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use glam::{vec3, Vec2, Vec3};
+
+use super::{NavArea, NavAttributes, NavDirection, NavMesh};
+
+/// Height a player can step up without jumping
+pub const STEP_HEIGHT: f32 = 18.0;
+
+/// How expensive each way of moving is, compared to walking the same distance.
+///
+/// Multipliers below 1 make the search prefer that movement, but may no longer find the cheapest path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathCosts {
+    /// Multiplier on the distance travelled into crouch areas
+    pub crouch: f32,
+    /// Added for every jump, into a jump area or up more than [`STEP_HEIGHT`]
+    pub jump: f32,
+    /// Multiplier on the length of every ladder climbed
+    pub ladder: f32,
+}
+
+impl Default for PathCosts {
+    fn default() -> Self {
+        Self {
+            crouch: 3.0,
+            jump: 100.0,
+            ladder: 2.0,
+        }
+    }
+}
+
+/// How a point on a path is reached from the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NavTraverse {
+    Walk,
+    Crouch,
+    Jump,
+    Ladder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathPoint {
+    pub position: Vec3,
+    /// Index into [`NavMesh::areas`] of the area this point is in
+    pub area: usize,
+    pub traverse: NavTraverse,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavPath {
+    /// From the start to the goal, both moved onto their areas
+    pub points: Vec<PathPoint>,
+    /// Total cost, as weighted by the [`PathCosts`] the path was found with
+    pub cost: f32,
+}
+
+impl NavPath {
+    /// Distance along the path
+    pub fn length(&self) -> f32 {
+        self.points
+            .windows(2)
+            .map(|w| w[0].position.distance(w[1].position))
+            .sum()
+    }
+
+    /// Indices of the areas the path goes through, in order
+    pub fn areas(&self) -> Vec<usize> {
+        let mut areas: Vec<usize> = self.points.iter().map(|p| p.area).collect();
+        areas.dedup();
+        areas
+    }
+}
+
+/// A way out of an area
+#[derive(Debug, Clone, Copy)]
+enum Link {
+    Walk(NavDirection),
+    /// Index into [`NavMesh::ladders`], and whether it is climbed up
+    Ladder(usize, bool),
+}
+
+/// Area waiting to be searched, ordered so the heap pops the lowest estimate first
+struct Open {
+    estimate: f32,
+    area: usize,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl NavMesh {
+    /// Index of the area `point` is standing on: the highest area under it, allowing for a step up
+    pub fn area_at(&self, point: Vec3) -> Option<usize> {
+        self.areas
+            .iter()
+            .enumerate()
+            .filter(|(_, area)| area.contains_xy(point.x, point.y))
+            .map(|(i, area)| (i, area.z_at(point.x, point.y)))
+            .filter(|&(_, z)| z <= point.z + STEP_HEIGHT)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    /// Index of the area closest to `point`
+    pub fn nearest_area(&self, point: Vec3) -> Option<usize> {
+        self.areas
+            .iter()
+            .enumerate()
+            .map(|(i, area)| (i, closest_point(area, point).distance_squared(point)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    /// Find the cheapest path between two points, each starting from the area under it or else the nearest area.
+    ///
+    /// Returns `None` if the mesh is empty or the goal cannot be reached.
+    pub fn find_path(&self, start: Vec3, goal: Vec3, costs: &PathCosts) -> Option<NavPath> {
+        let start_area = self.area_at(start).or_else(|| self.nearest_area(start))?;
+        let goal_area = self.area_at(goal).or_else(|| self.nearest_area(goal))?;
+        // Link costs run between area centres, so estimate towards the goal area's centre too
+        let target = self.areas[goal_area].center();
+
+        let mut cost = vec![f32::INFINITY; self.areas.len()];
+        let mut came_from: Vec<Option<(usize, Link)>> = vec![None; self.areas.len()];
+        let mut open = BinaryHeap::new();
+
+        cost[start_area] = 0.0;
+        open.push(Open {
+            estimate: self.areas[start_area].center().distance(target),
+            area: start_area,
+        });
+
+        while let Some(Open { area, .. }) = open.pop() {
+            if area == goal_area {
+                break;
+            }
+            for (link, next) in self.links(area) {
+                let next_cost = cost[area] + self.link_cost(area, link, next, costs);
+                if next_cost < cost[next] {
+                    cost[next] = next_cost;
+                    came_from[next] = Some((area, link));
+                    open.push(Open {
+                        estimate: next_cost + self.areas[next].center().distance(target),
+                        area: next,
+                    });
+                }
+            }
+        }
+
+        if !cost[goal_area].is_finite() {
+            return None;
+        }
+
+        let mut steps = Vec::new();
+        let mut area = goal_area;
+        while let Some((from, link)) = came_from[area] {
+            steps.push((from, link, area));
+            area = from;
+        }
+        steps.reverse();
+
+        Some(NavPath {
+            points: self.path_points(start, start_area, goal, goal_area, &steps),
+            cost: cost[goal_area],
+        })
+    }
+
+    /// Every area reachable from the area at `index`, walking or by ladder
+    fn links(&self, index: usize) -> impl Iterator<Item = (Link, usize)> + '_ {
+        let area = &self.areas[index];
+        let walks = self
+            .neighbours(index)
+            .map(|(dir, next)| (Link::Walk(dir), next));
+
+        let ladder = |id: &u32, up: bool| {
+            let i = self.ladders.iter().position(|ladder| ladder.id == *id)?;
+            Some((i, up))
+        };
+        let ladders = area
+            .ladders_up
+            .iter()
+            .filter_map(move |id| ladder(id, true))
+            .chain(
+                area.ladders_down
+                    .iter()
+                    .filter_map(move |id| ladder(id, false)),
+            )
+            .flat_map(move |(i, up)| {
+                let ladder = &self.ladders[i];
+                let ends = if up {
+                    vec![
+                        ladder.top_forward_area,
+                        ladder.top_left_area,
+                        ladder.top_right_area,
+                        ladder.top_behind_area,
+                    ]
+                } else {
+                    vec![ladder.bottom_area]
+                };
+                ends.into_iter()
+                    .filter(|&id| id != 0)
+                    .filter_map(move |id| Some((Link::Ladder(i, up), self.area_index(id)?)))
+            });
+
+        walks.chain(ladders)
+    }
+
+    fn link_cost(&self, from: usize, link: Link, to: usize, costs: &PathCosts) -> f32 {
+        let (a, b) = (&self.areas[from], &self.areas[to]);
+        match link {
+            Link::Walk(dir) => {
+                let [p0, p1] = portal(a, b, dir);
+                let crossing = (p0 + p1) / 2.0;
+
+                let mut cost = a.center().distance(b.center());
+                if b.attributes.contains(NavAttributes::CROUCH) {
+                    cost *= costs.crouch;
+                }
+                if needs_jump(a, b, crossing) {
+                    cost += costs.jump;
+                }
+                cost
+            }
+            Link::Ladder(i, up) => {
+                let ladder = &self.ladders[i];
+                let (near, far) = if up {
+                    (ladder.bottom, ladder.top)
+                } else {
+                    (ladder.top, ladder.bottom)
+                };
+                a.center().distance(near) + ladder.length * costs.ladder + far.distance(b.center())
+            }
+        }
+    }
+
+    /// Turn the areas of a path into points, pulling every crossing between areas towards a straight line
+    fn path_points(
+        &self,
+        start: Vec3,
+        start_area: usize,
+        goal: Vec3,
+        goal_area: usize,
+        steps: &[(usize, Link, usize)],
+    ) -> Vec<PathPoint> {
+        let on_area = |point: Vec3, area: usize| {
+            let area = &self.areas[area];
+            let point = closest_point(area, point);
+            vec3(point.x, point.y, area.z_at(point.x, point.y))
+        };
+
+        // Walking crossings can slide along their portal, ladders are fixed
+        enum Waypoint {
+            Fixed(Vec3),
+            Portal([Vec2; 2], Vec2),
+        }
+        let mut waypoints = vec![Waypoint::Fixed(on_area(start, start_area))];
+        for &(from, link, to) in steps {
+            match link {
+                Link::Walk(dir) => {
+                    let portal = portal(&self.areas[from], &self.areas[to], dir);
+                    waypoints.push(Waypoint::Portal(portal, (portal[0] + portal[1]) / 2.0));
+                }
+                Link::Ladder(i, up) => {
+                    let ladder = &self.ladders[i];
+                    let (near, far) = if up {
+                        (ladder.bottom, ladder.top)
+                    } else {
+                        (ladder.top, ladder.bottom)
+                    };
+                    waypoints.push(Waypoint::Fixed(near));
+                    waypoints.push(Waypoint::Fixed(far));
+                }
+            }
+        }
+        waypoints.push(Waypoint::Fixed(on_area(goal, goal_area)));
+
+        fn position_of(waypoint: &Waypoint) -> Vec3 {
+            match waypoint {
+                Waypoint::Fixed(p) => *p,
+                Waypoint::Portal(_, p) => p.extend(0.0),
+            }
+        }
+        let position = |waypoint: &Waypoint| position_of(waypoint).truncate();
+        // Each crossing moves to where the line between its neighbours meets its portal, until they settle
+        for _ in 0..8 {
+            for i in 1..waypoints.len() - 1 {
+                let (prev, next) = (position(&waypoints[i - 1]), position(&waypoints[i + 1]));
+                if let Waypoint::Portal(portal, crossing) = &mut waypoints[i] {
+                    *crossing = cross_portal(*portal, prev, next);
+                }
+            }
+        }
+
+        let traverse = |to: usize| {
+            if self.areas[to].attributes.contains(NavAttributes::CROUCH) {
+                NavTraverse::Crouch
+            } else {
+                NavTraverse::Walk
+            }
+        };
+
+        let mut waypoints = waypoints.into_iter();
+        let mut points = vec![PathPoint {
+            position: position_of(&waypoints.next().unwrap()),
+            area: start_area,
+            traverse: traverse(start_area),
+        }];
+
+        for &(from, link, to) in steps {
+            let (a, b) = (&self.areas[from], &self.areas[to]);
+            match link {
+                Link::Walk(_) => {
+                    let Some(Waypoint::Portal(_, crossing)) = waypoints.next() else {
+                        unreachable!("walking steps have portals");
+                    };
+                    let leave = crossing.extend(a.z_at(crossing.x, crossing.y));
+                    let enter = crossing.extend(b.z_at(crossing.x, crossing.y));
+
+                    if needs_jump(a, b, crossing) {
+                        points.push(PathPoint {
+                            position: leave,
+                            area: from,
+                            traverse: traverse(from),
+                        });
+                        points.push(PathPoint {
+                            position: enter,
+                            area: to,
+                            traverse: NavTraverse::Jump,
+                        });
+                    } else {
+                        points.push(PathPoint {
+                            position: enter,
+                            area: to,
+                            traverse: traverse(to),
+                        });
+                    }
+                }
+                Link::Ladder(..) => {
+                    let near = position_of(&waypoints.next().unwrap());
+                    let far = position_of(&waypoints.next().unwrap());
+                    points.push(PathPoint {
+                        position: near,
+                        area: from,
+                        traverse: traverse(from),
+                    });
+                    points.push(PathPoint {
+                        position: far,
+                        area: to,
+                        traverse: NavTraverse::Ladder,
+                    });
+                }
+            }
+        }
+
+        if let Some(goal) = waypoints.next() {
+            points.push(PathPoint {
+                position: position_of(&goal),
+                area: goal_area,
+                traverse: traverse(goal_area),
+            });
+        }
+
+        points
+    }
+}
+
+/// The point on `area` closest to `point`
+fn closest_point(area: &NavArea, point: Vec3) -> Vec3 {
+    let x = point.x.clamp(area.north_west.x, area.south_east.x);
+    let y = point.y.clamp(area.north_west.y, area.south_east.y);
+    vec3(x, y, area.z_at(x, y))
+}
+
+/// The edge shared by two areas, on the side of `from` facing `dir`. Areas that do not overlap along that edge
+/// share the point between them.
+fn portal(from: &NavArea, to: &NavArea, dir: NavDirection) -> [Vec2; 2] {
+    let (nw, se) = (from.north_west, from.south_east);
+    let overlap = |min_a: f32, max_a: f32, min_b: f32, max_b: f32| {
+        let (min, max) = (min_a.max(min_b), max_a.min(max_b));
+        if min <= max {
+            (min, max)
+        } else {
+            let mid = (min + max) / 2.0;
+            (mid, mid)
+        }
+    };
+
+    match dir {
+        NavDirection::North | NavDirection::South => {
+            // North is towards -y
+            let y = if dir == NavDirection::North {
+                nw.y
+            } else {
+                se.y
+            };
+            let (x0, x1) = overlap(nw.x, se.x, to.north_west.x, to.south_east.x);
+            [Vec2::new(x0, y), Vec2::new(x1, y)]
+        }
+        NavDirection::East | NavDirection::West => {
+            let x = if dir == NavDirection::East {
+                se.x
+            } else {
+                nw.x
+            };
+            let (y0, y1) = overlap(nw.y, se.y, to.north_west.y, to.south_east.y);
+            [Vec2::new(x, y0), Vec2::new(x, y1)]
+        }
+    }
+}
+
+/// Where the line from `prev` to `next` crosses `portal`, clamped onto it
+fn cross_portal([p0, p1]: [Vec2; 2], prev: Vec2, next: Vec2) -> Vec2 {
+    let edge = p1 - p0;
+    let line = next - prev;
+    let denom = line.perp_dot(edge);
+    let t = if denom.abs() > f32::EPSILON {
+        line.perp_dot(prev - p0) / denom
+    } else {
+        // Parallel to the portal, so take the closest point to where it was headed
+        (next - p0).dot(edge) / edge.length_squared().max(f32::EPSILON)
+    };
+    p0 + edge * t.clamp(0.0, 1.0)
+}
+
+/// Whether moving from `a` to `b` at `crossing` needs a jump
+fn needs_jump(a: &NavArea, b: &NavArea, crossing: Vec2) -> bool {
+    b.attributes.contains(NavAttributes::JUMP)
+        || b.z_at(crossing.x, crossing.y) - a.z_at(crossing.x, crossing.y) > STEP_HEIGHT
+}
+
+#[cfg(test)]
+mod path_tests {
+    use super::*;
+    use crate::nav::{nav_tests::area, NavLadder};
+
+    fn connect(areas: &mut [NavArea], from: usize, dir: NavDirection, to: usize) {
+        let (from_id, to_id) = (areas[from].id, areas[to].id);
+        areas[from].connections[dir as usize].push(to_id);
+        areas[to].connections[dir.opposite() as usize].push(from_id);
+    }
+
+    /// A row of three areas along x, with a detour through a second row south of them.
+    /// An area on a platform above the first is reached by ladder.
+    fn mesh(middle: impl FnOnce(&mut NavArea)) -> NavMesh {
+        let square =
+            |id, x: f32, y: f32| area(id, vec3(x, y, 0.0), vec3(x + 100.0, y + 100.0, 0.0));
+        let mut areas = vec![
+            square(1, 0.0, 0.0),
+            square(2, 100.0, 0.0),
+            square(3, 200.0, 0.0),
+            square(4, 0.0, 100.0),
+            square(5, 100.0, 100.0),
+            square(6, 200.0, 100.0),
+            area(7, vec3(0.0, 0.0, 200.0), vec3(100.0, 100.0, 200.0)),
+        ];
+        middle(&mut areas[1]);
+
+        connect(&mut areas, 0, NavDirection::East, 1);
+        connect(&mut areas, 1, NavDirection::East, 2);
+        connect(&mut areas, 0, NavDirection::South, 3);
+        connect(&mut areas, 3, NavDirection::East, 4);
+        connect(&mut areas, 4, NavDirection::East, 5);
+        connect(&mut areas, 5, NavDirection::North, 2);
+
+        areas[0].ladders_up.push(1);
+        areas[6].ladders_down.push(1);
+        let ladder = NavLadder {
+            id: 1,
+            width: 32.0,
+            top: vec3(50.0, 0.0, 200.0),
+            bottom: vec3(50.0, 0.0, 0.0),
+            length: 200.0,
+            direction: NavDirection::North,
+            top_forward_area: 7,
+            top_left_area: 0,
+            top_right_area: 0,
+            top_behind_area: 0,
+            bottom_area: 1,
+        };
+
+        NavMesh::from_areas(areas, vec![ladder])
+    }
+
+    #[test]
+    fn area_at() {
+        let mesh = mesh(|_| ());
+        assert_eq!(mesh.area_at(vec3(50.0, 50.0, 10.0)), Some(0));
+        assert_eq!(mesh.area_at(vec3(50.0, 50.0, 250.0)), Some(6));
+        assert_eq!(mesh.area_at(vec3(250.0, 150.0, 0.0)), Some(5));
+        assert_eq!(mesh.area_at(vec3(-50.0, 50.0, 0.0)), None);
+        assert_eq!(mesh.nearest_area(vec3(-50.0, 50.0, 0.0)), Some(0));
+    }
+
+    #[test]
+    fn smoothed() {
+        let mesh = mesh(|_| ());
+        let start = vec3(10.0, 10.0, 0.0);
+        let goal = vec3(290.0, 90.0, 0.0);
+        let path = mesh.find_path(start, goal, &PathCosts::default()).unwrap();
+
+        assert_eq!(path.areas(), [0, 1, 2]);
+        assert_eq!(path.points.first().unwrap().position, start);
+        assert_eq!(path.points.last().unwrap().position, goal);
+        // Pulled straight, so every crossing is on the line from start to goal
+        for point in &path.points {
+            let t = (point.position.x - start.x) / (goal.x - start.x);
+            assert!((point.position - start.lerp(goal, t)).length() < 0.01);
+        }
+        assert!((path.length() - start.distance(goal)).abs() < 0.01);
+    }
+
+    #[test]
+    fn crouch_and_jump() {
+        let start = vec3(50.0, 50.0, 0.0);
+        let goal = vec3(250.0, 50.0, 0.0);
+
+        let crouch = mesh(|area| area.attributes = NavAttributes::CROUCH.into());
+        let path = crouch
+            .find_path(
+                start,
+                goal,
+                &PathCosts {
+                    crouch: 1.0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(path.areas(), [0, 1, 2]);
+        assert_eq!(path.points[1].traverse, NavTraverse::Crouch);
+        let path = crouch
+            .find_path(
+                start,
+                goal,
+                &PathCosts {
+                    crouch: 10.0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(path.areas(), [0, 3, 4, 5, 2]);
+
+        // A box in the way, too high to step onto
+        let raised = mesh(|area| {
+            area.north_west.z = 32.0;
+            area.south_east.z = 32.0;
+            area.north_east_z = 32.0;
+            area.south_west_z = 32.0;
+        });
+        let path = raised
+            .find_path(
+                start,
+                goal,
+                &PathCosts {
+                    jump: 0.0,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(path.areas(), [0, 1, 2]);
+        assert_eq!(
+            path.points.iter().map(|p| p.traverse).collect::<Vec<_>>(),
+            [
+                NavTraverse::Walk,
+                NavTraverse::Walk,
+                NavTraverse::Jump,
+                NavTraverse::Walk,
+                NavTraverse::Walk
+            ]
+        );
+        let jump = PathCosts {
+            jump: 500.0,
+            ..Default::default()
+        };
+        let path = raised.find_path(start, goal, &jump).unwrap();
+        assert_eq!(path.areas(), [0, 3, 4, 5, 2]);
+    }
+
+    #[test]
+    fn cheaper_later() {
+        // A ramp down to the east, so it takes a jump to enter from the west but not from the east
+        let ramp = mesh(|area| {
+            area.north_west.z = 32.0;
+            area.south_west_z = 32.0;
+        });
+        let jump = PathCosts {
+            jump: 500.0,
+            ..Default::default()
+        };
+        // The goal area is first reached by the jump, then more cheaply around the detour
+        let path = ramp
+            .find_path(vec3(50.0, 50.0, 0.0), vec3(150.0, 50.0, 16.0), &jump)
+            .unwrap();
+        assert_eq!(path.areas(), [0, 3, 4, 5, 2, 1]);
+        assert!(path.cost < 510.0);
+    }
+
+    #[test]
+    fn ladder() {
+        let mesh = mesh(|_| ());
+        let path = mesh
+            .find_path(
+                vec3(250.0, 50.0, 0.0),
+                vec3(50.0, 50.0, 200.0),
+                &PathCosts::default(),
+            )
+            .unwrap();
+
+        assert_eq!(path.areas(), [2, 1, 0, 6]);
+        let climb = &path.points[path.points.len() - 3..];
+        assert_eq!(climb[0].position, vec3(50.0, 0.0, 0.0));
+        assert_eq!(climb[1].position, vec3(50.0, 0.0, 200.0));
+        assert_eq!(climb[1].traverse, NavTraverse::Ladder);
+        assert_eq!(climb[2].position, vec3(50.0, 50.0, 200.0));
+
+        // Coming back down
+        let back = mesh
+            .find_path(
+                vec3(50.0, 50.0, 200.0),
+                vec3(50.0, 50.0, 0.0),
+                &PathCosts::default(),
+            )
+            .unwrap();
+        assert_eq!(back.areas(), [6, 0]);
+
+        let mut unreachable = mesh;
+        unreachable.ladders.clear();
+        assert!(unreachable
+            .find_path(
+                vec3(50.0, 50.0, 0.0),
+                vec3(50.0, 50.0, 200.0),
+                &PathCosts::default()
+            )
+            .is_none());
+    }
+}
@@ -1,13 +1,20 @@
 pub use crate::bsp::{
+    brush::{BSPBrush, BSPBrushSide, BSPLeafBrush},
+    consts::Contents,
     consts::LumpType,
     displacement::{BSPDispInfo, BSPDispVert},
     edges::{BSPEdge, BSPSurfEdge},
+    entities::BSPEntity,
     face::BSPFace,
     header::BSPHeader,
     lightmap::{ColorRGBExp32, LightingData},
+    map::Bsp,
     model::BSPModel,
     textures::{BSPTexData, BSPTexDataStringTable, BSPTexInfo},
+    trace::Trace,
+    tree::{BSPLeaf, BSPLeafFace, BSPNode},
 };
+pub use crate::error::{SourceError, SourceResult};
 pub use crate::game_data::{Game, GameData};
 pub use crate::vmt::VMT;
 pub use crate::vpk::{VPKDirectory, VPKFile};
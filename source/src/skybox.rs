@@ -0,0 +1,608 @@
+//! Skies. The 2D skybox is drawn behind everything from six materials named after the worldspawn `skyname`,
+//! and the 3D skybox is a scaled down area of the map drawn between the 2D sky and the world.
+
+use std::{
+    io::{Read, Seek},
+    sync::Arc,
+};
+
+use common::vpath::{VLocalPath, VPath};
+use glam::{Mat4, Vec3};
+use half::f16;
+
+use crate::{
+    bsp::{entities::BSPEntity, gamelump::GameLump, Bsp},
+    error::{SourceError, SourceResult},
+    game_data::GameData,
+    vmt::VMT,
+    vtf::{consts::ImageFormat, VTF},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaceRotation {
+    None,
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Material suffix of each face in cubemap layer order (+X, -X, +Y, -Y, +Z, -Z), and how the face is turned to fit.
+///
+/// Source is Z up, so the cubemap is sampled with a Source direction swizzled to `(x, z, y)`.
+pub const SKYBOX_FACES: [(&str, FaceRotation); 6] = [
+    ("rt", FaceRotation::None),
+    ("lf", FaceRotation::None),
+    ("up", FaceRotation::CounterClockwise),
+    ("dn", FaceRotation::Clockwise),
+    ("bk", FaceRotation::None),
+    ("ft", FaceRotation::None),
+];
+
+/// Material parameters an HDR sky can take its texture from, in order of preference
+const HDR_TEXTURES: [&str; 3] = ["$hdrcompressedtexture", "$hdrbasetexture", "$basetexture"];
+
+/// The `skyname` of the worldspawn entity
+pub fn sky_name(entities: &[BSPEntity]) -> Option<&str> {
+    entities
+        .iter()
+        .find(|e| e.classname() == Some("worldspawn"))?
+        .get("skyname")
+}
+
+/// The `sky_camera` entity, which marks the point in the 3D skybox that matches the origin of the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyCamera {
+    pub origin: Vec3,
+    /// How many world units one unit of the skybox covers
+    pub scale: f32,
+}
+
+impl SkyCamera {
+    pub const DEFAULT_SCALE: f32 = 16.0;
+
+    pub fn find(entities: &[BSPEntity]) -> Option<Self> {
+        let camera = entities
+            .iter()
+            .find(|e| e.classname() == Some("sky_camera"))?;
+
+        Some(Self {
+            origin: camera.origin().unwrap_or_default(),
+            scale: camera
+                .get("scale")
+                .and_then(|scale| scale.trim().parse().ok())
+                .filter(|&scale: &f32| scale > 0.0)
+                .unwrap_or(Self::DEFAULT_SCALE),
+        })
+    }
+
+    /// Moves skybox geometry from where it was built in the map to where it appears around the world.
+    ///
+    /// The engine draws the skybox from `origin + eye / scale`, which is the same as scaling the skybox up
+    /// about `origin` and drawing it from the eye, behind the world.
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_scale(Vec3::splat(self.scale)) * Mat4::from_translation(-self.origin)
+    }
+}
+
+/// The parts of a map that make up its 3D skybox: everything in the same area as the `sky_camera`
+#[derive(Debug, Clone)]
+pub struct Skybox3D {
+    pub camera: SkyCamera,
+    /// Area of the leaf containing the camera
+    pub area: u16,
+    /// Indices into [`Bsp::leafs`]
+    pub leafs: Vec<usize>,
+    /// Indices into [`Bsp::faces`], sorted, including displacements
+    pub faces: Vec<usize>,
+    /// Indices into [`GameLump::props`] of the static props touching a skybox leaf
+    pub props: Vec<usize>,
+}
+
+impl Skybox3D {
+    /// Find the 3D skybox, or `None` if the map has no `sky_camera`
+    pub fn find<R: Read + Seek>(bsp: &Bsp<R>) -> SourceResult<Option<Self>> {
+        let Some(camera) = SkyCamera::find(bsp.entities()?) else {
+            return Ok(None);
+        };
+
+        let leafs = bsp.leafs()?;
+        let area = leafs
+            .get(bsp.find_leaf(camera.origin)?)
+            .ok_or_else(|| SourceError::NotFound("Leaf containing the sky camera".to_owned()))?
+            .area();
+
+        let in_sky: Vec<bool> = leafs.iter().map(|leaf| leaf.area() == area).collect();
+
+        let leaf_faces = bsp.leaf_faces()?;
+        let mut faces = Vec::new();
+        for (i_leaf, leaf) in leafs.iter().enumerate().filter(|(i, _)| in_sky[*i]) {
+            let first = leaf.first_leaf_face as usize;
+            let range = leaf_faces
+                .get(first..first + leaf.num_leaf_faces as usize)
+                .ok_or_else(|| {
+                    SourceError::invalid("BSPLeaf", 0, format!("Leaf {i_leaf} faces out of range"))
+                })?;
+            faces.extend(range.iter().map(|leaf_face| leaf_face.face as usize));
+        }
+
+        // Displacements are not in the leaf face lists, so place them by the leaf just in front of their face
+        let planes = bsp.planes()?;
+        for (i_face, face) in bsp.faces()?.iter().enumerate() {
+            if face.disp_info < 0 {
+                continue;
+            }
+            let Some(plane) = planes.get(face.plane_num as usize) else {
+                continue;
+            };
+            let normal = plane.normal;
+            let normal = if face.side == 0 { normal } else { -normal };
+
+            let verts = bsp.face_vertices(face)?;
+            let center = verts.iter().sum::<Vec3>() / verts.len().max(1) as f32;
+            if in_sky.get(bsp.find_leaf(center + normal)?) == Some(&true) {
+                faces.push(i_face);
+            }
+        }
+
+        faces.sort_unstable();
+        faces.dedup();
+
+        Ok(Some(Self {
+            camera,
+            area,
+            leafs: (0..leafs.len()).filter(|&i| in_sky[i]).collect(),
+            faces,
+            props: sky_props(bsp.game_lump()?, &in_sky),
+        }))
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        self.camera.transform()
+    }
+}
+
+/// Props with any of their leafs in the skybox
+fn sky_props(game_lump: &GameLump, in_sky: &[bool]) -> Vec<usize> {
+    game_lump
+        .props
+        .iter()
+        .enumerate()
+        .filter(|(_, prop)| {
+            let first = prop.first_leaf as usize;
+            game_lump
+                .static_prop_leafs
+                .get(first..first + prop.leaf_count as usize)
+                .unwrap_or_default()
+                .iter()
+                .any(|&leaf| in_sky.get(leaf as usize).copied().unwrap_or(false))
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// The six faces of a sky, resampled to one size and packed ready to upload as a cube texture
+#[derive(Debug, Clone)]
+pub struct SkyboxCubemap {
+    /// Name the materials were found under, ending in `_hdr` for HDR skies
+    pub name: String,
+    /// Width and height of every face
+    pub size: u32,
+    /// `Rgba8UnormSrgb` for LDR skies, or linear `Rgba16Float` for HDR skies
+    pub format: wgpu::TextureFormat,
+    /// Faces in [`SKYBOX_FACES`] order
+    pub data: Vec<u8>,
+}
+
+impl SkyboxCubemap {
+    /// Load the sky materials `skybox/<name><suffix>`. If `hdr` is set the `<name>_hdr` materials are tried first,
+    /// falling back to LDR as the engine does.
+    pub fn load(game: &GameData, name: &str, hdr: bool) -> SourceResult<Self> {
+        Self::load_with(
+            name,
+            hdr,
+            |path| game.load_vmt(path).cloned(),
+            |path| game.load_vtf(path).cloned(),
+        )
+    }
+
+    fn load_with(
+        name: &str,
+        hdr: bool,
+        load_vmt: impl Fn(&dyn VPath) -> Option<Arc<VMT>>,
+        load_vtf: impl Fn(&dyn VPath) -> Option<Arc<VTF>>,
+    ) -> SourceResult<Self> {
+        if hdr {
+            match Self::load_faces(&format!("{name}_hdr"), true, &load_vmt, &load_vtf) {
+                Ok(sky) => return Ok(sky),
+                Err(e) => log::info!("Using LDR sky for {name}: {e}"),
+            }
+        }
+        Self::load_faces(name, false, &load_vmt, &load_vtf)
+    }
+
+    fn load_faces(
+        name: &str,
+        hdr: bool,
+        load_vmt: &impl Fn(&dyn VPath) -> Option<Arc<VMT>>,
+        load_vtf: &impl Fn(&dyn VPath) -> Option<Arc<VTF>>,
+    ) -> SourceResult<Self> {
+        let params: &[&str] = if hdr {
+            &HDR_TEXTURES
+        } else {
+            &["$basetexture"]
+        };
+
+        let faces = SKYBOX_FACES
+            .iter()
+            .map(|(suffix, _)| {
+                let material = format!("skybox/{name}{suffix}");
+                let vmt = load_vmt(&VLocalPath::new("materials", &material, "vmt"))
+                    .ok_or_else(|| SourceError::NotFound(format!("materials/{material}.vmt")))?;
+
+                let (param, texture) = params
+                    .iter()
+                    .find_map(|&param| Some((param, vmt.get(param)?.replace('\\', "/"))))
+                    .ok_or_else(|| {
+                        SourceError::invalid("VMT", 0, "Sky material has no base texture")
+                            .in_file(format!("materials/{material}.vmt"))
+                    })?;
+
+                let vtf = load_vtf(&VLocalPath::new("materials", &texture, "vtf"))
+                    .ok_or_else(|| SourceError::NotFound(format!("materials/{texture}.vtf")))?;
+
+                Ok((vtf, param == "$hdrcompressedtexture"))
+            })
+            .collect::<SourceResult<Vec<_>>>()?;
+
+        let size = faces.iter().map(|(vtf, _)| vtf.width()).max().unwrap_or(1);
+
+        let (format, data) = if hdr {
+            let data = assemble(&faces, size as usize, decode_hdr)?;
+            (wgpu::TextureFormat::Rgba16Float, data)
+        } else {
+            let data = assemble(&faces, size as usize, |vtf, _| {
+                Ok(bytemuck::cast_slice::<_, [u8; 4]>(&vtf.high_res_rgba8(0)?).to_vec())
+            })?;
+            (wgpu::TextureFormat::Rgba8UnormSrgb, data)
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            size,
+            format,
+            data,
+        })
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.format == wgpu::TextureFormat::Rgba16Float
+    }
+
+    pub fn descriptor(&self) -> wgpu::TextureDescriptor<'static> {
+        wgpu::TextureDescriptor {
+            label: Some("skybox"),
+            size: wgpu::Extent3d {
+                width: self.size,
+                height: self.size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        }
+    }
+}
+
+/// Decode every face and place it in the cubemap, returning the packed bytes
+fn assemble<P: bytemuck::Pod>(
+    faces: &[(Arc<VTF>, bool)],
+    size: usize,
+    decode: impl Fn(&VTF, bool) -> SourceResult<Vec<P>>,
+) -> SourceResult<Vec<u8>> {
+    let mut data = Vec::with_capacity(size * size * 6);
+
+    for ((vtf, compressed), (_, rotation)) in faces.iter().zip(SKYBOX_FACES) {
+        let pixels = decode(vtf, *compressed)?;
+        place_face(&pixels, vtf.mip_size(0), size, rotation, &mut data);
+    }
+
+    Ok(bytemuck::cast_slice(&data).to_vec())
+}
+
+fn decode_hdr(vtf: &VTF, compressed: bool) -> SourceResult<Vec<[f16; 4]>> {
+    if vtf.high_res_image_format() == ImageFormat::RGBA16161616F {
+        return Ok(bytemuck::cast_slice(&vtf.high_res_rgba16f(0)?).to_vec());
+    }
+
+    Ok(vtf
+        .high_res_rgba8(0)?
+        .chunks_exact(4)
+        .map(|c| {
+            // Compressed HDR stores a linear colour with a scale in alpha, everything else is an ordinary sRGB texture
+            let channel = |i: usize| {
+                f16::from_f32(if compressed {
+                    c[i] as f32 * c[3] as f32 * 16.0 / (255.0 * 255.0)
+                } else {
+                    srgb_to_linear(c[i])
+                })
+            };
+            [channel(0), channel(1), channel(2), f16::ONE]
+        })
+        .collect())
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Resample a face to `size` squared, turning it by `rotation`.
+///
+/// Faces are scaled by their width. Faces shorter than they are wide only cover the top of the sky,
+/// and repeat their bottom row below that as the engine clamps them.
+fn place_face<P: Copy>(
+    pixels: &[P],
+    (width, height): (usize, usize),
+    size: usize,
+    rotation: FaceRotation,
+    out: &mut Vec<P>,
+) {
+    for y in 0..size {
+        for x in 0..size {
+            let (u, v) = match rotation {
+                FaceRotation::None => (x, y),
+                FaceRotation::Clockwise => (y, size - 1 - x),
+                FaceRotation::CounterClockwise => (size - 1 - y, x),
+            };
+            let sx = (u * width / size).min(width - 1);
+            let sy = (v * width / size).min(height - 1);
+            out.push(pixels[sy * width + sx]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod skybox_tests {
+    use std::collections::HashMap;
+
+    use bytemuck::Zeroable;
+    use glam::vec3;
+
+    use crate::{
+        bsp::{entities::parse_entities, gamelump::StaticPropLumpV5, test_map::TestMap},
+        vtf::test_vtf::vtf,
+    };
+
+    use super::*;
+
+    fn solid(color: [u8; 4]) -> Arc<VTF> {
+        vtf(4, 4, ImageFormat::RGBA8888, &color.repeat(16))
+    }
+
+    fn material(param: &str, texture: &str) -> Arc<VMT> {
+        let mut vmt = VMT::new(String::new(), "Sky".to_owned());
+        vmt.data.insert(param.to_owned(), texture.to_owned());
+        Arc::new(vmt)
+    }
+
+    struct Files {
+        vmts: HashMap<String, Arc<VMT>>,
+        vtfs: HashMap<String, Arc<VTF>>,
+    }
+
+    impl Files {
+        fn load(&self, name: &str, hdr: bool) -> SourceResult<SkyboxCubemap> {
+            let key =
+                |path: &dyn VPath| format!("{}/{}.{}", path.dir(), path.filename(), path.ext());
+            SkyboxCubemap::load_with(
+                name,
+                hdr,
+                |path| self.vmts.get(&key(path)).cloned(),
+                |path| self.vtfs.get(&key(path)).cloned(),
+            )
+        }
+    }
+
+    /// LDR faces each a different shade of red, except the top which is black with a white top left corner
+    fn ldr_sky() -> Files {
+        let mut files = Files {
+            vmts: HashMap::new(),
+            vtfs: HashMap::new(),
+        };
+
+        for (i, (suffix, _)) in SKYBOX_FACES.iter().enumerate() {
+            let texture = format!("skybox/test{suffix}");
+            files.vmts.insert(
+                format!("materials/{texture}.vmt"),
+                material("$basetexture", &texture),
+            );
+
+            let vtf = if *suffix == "up" {
+                let mut data = [0, 0, 0, 255].repeat(16);
+                data[..4].copy_from_slice(&[255; 4]);
+                vtf(4, 4, ImageFormat::RGBA8888, &data)
+            } else {
+                solid([i as u8 * 10, 0, 0, 255])
+            };
+            files.vtfs.insert(format!("materials/{texture}.vtf"), vtf);
+        }
+        files
+    }
+
+    fn pixel(sky: &SkyboxCubemap, face: usize, x: usize, y: usize) -> &[u8] {
+        let size = sky.size as usize;
+        let p = ((face * size + y) * size + x) * 4;
+        &sky.data[p..p + 4]
+    }
+
+    #[test]
+    fn ldr() {
+        let files = ldr_sky();
+        // Without HDR materials, asking for HDR falls back
+        let sky = files.load("test", true).unwrap();
+
+        assert_eq!(sky.name, "test");
+        assert!(!sky.is_hdr());
+        assert_eq!(sky.size, 4);
+        assert_eq!(sky.data.len(), 4 * 4 * 4 * 6);
+
+        // rt is +X, bk is +Z
+        assert_eq!(pixel(&sky, 0, 1, 1), [0, 0, 0, 255]);
+        assert_eq!(pixel(&sky, 4, 3, 3), [40, 0, 0, 255]);
+
+        // The top is turned counter clockwise, moving its top left corner to the bottom left
+        assert_eq!(pixel(&sky, 2, 0, 3), [255; 4]);
+        assert_eq!(pixel(&sky, 2, 0, 0), [0, 0, 0, 255]);
+
+        assert!(matches!(
+            files.load("missing", false),
+            Err(SourceError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn hdr() {
+        let mut files = ldr_sky();
+
+        for (suffix, _) in SKYBOX_FACES {
+            let texture = format!("skybox/test_hdr{suffix}");
+            let (param, vtf) = if suffix == "up" {
+                let one = f16::ONE.to_le_bytes();
+                let data = [one, one, one, one].concat().repeat(64);
+                (
+                    "$hdrbasetexture",
+                    vtf(8, 8, ImageFormat::RGBA16161616F, &data),
+                )
+            } else {
+                // Stored as BGRA, with a red of 255 scaled by 16 / 255 in alpha
+                let data = [0, 0, 255, 16].repeat(16);
+                (
+                    "$hdrcompressedtexture",
+                    vtf(4, 4, ImageFormat::BGRA8888, &data),
+                )
+            };
+            files.vmts.insert(
+                format!("materials/{texture}.vmt"),
+                material(param, &texture),
+            );
+            files.vtfs.insert(format!("materials/{texture}.vtf"), vtf);
+        }
+
+        let sky = files.load("test", true).unwrap();
+        assert_eq!(sky.name, "test_hdr");
+        assert!(sky.is_hdr());
+        // Smaller faces are scaled up to the largest
+        assert_eq!(sky.size, 8);
+
+        let texels: &[[f16; 4]] = bytemuck::cast_slice(&sky.data);
+        assert_eq!(texels.len(), 8 * 8 * 6);
+
+        let red = texels[0];
+        assert!((red[0].to_f32() - 1.0).abs() < 0.01, "{red:?}");
+        assert_eq!(red[1], f16::ZERO);
+        assert_eq!(texels[2 * 64], [f16::ONE; 4]);
+        assert_eq!(texels[64 - 1], red);
+
+        // LDR can still be asked for
+        assert!(!files.load("test", false).unwrap().is_hdr());
+    }
+
+    #[test]
+    fn short_faces() {
+        let mut pixels = Vec::new();
+        place_face(
+            &[1, 2, 3, 4, 5, 6, 7, 8],
+            (4, 2),
+            4,
+            FaceRotation::None,
+            &mut pixels,
+        );
+        assert_eq!(pixels, [1, 2, 3, 4, 5, 6, 7, 8, 5, 6, 7, 8, 5, 6, 7, 8]);
+
+        pixels.clear();
+        place_face(
+            &[1, 2, 3, 4],
+            (2, 2),
+            2,
+            FaceRotation::Clockwise,
+            &mut pixels,
+        );
+        assert_eq!(pixels, [3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn name() {
+        let entities =
+            parse_entities(r#"{ "classname" "worldspawn" "skyname" "sky_day01_01" }"#).unwrap();
+        assert_eq!(sky_name(&entities), Some("sky_day01_01"));
+        assert_eq!(sky_name(&[]), None);
+    }
+
+    #[test]
+    fn sky_3d() {
+        let bsp = Bsp::new(TestMap::two_areas().reader()).unwrap();
+        let sky = Skybox3D::find(&bsp).unwrap().unwrap();
+
+        assert_eq!(
+            sky.camera,
+            SkyCamera {
+                origin: vec3(1024.0, 0.0, 16.0),
+                scale: 32.0
+            }
+        );
+        assert_eq!(sky.area, 2);
+        assert_eq!(sky.leafs, [1]);
+        assert_eq!(sky.faces, [1]);
+        assert!(sky.props.is_empty());
+
+        // The camera lands on the world origin, and the skybox grows around it
+        let transform = sky.transform();
+        assert_eq!(transform.transform_point3(sky.camera.origin), Vec3::ZERO);
+        assert_eq!(
+            transform.transform_point3(vec3(1025.0, 0.0, 16.0)),
+            vec3(32.0, 0.0, 0.0)
+        );
+
+        let bsp = Bsp::new(TestMap::quad().reader()).unwrap();
+        assert!(Skybox3D::find(&bsp).unwrap().is_none());
+    }
+
+    #[test]
+    fn sky_camera_scale() {
+        for (scale, expected) in [
+            ("", 16.0),
+            (r#""scale" "0""#, 16.0),
+            (r#""scale" "8""#, 8.0),
+        ] {
+            let entities =
+                parse_entities(&format!(r#"{{ "classname" "sky_camera" {scale} }}"#)).unwrap();
+            let camera = SkyCamera::find(&entities).unwrap();
+            assert_eq!(camera.scale, expected, "{scale}");
+            assert_eq!(camera.origin, Vec3::ZERO);
+        }
+        assert_eq!(SkyCamera::find(&[]), None);
+    }
+
+    #[test]
+    fn props() {
+        let mut game_lump = GameLump {
+            static_prop_leafs: vec![0, 1, 1],
+            ..Default::default()
+        };
+        // The last two have no leafs, or leafs past the end of the list
+        for (first_leaf, leaf_count) in [(0, 1), (0, 2), (2, 1), (3, 0), (2, 5)] {
+            let mut prop = StaticPropLumpV5::zeroed();
+            prop.first_leaf = first_leaf;
+            prop.leaf_count = leaf_count;
+            game_lump.props.push(prop);
+        }
+
+        assert_eq!(sky_props(&game_lump, &[false, true]), [1, 2]);
+    }
+}
@@ -2,6 +2,7 @@ use std::mem;
 
 use crate::{
     binaries::BinaryData,
+    error::{SourceError, SourceResult},
     studio::mdl_headers::{StudioMesh, StudioModel},
 };
 
@@ -37,7 +38,7 @@ impl BinaryData for MDL {
     fn read<R: std::io::Read + std::io::Seek>(
         buffer: &mut std::io::BufReader<R>,
         _max_size: Option<usize>,
-    ) -> std::io::Result<Self>
+    ) -> SourceResult<Self>
     where
         Self: Sized,
     {
@@ -74,8 +75,13 @@ impl BinaryData for MDL {
             for (ii, model) in model_heads {
                 //println!("{:?}", model);
 
-                assert!(model.vertexindex % 0x30 == 0);
-                assert!(model.tangentsindex % 0x10 == 0);
+                if model.vertexindex % 0x30 != 0 || model.tangentsindex % 0x10 != 0 {
+                    return Err(SourceError::invalid(
+                        "StudioModel",
+                        ii as u64,
+                        "Misaligned vertex or tangent index",
+                    ));
+                }
 
                 let _v = model.vertexindex;
 
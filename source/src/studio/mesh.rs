@@ -0,0 +1,276 @@
+//! Triangle meshes for studio models, put together from the MDL, VTX and VVD files of a model
+
+use common::vpath::{VPath, VSplitPath};
+use glam::{Vec2, Vec3};
+
+use super::{MDL, VTX, VVD};
+use crate::{
+    error::{SourceError, SourceResult},
+    prelude::*,
+};
+
+/// Size of `mstudiovertex_t`, the unit of [`StudioModel::vertexindex`](super::mdl_headers::StudioModel)
+const STUDIO_VERTEX_SIZE: i32 = 0x30;
+
+/// Triangles of one material of a [`PropMesh`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropMeshPart {
+    /// Material path under `materials/`, without an extension
+    pub material: String,
+    /// Counter clockwise triangles, indexing the mesh's vertices
+    pub indices: Vec<u32>,
+}
+
+/// The highest detail triangles of a model's default body groups and skin
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PropMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub parts: Vec<PropMeshPart>,
+}
+
+impl PropMesh {
+    /// Load a model, such as `models/props_c17/oildrum001.mdl`, and its VTX and VVD files from the game's VPKs
+    pub fn load(game: &GameData, mdl_path: &str) -> SourceResult<Self> {
+        let mdl_path = mdl_path.to_ascii_lowercase().replace('\\', "/");
+        let (dir, filename) = mdl_path.rsplit_once('/').unwrap_or(("", &mdl_path));
+        let filename = filename.strip_suffix(".mdl").unwrap_or(filename);
+        let vtx_filename = format!("{filename}.dx90");
+
+        let load_error = |ext: &str| SourceError::NotFound(format!("{dir}/{filename}.{ext}"));
+        let mdl = game
+            .load(&VSplitPath::new(dir, filename, "mdl"), VPKFile::mdl)
+            .ok_or_else(|| load_error("mdl"))?;
+        let vtx = game
+            .load(&VSplitPath::new(dir, &vtx_filename, "vtx"), VPKFile::vtx)
+            .ok_or_else(|| load_error("dx90.vtx"))?;
+        let vvd = game
+            .load(&VSplitPath::new(dir, filename, "vvd"), VPKFile::vvd)
+            .ok_or_else(|| load_error("vvd"))?;
+
+        // Model materials are looked up next to the model, as the explorers do
+        let material_dir = VSplitPath::new(dir, filename, "mdl").dir();
+        let mut mesh = Self::new(mdl, vtx, vvd)?;
+        for part in &mut mesh.parts {
+            part.material = format!("{material_dir}/{}", part.material);
+        }
+        Ok(mesh)
+    }
+
+    /// Put together the first model of each body part at the highest LOD. Part materials are the MDL's texture names.
+    pub fn new(mdl: &MDL, vtx: &VTX, vvd: &VVD) -> SourceResult<Self> {
+        // With fixups, the LOD 0 vertices are pieces of the full vertex list
+        let verts: Vec<_> = if vvd.fixups.is_empty() {
+            vvd.verts.to_vec()
+        } else {
+            let mut verts = Vec::new();
+            for fixup in vvd.fixups.iter().filter(|fixup| fixup.lod >= 0) {
+                let src = fixup.src as usize;
+                let piece = vvd
+                    .verts
+                    .get(src..src + fixup.count as usize)
+                    .ok_or_else(|| invalid(format!("Fixup {fixup:?} out of range")))?;
+                verts.extend_from_slice(piece);
+            }
+            verts
+        };
+
+        let mut mesh = Self {
+            positions: verts.iter().map(|v| v.pos).collect(),
+            normals: verts.iter().map(|v| v.norm).collect(),
+            uvs: verts.iter().map(|v| v.uv).collect(),
+            parts: Vec::new(),
+        };
+
+        for (body_part, vtx_body_part) in mdl.body.iter().zip(&vtx.body) {
+            let (Some(model), Some(vtx_model)) =
+                (body_part.models.first(), vtx_body_part.0.first())
+            else {
+                continue;
+            };
+            let Some(lod) = vtx_model.0.first() else {
+                continue;
+            };
+            let model_start = model.head.vertexindex / STUDIO_VERTEX_SIZE;
+
+            for (studio_mesh, vtx_mesh) in model.meshes.iter().zip(&lod.0) {
+                let material = { studio_mesh.head.material };
+                let vertex_start = model_start + studio_mesh.head.vertexoffset;
+                let texture = mdl.textures.get(material as usize).ok_or_else(|| {
+                    invalid(format!(
+                        "Material {material} of {} textures",
+                        mdl.textures.len()
+                    ))
+                })?;
+
+                let mut indices = Vec::new();
+                for strip_group in &vtx_mesh.strip_groups {
+                    for &index in strip_group.indices.iter() {
+                        let vert = strip_group
+                            .verts
+                            .get(index as usize)
+                            .ok_or_else(|| invalid(format!("Strip index {index} out of range")))?;
+                        let vert = vertex_start + { vert.orig_mesh_vert_id } as i32;
+                        if !(0..mesh.positions.len() as i32).contains(&vert) {
+                            return Err(invalid(format!("Vertex {vert} out of range")));
+                        }
+                        indices.push(vert as u32);
+                    }
+                }
+                // Stored clockwise, for Direct3D
+                for tri in indices.chunks_exact_mut(3) {
+                    tri.swap(1, 2);
+                }
+
+                match mesh
+                    .parts
+                    .iter_mut()
+                    .find(|part| part.material == texture.name)
+                {
+                    Some(part) => part.indices.extend(indices),
+                    None => mesh.parts.push(PropMeshPart {
+                        material: texture.name.clone(),
+                        indices,
+                    }),
+                }
+            }
+        }
+
+        Ok(mesh)
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> SourceError {
+    SourceError::invalid("PropMesh", 0, reason)
+}
+
+#[cfg(test)]
+mod mesh_tests {
+    use bytemuck::Zeroable;
+    use glam::vec3;
+
+    use super::*;
+    use crate::studio::{
+        mdl::{MDLBodyPart, MDLMesh, MDLModel, MDLTexture},
+        mdl_headers::{StudioBodyparts, StudioMesh, StudioModel},
+        vtx::{
+            StripGroupHeader, VTXBodyPart, VTXFileHeader, VTXMesh, VTXModel, VTXModelLOD,
+            VTXStripGroup, VTXVertex,
+        },
+        vvd::{Fixup, ModelVertex, VertexFileHeader},
+    };
+
+    fn mesh_head(material: i32, vertexoffset: i32) -> MDLMesh {
+        let mut head = StudioMesh::zeroed();
+        head.material = material;
+        head.vertexoffset = vertexoffset;
+        MDLMesh { head }
+    }
+
+    fn vtx_mesh(indices: &[u16]) -> VTXMesh {
+        let verts = (0..3)
+            .map(|i| {
+                let mut vert = VTXVertex::zeroed();
+                vert.orig_mesh_vert_id = i;
+                vert
+            })
+            .collect();
+        VTXMesh {
+            flags: 0,
+            strip_groups: vec![VTXStripGroup {
+                head: StripGroupHeader::zeroed(),
+                strips: Vec::new(),
+                indices: indices.into(),
+                verts,
+            }],
+        }
+    }
+
+    /// Two triangles with different materials, the second using vertices after the first's
+    fn model(fixups: Vec<Fixup>) -> (MDL, VTX, VVD) {
+        let mdl = MDL {
+            version: 48,
+            body: vec![MDLBodyPart {
+                name: "body".to_owned(),
+                head: StudioBodyparts::zeroed(),
+                models: vec![MDLModel {
+                    head: StudioModel::zeroed(),
+                    meshes: vec![mesh_head(1, 0), mesh_head(0, 3)],
+                }],
+            }],
+            textures: ["metal", "wood"]
+                .map(|name| MDLTexture {
+                    name: name.to_owned(),
+                })
+                .into(),
+        };
+        let vtx = VTX {
+            header: VTXFileHeader::zeroed(),
+            body: vec![VTXBodyPart(vec![VTXModel(vec![VTXModelLOD(vec![
+                vtx_mesh(&[0, 1, 2]),
+                vtx_mesh(&[2, 1, 0]),
+            ])])])],
+        };
+        let verts: Box<[ModelVertex]> = (0..6)
+            .map(|i| {
+                let mut vert = ModelVertex::zeroed();
+                vert.pos = vec3(i as f32, 0.0, 0.0);
+                vert
+            })
+            .collect();
+        let vvd = VVD {
+            header: VertexFileHeader::zeroed(),
+            tangents: vec![Default::default(); verts.len()].into(),
+            verts,
+            fixups: fixups.into(),
+        };
+        (mdl, vtx, vvd)
+    }
+
+    #[test]
+    fn parts() {
+        let (mdl, vtx, vvd) = model(Vec::new());
+        let mesh = PropMesh::new(&mdl, &vtx, &vvd).unwrap();
+
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(
+            mesh.parts,
+            [
+                PropMeshPart {
+                    material: "wood".to_owned(),
+                    indices: vec![0, 2, 1],
+                },
+                PropMeshPart {
+                    material: "metal".to_owned(),
+                    indices: vec![5, 3, 4],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fixups() {
+        // LOD 0 is the second half of the vertices, then the first
+        let fixups = [(3, 0), (0, 3)]
+            .map(|(src, dst)| Fixup {
+                lod: 0,
+                dst,
+                src,
+                count: 3,
+            })
+            .into();
+        let (mdl, vtx, vvd) = model(fixups);
+        let mesh = PropMesh::new(&mdl, &vtx, &vvd).unwrap();
+
+        assert_eq!(mesh.positions[0], vec3(3.0, 0.0, 0.0));
+        assert_eq!(mesh.positions[3], Vec3::ZERO);
+
+        let mut bad = model(Vec::new());
+        bad.0.body[0].models[0].meshes[1].head.vertexoffset = 4;
+        assert!(matches!(
+            PropMesh::new(&bad.0, &bad.1, &bad.2),
+            Err(SourceError::Invalid { .. })
+        ));
+    }
+}
@@ -1,5 +1,6 @@
 pub mod mdl;
 pub mod mdl_headers;
+pub mod mesh;
 pub mod vtx;
 pub mod vvd;
 
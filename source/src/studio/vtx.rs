@@ -57,6 +57,7 @@
 use std::mem;
 
 use crate::binaries::{BinArray, BinaryData};
+use crate::error::SourceResult;
 
 pub struct VTX {
     pub header: VTXFileHeader,
@@ -78,7 +79,7 @@ impl BinaryData for VTX {
     fn read<R: std::io::Read + std::io::Seek>(
         buffer: &mut std::io::BufReader<R>,
         _max_size: Option<usize>,
-    ) -> std::io::Result<Self>
+    ) -> SourceResult<Self>
     where
         Self: Sized,
     {
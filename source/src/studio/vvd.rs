@@ -1,4 +1,5 @@
 use crate::binaries::{BinOffset, BinaryData};
+use crate::error::{ResultExt, SourceError, SourceResult};
 use glam::{Vec2, Vec3, Vec4};
 use std::{io::Seek, mem};
 
@@ -54,11 +55,11 @@ impl BinaryData for VVD {
     fn read<R: std::io::Read + std::io::Seek>(
         buffer: &mut std::io::BufReader<R>,
         _max_size: Option<usize>,
-    ) -> std::io::Result<Self>
+    ) -> SourceResult<Self>
     where
         Self: Sized,
     {
-        let _s = buffer.stream_position()?;
+        let _s = buffer.stream_position().at("VVD", buffer)?;
         let header = VertexFileHeader::read(buffer, None)?;
 
         let mut pos = mem::size_of::<VertexFileHeader>() as i64;
@@ -68,7 +69,13 @@ impl BinaryData for VVD {
         let v = header.vertex_data_start.index;
         let v1 = v + header.num_lod_vertexes[0] * 0x30;
         let t = header.tangent_data_start.index;
-        assert_eq!(v1, t);
+        if v1 != t {
+            return Err(SourceError::invalid(
+                "VertexFileHeader",
+                0,
+                format!("Tangent data at {t} does not follow vertex data ending at {v1}"),
+            ));
+        }
 
         let verts: Box<[ModelVertex]> =
             header
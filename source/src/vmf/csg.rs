@@ -0,0 +1,395 @@
+//! Preview meshes for uncompiled maps, built straight from the planes of a VMF's solids.
+//!
+//! Every side of a solid is a huge square on its plane, clipped by the planes of every other side. Nothing is
+//! merged or split as `vbsp` would, so faces hidden inside the map are kept.
+
+use std::collections::HashMap;
+
+use common::vertex::UVVertex;
+use flagset::FlagSet;
+use glam::{ivec3, UVec2, Vec3, Vec4};
+
+use super::{VMFSide, VMFSolid, Vmf};
+use crate::{
+    bsp::consts::SurfaceFlags,
+    meshes::{MeshBuildOptions, MeshBuilder},
+};
+
+/// Half the size of the starting square for each side, larger than any map
+const MAX_COORD: f32 = 65536.0;
+/// Points this close to a plane are on it
+const ON_EPSILON: f32 = 0.01;
+/// Texture size for materials whose size is unknown
+pub const DEFAULT_TEXTURE_SIZE: UVec2 = UVec2::splat(512);
+
+/// Meshes for the solids of a VMF, in the format of [`crate::meshes::build_meshes`]
+#[derive(Default)]
+pub struct VmfMeshes {
+    /// Material of each mesh, indexed by its key in `meshes`
+    pub materials: Vec<String>,
+    pub meshes: HashMap<i32, MeshBuilder<UVVertex>>,
+    /// IDs of sides clipped away to nothing, which means their solid is not convex or has a duplicate plane
+    pub degenerate: Vec<u32>,
+}
+
+impl VmfMeshes {
+    pub fn material(&self, key: i32) -> Option<&str> {
+        self.materials.get(key as usize).map(String::as_str)
+    }
+}
+
+impl VMFSolid {
+    /// The polygon of every side, in order, wound clockwise looking at the side from outside as BSP faces are.
+    /// Sides clipped away entirely are empty.
+    pub fn windings(&self) -> Vec<Vec<Vec3>> {
+        let planes: Vec<_> = self
+            .sides
+            .iter()
+            .map(|side| (side.normal(), side.dist()))
+            .collect();
+        plane_windings(&planes)
+    }
+}
+
+/// The polygon on each of the planes, given as normal and distance, inside all of the others
+pub(super) fn plane_windings(planes: &[(Vec3, f32)]) -> Vec<Vec<Vec3>> {
+    planes
+        .iter()
+        .enumerate()
+        .map(|(i, &(normal, dist))| {
+            let mut winding = base_winding(normal, dist);
+            for (j, &(other_normal, other_dist)) in planes.iter().enumerate() {
+                if i != j && !winding.is_empty() {
+                    winding = clip_winding(&winding, other_normal, other_dist);
+                }
+            }
+            winding
+        })
+        .collect()
+}
+
+/// Tool materials that are never drawn in game, without the `tools/` prefix
+const INVISIBLE_TOOLS: &[&str] = &[
+    "toolsnodraw",
+    "toolsclip",
+    "toolsplayerclip",
+    "toolsnpcclip",
+    "toolsareaportal",
+    "toolsoccluder",
+    "toolsinvisible",
+    "toolsinvisibleladder",
+    "toolsblocklight",
+    "toolsblockbullets",
+    "toolsblock_los",
+    "toolsorigin",
+    "toolsfog",
+];
+
+/// Surface flags `vbsp` would give a tool material. Other materials set theirs in their VMT, which is not read.
+pub fn tool_surface_flags(material: &str) -> FlagSet<SurfaceFlags> {
+    let material = material.to_ascii_lowercase().replace('\\', "/");
+    let Some(tool) = material.strip_prefix("tools/") else {
+        return FlagSet::default();
+    };
+    match tool {
+        "toolsskybox" => SurfaceFlags::SKY.into(),
+        "toolsskybox2d" => SurfaceFlags::SKY2D.into(),
+        "toolstrigger" => SurfaceFlags::TRIGGER.into(),
+        "toolshint" => SurfaceFlags::HINT.into(),
+        "toolsskip" => SurfaceFlags::SKIP.into(),
+        tool if INVISIBLE_TOOLS.contains(&tool) => SurfaceFlags::NODRAW.into(),
+        // Visible tools such as `toolsblack` are drawn like any other material
+        _ => FlagSet::default(),
+    }
+}
+
+/// Build meshes for every solid in the map, in world space, keeping the surfaces `options` asks for.
+///
+/// [`MeshBuildOptions::include_unlit`] is ignored, as sides have no lightmaps before compiling.
+/// Texture coordinates are divided by `texture_size` of each material, as [`crate::meshes::build_meshes`] divides
+/// by the size in the BSP's tex data, or by [`DEFAULT_TEXTURE_SIZE`] if it is unknown.
+pub fn build_vmf_meshes(
+    vmf: &Vmf,
+    options: &MeshBuildOptions,
+    mut texture_size: impl FnMut(&str) -> Option<UVec2>,
+) -> VmfMeshes {
+    let mut out = VmfMeshes::default();
+    let mut material_keys = HashMap::<String, (i32, UVec2)>::new();
+
+    for solid in vmf.solids() {
+        // Displacements replace their whole solid
+        let displacement = solid.is_displacement();
+
+        for (side, winding) in solid.sides.iter().zip(solid.windings()) {
+            if displacement && side.disp_info.is_none() {
+                continue;
+            }
+            if !(tool_surface_flags(&side.material) & options.exclude).is_empty() {
+                continue;
+            }
+            if winding.len() < 3 {
+                out.degenerate.push(side.id);
+                continue;
+            }
+
+            let material = side.material.to_ascii_lowercase();
+            let (key, size) = *material_keys
+                .entry(material)
+                .or_insert_with_key(|material| {
+                    out.materials.push(material.clone());
+                    (
+                        (out.materials.len() - 1) as i32,
+                        texture_size(material).unwrap_or(DEFAULT_TEXTURE_SIZE),
+                    )
+                });
+            let builder = out.meshes.entry(key).or_default();
+
+            if side.disp_info.is_some() {
+                if winding.len() == 4 {
+                    add_displacement(builder, side, &winding, size);
+                } else {
+                    out.degenerate.push(side.id);
+                }
+            } else {
+                add_side(builder, side, &winding, size);
+            }
+        }
+    }
+
+    out
+}
+
+/// Texture projections as tex info vectors, divided by the texture size
+fn texture_vecs(side: &VMFSide, size: UVec2) -> (Vec4, Vec4) {
+    let (u, v) = (side.u_axis, side.v_axis);
+    let s = Vec4::from((u.axis / u.scale, u.shift)) / size.x.max(1) as f32;
+    let t = Vec4::from((v.axis / v.scale, v.shift)) / size.y.max(1) as f32;
+    (s, t)
+}
+
+/// VMF sides have no lightmaps, so point every vertex at no light data as unlit BSP faces do
+const NO_LIGHT: glam::IVec3 = ivec3(-1, 0, 0);
+
+fn add_side(builder: &mut MeshBuilder<UVVertex>, side: &VMFSide, winding: &[Vec3], size: UVec2) {
+    let (s, t) = texture_vecs(side, size);
+    builder.start_face();
+
+    let verts: Vec<u32> = winding
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| builder.add_vert(i as u32, p, s, t, Vec4::ZERO, Vec4::ZERO, 1.0, NO_LIGHT))
+        .collect();
+    // The same fan as faces from a BSP, so both face the same way
+    for i in 1..verts.len() - 1 {
+        builder.add_tri([verts[i], verts[0], verts[i + 1]]);
+    }
+}
+
+/// Expand a displacement over its four sided winding, the same way displacements in a BSP are built
+fn add_displacement(
+    builder: &mut MeshBuilder<UVVertex>,
+    side: &VMFSide,
+    winding: &[Vec3],
+    size: UVec2,
+) {
+    let disp = side.disp_info.as_ref().unwrap();
+    let (s, t) = texture_vecs(side, size);
+    let normal = side.normal();
+
+    // Start from the corner at the start position, as a BSP face does
+    let mut corners = winding.to_vec();
+    let start = corners
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            a.distance_squared(disp.start_position)
+                .total_cmp(&b.distance_squared(disp.start_position))
+        })
+        .map_or(0, |(i, _)| i);
+    corners.rotate_left(start);
+
+    let side_len = disp.size();
+    // Rows missing from the VMF are all zero
+    fn at<T: Copy + Default>(rows: &[Vec<T>], x: usize, y: usize) -> T {
+        rows.get(y)
+            .and_then(|row| row.get(x))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    builder.start_face();
+    let mut grid = Vec::with_capacity(side_len * side_len);
+    for y in 0..side_len {
+        let dy = y as f32 / (side_len as f32 - 1.0);
+        let v0 = corners[0].lerp(corners[3], dy);
+        let v1 = corners[1].lerp(corners[2], dy);
+
+        for x in 0..side_len {
+            let dx = x as f32 / (side_len as f32 - 1.0);
+            let pos = v0.lerp(v1, dx)
+                + at(&disp.normals, x, y) * at(&disp.distances, x, y)
+                + at(&disp.offsets, x, y)
+                + normal * disp.elevation;
+            let alpha = at(&disp.alphas, x, y);
+
+            let i = (x + side_len * y) as u32;
+            grid.push(builder.add_vert(i, pos, s, t, Vec4::ZERO, Vec4::ZERO, alpha, NO_LIGHT));
+        }
+    }
+
+    for y in 0..side_len - 1 {
+        for x in 0..side_len - 1 {
+            let i = y * side_len + x;
+            let [a, b, c, d] = [i, i + side_len, i + side_len + 1, i + 1].map(|i| grid[i]);
+            builder.add_tri([a, b, c]);
+            builder.add_tri([a, c, d]);
+        }
+    }
+}
+
+/// A square on the plane, much bigger than the map, wound clockwise looking at its front
+fn base_winding(normal: Vec3, dist: f32) -> Vec<Vec3> {
+    if normal == Vec3::ZERO {
+        return Vec::new();
+    }
+
+    // Start from the world axis most unlike the normal
+    let up = if normal.z.abs() > normal.x.abs().max(normal.y.abs()) {
+        Vec3::X
+    } else {
+        Vec3::Z
+    };
+    let up = (up - normal * up.dot(normal)).normalize() * MAX_COORD;
+    let right = up.cross(normal);
+    let origin = normal * dist;
+
+    vec![
+        origin - right + up,
+        origin + right + up,
+        origin + right - up,
+        origin - right - up,
+    ]
+}
+
+/// Keep the part of `winding` behind the plane
+fn clip_winding(winding: &[Vec3], normal: Vec3, dist: f32) -> Vec<Vec3> {
+    let dists: Vec<f32> = winding.iter().map(|p| p.dot(normal) - dist).collect();
+    if dists.iter().all(|&d| d <= ON_EPSILON) {
+        return winding.to_vec();
+    }
+    if dists.iter().all(|&d| d >= -ON_EPSILON) {
+        return Vec::new();
+    }
+
+    let mut out = Vec::with_capacity(winding.len() + 1);
+    for i in 0..winding.len() {
+        let j = (i + 1) % winding.len();
+        let (p, d) = (winding[i], dists[i]);
+        let (q, e) = (winding[j], dists[j]);
+
+        if d <= ON_EPSILON {
+            out.push(p);
+        }
+        if (d > ON_EPSILON && e < -ON_EPSILON) || (d < -ON_EPSILON && e > ON_EPSILON) {
+            out.push(p + (q - p) * (d / (d - e)));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod csg_tests {
+    use glam::{uvec2, vec3};
+
+    use super::*;
+    use crate::vmf::vmf_tests::vmf;
+
+    #[test]
+    fn windings() {
+        let vmf = Vmf::parse(&vmf()).unwrap();
+        let cube = &vmf.world.solids[1];
+
+        for (side, winding) in cube.sides.iter().zip(cube.windings()) {
+            assert_eq!(winding.len(), 4);
+            for p in &winding {
+                assert!(p.abs().abs_diff_eq(Vec3::splat(64.0), 1e-3), "{p}");
+                assert!((p.dot(side.normal()) - side.dist()).abs() < 1e-3);
+            }
+            // Clockwise from outside
+            let turn = (winding[1] - winding[0]).cross(winding[2] - winding[1]);
+            assert!(turn.dot(side.normal()) < 0.0);
+        }
+
+        // A side beyond the top of the cube is clipped away by it
+        let mut side = cube.sides[0].clone();
+        side.plane = side.plane.map(|p| p + Vec3::Z * 36.0);
+        let mut solid = cube.clone();
+        solid.sides.push(side);
+        assert!(solid.windings()[6].is_empty());
+    }
+
+    #[test]
+    fn meshes() {
+        let vmf = Vmf::parse(&vmf()).unwrap();
+        let mut sizes = Vec::new();
+        let built = build_vmf_meshes(&vmf, &MeshBuildOptions::default(), |material| {
+            sizes.push(material.to_owned());
+            Some(uvec2(128, 128))
+        });
+
+        assert_eq!(sizes, ["dev/dev_measuregeneric01"]);
+        assert_eq!(built.material(0), Some("dev/dev_measuregeneric01"));
+        assert!(built.degenerate.is_empty());
+
+        let mesh = &built.meshes[&0];
+        // Two cubes, and the 4x4 quads of the displacement that replaces the first
+        assert_eq!(mesh.tris().len(), (12 + 12 + 32) * 3);
+
+        for tri in mesh.tris().chunks(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| mesh.verts()[i as usize].position);
+            if a.z == 64.0 && b.z == 64.0 && c.z == 64.0 {
+                continue;
+            }
+            // Facing out of the cube, as BSP faces face out of solids
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot((a + b + c) / 3.0) > 0.0, "{a} {b} {c}");
+        }
+
+        // The middle of the displacement is pulled down its normal, and painted
+        let middle = mesh
+            .verts()
+            .iter()
+            .find(|v| v.position == vec3(0.0, 0.0, 48.0))
+            .unwrap();
+        assert_eq!(middle.alpha, 255.0);
+
+        // u is x / 0.25 / 128 on the top and bottom
+        let corner = mesh
+            .verts()
+            .iter()
+            .find(|v| v.position == vec3(64.0, 64.0, -64.0))
+            .unwrap();
+        assert_eq!(corner.uv.x, 2.0);
+    }
+
+    #[test]
+    fn tools() {
+        assert_eq!(
+            tool_surface_flags("TOOLS/TOOLSNODRAW"),
+            SurfaceFlags::NODRAW
+        );
+        assert_eq!(tool_surface_flags("tools\\toolsskybox"), SurfaceFlags::SKY);
+        assert_eq!(tool_surface_flags("tools/toolsclip"), SurfaceFlags::NODRAW);
+        assert!(tool_surface_flags("tools/toolsblack").is_empty());
+        assert!(tool_surface_flags("dev/dev_measuregeneric01").is_empty());
+
+        let text = vmf().replacen("DEV/DEV_MEASUREGENERIC01", "TOOLS/TOOLSNODRAW", 7);
+        let vmf = Vmf::parse(&text).unwrap();
+
+        // The first solid's displacement and the next side are nodraw
+        let built = build_vmf_meshes(&vmf, &MeshBuildOptions::default(), |_| None);
+        assert_eq!(built.meshes[&0].tris().len(), (10 + 12) * 3);
+        let built = build_vmf_meshes(&vmf, &MeshBuildOptions::all(), |_| None);
+        assert_eq!(built.meshes.len(), 2);
+    }
+}
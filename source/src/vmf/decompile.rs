@@ -0,0 +1,697 @@
+//! Rebuilding a map's source from the compiled map, to recover maps whose VMF has been lost.
+//!
+//! Solids come back from the brush lump, with the texture of each side from its tex info, and entities from the
+//! entity lump, with brush entities taking the brushes of their model. Displacement brushes are not kept in the
+//! brush lump, so each displacement is rebuilt on a thin solid under its face. Static props become `prop_static`
+//! entities again. Visgroups, cameras and anything else only the editor knew about are lost.
+
+use std::io::{Read, Seek};
+
+use flagset::FlagSet;
+use glam::{Mat4, Vec3};
+
+use super::{
+    csg::plane_windings, VMFConnection, VMFDispInfo, VMFEditor, VMFEntity, VMFSide, VMFSolid,
+    VMFTextureAxis, VMFVersion, Vmf,
+};
+use crate::{
+    bsp::{
+        brush::{BSPBrush, BSPBrushSide},
+        consts::{Contents, MAX_MAP_DISP_POWER, MIN_MAP_DISP_POWER},
+        displacement::{BSPDispInfo, BSPDispVert},
+        entities::BSPEntity,
+        gamelump::{GameLump, StaticPropLumpV5},
+        plane::BSPPlane,
+        textures::BSPTexInfo,
+        Bsp,
+    },
+    error::{SourceError, SourceResult},
+};
+
+/// Hammer's default texture scale, for sides the compiled map has no texture for
+const DEFAULT_SCALE: f32 = 0.25;
+/// Thickness of the solids rebuilt under displacements
+const DISP_DEPTH: f32 = 8.0;
+/// Corners this close to a whole number are snapped to it, undoing the error from clipping
+const SNAP_EPSILON: f32 = 0.05;
+/// Static prop flag for props that cast no shadows
+const STATIC_PROP_NO_SHADOW: u8 = 0x10;
+const NODRAW: &str = "TOOLS/TOOLSNODRAW";
+
+impl Vmf {
+    /// Rebuild the source of a compiled map. World brushes marked as detail are gathered into one `func_detail`.
+    pub fn decompile<R: Read + Seek>(bsp: &Bsp<R>) -> SourceResult<Self> {
+        let mut decompiler = Decompiler::new(bsp)?;
+
+        let mut world = None;
+        let mut entities = Vec::new();
+        let mut detail = Vec::new();
+
+        for entity in bsp.entities()? {
+            let mut vmf_entity = decompiler.entity(entity);
+
+            if entity.classname() == Some("worldspawn") {
+                for brush in bsp.model_brushes(0)? {
+                    let Some(solid) = decompiler.solid(brush, Mat4::IDENTITY)? else {
+                        continue;
+                    };
+                    if decompiler.brushes[brush]
+                        .contents()
+                        .contains(Contents::DETAIL)
+                    {
+                        detail.push(solid);
+                    } else {
+                        vmf_entity.solids.push(solid);
+                    }
+                }
+                world = Some(vmf_entity);
+                continue;
+            }
+
+            // Brush models are stored relative to their entity
+            if let Some(model) = entity.brush_model() {
+                let transform = entity.transform();
+                for brush in bsp.model_brushes(model)? {
+                    vmf_entity
+                        .solids
+                        .extend(decompiler.solid(brush, transform)?);
+                }
+            }
+            entities.push(vmf_entity);
+        }
+
+        let mut world =
+            world.ok_or_else(|| SourceError::NotFound("worldspawn entity in map".to_owned()))?;
+
+        if !detail.is_empty() {
+            entities.push(VMFEntity {
+                id: decompiler.id(),
+                entity: BSPEntity {
+                    properties: vec![("classname".to_owned(), "func_detail".to_owned())],
+                },
+                connections: Vec::new(),
+                solids: detail,
+                editor: VMFEditor::default(),
+                hidden: false,
+            });
+        }
+
+        let faces = bsp.faces()?;
+        let disp_verts = bsp.disp_verts()?;
+        for (i, info) in bsp.disp_infos()?.iter().enumerate() {
+            let power = info.power as usize;
+            if !(MIN_MAP_DISP_POWER..=MAX_MAP_DISP_POWER).contains(&power) {
+                return Err(invalid(
+                    "BSPDispInfo",
+                    format!("Displacement {i} has power {power}"),
+                ));
+            }
+            let size = (1 << power) + 1;
+
+            let face = faces.get(info.map_face as usize).ok_or_else(|| {
+                invalid(
+                    "BSPDispInfo",
+                    format!("Face {} of displacement {i} out of range", info.map_face),
+                )
+            })?;
+            let verts = usize::try_from(info.disp_vert_start)
+                .ok()
+                .and_then(|first| disp_verts.get(first..first + size * size))
+                .ok_or_else(|| {
+                    invalid(
+                        "BSPDispInfo",
+                        format!("Vertices of displacement {i} out of range"),
+                    )
+                })?;
+
+            let winding = bsp.face_vertices(face)?;
+            world
+                .solids
+                .extend(decompiler.displacement(info, &winding, verts, face.tex_info));
+        }
+
+        if let Some(game_lump) = bsp.static_prop_lump()? {
+            for prop in &game_lump.props {
+                entities.push(VMFEntity {
+                    id: decompiler.id(),
+                    entity: static_prop(game_lump, prop),
+                    connections: Vec::new(),
+                    solids: Vec::new(),
+                    editor: VMFEditor::default(),
+                    hidden: false,
+                });
+            }
+        }
+
+        Ok(Self {
+            version: VMFVersion {
+                editor_version: 400,
+                editor_build: 0,
+                map_version: bsp.header().map_revision as u32,
+                format_version: 100,
+                prefab: false,
+            },
+            visgroups: Vec::new(),
+            world,
+            entities,
+            cameras: Vec::new(),
+            active_camera: None,
+        })
+    }
+}
+
+/// The lumps solids are rebuilt from, and the next free ID
+struct Decompiler<'a> {
+    brushes: &'a [BSPBrush],
+    brush_sides: &'a [BSPBrushSide],
+    planes: &'a [BSPPlane],
+    tex_info: &'a [BSPTexInfo],
+    texture_names: &'a [String],
+    next_id: u32,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new<R: Read + Seek>(bsp: &'a Bsp<R>) -> SourceResult<Self> {
+        Ok(Self {
+            brushes: bsp.brushes()?,
+            brush_sides: bsp.brush_sides()?,
+            planes: bsp.planes()?,
+            tex_info: bsp.tex_info()?,
+            texture_names: bsp.texture_names()?,
+            // Keep the IDs Hammer gave entities, and number everything else after them
+            next_id: bsp
+                .entities()?
+                .iter()
+                .filter_map(hammer_id)
+                .max()
+                .unwrap_or(0)
+                + 1,
+        })
+    }
+
+    fn id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// The entity's key values, with its outputs moved into connections
+    fn entity(&mut self, entity: &BSPEntity) -> VMFEntity {
+        let mut properties = Vec::new();
+        let mut connections = Vec::new();
+
+        for (key, value) in &entity.properties {
+            // Added by the compiler
+            if key.eq_ignore_ascii_case("hammerid")
+                || (key.eq_ignore_ascii_case("model") && value.starts_with('*'))
+            {
+                continue;
+            }
+            match VMFConnection::parse(key, value) {
+                Some(connection) => connections.push(connection),
+                None => properties.push((key.clone(), value.clone())),
+            }
+        }
+
+        VMFEntity {
+            id: hammer_id(entity).unwrap_or_else(|| self.id()),
+            entity: BSPEntity { properties },
+            connections,
+            solids: Vec::new(),
+            editor: VMFEditor::default(),
+            hidden: false,
+        }
+    }
+
+    /// A side on the plane through `plane`, textured by a tex info or with `untextured` if it has none
+    fn side(&mut self, plane: [Vec3; 3], tex_info: i16, untextured: &str) -> VMFSide {
+        let mut side = VMFSide {
+            id: self.id(),
+            plane,
+            material: untextured.to_owned(),
+            u_axis: VMFTextureAxis {
+                axis: Vec3::X,
+                shift: 0.0,
+                scale: DEFAULT_SCALE,
+            },
+            v_axis: VMFTextureAxis {
+                axis: -Vec3::Y,
+                shift: 0.0,
+                scale: DEFAULT_SCALE,
+            },
+            rotation: 0.0,
+            lightmap_scale: 16,
+            smoothing_groups: 0,
+            disp_info: None,
+        };
+        (side.u_axis.axis, side.v_axis.axis) = default_axes(side.normal());
+
+        let Some(info) = usize::try_from(tex_info)
+            .ok()
+            .and_then(|i| self.tex_info.get(i))
+        else {
+            return side;
+        };
+
+        if let Some(name) = self.texture_names.get(info.tex_data as usize) {
+            side.material = name.to_ascii_uppercase();
+        }
+        if let (Some(u), Some(v)) = (texture_axis(info.tex_s), texture_axis(info.tex_t)) {
+            (side.u_axis, side.v_axis) = (u, v);
+        }
+        // Lightmap vectors are luxels per world unit
+        let lightmap_s = info.lightmap_s;
+        let luxels = Vec3::from_slice(&lightmap_s[..3]).length();
+        if luxels > 0.0 {
+            side.lightmap_scale = (1.0 / luxels).round() as u32;
+        }
+        side
+    }
+
+    /// A solid for a brush, moved by `transform`, or `None` if it has too few sides left to be one
+    fn solid(&mut self, i_brush: usize, transform: Mat4) -> SourceResult<Option<VMFSolid>> {
+        let brush = self
+            .brushes
+            .get(i_brush)
+            .ok_or_else(|| invalid("BSPBrush", format!("Brush {i_brush} out of range")))?;
+        let sides = usize::try_from(brush.first_side)
+            .ok()
+            .zip(usize::try_from(brush.num_sides).ok())
+            .and_then(|(first, num)| self.brush_sides.get(first..first + num))
+            .ok_or_else(|| invalid("BSPBrush", format!("Sides of brush {i_brush} out of range")))?;
+        let untextured = tool_material(brush.contents());
+
+        // Bevels only smooth collision, they were never sides in the editor
+        let sides: Vec<&BSPBrushSide> = sides.iter().filter(|side| !side.is_bevel()).collect();
+        let planes = sides
+            .iter()
+            .map(|side| {
+                let plane_num = side.plane_num;
+                let plane = self.planes.get(plane_num as usize).ok_or_else(|| {
+                    invalid(
+                        "BSPBrushSide",
+                        format!("Plane {plane_num} of brush {i_brush} out of range"),
+                    )
+                })?;
+                Ok((plane.normal, plane.dist))
+            })
+            .collect::<SourceResult<Vec<_>>>()?;
+
+        // Each side goes through corners of its polygon, which are usually on the grid. Planes that do not touch
+        // the brush are dropped.
+        let mut solid = VMFSolid {
+            id: self.id(),
+            sides: Vec::new(),
+            editor: VMFEditor::default(),
+            hidden: false,
+        };
+        for (side, winding) in sides.iter().zip(plane_windings(&planes)) {
+            if winding.len() >= 3 {
+                let side = self.side(plane_points(&winding), side.tex_info, untextured);
+                solid.sides.push(side);
+            }
+        }
+        if solid.sides.len() < 4 {
+            return Ok(None);
+        }
+
+        if transform != Mat4::IDENTITY {
+            transform_solid(&mut solid, transform);
+        }
+        Ok(Some(solid))
+    }
+
+    /// A displacement on the top of a thin nodraw solid under its face, as Hammer needs a solid to hold it
+    fn displacement(
+        &mut self,
+        info: &BSPDispInfo,
+        winding: &[Vec3],
+        verts: &[BSPDispVert],
+        tex_info: i16,
+    ) -> Option<VMFSolid> {
+        let size = (1 << info.power) + 1;
+        if winding.len() != 4 || verts.len() != size * size {
+            return None;
+        }
+        let winding: Vec<Vec3> = winding.iter().map(|&p| snap(p)).collect();
+
+        let mut top = self.side(plane_points(&winding), tex_info, NODRAW);
+        let normal = top.normal();
+        let below = |p: Vec3| p - normal * DISP_DEPTH;
+
+        fn grid<T>(
+            verts: &[BSPDispVert],
+            size: usize,
+            f: impl Fn(&BSPDispVert) -> T,
+        ) -> Vec<Vec<T>> {
+            verts
+                .chunks(size)
+                .map(|row| row.iter().map(&f).collect())
+                .collect()
+        }
+        top.disp_info = Some(VMFDispInfo {
+            power: info.power,
+            start_position: info.start_position,
+            flags: 0,
+            elevation: 0.0,
+            subdiv: false,
+            // Vertices that do not move have no direction
+            normals: grid(verts, size, |v| {
+                if v.vec == Vec3::ZERO {
+                    normal
+                } else {
+                    v.vec
+                }
+            }),
+            distances: grid(verts, size, |v| v.dist),
+            offsets: grid(verts, size, |_| Vec3::ZERO),
+            offset_normals: grid(verts, size, |_| normal),
+            alphas: grid(verts, size, |v| v.alpha),
+            // Hammer works these out again
+            triangle_tags: Vec::new(),
+            allowed_verts: info.allowed_verts.iter().map(|&v| v as i32).collect(),
+        });
+
+        let [a, b, c] = top.plane;
+        let mut sides = vec![top];
+        sides.push(self.side([c, b, a].map(below), -1, NODRAW));
+        for (i, &p) in winding.iter().enumerate() {
+            let q = winding[(i + 1) % winding.len()];
+            sides.push(self.side([q, p, below(p)], -1, NODRAW));
+        }
+
+        Some(VMFSolid {
+            id: self.id(),
+            sides,
+            editor: VMFEditor::default(),
+            hidden: false,
+        })
+    }
+}
+
+fn invalid(structure: &'static str, reason: impl Into<String>) -> SourceError {
+    SourceError::invalid(structure, 0, reason)
+}
+
+fn hammer_id(entity: &BSPEntity) -> Option<u32> {
+    entity.get("hammerid")?.trim().parse().ok()
+}
+
+/// The tool texture that gives a side these contents. Clip brushes have no tex info once compiled.
+fn tool_material(contents: FlagSet<Contents>) -> &'static str {
+    match (
+        contents.contains(Contents::PLAYERCLIP),
+        contents.contains(Contents::MONSTERCLIP),
+    ) {
+        (true, true) => "TOOLS/TOOLSCLIP",
+        (true, false) => "TOOLS/TOOLSPLAYERCLIP",
+        (false, true) => "TOOLS/TOOLSNPCCLIP",
+        (false, false) => NODRAW,
+    }
+}
+
+/// Hammer's world aligned texture axes for a side facing `normal`
+fn default_axes(normal: Vec3) -> (Vec3, Vec3) {
+    let n = normal.abs();
+    if n.z >= n.x && n.z >= n.y {
+        (Vec3::X, -Vec3::Y)
+    } else if n.x >= n.y {
+        (Vec3::Y, -Vec3::Z)
+    } else {
+        (Vec3::X, -Vec3::Z)
+    }
+}
+
+/// A tex info vector, texels per world unit along each axis and then a shift, as a unit axis and scale
+fn texture_axis([x, y, z, shift]: [f32; 4]) -> Option<VMFTextureAxis> {
+    let axis = Vec3::new(x, y, z);
+    let texels = axis.length();
+    (texels > 0.0).then(|| VMFTextureAxis {
+        axis: axis / texels,
+        shift,
+        scale: 1.0 / texels,
+    })
+}
+
+fn snap(p: Vec3) -> Vec3 {
+    let rounded = p.round();
+    Vec3::select(
+        (p - rounded).abs().cmplt(Vec3::splat(SNAP_EPSILON)),
+        rounded,
+        p,
+    )
+}
+
+/// The three corners of a winding making the largest triangle, snapped, in the winding's order so the plane
+/// faces the same way
+fn plane_points(winding: &[Vec3]) -> [Vec3; 3] {
+    let points: Vec<Vec3> = winding.iter().map(|&p| snap(p)).collect();
+    let n = points.len();
+
+    let mut best = ([0, 1, 2], f32::MIN);
+    for i in 0..n {
+        for j in i + 1..n {
+            for k in j + 1..n {
+                let area = (points[j] - points[i])
+                    .cross(points[k] - points[j])
+                    .length_squared();
+                if area > best.1 {
+                    best = ([i, j, k], area);
+                }
+            }
+        }
+    }
+    best.0.map(|i| points[i])
+}
+
+/// Move a solid from a brush model's space into the world
+fn transform_solid(solid: &mut VMFSolid, transform: Mat4) {
+    let translation = transform.w_axis.truncate();
+    for side in &mut solid.sides {
+        side.plane = side.plane.map(|p| snap(transform.transform_point3(p)));
+        for axis in [&mut side.u_axis, &mut side.v_axis] {
+            axis.axis = transform.transform_vector3(axis.axis);
+            axis.shift -= translation.dot(axis.axis) / axis.scale;
+        }
+    }
+}
+
+/// A `prop_static` entity for a prop from the game lump
+fn static_prop(game_lump: &GameLump, prop: &StaticPropLumpV5) -> BSPEntity {
+    let (origin, angles, prop_type, flags) =
+        (prop.m_origin, prop.angles, prop.prop_type, prop.flags);
+    let model = game_lump
+        .static_prop_names
+        .get(prop_type as usize)
+        .cloned()
+        .unwrap_or_default();
+
+    let properties = [
+        ("classname", "prop_static".to_owned()),
+        ("origin", format!("{} {} {}", origin.x, origin.y, origin.z)),
+        ("angles", format!("{} {} {}", angles.x, angles.y, angles.z)),
+        ("model", model),
+        ("skin", { prop.skin }.to_string()),
+        ("solid", { prop.solid }.to_string()),
+        ("fademindist", { prop.fade_min_dist }.to_string()),
+        ("fademaxdist", { prop.fade_max_dist }.to_string()),
+        ("fadescale", { prop.fl_forced_fade_scale }.to_string()),
+        (
+            "disableshadows",
+            ((flags & STATIC_PROP_NO_SHADOW != 0) as u8).to_string(),
+        ),
+    ];
+    BSPEntity {
+        properties: properties
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod decompile_tests {
+    use std::io::Cursor;
+
+    use bytemuck::Zeroable;
+    use glam::vec3;
+
+    use super::*;
+    use crate::bsp::{
+        brush::BSPLeafBrush,
+        consts::LumpType,
+        test_map::TestMap,
+        tree::{BSPLeaf, BSPNode},
+    };
+
+    /// The ground map, with a copy of its brush as the model of a `func_brush` placed 256 units up
+    fn map() -> Bsp<Cursor<Vec<u8>>> {
+        Bsp::new(test_map().reader()).unwrap()
+    }
+
+    fn test_map() -> TestMap {
+        let map = TestMap::ground();
+
+        let brushes: Vec<BSPBrush> = map.lump_data(LumpType::Brushes);
+        let mut nodes: Vec<BSPNode> = map.lump_data(LumpType::Nodes);
+        let mut leafs: Vec<BSPLeaf> = map.lump_data(LumpType::Leafs);
+
+        let mut node = BSPNode::zeroed();
+        node.children = [-3, -3];
+        nodes.push(node);
+        let mut leaf = BSPLeaf::zeroed();
+        leaf.first_leaf_brush = 1;
+        leaf.num_leaf_brushes = 1;
+        leafs.push(leaf);
+
+        let mut models = TestMap::model_bytes(Vec3::ZERO, vec3(64.0, 64.0, 0.0), 0, 0, 1);
+        models.extend(TestMap::model_bytes(
+            Vec3::splat(-64.0),
+            vec3(128.0, 128.0, 0.0),
+            1,
+            0,
+            0,
+        ));
+
+        map.with_bytes(
+                LumpType::Entities,
+                br#"{ "classname" "worldspawn" "skyname" "sky_day01_01" }
+{ "classname" "func_brush" "model" "*1" "origin" "0 0 256" "hammerid" "7" "OnUser1" "relay,Trigger,,0,-1" }
+"#
+                .to_vec(),
+            )
+            .with_lump(LumpType::Brushes, &[brushes[0], brushes[0]])
+            .with_lump(
+                LumpType::LeafBrushes,
+                &[0, 1].map(|brush| BSPLeafBrush { brush }),
+            )
+            .with_lump(LumpType::Nodes, &nodes)
+            .with_versioned_bytes(LumpType::Leafs, 1, bytemuck::cast_slice(&leafs).to_vec())
+            .with_bytes(LumpType::Models, models)
+    }
+
+    #[test]
+    fn brushes() {
+        let bsp = map();
+        assert_eq!(bsp.model_brushes(0).unwrap(), [0]);
+        assert_eq!(bsp.model_brushes(1).unwrap(), [1]);
+
+        let vmf = Vmf::decompile(&bsp).unwrap();
+        assert_eq!(vmf.world.entity.get("skyname"), Some("sky_day01_01"));
+        assert_eq!(vmf.world.solids.len(), 1);
+
+        let solid = &vmf.world.solids[0];
+        assert_eq!(solid.sides.len(), 6);
+        for (side, winding) in solid.sides.iter().zip(solid.windings()) {
+            assert_eq!(winding.len(), 4);
+            assert_eq!(side.plane.map(Vec3::round), side.plane);
+        }
+
+        let top = &solid.sides[0];
+        assert_eq!((top.normal(), top.dist()), (Vec3::Z, 0.0));
+        assert_eq!(top.material, "DEV/DEV_MEASUREGENERIC01");
+        assert_eq!(
+            top.u_axis,
+            VMFTextureAxis {
+                axis: Vec3::X,
+                shift: 0.0,
+                scale: 1.0
+            }
+        );
+        assert_eq!(top.lightmap_scale, 16);
+        assert_eq!(solid.sides[1].material, NODRAW);
+        assert_eq!(solid.sides[2].u_axis.axis, Vec3::Y);
+
+        let brush = &vmf.entities[0];
+        assert_eq!(brush.id, 7);
+        assert_eq!(brush.classname(), Some("func_brush"));
+        assert_eq!(brush.entity.get("model"), None);
+        assert_eq!(brush.entity.get("OnUser1"), None);
+        assert_eq!(brush.connections[0].target, "relay");
+        // Moved up to the entity's origin
+        let top = &brush.solids[0].sides[0];
+        assert_eq!((top.normal(), top.dist()), (Vec3::Z, 256.0));
+        assert!(solid.id > 7 && top.id > 7);
+
+        assert_eq!(Vmf::parse(&vmf.to_text()).unwrap(), vmf);
+    }
+
+    #[test]
+    fn displacement() {
+        let bsp = Bsp::new(TestMap::quad().reader()).unwrap();
+        let mut decompiler = Decompiler::new(&bsp).unwrap();
+
+        let mut info = BSPDispInfo::zeroed();
+        info.power = 2;
+        let verts: Vec<BSPDispVert> = (0..25)
+            .map(|i| BSPDispVert {
+                vec: if i == 12 { Vec3::Z } else { Vec3::ZERO },
+                dist: if i == 12 { 16.0 } else { 0.0 },
+                alpha: i as f32,
+            })
+            .collect();
+        let winding = bsp.face_vertices(&bsp.faces().unwrap()[0]).unwrap();
+
+        let solid = decompiler.displacement(&info, &winding, &verts, 0).unwrap();
+        assert_eq!(solid.sides.len(), 6);
+        assert!(solid.windings().iter().all(|winding| winding.len() == 4));
+
+        let top = &solid.sides[0];
+        assert_eq!((top.normal(), top.dist()), (Vec3::Z, 0.0));
+        assert_eq!(top.material, "DEV/DEV_MEASUREGENERIC01");
+        let bottom = &solid.sides[1];
+        assert_eq!((bottom.normal(), bottom.dist()), (-Vec3::Z, DISP_DEPTH));
+        assert_eq!(bottom.material, NODRAW);
+
+        let disp = top.disp_info.as_ref().unwrap();
+        assert_eq!(disp.size(), 5);
+        assert_eq!(disp.distances[2][2], 16.0);
+        assert_eq!(disp.alphas[1][0], 5.0);
+        assert_eq!(disp.normals[0][0], Vec3::Z);
+        assert_eq!(disp.allowed_verts, [0; 10]);
+
+        assert!(decompiler
+            .displacement(&info, &winding[..3], &verts, 0)
+            .is_none());
+    }
+
+    #[test]
+    fn unsupported_props() {
+        let map = test_map().with_static_props(7, &["models/props/crate.mdl"], &[0; 72], 1);
+        let bsp = Bsp::new(map.reader()).unwrap();
+
+        // The brushes and entities are still decompiled, without the props
+        let vmf = Vmf::decompile(&bsp).unwrap();
+        assert_eq!(vmf.world.solids.len(), 1);
+        assert_eq!(vmf.entities.len(), 1);
+        assert_eq!(vmf.entities[0].classname(), Some("func_brush"));
+    }
+
+    #[test]
+    fn static_props() {
+        let mut prop = StaticPropLumpV5::zeroed();
+        prop.m_origin = vec3(1.0, 2.0, 3.5);
+        prop.angles = vec3(0.0, 90.0, 0.0);
+        prop.prop_type = 1;
+        prop.solid = 6;
+        prop.flags = STATIC_PROP_NO_SHADOW;
+        prop.skin = 2;
+        prop.fade_max_dist = 1000.0;
+
+        let game_lump = GameLump {
+            static_prop_names: vec![
+                "models/props/a.mdl".to_owned(),
+                "models/props/crate.mdl".to_owned(),
+            ],
+            ..Default::default()
+        };
+        let entity = static_prop(&game_lump, &prop);
+
+        assert_eq!(entity.classname(), Some("prop_static"));
+        assert_eq!(entity.get("model"), Some("models/props/crate.mdl"));
+        assert_eq!(entity.origin(), Some(vec3(1.0, 2.0, 3.5)));
+        assert_eq!(entity.angles(), Some(vec3(0.0, 90.0, 0.0)));
+        assert_eq!(entity.get("skin"), Some("2"));
+        assert_eq!(entity.get("fademaxdist"), Some("1000"));
+        assert_eq!(entity.get("disableshadows"), Some("1"));
+    }
+}
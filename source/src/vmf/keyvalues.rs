@@ -0,0 +1,273 @@
+//! KeyValues text, the nested `name { "key" "value" child { ... } }` format Hammer saves maps in
+
+use crate::error::{SourceError, SourceResult};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyValues {
+    pub name: String,
+    /// Properties in file order. Keys can repeat, such as for entity outputs
+    pub properties: Vec<(String, String)>,
+    pub children: Vec<KeyValues>,
+    /// Byte offset of the block's name in the text, for errors
+    pub offset: usize,
+}
+
+impl KeyValues {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Value of the first property called `key`, ignoring case
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of the properties called `key`
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.properties
+            .iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The first child block called `name`
+    pub fn child(&self, name: &str) -> Option<&KeyValues> {
+        self.children
+            .iter()
+            .find(|child| child.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a KeyValues> {
+        self.children
+            .iter()
+            .filter(move |child| child.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn with_property(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.properties.push((key.into(), value.to_string()));
+        self
+    }
+
+    pub fn with_child(mut self, child: KeyValues) -> Self {
+        self.children.push(child);
+        self
+    }
+}
+
+pub(crate) enum Token<'a> {
+    Open,
+    Close,
+    String(&'a str),
+}
+
+/// Splits text into braces and strings, quoted or not, with the offset of each. `//` comments are skipped.
+pub(crate) struct Tokens<'a> {
+    text: &'a str,
+    pos: usize,
+    /// Named in errors
+    structure: &'static str,
+    /// Reject strings without quotes, as the entity lump always quotes them
+    quoted_only: bool,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(text: &'a str, structure: &'static str) -> Self {
+        Self {
+            text,
+            pos: 0,
+            structure,
+            quoted_only: false,
+        }
+    }
+
+    pub fn quoted_only(self) -> Self {
+        Self {
+            quoted_only: true,
+            ..self
+        }
+    }
+
+    pub fn next(&mut self) -> SourceResult<Option<(usize, Token<'a>)>> {
+        loop {
+            let rest = &self.text[self.pos..];
+            let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '\0');
+            self.pos += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                break;
+            }
+        }
+
+        let trimmed = &self.text[self.pos..];
+        let start = self.pos;
+        let token = match trimmed.chars().next() {
+            None => return Ok(None),
+            Some('{') => {
+                self.pos += 1;
+                Token::Open
+            }
+            Some('}') => {
+                self.pos += 1;
+                Token::Close
+            }
+            Some('"') => {
+                let len = trimmed[1..]
+                    .find('"')
+                    .ok_or_else(|| self.invalid(start, "Unterminated string"))?;
+                self.pos += len + 2;
+                Token::String(&trimmed[1..len + 1])
+            }
+            Some(c) if self.quoted_only => {
+                return Err(self.invalid(start, format!("Unexpected character {c:?}")))
+            }
+            Some(_) => {
+                let len = trimmed
+                    .find(|c: char| c.is_whitespace() || matches!(c, '{' | '}' | '"'))
+                    .unwrap_or(trimmed.len());
+                self.pos += len;
+                Token::String(&trimmed[..len])
+            }
+        };
+
+        Ok(Some((start, token)))
+    }
+
+    pub fn invalid(&self, offset: usize, reason: impl Into<String>) -> SourceError {
+        SourceError::invalid(self.structure, offset as u64, reason)
+    }
+}
+
+/// Parse every top level block in `text`
+pub fn parse_keyvalues(text: &str) -> SourceResult<Vec<KeyValues>> {
+    let mut tokens = Tokens::new(text, "KeyValues");
+    let mut blocks = Vec::new();
+
+    while let Some((offset, token)) = tokens.next()? {
+        let Token::String(name) = token else {
+            return Err(tokens.invalid(offset, "Expected the name of a block"));
+        };
+        match tokens.next()? {
+            Some((_, Token::Open)) => blocks.push(parse_block(&mut tokens, name, offset)?),
+            _ => return Err(tokens.invalid(offset, format!("Expected '{{' after {name:?}"))),
+        }
+    }
+
+    Ok(blocks)
+}
+
+/// Parse the inside of a block, after its opening brace
+fn parse_block(tokens: &mut Tokens, name: &str, offset: usize) -> SourceResult<KeyValues> {
+    let mut block = KeyValues {
+        name: name.to_owned(),
+        offset,
+        ..Default::default()
+    };
+
+    loop {
+        match tokens.next()? {
+            Some((_, Token::Close)) => return Ok(block),
+            Some((key_offset, Token::String(key))) => match tokens.next()? {
+                Some((_, Token::String(value))) => {
+                    block.properties.push((key.to_owned(), value.to_owned()))
+                }
+                Some((_, Token::Open)) => {
+                    block.children.push(parse_block(tokens, key, key_offset)?);
+                }
+                _ => return Err(tokens.invalid(key_offset, format!("Key {key:?} has no value"))),
+            },
+            Some((open, Token::Open)) => return Err(tokens.invalid(open, "Block has no name")),
+            None => return Err(tokens.invalid(offset, format!("Unterminated block {name:?}"))),
+        }
+    }
+}
+
+/// Write blocks out as text, indented with tabs as Hammer does
+pub fn write_keyvalues(blocks: &[KeyValues]) -> String {
+    let mut out = String::new();
+    for block in blocks {
+        write_block(&mut out, block, 0);
+    }
+    out
+}
+
+fn write_block(out: &mut String, block: &KeyValues, depth: usize) {
+    let indent = "\t".repeat(depth);
+    out.push_str(&format!("{indent}{}\n{indent}{{\n", block.name));
+    for (key, value) in &block.properties {
+        out.push_str(&format!("{indent}\t\"{key}\" \"{value}\"\n"));
+    }
+    for child in &block.children {
+        write_block(out, child, depth + 1);
+    }
+    out.push_str(&format!("{indent}}}\n"));
+}
+
+#[cfg(test)]
+mod keyvalues_tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let text = "// A comment\nversioninfo\n{\n\t\"editorversion\" \"400\"\n}\n\
+            world { \"id\" \"1\" solid { \"id\" \"2\" } \"key\" value // trailing\n hidden{solid{}} }";
+        let blocks = parse_keyvalues(text).unwrap();
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].get("EditorVersion"), Some("400"));
+        assert_eq!(blocks[1].offset, text.find("world").unwrap());
+
+        let world = &blocks[1];
+        assert_eq!(world.properties.len(), 2);
+        assert_eq!(world.get("key"), Some("value"));
+        assert_eq!(world.child("solid").unwrap().get("id"), Some("2"));
+        assert_eq!(world.child("hidden").unwrap().children.len(), 1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let blocks = vec![
+            KeyValues::new("entity")
+                .with_property("classname", "info_target")
+                .with_property("OnUser1", "a,b,,0,-1")
+                .with_property("OnUser1", "c,d,,0,-1")
+                .with_child(KeyValues::new("editor").with_property("color", "0 255 0")),
+            KeyValues::new("cameras").with_property("activecamera", -1),
+        ];
+        let text = write_keyvalues(&blocks);
+        let mut read = parse_keyvalues(&text).unwrap();
+        for block in &mut read {
+            block.offset = 0;
+            for child in &mut block.children {
+                child.offset = 0;
+            }
+        }
+
+        assert_eq!(read, blocks);
+        assert_eq!(read[0].get_all("onuser1").count(), 2);
+    }
+
+    #[test]
+    fn errors() {
+        for bad in [
+            "world { \"id\" }",
+            "world { \"id\" \"1\"",
+            "world \"id\"",
+            "{ }",
+            "world { { } }",
+            "world { \"id }",
+        ] {
+            assert!(
+                matches!(parse_keyvalues(bad), Err(SourceError::Invalid { .. })),
+                "{bad}"
+            );
+        }
+    }
+}
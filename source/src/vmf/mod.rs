@@ -0,0 +1,1086 @@
+//! Hammer map sources, the `.vmf` files maps are compiled from.
+//!
+//! A VMF is KeyValues text. The world and every entity hold their brushes as solids, each a convex shape bounded by
+//! the planes of its sides, and any side can be a displacement.
+//!
+//! https://developer.valvesoftware.com/wiki/Valve_Map_Format
+
+pub mod csg;
+pub mod decompile;
+pub mod keyvalues;
+
+use std::{path::Path, str::FromStr};
+
+use glam::Vec3;
+
+use crate::{
+    bsp::entities::BSPEntity,
+    error::{SourceError, SourceResult},
+};
+
+use self::keyvalues::{parse_keyvalues, write_keyvalues, KeyValues};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VMFVersion {
+    pub editor_version: u32,
+    pub editor_build: u32,
+    /// Increases every time the map is saved
+    pub map_version: u32,
+    pub format_version: u32,
+    pub prefab: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMFVisGroup {
+    pub name: String,
+    pub id: u32,
+    pub color: [u8; 3],
+    pub children: Vec<VMFVisGroup>,
+}
+
+/// Editor only state of a solid or entity
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMFEditor {
+    pub color: [u8; 3],
+    /// IDs of the visgroups this is in
+    pub visgroups: Vec<u32>,
+    pub visgroup_shown: bool,
+}
+
+impl Default for VMFEditor {
+    /// White and shown, as Hammer reads a missing `editor` block
+    fn default() -> Self {
+        Self {
+            color: [255; 3],
+            visgroups: Vec::new(),
+            visgroup_shown: true,
+        }
+    }
+}
+
+/// Texture projection along one axis: `u = dot(point, axis) / scale + shift`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VMFTextureAxis {
+    pub axis: Vec3,
+    pub shift: f32,
+    /// World units per texel
+    pub scale: f32,
+}
+
+/// A displacement on a side. Every array has a row per row of vertices, `2^power + 1` in each direction,
+/// starting from `start_position`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMFDispInfo {
+    pub power: u32,
+    /// The corner of the side the first vertex is on
+    pub start_position: Vec3,
+    pub flags: u32,
+    pub elevation: f32,
+    pub subdiv: bool,
+    /// Direction and distance of every vertex from its place on the flat side
+    pub normals: Vec<Vec<Vec3>>,
+    pub distances: Vec<Vec<f32>>,
+    /// Extra offsets added after the normals, from sculpting
+    pub offsets: Vec<Vec<Vec3>>,
+    pub offset_normals: Vec<Vec<Vec3>>,
+    /// Blend between the two textures of the material, from 0 to 255
+    pub alphas: Vec<Vec<f32>>,
+    /// Walkable and buildable flags of each triangle, two per quad
+    pub triangle_tags: Vec<Vec<u32>>,
+    pub allowed_verts: Vec<i32>,
+}
+
+impl VMFDispInfo {
+    /// Vertices along each side
+    pub fn size(&self) -> usize {
+        (1 << self.power) + 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMFSide {
+    pub id: u32,
+    /// Three points on the plane, clockwise when looking at the side from outside the solid
+    pub plane: [Vec3; 3],
+    pub material: String,
+    pub u_axis: VMFTextureAxis,
+    pub v_axis: VMFTextureAxis,
+    /// Only for the editor, the axes are already rotated
+    pub rotation: f32,
+    /// World units per luxel
+    pub lightmap_scale: u32,
+    pub smoothing_groups: u32,
+    pub disp_info: Option<VMFDispInfo>,
+}
+
+impl VMFSide {
+    /// Outwards normal of the side's plane
+    pub fn normal(&self) -> Vec3 {
+        let [a, b, c] = self.plane;
+        (a - b).cross(c - b).normalize_or_zero()
+    }
+
+    /// Distance of the plane from the origin along its normal
+    pub fn dist(&self) -> f32 {
+        self.plane[0].dot(self.normal())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMFSolid {
+    pub id: u32,
+    pub sides: Vec<VMFSide>,
+    pub editor: VMFEditor,
+    /// Inside a `hidden` block, hidden in the editor
+    pub hidden: bool,
+}
+
+impl VMFSolid {
+    pub fn is_displacement(&self) -> bool {
+        self.sides.iter().any(|side| side.disp_info.is_some())
+    }
+}
+
+/// An entity output, firing `input` on `target` when `output` happens
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMFConnection {
+    pub output: String,
+    pub target: String,
+    pub input: String,
+    pub parameter: String,
+    pub delay: f32,
+    /// Times the output can fire, or -1 for no limit
+    pub times: i32,
+}
+
+impl VMFConnection {
+    /// Read an output from its key and `target,input,parameter,delay,times` value, separated by commas or,
+    /// since the L4D branch, escape. Compiled maps keep outputs in this form as entity key values.
+    pub fn parse(output: &str, value: &str) -> Option<Self> {
+        let separator = if value.contains('\x1b') { '\x1b' } else { ',' };
+        let parts: Vec<&str> = value.split(separator).collect();
+        let &[target, input, parameter, delay, times] = parts.as_slice() else {
+            return None;
+        };
+
+        Some(Self {
+            output: output.to_owned(),
+            target: target.to_owned(),
+            input: input.to_owned(),
+            parameter: parameter.to_owned(),
+            delay: delay.trim().parse().ok()?,
+            times: times.trim().parse().ok()?,
+        })
+    }
+
+    /// The value [`VMFConnection::parse`] reads, separated by escape if any part has a comma in it
+    pub fn value(&self) -> String {
+        let parts = [&self.target, &self.input, &self.parameter];
+        let separator = if parts.iter().any(|part| part.contains(',')) {
+            "\x1b"
+        } else {
+            ","
+        };
+        [
+            self.target.clone(),
+            self.input.clone(),
+            self.parameter.clone(),
+            self.delay.to_string(),
+            self.times.to_string(),
+        ]
+        .join(separator)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMFEntity {
+    pub id: u32,
+    /// Every key value, including `classname`
+    pub entity: BSPEntity,
+    pub connections: Vec<VMFConnection>,
+    /// Brushes of a brush entity, or of the world
+    pub solids: Vec<VMFSolid>,
+    pub editor: VMFEditor,
+    /// Inside a `hidden` block, hidden in the editor
+    pub hidden: bool,
+}
+
+impl VMFEntity {
+    pub fn classname(&self) -> Option<&str> {
+        self.entity.classname()
+    }
+}
+
+/// A camera placed in the 3D view
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VMFCamera {
+    pub position: Vec3,
+    pub look: Vec3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vmf {
+    pub version: VMFVersion,
+    pub visgroups: Vec<VMFVisGroup>,
+    /// The `worldspawn` entity, holding the world's brushes
+    pub world: VMFEntity,
+    pub entities: Vec<VMFEntity>,
+    pub cameras: Vec<VMFCamera>,
+    /// Index into `cameras`, if one is active
+    pub active_camera: Option<usize>,
+}
+
+impl Vmf {
+    pub fn parse(text: &str) -> SourceResult<Self> {
+        let blocks = parse_keyvalues(text)?;
+
+        let version = match blocks
+            .iter()
+            .find(|b| b.name.eq_ignore_ascii_case("versioninfo"))
+        {
+            Some(block) => VMFVersion {
+                editor_version: value_or(block, "editorversion", "VMFVersion", 0)?,
+                editor_build: value_or(block, "editorbuild", "VMFVersion", 0)?,
+                map_version: value_or(block, "mapversion", "VMFVersion", 0)?,
+                format_version: value_or(block, "formatversion", "VMFVersion", 0)?,
+                prefab: value_or::<u8>(block, "prefab", "VMFVersion", 0)? != 0,
+            },
+            None => VMFVersion::default(),
+        };
+
+        let mut world = None;
+        let mut entities = Vec::new();
+        let mut visgroups = Vec::new();
+        let mut cameras = Vec::new();
+        let mut active_camera = None;
+
+        for block in &blocks {
+            match block.name.to_ascii_lowercase().as_str() {
+                "world" => world = Some(read_entity(block, false)?),
+                "entity" => entities.push(read_entity(block, false)?),
+                "hidden" => {
+                    for entity in block.children_named("entity") {
+                        entities.push(read_entity(entity, true)?);
+                    }
+                }
+                "visgroups" => {
+                    visgroups = block
+                        .children_named("visgroup")
+                        .map(read_visgroup)
+                        .collect::<SourceResult<_>>()?;
+                }
+                "cameras" => {
+                    for camera in block.children_named("camera") {
+                        cameras.push(VMFCamera {
+                            position: vec3_value(camera, "position", "VMFCamera")?,
+                            look: vec3_value(camera, "look", "VMFCamera")?,
+                        });
+                    }
+                    let active: i32 = value_or(block, "activecamera", "VMFCamera", -1)?;
+                    active_camera = usize::try_from(active).ok().filter(|&i| i < cameras.len());
+                }
+                _ => (),
+            }
+        }
+
+        let world = world.ok_or_else(|| SourceError::NotFound("world block in VMF".to_owned()))?;
+
+        Ok(Self {
+            version,
+            visgroups,
+            world,
+            entities,
+            cameras,
+            active_camera,
+        })
+    }
+
+    pub fn load(path: &Path) -> SourceResult<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| SourceError::io("Vmf", 0, e).in_file(path))?;
+        Self::parse(&text).map_err(|e| e.in_file(path))
+    }
+
+    /// The map as the blocks Hammer saves, in the same order
+    pub fn to_keyvalues(&self) -> Vec<KeyValues> {
+        let version = &self.version;
+        let mut blocks = vec![
+            KeyValues::new("versioninfo")
+                .with_property("editorversion", version.editor_version)
+                .with_property("editorbuild", version.editor_build)
+                .with_property("mapversion", version.map_version)
+                .with_property("formatversion", version.format_version)
+                .with_property("prefab", version.prefab as u8),
+            KeyValues {
+                children: self.visgroups.iter().map(write_visgroup).collect(),
+                ..KeyValues::new("visgroups")
+            },
+            write_entity("world", &self.world),
+        ];
+
+        for entity in &self.entities {
+            let block = write_entity("entity", entity);
+            blocks.push(if entity.hidden {
+                KeyValues::new("hidden").with_child(block)
+            } else {
+                block
+            });
+        }
+
+        let active = self.active_camera.map_or(-1, |i| i as i32);
+        let mut cameras = KeyValues::new("cameras").with_property("activecamera", active);
+        for camera in &self.cameras {
+            cameras = cameras.with_child(
+                KeyValues::new("camera")
+                    .with_property("position", format!("[{}]", vec3_text(camera.position)))
+                    .with_property("look", format!("[{}]", vec3_text(camera.look))),
+            );
+        }
+        blocks.push(cameras);
+
+        blocks
+    }
+
+    /// The map as VMF text
+    pub fn to_text(&self) -> String {
+        write_keyvalues(&self.to_keyvalues())
+    }
+
+    pub fn save(&self, path: &Path) -> SourceResult<()> {
+        std::fs::write(path, self.to_text()).map_err(|e| SourceError::io("Vmf", 0, e).in_file(path))
+    }
+
+    /// Every solid in the map, from the world then brush entities
+    pub fn solids(&self) -> impl Iterator<Item = &VMFSolid> {
+        std::iter::once(&self.world)
+            .chain(&self.entities)
+            .flat_map(|entity| &entity.solids)
+    }
+
+    /// The visgroup with this ID, searching inside other visgroups
+    pub fn visgroup(&self, id: u32) -> Option<&VMFVisGroup> {
+        fn find(groups: &[VMFVisGroup], id: u32) -> Option<&VMFVisGroup> {
+            groups.iter().find_map(|group| {
+                (group.id == id)
+                    .then_some(group)
+                    .or_else(|| find(&group.children, id))
+            })
+        }
+        find(&self.visgroups, id)
+    }
+}
+
+fn read_visgroup(block: &KeyValues) -> SourceResult<VMFVisGroup> {
+    Ok(VMFVisGroup {
+        name: block.get("name").unwrap_or_default().to_owned(),
+        id: value(block, "visgroupid", "VMFVisGroup")?,
+        color: color_value(block)?,
+        children: block
+            .children_named("visgroup")
+            .map(read_visgroup)
+            .collect::<SourceResult<_>>()?,
+    })
+}
+
+fn read_editor(block: &KeyValues) -> SourceResult<VMFEditor> {
+    let Some(editor) = block.child("editor") else {
+        return Ok(VMFEditor::default());
+    };
+    Ok(VMFEditor {
+        color: color_value(editor)?,
+        visgroups: editor
+            .get_all("visgroupid")
+            .map(|id| parse(editor, "visgroupid", id, "VMFEditor"))
+            .collect::<SourceResult<_>>()?,
+        visgroup_shown: value_or::<u8>(editor, "visgroupshown", "VMFEditor", 1)? != 0,
+    })
+}
+
+fn read_entity(block: &KeyValues, hidden: bool) -> SourceResult<VMFEntity> {
+    let connections = match block.child("connections") {
+        Some(connections) => connections
+            .properties
+            .iter()
+            .map(|(output, value)| read_connection(connections, output, value))
+            .collect::<SourceResult<_>>()?,
+        None => Vec::new(),
+    };
+
+    let mut solids = Vec::new();
+    for child in &block.children {
+        match child.name.to_ascii_lowercase().as_str() {
+            "solid" => solids.push(read_solid(child, false)?),
+            // Solids hidden in the editor
+            "hidden" => {
+                for solid in child.children_named("solid") {
+                    solids.push(read_solid(solid, true)?);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(VMFEntity {
+        id: value_or(block, "id", "VMFEntity", 0)?,
+        entity: BSPEntity {
+            properties: block
+                .properties
+                .iter()
+                .filter(|(key, _)| !key.eq_ignore_ascii_case("id"))
+                .cloned()
+                .collect(),
+        },
+        connections,
+        solids,
+        editor: read_editor(block)?,
+        hidden,
+    })
+}
+
+fn read_connection(block: &KeyValues, output: &str, value: &str) -> SourceResult<VMFConnection> {
+    VMFConnection::parse(output, value).ok_or_else(|| {
+        invalid(
+            block,
+            "VMFConnection",
+            format!("{output} should be target,input,parameter,delay,times: {value:?}"),
+        )
+    })
+}
+
+fn read_solid(block: &KeyValues, hidden: bool) -> SourceResult<VMFSolid> {
+    Ok(VMFSolid {
+        id: value_or(block, "id", "VMFSolid", 0)?,
+        sides: block
+            .children_named("side")
+            .map(read_side)
+            .collect::<SourceResult<_>>()?,
+        editor: read_editor(block)?,
+        hidden,
+    })
+}
+
+fn read_side(block: &KeyValues) -> SourceResult<VMFSide> {
+    const S: &str = "VMFSide";
+
+    // `(x y z) (x y z) (x y z)`
+    let plane = required(block, "plane", S)?;
+    let points = floats(block, "plane", &plane.replace(['(', ')'], " "), S)?;
+    let &[ax, ay, az, bx, by, bz, cx, cy, cz] = points.as_slice() else {
+        return Err(invalid(
+            block,
+            S,
+            format!("plane needs 3 points: {plane:?}"),
+        ));
+    };
+
+    Ok(VMFSide {
+        id: value_or(block, "id", S, 0)?,
+        plane: [
+            Vec3::new(ax, ay, az),
+            Vec3::new(bx, by, bz),
+            Vec3::new(cx, cy, cz),
+        ],
+        material: required(block, "material", S)?.to_owned(),
+        u_axis: texture_axis(block, "uaxis")?,
+        v_axis: texture_axis(block, "vaxis")?,
+        rotation: value_or(block, "rotation", S, 0.0)?,
+        lightmap_scale: value_or(block, "lightmapscale", S, 16)?,
+        smoothing_groups: value_or(block, "smoothing_groups", S, 0)?,
+        disp_info: block.child("dispinfo").map(read_disp_info).transpose()?,
+    })
+}
+
+/// `[x y z shift] scale`
+fn texture_axis(block: &KeyValues, key: &str) -> SourceResult<VMFTextureAxis> {
+    let text = required(block, key, "VMFTextureAxis")?;
+    let values = floats(block, key, &text.replace(['[', ']'], " "), "VMFTextureAxis")?;
+    let &[x, y, z, shift, scale] = values.as_slice() else {
+        return Err(invalid(
+            block,
+            "VMFTextureAxis",
+            format!("{key} should be [x y z shift] scale: {text:?}"),
+        ));
+    };
+    Ok(VMFTextureAxis {
+        axis: Vec3::new(x, y, z),
+        shift,
+        scale,
+    })
+}
+
+fn read_disp_info(block: &KeyValues) -> SourceResult<VMFDispInfo> {
+    const S: &str = "VMFDispInfo";
+
+    let power: u32 = value(block, "power", S)?;
+    if !(2..=4).contains(&power) {
+        return Err(invalid(block, S, format!("power {power} is not 2, 3 or 4")));
+    }
+    let size = (1 << power) + 1;
+
+    // Every row is `rowN` in a child block, holding `per_vertex` numbers for each of `columns`
+    let rows = |name: &str, columns: usize, per_vertex: usize| -> SourceResult<Vec<Vec<f32>>> {
+        let Some(child) = block.child(name) else {
+            return Ok(Vec::new());
+        };
+        let rows = if name == "triangle_tags" {
+            size - 1
+        } else {
+            size
+        };
+        (0..rows)
+            .map(|row| {
+                let key = format!("row{row}");
+                let values = floats(child, &key, required(child, &key, S)?, S)?;
+                if values.len() != columns * per_vertex {
+                    return Err(invalid(
+                        child,
+                        S,
+                        format!(
+                            "{name} {key} has {} numbers, expected {}",
+                            values.len(),
+                            columns * per_vertex
+                        ),
+                    ));
+                }
+                Ok(values)
+            })
+            .collect()
+    };
+    let vectors = |name: &str| -> SourceResult<Vec<Vec<Vec3>>> {
+        Ok(rows(name, size, 3)?
+            .into_iter()
+            .map(|row| row.chunks(3).map(Vec3::from_slice).collect())
+            .collect())
+    };
+
+    Ok(VMFDispInfo {
+        power,
+        start_position: vec3_value(block, "startposition", S)?,
+        flags: value_or(block, "flags", S, 0)?,
+        elevation: value_or(block, "elevation", S, 0.0)?,
+        subdiv: value_or::<u8>(block, "subdiv", S, 0)? != 0,
+        normals: vectors("normals")?,
+        distances: rows("distances", size, 1)?,
+        offsets: vectors("offsets")?,
+        offset_normals: vectors("offset_normals")?,
+        alphas: rows("alphas", size, 1)?,
+        triangle_tags: rows("triangle_tags", (size - 1) * 2, 1)?
+            .into_iter()
+            .map(|row| row.into_iter().map(|tag| tag as u32).collect())
+            .collect(),
+        allowed_verts: match block.child("allowed_verts") {
+            Some(child) => required(child, "10", S)?
+                .split_whitespace()
+                .map(|v| parse(child, "10", v, S))
+                .collect::<SourceResult<_>>()?,
+            None => Vec::new(),
+        },
+    })
+}
+
+fn write_visgroup(visgroup: &VMFVisGroup) -> KeyValues {
+    KeyValues {
+        children: visgroup.children.iter().map(write_visgroup).collect(),
+        ..KeyValues::new("visgroup")
+            .with_property("name", &visgroup.name)
+            .with_property("visgroupid", visgroup.id)
+            .with_property("color", color_text(visgroup.color))
+    }
+}
+
+fn write_editor(editor: &VMFEditor) -> KeyValues {
+    let mut block = KeyValues::new("editor").with_property("color", color_text(editor.color));
+    for id in &editor.visgroups {
+        block = block.with_property("visgroupid", id);
+    }
+    block.with_property("visgroupshown", editor.visgroup_shown as u8)
+}
+
+fn write_entity(name: &str, entity: &VMFEntity) -> KeyValues {
+    let mut block = KeyValues::new(name).with_property("id", entity.id);
+    block.properties.extend(entity.entity.properties.iter().cloned());
+
+    if !entity.connections.is_empty() {
+        let mut connections = KeyValues::new("connections");
+        for connection in &entity.connections {
+            connections = connections.with_property(&connection.output, connection.value());
+        }
+        block = block.with_child(connections);
+    }
+    block.children.extend(entity.solids.iter().map(write_solid));
+
+    // The world has no editor state
+    if name == "entity" {
+        block = block.with_child(write_editor(&entity.editor));
+    }
+    block
+}
+
+fn write_solid(solid: &VMFSolid) -> KeyValues {
+    let block = KeyValues {
+        children: solid.sides.iter().map(write_side).collect(),
+        ..KeyValues::new("solid").with_property("id", solid.id)
+    }
+    .with_child(write_editor(&solid.editor));
+    if solid.hidden {
+        KeyValues::new("hidden").with_child(block)
+    } else {
+        block
+    }
+}
+
+fn write_side(side: &VMFSide) -> KeyValues {
+    let [a, b, c] = side.plane.map(vec3_text);
+    let axis = |axis: &VMFTextureAxis| {
+        format!("[{} {}] {}", vec3_text(axis.axis), axis.shift, axis.scale)
+    };
+
+    let block = KeyValues::new("side")
+        .with_property("id", side.id)
+        .with_property("plane", format!("({a}) ({b}) ({c})"))
+        .with_property("material", &side.material)
+        .with_property("uaxis", axis(&side.u_axis))
+        .with_property("vaxis", axis(&side.v_axis))
+        .with_property("rotation", side.rotation)
+        .with_property("lightmapscale", side.lightmap_scale)
+        .with_property("smoothing_groups", side.smoothing_groups);
+    match &side.disp_info {
+        Some(disp) => block.with_child(write_disp_info(disp)),
+        None => block,
+    }
+}
+
+fn write_disp_info(disp: &VMFDispInfo) -> KeyValues {
+    // Each row in a child block of `rowN` keys, skipping arrays the displacement does not have
+    fn rows<T>(name: &str, rows: &[Vec<T>], text: impl Fn(&T) -> String) -> Option<KeyValues> {
+        (!rows.is_empty()).then(|| KeyValues {
+            properties: rows
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let values: Vec<String> = row.iter().map(&text).collect();
+                    (format!("row{i}"), values.join(" "))
+                })
+                .collect(),
+            ..KeyValues::new(name)
+        })
+    }
+
+    let mut block = KeyValues::new("dispinfo")
+        .with_property("power", disp.power)
+        .with_property("startposition", format!("[{}]", vec3_text(disp.start_position)))
+        .with_property("flags", disp.flags)
+        .with_property("elevation", disp.elevation)
+        .with_property("subdiv", disp.subdiv as u8);
+
+    block.children.extend(
+        [
+            rows("normals", &disp.normals, |&v| vec3_text(v)),
+            rows("distances", &disp.distances, f32::to_string),
+            rows("offsets", &disp.offsets, |&v| vec3_text(v)),
+            rows("offset_normals", &disp.offset_normals, |&v| vec3_text(v)),
+            rows("alphas", &disp.alphas, f32::to_string),
+            rows("triangle_tags", &disp.triangle_tags, u32::to_string),
+        ]
+        .into_iter()
+        .flatten(),
+    );
+
+    if !disp.allowed_verts.is_empty() {
+        let verts: Vec<String> = disp.allowed_verts.iter().map(i32::to_string).collect();
+        block = block.with_child(KeyValues::new("allowed_verts").with_property("10", verts.join(" ")));
+    }
+    block
+}
+
+fn vec3_text(v: Vec3) -> String {
+    format!("{} {} {}", v.x, v.y, v.z)
+}
+
+fn color_text([r, g, b]: [u8; 3]) -> String {
+    format!("{r} {g} {b}")
+}
+
+fn invalid(block: &KeyValues, structure: &'static str, reason: impl Into<String>) -> SourceError {
+    SourceError::invalid(structure, block.offset as u64, reason)
+}
+
+fn required<'a>(block: &'a KeyValues, key: &str, structure: &'static str) -> SourceResult<&'a str> {
+    block
+        .get(key)
+        .ok_or_else(|| invalid(block, structure, format!("{} has no {key}", block.name)))
+}
+
+fn parse<T: FromStr>(
+    block: &KeyValues,
+    key: &str,
+    text: &str,
+    structure: &'static str,
+) -> SourceResult<T> {
+    text.trim()
+        .parse()
+        .map_err(|_| invalid(block, structure, format!("bad {key}: {text:?}")))
+}
+
+fn value<T: FromStr>(block: &KeyValues, key: &str, structure: &'static str) -> SourceResult<T> {
+    parse(block, key, required(block, key, structure)?, structure)
+}
+
+fn value_or<T: FromStr>(
+    block: &KeyValues,
+    key: &str,
+    structure: &'static str,
+    default: T,
+) -> SourceResult<T> {
+    match block.get(key) {
+        Some(text) => parse(block, key, text, structure),
+        None => Ok(default),
+    }
+}
+
+fn floats(
+    block: &KeyValues,
+    key: &str,
+    text: &str,
+    structure: &'static str,
+) -> SourceResult<Vec<f32>> {
+    text.split_whitespace()
+        .map(|v| parse(block, key, v, structure))
+        .collect()
+}
+
+/// `[x y z]`
+fn vec3_value(block: &KeyValues, key: &str, structure: &'static str) -> SourceResult<Vec3> {
+    let text = required(block, key, structure)?;
+    let values = floats(block, key, &text.replace(['[', ']'], " "), structure)?;
+    match values.as_slice() {
+        &[x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(invalid(
+            block,
+            structure,
+            format!("{key} needs 3 numbers: {text:?}"),
+        )),
+    }
+}
+
+fn color_value(block: &KeyValues) -> SourceResult<[u8; 3]> {
+    let Some(text) = block.get("color") else {
+        return Ok([255; 3]);
+    };
+    let values: Vec<u8> = text
+        .split_whitespace()
+        .map(|v| parse(block, "color", v, "VMFEditor"))
+        .collect::<SourceResult<_>>()?;
+    values.try_into().map_err(|_| {
+        invalid(
+            block,
+            "VMFEditor",
+            format!("color needs 3 numbers: {text:?}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod vmf_tests {
+    use glam::vec3;
+
+    use super::*;
+
+    /// Sides of a 128 unit cube around the origin, as Hammer writes them. The top side is a displacement.
+    fn cube(id: u32, disp: &str) -> String {
+        let planes = [
+            (
+                "(-64 64 64) (64 64 64) (64 -64 64)",
+                "[1 0 0 0] 0.25",
+                "[0 -1 0 0] 0.25",
+            ),
+            (
+                "(-64 -64 -64) (64 -64 -64) (64 64 -64)",
+                "[1 0 0 0] 0.25",
+                "[0 -1 0 0] 0.25",
+            ),
+            (
+                "(-64 64 64) (-64 -64 64) (-64 -64 -64)",
+                "[0 1 0 0] 0.25",
+                "[0 0 -1 0] 0.25",
+            ),
+            (
+                "(64 64 -64) (64 -64 -64) (64 -64 64)",
+                "[0 1 0 0] 0.25",
+                "[0 0 -1 0] 0.25",
+            ),
+            (
+                "(64 64 64) (-64 64 64) (-64 64 -64)",
+                "[1 0 0 0] 0.25",
+                "[0 0 -1 0] 0.25",
+            ),
+            (
+                "(64 -64 -64) (-64 -64 -64) (-64 -64 64)",
+                "[1 0 0 0] 0.25",
+                "[0 0 -1 0] 0.25",
+            ),
+        ];
+        let sides: String = planes
+            .iter()
+            .enumerate()
+            .map(|(i, (plane, u, v))| {
+                format!(
+                    "side {{ \"id\" \"{}\" \"plane\" \"{plane}\" \"material\" \"DEV/DEV_MEASUREGENERIC01\" \
+                     \"uaxis\" \"{u}\" \"vaxis\" \"{v}\" \"rotation\" \"0\" \"lightmapscale\" \"16\" \
+                     \"smoothing_groups\" \"0\" {} }}\n",
+                    id * 10 + i as u32,
+                    if i == 0 { disp } else { "" }
+                )
+            })
+            .collect();
+        format!("solid {{ \"id\" \"{id}\" {sides} editor {{ \"color\" \"0 180 0\" \"visgroupid\" \"2\" \"visgroupshown\" \"1\" }} }}")
+    }
+
+    const DISP: &str = r#"dispinfo
+{
+    "power" "2"
+    "startposition" "[-64 -64 64]"
+    "flags" "0"
+    "elevation" "0"
+    "subdiv" "0"
+    normals
+    {
+        "row0" "0 0 1 0 0 1 0 0 1 0 0 1 0 0 1"
+        "row1" "0 0 1 0 0 1 0 0 1 0 0 1 0 0 1"
+        "row2" "0 0 1 0 0 1 0 0 -1 0 0 1 0 0 1"
+        "row3" "0 0 1 0 0 1 0 0 1 0 0 1 0 0 1"
+        "row4" "0 0 1 0 0 1 0 0 1 0 0 1 0 0 1"
+    }
+    distances
+    {
+        "row0" "0 0 0 0 0"
+        "row1" "0 8 8 8 0"
+        "row2" "0 8 16 8 0"
+        "row3" "0 8 8 8 0"
+        "row4" "0 0 0 0 0"
+    }
+    alphas
+    {
+        "row0" "0 0 0 0 0"
+        "row1" "0 0 0 0 0"
+        "row2" "0 0 255 0 0"
+        "row3" "0 0 0 0 0"
+        "row4" "0 0 0 0 0"
+    }
+    triangle_tags
+    {
+        "row0" "9 9 9 9 9 9 9 9"
+        "row1" "9 9 1 0 9 9 9 9"
+        "row2" "9 9 9 9 9 9 9 9"
+        "row3" "9 9 9 9 9 9 9 9"
+    }
+    allowed_verts
+    {
+        "10" "-1 -1 -1 -1 -1 -1 -1 -1 -1 -1"
+    }
+}"#;
+
+    pub(crate) fn vmf() -> String {
+        format!(
+            r#"versioninfo
+{{
+	"editorversion" "400"
+	"editorbuild" "8864"
+	"mapversion" "12"
+	"formatversion" "100"
+	"prefab" "0"
+}}
+visgroups
+{{
+	visgroup
+	{{
+		"name" "Detail"
+		"visgroupid" "1"
+		"color" "65 45 0"
+		visgroup
+		{{
+			"name" "Rocks"
+			"visgroupid" "2"
+			"color" "100 100 100"
+		}}
+	}}
+}}
+world
+{{
+	"id" "1"
+	"mapversion" "12"
+	"classname" "worldspawn"
+	"skyname" "sky_day01_01"
+	{}
+	hidden
+	{{
+		{}
+	}}
+}}
+entity
+{{
+	"id" "40"
+	"classname" "logic_relay"
+	"targetname" "relay"
+	connections
+	{{
+		"OnTrigger" "door,Open,,0.5,-1"
+		"OnTrigger" "lights{esc}TurnOn{esc}{esc}0{esc}1"
+	}}
+	"origin" "0 0 128"
+	editor
+	{{
+		"color" "220 30 220"
+		"visgroupshown" "1"
+	}}
+}}
+hidden
+{{
+	entity
+	{{
+		"id" "50"
+		"classname" "func_detail"
+		{}
+	}}
+}}
+cameras
+{{
+	"activecamera" "0"
+	camera
+	{{
+		"position" "[-200 0 64]"
+		"look" "[0 0 64]"
+	}}
+}}
+cordon
+{{
+	"mins" "(-1024 -1024 -1024)"
+	"maxs" "(1024 1024 1024)"
+	"active" "0"
+}}
+"#,
+            cube(2, DISP),
+            cube(3, ""),
+            cube(5, ""),
+            esc = '\x1b'
+        )
+    }
+
+    #[test]
+    fn parse() {
+        let vmf = Vmf::parse(&vmf()).unwrap();
+
+        assert_eq!(vmf.version.editor_build, 8864);
+        assert_eq!(vmf.version.map_version, 12);
+        assert_eq!(vmf.visgroup(2).unwrap().name, "Rocks");
+        assert_eq!(vmf.visgroups[0].color, [65, 45, 0]);
+
+        assert_eq!(vmf.world.classname(), Some("worldspawn"));
+        assert_eq!(vmf.world.entity.get("skyname"), Some("sky_day01_01"));
+        assert_eq!(vmf.world.solids.len(), 2);
+        assert_eq!(vmf.solids().count(), 3);
+
+        let solid = &vmf.world.solids[0];
+        assert_eq!(solid.id, 2);
+        assert_eq!(solid.editor.visgroups, [2]);
+        assert_eq!(solid.sides.len(), 6);
+        assert!(solid.is_displacement());
+        assert!(!vmf.world.solids[1].is_displacement());
+        assert!(!solid.hidden && vmf.world.solids[1].hidden);
+
+        let side = &solid.sides[1];
+        assert_eq!(side.material, "DEV/DEV_MEASUREGENERIC01");
+        assert_eq!(side.u_axis.axis, Vec3::X);
+        assert_eq!(side.v_axis.scale, 0.25);
+        assert_eq!(side.normal(), -Vec3::Z);
+        assert_eq!(side.dist(), 64.0);
+        for side in &solid.sides {
+            // Every side faces out of the cube
+            assert_eq!(side.dist(), 64.0, "{:?}", side.plane);
+        }
+
+        let relay = &vmf.entities[0];
+        assert_eq!(relay.id, 40);
+        assert!(!relay.hidden);
+        assert_eq!(relay.entity.origin(), Some(vec3(0.0, 0.0, 128.0)));
+        assert_eq!(relay.connections.len(), 2);
+        assert_eq!(relay.connections[0].input, "Open");
+        assert_eq!(relay.connections[0].delay, 0.5);
+        assert_eq!(relay.connections[1].target, "lights");
+        assert_eq!(relay.connections[1].times, 1);
+
+        let detail = &vmf.entities[1];
+        assert!(detail.hidden);
+        assert_eq!(detail.solids[0].id, 5);
+
+        assert_eq!(vmf.cameras[0].look, vec3(0.0, 0.0, 64.0));
+        assert_eq!(vmf.active_camera, Some(0));
+    }
+
+    #[test]
+    fn displacement() {
+        let vmf = Vmf::parse(&vmf()).unwrap();
+        let disp = vmf.world.solids[0].sides[0].disp_info.as_ref().unwrap();
+
+        assert_eq!(disp.size(), 5);
+        assert_eq!(disp.start_position, vec3(-64.0, -64.0, 64.0));
+        assert_eq!(disp.normals.len(), 5);
+        assert_eq!(disp.normals[2][2], -Vec3::Z);
+        assert_eq!(disp.distances[2], [0.0, 8.0, 16.0, 8.0, 0.0]);
+        assert_eq!(disp.alphas[2][2], 255.0);
+        assert!(disp.offsets.is_empty());
+        assert_eq!(disp.triangle_tags.len(), 4);
+        assert_eq!(disp.triangle_tags[1][2], 1);
+        assert_eq!(disp.allowed_verts, [-1; 10]);
+    }
+
+    #[test]
+    fn errors() {
+        let text = vmf();
+        for (from, to) in [
+            ("\"row2\" \"0 8 16 8 0\"", "\"row2\" \"0 8 16 8\""),
+            ("\"power\" \"2\"", "\"power\" \"7\""),
+            (
+                "(64 64 -64) (64 -64 -64) (64 -64 64)",
+                "(64 64 -64) (64 -64 -64)",
+            ),
+            ("\"uaxis\" \"[0 1 0 0] 0.25\"", "\"uaxis\" \"[0 1 0] 0.25\""),
+            ("door,Open,,0.5,-1", "door,Open,0.5,-1"),
+        ] {
+            assert!(text.contains(from), "{from}");
+            let bad = text.replacen(from, to, 1);
+            assert!(
+                matches!(Vmf::parse(&bad), Err(SourceError::Invalid { .. })),
+                "{to}"
+            );
+        }
+
+        assert!(matches!(
+            Vmf::parse("versioninfo { }"),
+            Err(SourceError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn write() {
+        let vmf = Vmf::parse(&vmf()).unwrap();
+        let text = vmf.to_text();
+
+        assert_eq!(Vmf::parse(&text).unwrap(), vmf);
+        // The hidden solid and the hidden entity are each written in their own block
+        assert_eq!(text.matches("hidden\n").count(), 2);
+        assert!(text.contains("\"plane\" \"(-64 64 64) (64 64 64) (64 -64 64)\""));
+        assert!(text.contains("\"uaxis\" \"[1 0 0 0] 0.25\""));
+        assert!(text.contains("\"OnTrigger\" \"door,Open,,0.5,-1\""));
+
+        let connection = VMFConnection {
+            parameter: "a,b".to_owned(),
+            ..vmf.entities[0].connections[0].clone()
+        };
+        assert_eq!(connection.value(), "door\x1bOpen\x1ba,b\x1b0.5\x1b-1");
+        assert_eq!(
+            VMFConnection::parse(&connection.output, &connection.value()),
+            Some(connection)
+        );
+    }
+}
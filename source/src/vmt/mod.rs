@@ -10,13 +10,16 @@
 
 use std::{
     collections::HashMap,
-    io::{self, Read, Seek},
+    io::{Read, Seek},
     sync::{Arc, OnceLock},
 };
 
 use thiserror::Error;
 
-use crate::binaries::BinaryData;
+use crate::{
+    binaries::BinaryData,
+    error::{ResultExt, SourceError, SourceResult},
+};
 
 #[derive(Debug)]
 pub enum VMTUnit {
@@ -33,10 +36,11 @@ pub struct VMT {
     pub patch: OnceLock<Option<Arc<VMT>>>,
 }
 
-#[derive(Error, Debug)]
-pub enum VMTError{
-	#[error("Failed to load VMT `{0}`")]
-	Invalid(String),
+#[derive(Error, Debug, Clone)]
+pub enum VMTError {
+    /// `offset` is into the material's text, after comments are removed
+    #[error("Invalid VMT at offset {offset}: {reason}")]
+    Invalid { offset: usize, reason: String },
 }
 
 impl VMT {
@@ -50,8 +54,7 @@ impl VMT {
     }
 
     pub fn from_string(source: String) -> Result<Self, VMTError> {
-        let mut s = source.as_str();
-        consume_vmt(&mut s).map_err(|e|VMTError::Invalid(source))
+        consume_vmt(&mut source.as_str())
     }
 
     pub fn shader(&self) -> &str {
@@ -104,13 +107,14 @@ fn view_up_to_line(data: &str) -> &str {
     }
 }
 
-fn consume_line(data: &mut &str) -> Result<(), VMTError> {
+/// Whether there was another line to move on to
+fn consume_line(data: &mut &str) -> bool {
     if let Some(next_newline) = data[..].find("\n") {
         // clip up to next new line
         *data = &data[next_newline + 1..];
-        Ok(())
+        true
     } else {
-        Err(VMTError::Invalid("".to_owned()))
+        false
     }
 }
 
@@ -129,10 +133,10 @@ fn consume_string(data: &mut &str) -> Result<String, VMTError> {
     return Ok(str);
 }
 
-fn consume_word(data: &mut &str) -> Result<String, VMTError> {
-    let next = if data.find('"').ok_or(VMTError::Invalid("".to_owned()))? == 0 { 1 } else { 0 };
+fn consume_word(data: &mut &str) -> Result<String, &'static str> {
+    let next = if data.find('"').ok_or("Expected a quoted string")? == 0 { 1 } else { 0 };
 
-    let after = data[next..].find(&['"', ' ', '\n']).ok_or(VMTError::Invalid("".to_owned()))?;
+    let after = data[next..].find(['"', ' ', '\n']).ok_or("Unterminated string")?;
 
     let str = data[next..next + after]
         .trim()
@@ -145,9 +149,15 @@ fn consume_word(data: &mut &str) -> Result<String, VMTError> {
 }
 
 fn consume_vmt(data: &mut &str) -> Result<VMT, VMTError> {
+    let len = data.len();
     *data = data.trim();
+    let source = data.to_owned();
 
-    let mut vmt = VMT::new(data.to_owned(), consume_word(data)?);
+    let shader = consume_word(data).map_err(|reason| VMTError::Invalid {
+        offset: len - data.len(),
+        reason: reason.to_owned(),
+    })?;
+    let mut vmt = VMT::new(source, shader);
 
     loop {
         let mut line_data = view_up_to_line(data);
@@ -164,7 +174,7 @@ fn consume_vmt(data: &mut &str) -> Result<VMT, VMTError> {
         }
 
         // otherwise, consume lines until we find something, or break
-        if consume_line(data).is_err() {
+        if !consume_line(data) {
             break;
         }
     }
@@ -175,16 +185,24 @@ impl BinaryData for VMT {
     fn read<R: Read + Seek>(
         buffer: &mut std::io::BufReader<R>,
         max_size: Option<usize>,
-    ) -> std::io::Result<Self> {
-        let mut bytes = vec![0; max_size.unwrap()];
-        buffer.read_exact(&mut bytes)?;
-
-        let mut data = String::from_utf8(bytes).unwrap();
+    ) -> SourceResult<Self> {
+        let Some(max_size) = max_size else {
+            return Err(SourceError::invalid(
+                "VMT",
+                buffer.stream_position().unwrap_or(0),
+                "VMT size unknown",
+            ));
+        };
+        let mut bytes = vec![0; max_size];
+        buffer.read_exact(&mut bytes).at("VMT", buffer)?;
+
+        let mut data = String::from_utf8(bytes).map_err(|e| {
+            SourceError::invalid("VMT", buffer.stream_position().unwrap_or(0), e.to_string())
+        })?;
  
         remove_comments(&mut data);
 
-        consume_vmt(&mut data.as_str())
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Cannot read VMT"))
+        Ok(consume_vmt(&mut data.as_str())?)
     }
 }
 
@@ -192,7 +210,7 @@ impl BinaryData for VMT {
 mod vmt_tests {
     use crate::bsp::consts::LumpType;
     use crate::bsp::header::BSPHeader;
-    use crate::vmt::{consume_vmt, remove_comments};
+    use crate::vmt::{consume_vmt, remove_comments, VMTError, VMT};
     use crate::vpk::VPKDirectory;
     use std::collections::HashSet;
     use std::path::{Path, PathBuf};
@@ -225,6 +243,18 @@ mod vmt_tests {
         println!("{:?}", vmt.data);
     }
 
+    #[test]
+    fn invalid() {
+        let err = VMT::from_string("\n  LightmappedGeneric {}".to_owned()).unwrap_err();
+        let VMTError::Invalid { offset, reason } = &err;
+        assert_eq!(*offset, 3);
+        assert_eq!(reason, "Expected a quoted string");
+        assert_eq!(
+            err.to_string(),
+            "Invalid VMT at offset 3: Expected a quoted string"
+        );
+    }
+
     #[test]
     fn test_misc_dir() {
         let dir = VPKDirectory::load(Default::default(),PathBuf::from(
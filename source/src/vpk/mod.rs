@@ -25,14 +25,20 @@
 // }
 
 pub mod pak;
+pub mod verify;
+pub mod writer;
 
 use common::{vfile::VFileSystem, vpath::VPath};
 
-use crate::binaries::BinaryData;
+use crate::{
+    binaries::BinaryData,
+    error::{ResultExt, SourceError, SourceResult},
+};
 use ahash::{AHasher, RandomState};
 use std::{
+    borrow::Cow,
     collections::HashMap,
-    io::{self, BufRead, BufReader, Cursor, Read, Seek},
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
     sync::{Arc, OnceLock},
 };
@@ -40,40 +46,49 @@ use std::{
 #[cfg(target_arch = "x86_64")]
 use std::fs::File;
 
+use crate::binaries::mapped;
+#[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+use crate::binaries::mapped::SharedBytes;
+
 use super::{
     studio::{vtx::VTX, vvd::VVD, MDL},
     vmt::VMT,
     vtf::VTF,
 };
 
+/// Archive index of files stored in the directory file after the tree, rather than in a numbered archive
+pub const EMBEDDED_ARCHIVE: u16 = 0x7fff;
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct VPKHeaderV1 {
-    signature: u32,
+    pub signature: u32,
     // = 0x55aa1234;
-    version: u32, // = 2;
+    pub version: u32, // = 2;
 
     // The size, in bytes, of the directory tree
-    tree_size: u32,
+    pub tree_size: u32,
 }
 
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct VPKHeaderV2 {
     // How many bytes of file content are stored in this VPK file (0 in CSGO)
-    file_data_section_size: u32,
+    pub file_data_section_size: u32,
 
     // The size, in bytes, of the section containing MD5 checksums for external archive content
-    archive_md5_section_size: u32,
+    pub archive_md5_section_size: u32,
 
     // The size, in bytes, of the section containing MD5 checksums for content in this file (should always be 48)
-    other_md5_section_size: u32,
+    pub other_md5_section_size: u32,
 
     // The size, in bytes, of the section containing the public key and signature. This is either 0 (CSGO & The Ship) or 296 (HL2, HL2:DM, HL2:EP1, HL2:EP2, HL2:LC, TF2, DOD:S & CS:S)
-    signature_section_size: u32,
+    pub signature_section_size: u32,
 }
 
 impl VPKHeaderV1 {
+    pub const SIGNATURE: u32 = 0x55aa1234;
+
     pub fn pak_header() -> Self {
         Self {
             signature: 0,
@@ -83,6 +98,42 @@ impl VPKHeaderV1 {
     }
 }
 
+/// The signature section of a version 2 directory, present in VPKs of HL2-era games
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VPKSignature {
+    /// DER encoded `SubjectPublicKeyInfo` of an RSA key, 160 bytes in Valve's VPKs
+    pub public_key: Vec<u8>,
+    /// PKCS#1 v1.5 signature of the SHA-256 of the directory file up to the signature section
+    pub signature: Vec<u8>,
+}
+
+impl VPKSignature {
+    /// Read the key and signature, each prefixed with its length, from a section of `section_size` bytes
+    fn read<R: Read + Seek>(buffer: &mut BufReader<R>, section_size: u32) -> SourceResult<Self> {
+        let mut remaining = section_size as u64;
+        let mut read_blob = |name: &str| -> SourceResult<Vec<u8>> {
+            let len = read_u32(buffer)? as u64;
+            if len + 4 > remaining {
+                return Err(SourceError::invalid(
+                    "VPKSignature",
+                    buffer.stream_position().unwrap_or(0),
+                    format!("{name} of {len} bytes runs past the {section_size} byte section"),
+                ));
+            }
+            remaining -= len + 4;
+
+            let mut blob = vec![0; len as usize];
+            buffer.read_exact(&mut blob).at("VPKSignature", buffer)?;
+            Ok(blob)
+        };
+
+        Ok(Self {
+            public_key: read_blob("Public key")?,
+            signature: read_blob("Signature")?,
+        })
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct VPKDirectoryEntry {
@@ -116,60 +167,69 @@ pub enum VPKFileType {
 pub struct VPKFile {
     entry: VPKDirectoryEntry,
     preload: Option<Vec<u8>>,
-    vtf: OnceLock<io::Result<Arc<VTF>>>,
-    vmt: OnceLock<io::Result<Arc<VMT>>>,
-    mdl: OnceLock<io::Result<Arc<MDL>>>,
-    vvd: OnceLock<io::Result<Arc<VVD>>>,
-    vtx: OnceLock<io::Result<Arc<VTX>>>,
+    vtf: OnceLock<SourceResult<Arc<VTF>>>,
+    vmt: OnceLock<SourceResult<Arc<VMT>>>,
+    mdl: OnceLock<SourceResult<Arc<MDL>>>,
+    vvd: OnceLock<SourceResult<Arc<VVD>>>,
+    vtx: OnceLock<SourceResult<Arc<VTX>>>,
 }
 
 impl VPKFile {
-    pub fn load_vmt(&self, vpk: &VPKDirectory) -> io::Result<&Arc<VMT>> {
+    pub fn load_vmt(&self, vpk: &VPKDirectory) -> SourceResult<&Arc<VMT>> {
         self.load_file(vpk, |f| &f.vmt)
     }
 
-    pub fn load_vtf(&self, vpk: &VPKDirectory) -> io::Result<&Arc<VTF>> {
+    pub fn load_vtf(&self, vpk: &VPKDirectory) -> SourceResult<&Arc<VTF>> {
         self.load_file(vpk, |f| &f.vtf)
     }
 
-    pub fn load_mdl(&self, vpk: &VPKDirectory) -> io::Result<&Arc<MDL>> {
+    pub fn load_mdl(&self, vpk: &VPKDirectory) -> SourceResult<&Arc<MDL>> {
         self.load_file(vpk, |f| &f.mdl)
     }
 
-    fn load_file<'a, T: BinaryData, F: FnOnce(&'a VPKFile) -> &'a OnceLock<io::Result<Arc<T>>>>(
+    fn load_file<
+        'a,
+        T: BinaryData,
+        F: FnOnce(&'a VPKFile) -> &'a OnceLock<SourceResult<Arc<T>>>,
+    >(
         &'a self,
         vpk: &VPKDirectory,
         get_cell: F,
-    ) -> io::Result<&'a Arc<T>> {
+    ) -> SourceResult<&'a Arc<T>> {
         match get_cell(self).get_or_init(|| vpk.load_file::<T>(self).map(|f| Arc::new(f))) {
             Ok(x) => Ok(x),
             Err(x) => {
-                log::error!("Error loading data, {}", x.to_string());
-                Err(io::Error::new(x.kind(), ""))
+                log::error!("Error loading data, {}", x);
+                Err(x.clone())
             }
         }
     }
 
-    pub fn vtx(&self) -> &OnceLock<io::Result<Arc<VTX>>> {
+    pub fn vtx(&self) -> &OnceLock<SourceResult<Arc<VTX>>> {
         &self.vtx
     }
 
-    pub fn vvd(&self) -> &OnceLock<io::Result<Arc<VVD>>> {
+    pub fn vvd(&self) -> &OnceLock<SourceResult<Arc<VVD>>> {
         &self.vvd
     }
 
-    pub fn mdl(&self) -> &OnceLock<io::Result<Arc<MDL>>> {
+    pub fn mdl(&self) -> &OnceLock<SourceResult<Arc<MDL>>> {
         &self.mdl
     }
 
-    pub fn vmt(&self) -> &OnceLock<io::Result<Arc<VMT>>> {
+    pub fn vmt(&self) -> &OnceLock<SourceResult<Arc<VMT>>> {
         &self.vmt
     }
 
-    pub fn vtf(&self) -> &OnceLock<io::Result<Arc<VTF>>> {
+    pub fn vtf(&self) -> &OnceLock<SourceResult<Arc<VTF>>> {
         &self.vtf
     }
 
+    /// CRC32 of the whole file, preload bytes included
+    pub fn crc(&self) -> u32 {
+        self.entry.crc
+    }
+
     pub fn len(&self) -> u32 {
         self.entry.entry_length
     }
@@ -189,6 +249,7 @@ pub struct VPKDirectory {
     pub dir_path: PathBuf,
     header1: VPKHeaderV1,
     header2: Option<VPKHeaderV2>,
+    signature: Option<VPKSignature>,
     max_pack_file: u16,
     /// Files map, mapped by extension, then directory, then filename
     pub files: HashMap<
@@ -197,6 +258,12 @@ pub struct VPKDirectory {
         RandomState,
     >,
     pak_archives: Vec<PathBuf>,
+    /// Archives mapped into memory the first time a file is read from them
+    #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+    mapped_archives: Vec<OnceLock<SourceResult<SharedBytes>>>,
+    /// The directory file, mapped the first time an embedded file is read from it
+    #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+    mapped_dir: OnceLock<SourceResult<SharedBytes>>,
     data: VFileSystem,
 }
 
@@ -217,24 +284,36 @@ impl VPKDirectory {
             dir_path: Default::default(),
             header1,
             header2,
+            signature: None,
             max_pack_file: 0,
             files: Default::default(),
             data: Default::default(),
             pak_archives: Default::default(),
+            #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+            mapped_archives: Default::default(),
+            #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+            mapped_dir: OnceLock::new(),
 			
         }
     }
-    pub fn load(file_load: VFileSystem, dir_path: PathBuf) -> io::Result<Self> {
+    pub fn load(file_load: VFileSystem, dir_path: PathBuf) -> SourceResult<Self> {
+        let path = dir_path.clone();
+        Self::load_inner(file_load, dir_path).map_err(|e| e.in_file(path))
+    }
+
+    fn load_inner(file_load: VFileSystem, dir_path: PathBuf) -> SourceResult<Self> {
         match file_load.clone().get(&dir_path) {
             Some(mut buffer) => Self::read(&mut buffer, file_load, dir_path),
             None => {
                 #[cfg(target_arch = "x86_64")]
                 {
-                    let file = File::open(&dir_path)?;
+                    let file = File::open(&dir_path)
+                        .map_err(|e| SourceError::io("VPKDirectory", 0, e))?;
                     let mut buffer = BufReader::new(file);
                     return Self::read(&mut buffer, file_load, dir_path);
                 }
-                panic!("{:?} not found", dir_path);
+                #[allow(unreachable_code)]
+                Err(SourceError::NotFound(dir_path.display().to_string()))
             }
         }
     }
@@ -243,7 +322,7 @@ impl VPKDirectory {
         buffer: &mut BufReader<R>,
         file_load: VFileSystem,
         dir_path: PathBuf,
-    ) -> io::Result<Self> {
+    ) -> SourceResult<Self> {
         let header1 = VPKHeaderV1::read(buffer, None)?;
         let header2 = if header1.version == 2 {
             Some(VPKHeaderV2::read(buffer, None)?)
@@ -253,14 +332,15 @@ impl VPKDirectory {
 
         {
             let v = header1.version;
-            println!("Loading VPK version {}", v);
+            log::debug!("Loading VPK version {}", v);
         }
+        let tree_start = buffer.stream_position().at("VPK tree", buffer)?;
 
         let mut max_pack_file = 0;
         let mut files = HashMap::<_, HashMap<_, HashMap<_, _, _>, _>, _>::default();
 
         loop {
-            let ext = read_string(buffer);
+            let ext = read_string(buffer)?;
             if ext.len() == 0 {
                 break;
             }
@@ -268,7 +348,7 @@ impl VPKDirectory {
             let ext_files = files.entry(ext).or_default();
 
             loop {
-                let dir = read_string(buffer);
+                let dir = read_string(buffer)?;
                 if dir.len() == 0 {
                     break;
                 }
@@ -276,26 +356,33 @@ impl VPKDirectory {
                 let dir_files = ext_files.entry(dir).or_default();
 
                 loop {
-                    let filename = read_string(buffer);
+                    let filename = read_string(buffer)?;
 
                     if filename.len() == 0 {
                         break;
                     }
 
-                    let entry = VPKDirectoryEntry::read(buffer, None).unwrap();
+                    let entry = VPKDirectoryEntry::read(buffer, None)?;
                     let terminator = entry.terminator;
 
-                    assert_eq!(terminator, 0xffff);
+                    if terminator != 0xffff {
+                        return Err(SourceError::invalid(
+                            "VPKDirectoryEntry",
+                            buffer.stream_position().unwrap_or(0),
+                            format!("Bad terminator {terminator:#x} for {filename}"),
+                        ));
+                    }
 
-                    if entry.archive_index != 0x7fff {
-                        // 0x7fff means contained in this same file
+                    if entry.archive_index != EMBEDDED_ARCHIVE {
                         max_pack_file = u16::max(entry.archive_index, max_pack_file);
                     }
 
                     // Read metadata.
                     let preload = if entry.preload_bytes != 0 {
                         let mut buf = vec![0; entry.preload_bytes as usize];
-                        buffer.read_exact(&mut buf[..]).unwrap();
+                        buffer
+                            .read_exact(&mut buf[..])
+                            .at("VPK preload data", buffer)?;
                         Some(buf)
                     } else {
                         None
@@ -319,15 +406,30 @@ impl VPKDirectory {
             }
         }
 
+        let signature = match header2 {
+            Some(header2) if header2.signature_section_size > 0 => {
+                // The signature section follows the tree, embedded file data and MD5 sections
+                let offset = tree_start
+                    + header1.tree_size as u64
+                    + header2.file_data_section_size as u64
+                    + header2.archive_md5_section_size as u64
+                    + header2.other_md5_section_size as u64;
+                // Nothing needs the signature to read files, so a broken one is left for verifying to report
+                buffer
+                    .seek(SeekFrom::Start(offset))
+                    .at("VPKSignature", buffer)
+                    .and_then(|_| VPKSignature::read(buffer, header2.signature_section_size))
+                    .inspect_err(|e| {
+                        log::warn!("Ignoring signature of {}: {e}", dir_path.display())
+                    })
+                    .ok()
+            }
+            _ => None,
+        };
+
 		let mut pak_archives = Vec::new();
         for i in 0..=max_pack_file {
-            // replace dir with number
-            let mut header_pak_path =  dir_path.to_path_buf();
-            let dir_file = dir_path.file_name().unwrap().to_string_lossy();
-            let name = dir_file.replace("_dir", &format!("_{i:0>3}"));
-
-            header_pak_path.set_file_name(name);
-            pak_archives.push(header_pak_path);
+            pak_archives.push(archive_path(&dir_path, i));
         }
 
 
@@ -335,49 +437,51 @@ impl VPKDirectory {
             dir_path,
             header1,
             header2,
+            signature,
             max_pack_file,
             files,
+            #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+            mapped_archives: pak_archives.iter().map(|_| OnceLock::new()).collect(),
+            #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+            mapped_dir: OnceLock::new(),
 			pak_archives,
             data: file_load,
         })
     }
 
-    pub fn load_vtf(&self, path: &dyn VPath) -> io::Result<&Arc<VTF>> {
+    pub fn load_vtf(&self, path: &dyn VPath) -> SourceResult<&Arc<VTF>> {
         self.load_file_once(path, |f| &f.vtf)
     }
     /// Load material from global path (materials/x/y.vmt)
-    pub fn load_vmt(&self, path: &dyn VPath) -> io::Result<&Arc<VMT>> {
+    pub fn load_vmt(&self, path: &dyn VPath) -> SourceResult<&Arc<VMT>> {
         self.load_file_once(path, |f| &f.vmt)
     }
-    pub fn load_mdl(&self, path: &dyn VPath) -> io::Result<&Arc<MDL>> {
+    pub fn load_mdl(&self, path: &dyn VPath) -> SourceResult<&Arc<MDL>> {
         self.load_file_once(path, |f| &f.mdl)
     }
-    pub fn load_vvd(&self, path: &dyn VPath) -> io::Result<&Arc<VVD>> {
+    pub fn load_vvd(&self, path: &dyn VPath) -> SourceResult<&Arc<VVD>> {
         self.load_file_once(path, |f| &f.vvd)
     }
-    pub fn load_vtx(&self, path: &dyn VPath) -> io::Result<&Arc<VTX>> {
+    pub fn load_vtx(&self, path: &dyn VPath) -> SourceResult<&Arc<VTX>> {
         self.load_file_once(path, |f| &f.vtx)
     }
 
-    pub fn file_data<'a>(&'a self, path: &dyn VPath) -> io::Result<&'a VPKFile> {
-        let ext_files = self.files.get(path.ext()).ok_or(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("Extension {} not present", path.ext()),
-        ))?;
+    pub fn file_data<'a>(&'a self, path: &dyn VPath) -> SourceResult<&'a VPKFile> {
+        let ext_files = self.files.get(path.ext()).ok_or_else(|| {
+            SourceError::NotFound(format!("Extension {} not present", path.ext()))
+        })?;
 
-        let dir = ext_files.get(&path.dir()).ok_or(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!(
+        let dir = ext_files.get(&path.dir()).ok_or_else(|| {
+            SourceError::NotFound(format!(
                 "Directory Prefix {} not present while loading {}",
                 path.dir(),
                 path.filename()
-            ),
-        ))?;
+            ))
+        })?;
 
-        let file_data = dir.get(path.filename()).ok_or(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("File {} not present", path.filename()),
-        ))?;
+        let file_data = dir.get(path.filename()).ok_or_else(|| {
+            SourceError::NotFound(format!("File {} not present", path.filename()))
+        })?;
 
         Ok(file_data)
     }
@@ -385,58 +489,169 @@ impl VPKDirectory {
     pub fn load_file_once<
         'a,
         T: BinaryData,
-        F: FnOnce(&'a VPKFile) -> &'a OnceLock<io::Result<Arc<T>>>,
+        F: FnOnce(&'a VPKFile) -> &'a OnceLock<SourceResult<Arc<T>>>,
     >(
         &'a self,
         path: &dyn VPath,
         get_cell: F,
-    ) -> io::Result<&'a Arc<T>> {
+    ) -> SourceResult<&'a Arc<T>> {
         let file_data = self.file_data(path)?;
 
-        file_data.load_file(self, get_cell)
+        file_data.load_file(self, get_cell).map_err(|e| {
+            e.in_file(format!(
+                "{}/{}.{}",
+                path.dir(),
+                path.filename(),
+                path.ext()
+            ))
+        })
     }
 
-    fn load_file<F: BinaryData>(&self, file_data: &VPKFile) -> io::Result<F> {
-        if let Some(preload) = &file_data.preload {
-            // Load from preload data
-            //TODO: delete preload data after
+    pub fn header1(&self) -> &VPKHeaderV1 {
+        &self.header1
+    }
+
+    pub fn header2(&self) -> Option<&VPKHeaderV2> {
+        self.header2.as_ref()
+    }
+
+    /// The public key and signature of a signed version 2 directory
+    pub fn signature(&self) -> Option<&VPKSignature> {
+        self.signature.as_ref()
+    }
+
+    /// Size of the headers before the tree, 12 bytes for version 1 and 28 for version 2
+    fn header_size(&self) -> usize {
+        let header2_size = match self.header2 {
+            Some(_) => std::mem::size_of::<VPKHeaderV2>(),
+            None => 0,
+        };
+        std::mem::size_of::<VPKHeaderV1>() + header2_size
+    }
 
-            let c = Cursor::new(preload);
-            let mut buffer = BufReader::new(c);
+    /// Every file with its path, such as `materials/brick/brickwall001a.vtf`, sorted by path
+    pub fn entries(&self) -> Vec<(String, &VPKFile)> {
+        let mut entries = Vec::new();
+        for (ext, dirs) in &self.files {
+            for (dir, files) in dirs {
+                for (filename, file) in files {
+                    entries.push((entry_path(ext, dir, filename), file));
+                }
+            }
+        }
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        entries
+    }
+
+    /// Raw contents of a file, borrowed from the preload data or a mapped archive where possible
+    pub fn file_bytes(&self, path: &dyn VPath) -> SourceResult<Cow<'_, [u8]>> {
+        self.entry_bytes(self.file_data(path)?)
+    }
 
-            F::read(&mut buffer, Some(preload.len()))
+    /// Raw contents of one of this directory's files, as found by [`VPKDirectory::entries`]
+    pub fn entry_bytes<'a>(&'a self, file_data: &'a VPKFile) -> SourceResult<Cow<'a, [u8]>> {
+        let preload = file_data.preload.as_deref().unwrap_or_default();
+        if file_data.entry.entry_length == 0 {
+            return Ok(Cow::Borrowed(preload));
+        }
+
+        let archive = self.archive_bytes(file_data)?;
+        if preload.is_empty() {
+            Ok(archive)
         } else {
-            // Attempt to load
-            let index = file_data.entry.archive_index;
+            Ok(Cow::Owned([preload, &archive].concat()))
+        }
+    }
 
-            // replace dir with number
-            let mut header_pak_path = self.dir_path.to_path_buf();
-            let dir_file = self.dir_path.file_name().unwrap().to_string_lossy();
-            let name = dir_file.replace("_dir", &format!("_{index:0>3}"));
+    fn load_file<F: BinaryData>(&self, file_data: &VPKFile) -> SourceResult<F> {
+        // Preload data, followed by the rest of the file from its archive
+        //TODO: delete preload data after
+        let bytes = self.entry_bytes(file_data)?;
 
-            #[cfg(target_arch = "wasm32")]
-            if let Some(mut buffer) = self.data.get_str(&name) {
-                // seek and load
-                buffer.seek_relative(file_data.entry.entry_offset as i64)?;
+        let mut buffer = BufReader::new(Cursor::new(&bytes[..]));
+        F::read(&mut buffer, Some(bytes.len()))
+    }
 
-                return F::read(&mut buffer, Some(file_data.entry.entry_length as usize));
-            }
+    /// The file holding the part of an entry that follows its preload data, and that part's offset in it
+    pub fn archive_location(&self, file_data: &VPKFile) -> (&Path, u64) {
+        let offset = file_data.entry.entry_offset as u64;
+        match file_data.entry.archive_index {
+            EMBEDDED_ARCHIVE => (
+                &self.dir_path,
+                (self.header_size() + self.header1.tree_size as usize) as u64 + offset,
+            ),
+            index => (self.pak_archive(index), offset),
+        }
+    }
 
-            #[cfg(target_arch = "x86_64")]
-            {
-                header_pak_path.set_file_name(name);
+    /// The part of a file stored in its numbered archive
+    fn archive_bytes(&self, file_data: &VPKFile) -> SourceResult<Cow<'_, [u8]>> {
+        self.archive_range(
+            file_data.entry.archive_index,
+            file_data.entry.entry_offset as usize,
+            file_data.entry.entry_length as usize,
+        )
+    }
 
-                // open file
-                let file = File::open(header_pak_path)?;
-                let mut buffer = BufReader::new(file);
-                // seek and load
-                buffer.seek_relative(file_data.entry.entry_offset as i64)?;
+    /// `len` bytes at `offset` in a numbered archive, or after the tree for [`EMBEDDED_ARCHIVE`]
+    fn archive_range(&self, index: u16, offset: usize, len: usize) -> SourceResult<Cow<'_, [u8]>> {
+        if index == EMBEDDED_ARCHIVE {
+            return self.embedded_range(offset, len);
+        }
 
-                return F::read(&mut buffer, Some(file_data.entry.entry_length as usize));
-            }
+        // replace dir with number
+        let header_pak_path = archive_path(&self.dir_path, index);
 
-            panic!("Could not find {} without desktop support", name)
+        #[cfg(target_arch = "wasm32")]
+        if let Some(buffer) = header_pak_path
+            .file_name()
+            .and_then(|name| self.data.get_str(&name.to_string_lossy()))
+        {
+            // Already in memory, so borrow it
+            let archive: &[u8] = buffer.into_inner().into_inner();
+            return mapped::slice(archive, offset, len, "VPK archive").map(Cow::Borrowed);
         }
+
+        #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+        {
+            let archive = self
+                .mapped_archives
+                .get(index as usize)
+                .ok_or_else(|| SourceError::NotFound(header_pak_path.display().to_string()))?;
+            return mapped_range(archive, &header_pak_path, offset, len);
+        }
+
+        #[cfg(all(target_arch = "x86_64", not(feature = "mmap")))]
+        return read_range(&header_pak_path, offset, len).map(Cow::Owned);
+
+        #[allow(unreachable_code)]
+        Err(SourceError::NotFound(format!(
+            "{} without desktop support",
+            header_pak_path.display()
+        )))
+    }
+
+    /// `len` bytes at `offset` from the end of the tree, where standalone VPKs keep their data
+    fn embedded_range(&self, offset: usize, len: usize) -> SourceResult<Cow<'_, [u8]>> {
+        let offset = self.header_size() + self.header1.tree_size as usize + offset;
+
+        if let Some(buffer) = self.data.get(&self.dir_path) {
+            // Already in memory, so borrow it
+            let dir: &[u8] = buffer.into_inner().into_inner();
+            return mapped::slice(dir, offset, len, "VPK embedded data").map(Cow::Borrowed);
+        }
+
+        #[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+        return mapped_range(&self.mapped_dir, &self.dir_path, offset, len);
+
+        #[cfg(all(target_arch = "x86_64", not(feature = "mmap")))]
+        return read_range(&self.dir_path, offset, len).map(Cow::Owned);
+
+        #[allow(unreachable_code)]
+        Err(SourceError::NotFound(format!(
+            "{} without desktop support",
+            self.dir_path.display()
+        )))
     }
 
     pub fn max_pack_file(&self) -> u16 {
@@ -448,33 +663,95 @@ impl VPKDirectory {
 	}
 }
 
-pub fn read_string<R: Seek + Read>(buffer: &mut BufReader<R>) -> String {
-    let mut string_buf = Vec::new();
-
-    buffer.read_until(0, &mut string_buf).unwrap();
-    string_buf.pop();
+/// Path of a file within a VPK. The tree stores a single space for an empty directory or extension.
+fn entry_path(ext: &str, dir: &str, filename: &str) -> String {
+    match (dir.trim(), ext.trim()) {
+        ("", "") => filename.to_owned(),
+        ("", ext) => format!("{filename}.{ext}"),
+        (dir, "") => format!("{dir}/{filename}"),
+        (dir, ext) => format!("{dir}/{filename}.{ext}"),
+    }
+}
 
-    unsafe { std::str::from_utf8_unchecked(&string_buf[..]) }.to_owned()
+/// `len` bytes at `offset` in a file, mapped into memory the first time it is read
+#[cfg(all(feature = "mmap", target_arch = "x86_64"))]
+fn mapped_range<'a>(
+    mapped: &'a OnceLock<SourceResult<SharedBytes>>,
+    path: &Path,
+    offset: usize,
+    len: usize,
+) -> SourceResult<Cow<'a, [u8]>> {
+    let bytes = mapped
+        .get_or_init(|| SharedBytes::map(path))
+        .as_ref()
+        .map_err(Clone::clone)?;
+
+    mapped::slice(bytes.as_ref(), offset, len, "VPK archive")
+        .map(Cow::Borrowed)
+        .map_err(|e| e.in_file(path))
 }
 
-pub fn read_u32<R: Seek + Read>(buffer: &mut BufReader<R>) -> u32 {
-    let mut string_buf = [0; 4];
+/// `len` bytes at `offset` in a file
+#[cfg(all(target_arch = "x86_64", not(feature = "mmap")))]
+fn read_range(path: &Path, offset: usize, len: usize) -> SourceResult<Vec<u8>> {
+    // open file
+    let file = File::open(path).map_err(|e| SourceError::io("VPK archive", 0, e).in_file(path))?;
+    let mut buffer = BufReader::new(file);
+    // seek and load
+    buffer
+        .seek_relative(offset as i64)
+        .at("VPK archive", &mut buffer)
+        .map_err(|e| e.in_file(path))?;
+
+    let mut bytes = vec![0; len];
+    buffer
+        .read_exact(&mut bytes)
+        .at("VPK archive", &mut buffer)
+        .map_err(|e| e.in_file(path))?;
+
+    Ok(bytes)
+}
 
-    buffer.read_exact(&mut string_buf).unwrap();
+/// Path of a numbered archive next to a `_dir.vpk` file
+fn archive_path(dir_path: &Path, index: u16) -> PathBuf {
+    let dir_file = dir_path
+        .file_name()
+        .map(|f| f.to_string_lossy())
+        .unwrap_or_default();
+    let name = dir_file.replace("_dir", &format!("_{index:0>3}"));
 
-    u32::from_le_bytes(string_buf)
+    dir_path.with_file_name(name)
 }
 
-pub fn read_u16<R: Seek + Read>(buffer: &mut BufReader<R>) -> u16 {
-    let mut string_buf = [0; 2];
+pub fn read_string<R: Seek + Read>(buffer: &mut BufReader<R>) -> SourceResult<String> {
+    let mut string_buf = Vec::new();
+
+    buffer.read_until(0, &mut string_buf).at("string", buffer)?;
+    if string_buf.pop() != Some(0) {
+        return Err(SourceError::invalid(
+            "string",
+            buffer.stream_position().unwrap_or(0),
+            "Unexpected end of file",
+        ));
+    }
 
-    buffer.read_exact(&mut string_buf).unwrap();
+    String::from_utf8(string_buf).map_err(|e| {
+        SourceError::invalid("string", buffer.stream_position().unwrap_or(0), e.to_string())
+    })
+}
+
+pub fn read_u32<R: Seek + Read>(buffer: &mut BufReader<R>) -> SourceResult<u32> {
+    u32::read(buffer, None)
+}
 
-    u16::from_le_bytes(string_buf)
+pub fn read_u16<R: Seek + Read>(buffer: &mut BufReader<R>) -> SourceResult<u16> {
+    u16::read(buffer, None)
 }
 
 #[cfg(test)]
 mod vpk_tests {
+    use common::vpath::VSplitPath;
+
     use super::*;
 
     const PATH: &str =
@@ -491,6 +768,201 @@ mod vpk_tests {
         println!("{:?}", header);
     }
 
+    #[test]
+    fn unterminated_string() {
+        let mut buffer = BufReader::new(Cursor::new(b"vtf".to_vec()));
+
+        assert!(matches!(
+            read_string(&mut buffer),
+            Err(SourceError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn archive_bytes() {
+        let dir_path =
+            std::env::temp_dir().join(format!("vpk_tests_{}_dir.vpk", std::process::id()));
+        let archive = archive_path(&dir_path, 0);
+        std::fs::write(&archive, b"--abc").unwrap();
+
+        let mut tree = Vec::new();
+        tree.extend_from_slice(b"txt\0dir\0file\0");
+        tree.extend_from_slice(bytemuck::bytes_of(&VPKDirectoryEntry {
+            crc: 0,
+            preload_bytes: 0,
+            archive_index: 0,
+            entry_offset: 2,
+            entry_length: 3,
+            terminator: 0xffff,
+        }));
+        tree.extend_from_slice(b"\0\0\0");
+
+        let mut data = bytemuck::bytes_of(&VPKHeaderV1 {
+            signature: 0x55aa1234,
+            version: 1,
+            tree_size: tree.len() as u32,
+        })
+        .to_vec();
+        data.extend(tree);
+
+        let vpk = VPKDirectory::read(
+            &mut BufReader::new(Cursor::new(data)),
+            Default::default(),
+            dir_path,
+        )
+        .unwrap();
+        let bytes = vpk.file_bytes(&VSplitPath::new("dir", "file", "txt")).unwrap();
+
+        assert_eq!(&bytes[..], b"abc");
+        #[cfg(feature = "mmap")]
+        assert!(matches!(bytes, Cow::Borrowed(_)));
+
+        drop(bytes);
+        drop(vpk);
+        std::fs::remove_file(archive).unwrap();
+    }
+
+    /// A standalone VPK of one file, `dir/file.txt`, whose data `abc` is stored after the tree
+    fn standalone(version: u32) -> Vec<u8> {
+        let mut tree = Vec::new();
+        tree.extend_from_slice(b"txt\0dir\0file\0");
+        tree.extend_from_slice(bytemuck::bytes_of(&VPKDirectoryEntry {
+            crc: 0,
+            preload_bytes: 0,
+            archive_index: EMBEDDED_ARCHIVE,
+            entry_offset: 2,
+            entry_length: 3,
+            terminator: 0xffff,
+        }));
+        tree.extend_from_slice(b"\0\0\0");
+
+        let mut data = bytemuck::bytes_of(&VPKHeaderV1 {
+            signature: VPKHeaderV1::SIGNATURE,
+            version,
+            tree_size: tree.len() as u32,
+        })
+        .to_vec();
+        if version == 2 {
+            data.extend_from_slice(bytemuck::bytes_of(&VPKHeaderV2 {
+                file_data_section_size: 5,
+                archive_md5_section_size: 0,
+                other_md5_section_size: 0,
+                signature_section_size: 0,
+            }));
+        }
+        data.extend(tree);
+        data.extend_from_slice(b"--abc");
+        data
+    }
+
+    #[test]
+    fn embedded_in_memory() {
+        for version in [1, 2] {
+            let files = VFileSystem::from_iter([("addon.vpk".to_owned(), standalone(version))]);
+            let vpk = VPKDirectory::load(files, PathBuf::from("addon.vpk")).unwrap();
+            let bytes = vpk.file_bytes(&VSplitPath::new("dir", "file", "txt")).unwrap();
+
+            assert_eq!(vpk.max_pack_file(), 0);
+            assert_eq!(&bytes[..], b"abc");
+            assert!(matches!(bytes, Cow::Borrowed(_)));
+        }
+    }
+
+    #[test]
+    fn preload_and_archive() {
+        let vmt = b"\"LightmappedGeneric\"\n{\n\t\"$basetexture\" \"brick/wall\"\n}\n";
+        let (preload, rest) = vmt.split_at(12);
+
+        let mut tree = Vec::new();
+        tree.extend_from_slice(b"vmt\0materials/brick\0wall\0");
+        tree.extend_from_slice(bytemuck::bytes_of(&VPKDirectoryEntry {
+            crc: crc32fast::hash(vmt),
+            preload_bytes: preload.len() as u16,
+            archive_index: EMBEDDED_ARCHIVE,
+            entry_offset: 0,
+            entry_length: rest.len() as u32,
+            terminator: 0xffff,
+        }));
+        tree.extend_from_slice(preload);
+        tree.extend_from_slice(b"\0\0\0");
+
+        let mut data = bytemuck::bytes_of(&VPKHeaderV1 {
+            signature: VPKHeaderV1::SIGNATURE,
+            version: 1,
+            tree_size: tree.len() as u32,
+        })
+        .to_vec();
+        let tree_end = (data.len() + tree.len()) as u64;
+        data.extend(tree);
+        data.extend_from_slice(rest);
+
+        let files = VFileSystem::from_iter([("pak.vpk".to_owned(), data)]);
+        let vpk = VPKDirectory::load(files, PathBuf::from("pak.vpk")).unwrap();
+        let path = VSplitPath::new("materials/brick", "wall", "vmt");
+        let file = vpk.file_data(&path).unwrap();
+
+        assert_eq!(&vpk.file_bytes(&path).unwrap()[..], vmt);
+        assert_eq!(vpk.archive_location(file), (Path::new("pak.vpk"), tree_end));
+        assert!(vpk.verify_file(&path).unwrap().is_ok());
+        assert_eq!(vpk.load_vmt(&path).unwrap().get_basetex(), Some("brick/wall"));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn embedded_on_disk() {
+        let path = std::env::temp_dir().join(format!("vpk_tests_{}_addon.vpk", std::process::id()));
+        std::fs::write(&path, standalone(2)).unwrap();
+
+        let vpk = VPKDirectory::load(Default::default(), path.clone()).unwrap();
+        let bytes = vpk
+            .file_bytes(&VSplitPath::new("dir", "file", "txt"))
+            .map(Cow::into_owned);
+        drop(vpk);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(bytes.unwrap(), b"abc");
+    }
+
+    /// A version 2 directory with an empty tree, 48 bytes of other MD5s and then `signature_section`
+    fn signed(signature_section: &[u8]) -> SourceResult<VPKDirectory> {
+        let mut data = bytemuck::bytes_of(&VPKHeaderV1 {
+            signature: VPKHeaderV1::SIGNATURE,
+            version: 2,
+            tree_size: 1,
+        })
+        .to_vec();
+        data.extend_from_slice(bytemuck::bytes_of(&VPKHeaderV2 {
+            file_data_section_size: 0,
+            archive_md5_section_size: 0,
+            other_md5_section_size: 48,
+            signature_section_size: signature_section.len() as u32,
+        }));
+        data.push(0);
+        data.extend_from_slice(&[0; 48]);
+        data.extend_from_slice(signature_section);
+
+        VPKDirectory::read(
+            &mut BufReader::new(Cursor::new(data)),
+            Default::default(),
+            "test_dir.vpk".into(),
+        )
+    }
+
+    #[test]
+    fn signature_section() {
+        let vpk = signed(&[3, 0, 0, 0, 1, 2, 3, 2, 0, 0, 0, 4, 5]).unwrap();
+        let signature = vpk.signature().unwrap();
+        assert_eq!(signature.public_key, [1, 2, 3]);
+        assert_eq!(signature.signature, [4, 5]);
+
+        assert!(signed(&[]).unwrap().signature().is_none());
+        // A truncated section still loads, without a signature
+        let truncated = signed(&[3, 0, 0, 0, 1, 2, 3, 9, 0, 0, 0, 4, 5]).unwrap();
+        assert!(truncated.signature().is_none());
+        assert_eq!({ truncated.header2().unwrap().signature_section_size }, 13);
+    }
+
     #[test]
     fn test_dir() {
         let dir = VPKDirectory::load(Default::default(), PathBuf::from(PATH)).unwrap();
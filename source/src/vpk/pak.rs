@@ -1,11 +1,12 @@
 use std::{
-    io::{self, BufReader, Read, Seek},
+    io::{BufReader, Read, Seek},
     sync::OnceLock,
 };
 
 use stream_unzip::ZipReader;
 
 use crate::binaries::BinaryData;
+use crate::error::{ResultExt, SourceError, SourceResult};
 use crate::bsp::consts::LumpType;
 use crate::bsp::Lump;
 use crate::vpk::{VPKDirectory, VPKDirectoryEntry, VPKFile};
@@ -26,9 +27,16 @@ impl BinaryData for VPKDirectory {
     fn read<R: Read + Seek>(
         buffer: &mut BufReader<R>,
         max_size: Option<usize>,
-    ) -> io::Result<Self> {
-        let mut pakfile_data = bytemuck::zeroed_slice_box(max_size.unwrap());
-        buffer.read_exact(&mut pakfile_data)?;
+    ) -> SourceResult<Self> {
+        let Some(max_size) = max_size else {
+            return Err(SourceError::invalid(
+                "PakFile",
+                buffer.stream_position().unwrap_or(0),
+                "Pak file size unknown",
+            ));
+        };
+        let mut pakfile_data = bytemuck::zeroed_slice_box(max_size);
+        buffer.read_exact(&mut pakfile_data).at("PakFile", buffer)?;
         let mut zip_reader = ZipReader::default();
 
         zip_reader.update(pakfile_data.into());
@@ -41,10 +49,16 @@ impl BinaryData for VPKDirectory {
 
         for e in zip_reader.drain_entries() {
             let Some(filename_sep) = e.header().filename.rfind('/') else {
-                println!("Cannot work with {:?}", e.header().filename);
+                log::warn!("Skipping pak file {:?} without a directory", e.header().filename);
+                continue;
+            };
+            let Some(ext_sep) = e.header().filename[filename_sep..]
+                .rfind('.')
+                .map(|i| i + filename_sep)
+            else {
+                log::warn!("Skipping pak file {:?} without an extension", e.header().filename);
                 continue;
             };
-            let ext_sep = e.header().filename.find('.').unwrap();
 
             let ext = e.header().filename[ext_sep + 1..].to_owned();
             let dir = e.header().filename[..filename_sep].to_owned();
@@ -0,0 +1,457 @@
+//! Building VPKs, laid out the way [`VPKDirectory::read`](super::VPKDirectory::read) parses them
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest, Md5};
+
+use super::{archive_path, VPKDirectoryEntry, VPKHeaderV1, VPKHeaderV2, EMBEDDED_ARCHIVE};
+use crate::error::{SourceError, SourceResult};
+
+/// Archives are summed in pieces of this size for the archive MD5 section, as Valve's tools do
+const MD5_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How a [`VPKWriter`] lays out its files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VPKWriteOptions {
+    /// 1, or 2 for a header with MD5 and signature sections
+    pub version: u32,
+    /// Bytes from the start of each file to keep in the directory, so they can be read without opening an archive
+    pub preload_bytes: u16,
+    /// Size at which to start a new `_NNN.vpk` archive, which can't be 0. Entries hold 32 bit offsets, so
+    /// anything over `u32::MAX` is treated as `u32::MAX`.
+    pub max_archive_size: u64,
+    /// Write the MD5 sections of a version 2 directory
+    pub md5: bool,
+}
+
+impl Default for VPKWriteOptions {
+    fn default() -> Self {
+        Self {
+            version: 2,
+            preload_bytes: 0,
+            max_archive_size: 200 * 1024 * 1024,
+            md5: true,
+        }
+    }
+}
+
+/// Files to pack into a VPK, written out as a `_dir.vpk` with its data in numbered archives beside it
+#[derive(Debug, Default, Clone)]
+pub struct VPKWriter {
+    /// File contents by extension, directory and file name, as the tree is ordered
+    files: BTreeMap<String, BTreeMap<String, BTreeMap<String, Vec<u8>>>>,
+}
+
+impl VPKWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file by its path within the VPK, such as `materials/brick/brickwall001a.vtf`.
+    /// Paths are lowercased, as the engine looks files up in lowercase.
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) {
+        let path = path.to_ascii_lowercase().replace('\\', "/");
+        let (dir, filename) = path.rsplit_once('/').unwrap_or(("", &path));
+        let (filename, ext) = filename.rsplit_once('.').unwrap_or((filename, ""));
+
+        // The tree ends a level at an empty string, so empty names are stored as a space
+        let non_empty = |s: &str| {
+            if s.is_empty() {
+                " ".to_owned()
+            } else {
+                s.to_owned()
+            }
+        };
+        self.files
+            .entry(non_empty(ext))
+            .or_default()
+            .entry(non_empty(dir))
+            .or_default()
+            .insert(filename.to_owned(), data);
+    }
+
+    /// Add every file under `root`, with paths relative to it
+    pub fn add_dir(&mut self, root: &Path) -> SourceResult<()> {
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let read_error = |e| SourceError::io("VPKWriter", 0, e).in_file(&dir);
+            for entry in fs::read_dir(&dir).map_err(read_error)? {
+                let path = entry.map_err(read_error)?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+
+                let data = fs::read(&path)
+                    .map_err(|e| SourceError::io("VPKWriter", 0, e).in_file(&path))?;
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                self.add_file(&relative.to_string_lossy(), data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of files added
+    pub fn len(&self) -> usize {
+        self.files
+            .values()
+            .flat_map(|dirs| dirs.values())
+            .map(|files| files.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The directory file and the numbered archives holding the file data
+    pub fn build(&self, options: &VPKWriteOptions) -> SourceResult<(Vec<u8>, Vec<Vec<u8>>)> {
+        if !matches!(options.version, 1 | 2) {
+            return Err(SourceError::UnsupportedVersion {
+                structure: "VPK",
+                version: options.version.to_string(),
+            });
+        }
+        if options.max_archive_size == 0 {
+            return Err(SourceError::invalid(
+                "VPKWriter",
+                0,
+                "Archives need a size of at least 1 byte",
+            ));
+        }
+
+        let mut tree = Vec::new();
+        let mut archives: Vec<Vec<u8>> = Vec::new();
+        let max_archive_size = options.max_archive_size.min(u32::MAX as u64);
+
+        for (ext, dirs) in &self.files {
+            push_string(&mut tree, ext);
+            for (dir, files) in dirs {
+                push_string(&mut tree, dir);
+                for (filename, data) in files {
+                    push_string(&mut tree, filename);
+
+                    let (preload, rest) =
+                        data.split_at(data.len().min(options.preload_bytes as usize));
+                    let (archive_index, entry_offset) = if rest.is_empty() {
+                        (EMBEDDED_ARCHIVE, 0)
+                    } else {
+                        // Files are never split between archives, so a file larger than the limit gets one to itself
+                        let full = archives.last().is_none_or(|archive| {
+                            !archive.is_empty()
+                                && (archive.len() + rest.len()) as u64 > max_archive_size
+                        });
+                        if full {
+                            // The index after the last archive marks files kept in the directory
+                            if archives.len() == EMBEDDED_ARCHIVE as usize {
+                                return Err(SourceError::invalid(
+                                    "VPKWriter",
+                                    0,
+                                    format!(
+                                        "Files need more than {EMBEDDED_ARCHIVE} archives, so the archive size must be larger"
+                                    ),
+                                ));
+                            }
+                            archives.push(Vec::new());
+                        }
+                        let archive = archives.last_mut().unwrap();
+                        let offset = archive.len() as u32;
+                        archive.extend_from_slice(rest);
+                        (archives.len() as u16 - 1, offset)
+                    };
+                    let entry_length = u32::try_from(rest.len()).map_err(|_| {
+                        SourceError::invalid(
+                            "VPKWriter",
+                            0,
+                            format!("{dir}/{filename}.{ext} is 4 GiB or larger"),
+                        )
+                    })?;
+
+                    let entry = VPKDirectoryEntry {
+                        crc: crc32fast::hash(data),
+                        preload_bytes: preload.len() as u16,
+                        archive_index,
+                        entry_offset,
+                        entry_length,
+                        terminator: 0xffff,
+                    };
+                    tree.extend_from_slice(bytemuck::bytes_of(&entry));
+                    tree.extend_from_slice(preload);
+                }
+                tree.push(0);
+            }
+            tree.push(0);
+        }
+        tree.push(0);
+
+        let header = VPKHeaderV1 {
+            signature: VPKHeaderV1::SIGNATURE,
+            version: options.version,
+            tree_size: tree.len() as u32,
+        };
+        let mut dir = bytemuck::bytes_of(&header).to_vec();
+        if options.version == 1 {
+            dir.extend(tree);
+            return Ok((dir, archives));
+        }
+
+        let archive_md5 = if options.md5 {
+            archive_md5_section(&archives)
+        } else {
+            Vec::new()
+        };
+        let header2 = VPKHeaderV2 {
+            file_data_section_size: 0,
+            archive_md5_section_size: archive_md5.len() as u32,
+            other_md5_section_size: if options.md5 { 48 } else { 0 },
+            signature_section_size: 0,
+        };
+        dir.extend_from_slice(bytemuck::bytes_of(&header2));
+        dir.extend_from_slice(&tree);
+        dir.extend_from_slice(&archive_md5);
+        if options.md5 {
+            dir.extend_from_slice(&Md5::digest(&tree));
+            dir.extend_from_slice(&Md5::digest(&archive_md5));
+            // Covers everything before it, including the two sums just written
+            let whole_file = Md5::digest(&dir);
+            dir.extend_from_slice(&whole_file);
+        }
+        Ok((dir, archives))
+    }
+
+    /// Write the VPK to `dir_path`, which should end in `_dir.vpk`, with its archives beside it
+    pub fn write(&self, dir_path: &Path, options: &VPKWriteOptions) -> SourceResult<()> {
+        let (dir, archives) = self.build(options)?;
+        let write = |path: PathBuf, contents: &[u8]| {
+            fs::write(&path, contents).map_err(|e| SourceError::io("VPKWriter", 0, e).in_file(path))
+        };
+
+        let is_dir_file = dir_path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().ends_with("_dir.vpk"));
+        if !archives.is_empty() && !is_dir_file {
+            return Err(SourceError::invalid(
+                "VPKWriter",
+                0,
+                "Archives are named after the directory file, so it must end in _dir.vpk",
+            )
+            .in_file(dir_path));
+        }
+
+        write(dir_path.to_path_buf(), &dir)?;
+        for (i, archive) in archives.iter().enumerate() {
+            write(archive_path(dir_path, i as u16), archive)?;
+        }
+        Ok(())
+    }
+}
+
+/// `(archive, offset, length, MD5)` for each chunk of each archive
+fn archive_md5_section(archives: &[Vec<u8>]) -> Vec<u8> {
+    let mut section = Vec::new();
+    for (index, archive) in archives.iter().enumerate() {
+        for (i, chunk) in archive.chunks(MD5_CHUNK_SIZE).enumerate() {
+            for value in [index, i * MD5_CHUNK_SIZE, chunk.len()] {
+                section.extend_from_slice(&(value as u32).to_le_bytes());
+            }
+            section.extend_from_slice(&Md5::digest(chunk));
+        }
+    }
+    section
+}
+
+fn push_string(tree: &mut Vec<u8>, s: &str) {
+    tree.extend_from_slice(s.as_bytes());
+    tree.push(0);
+}
+
+#[cfg(test)]
+mod writer_tests {
+    use std::io::{BufReader, Cursor};
+
+    use common::vfile::VFileSystem;
+
+    use super::*;
+    use crate::vpk::VPKDirectory;
+
+    /// Files of several sizes, some smaller than the preload used in tests
+    fn files() -> Vec<(String, Vec<u8>)> {
+        (0..12u8)
+            .map(|i| {
+                let path = format!("materials/dir{}/file{i}.vtf", i % 3);
+                (path, (0..i as u32 * 20).map(|b| (b as u8) ^ i).collect())
+            })
+            .collect()
+    }
+
+    fn writer() -> VPKWriter {
+        let mut writer = VPKWriter::new();
+        for (path, data) in files() {
+            writer.add_file(&path, data);
+        }
+        writer
+    }
+
+    #[test]
+    fn tree() {
+        let mut writer = VPKWriter::new();
+        writer.add_file(
+            "Materials\\Brick\\Wall.vmt",
+            b"LightmappedGeneric {}".to_vec(),
+        );
+        writer.add_file("materials/brick/wall.vtf", vec![1, 2, 3]);
+        writer.add_file("README", b"hello".to_vec());
+        assert_eq!(writer.len(), 3);
+
+        let options = VPKWriteOptions {
+            version: 1,
+            ..Default::default()
+        };
+        let (dir, archives) = writer.build(&options).unwrap();
+        let mut buffer = BufReader::new(Cursor::new(dir));
+        let vpk =
+            VPKDirectory::read(&mut buffer, Default::default(), "test_dir.vpk".into()).unwrap();
+        assert!(vpk.header2().is_none());
+
+        let entries = vpk.entries();
+        let paths: Vec<_> = entries.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "materials/brick/wall.vmt",
+                "materials/brick/wall.vtf",
+                "readme"
+            ]
+        );
+
+        let (_, vtf) = entries[1];
+        assert_eq!(vtf.crc(), crc32fast::hash(&[1, 2, 3]));
+        let (offset, len) = (vtf.offset() as usize, vtf.len() as usize);
+        assert_eq!(&archives[0][offset..offset + len], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn md5_sections() {
+        let (dir, archives) = writer().build(&VPKWriteOptions::default()).unwrap();
+        let mut buffer = BufReader::new(Cursor::new(&dir));
+        let vpk =
+            VPKDirectory::read(&mut buffer, Default::default(), "test_dir.vpk".into()).unwrap();
+        let header2 = *vpk.header2().unwrap();
+        let (archive_md5_size, other_md5_size) = (
+            header2.archive_md5_section_size as usize,
+            header2.other_md5_section_size as usize,
+        );
+        assert_eq!(archives.len(), 1);
+        assert_eq!(archive_md5_size, 28);
+        assert_eq!(other_md5_size, 48);
+
+        let tree_end = 28 + vpk.header1().tree_size as usize;
+        let archive_md5 = &dir[tree_end..tree_end + 28];
+        assert_eq!(
+            &archive_md5[..12],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 0x28, 0x05, 0, 0]
+        );
+        assert_eq!(&archive_md5[12..], &Md5::digest(&archives[0])[..]);
+
+        let other = &dir[tree_end + 28..];
+        assert_eq!(other.len(), 48);
+        assert_eq!(&other[..16], &Md5::digest(&dir[28..tree_end])[..]);
+        assert_eq!(&other[16..32], &Md5::digest(archive_md5)[..]);
+        assert_eq!(&other[32..], &Md5::digest(&dir[..dir.len() - 16])[..]);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn round_trip() {
+        let out = std::env::temp_dir().join(format!("vpk_writer_{}", std::process::id()));
+        fs::create_dir_all(&out).unwrap();
+        let dir_path = out.join("test_dir.vpk");
+
+        let options = VPKWriteOptions {
+            preload_bytes: 16,
+            max_archive_size: 500,
+            ..Default::default()
+        };
+        writer().write(&dir_path, &options).unwrap();
+        let archives = fs::read_dir(&out).unwrap().count() - 1;
+        let vpk = VPKDirectory::load(VFileSystem::default(), dir_path).unwrap();
+        let read: Vec<_> = vpk
+            .entries()
+            .into_iter()
+            .map(|(path, file)| {
+                let preload = file.preload().map_or(0, Vec::len);
+                (
+                    path,
+                    file.crc(),
+                    preload,
+                    vpk.entry_bytes(file).unwrap().into_owned(),
+                )
+            })
+            .collect();
+        fs::remove_dir_all(&out).unwrap();
+
+        // 1144 bytes after preloads, in archives of up to 500
+        assert_eq!(archives, 3);
+        assert_eq!(vpk.max_pack_file(), 2);
+
+        let mut expected = files();
+        expected.sort();
+        assert_eq!(read.len(), expected.len());
+        for ((path, crc, preload, data), (expected_path, expected_data)) in
+            read.into_iter().zip(expected)
+        {
+            assert_eq!(path, expected_path);
+            assert_eq!(crc, crc32fast::hash(&expected_data));
+            assert_eq!(preload, expected_data.len().min(16));
+            assert_eq!(data, expected_data);
+        }
+    }
+
+    #[test]
+    fn bad_options() {
+        let build = |options| writer().build(&options).map(|_| ());
+        for version in [0, 3] {
+            let options = VPKWriteOptions {
+                version,
+                ..Default::default()
+            };
+            assert!(matches!(
+                build(options),
+                Err(SourceError::UnsupportedVersion { .. })
+            ));
+        }
+        let options = VPKWriteOptions {
+            max_archive_size: 0,
+            ..Default::default()
+        };
+        assert!(matches!(build(options), Err(SourceError::Invalid { .. })));
+    }
+
+    #[test]
+    fn too_many_archives() {
+        // Each file needs an archive of its own, so the last would take the embedded index
+        let mut writer = VPKWriter::new();
+        for i in 0..=EMBEDDED_ARCHIVE {
+            writer.add_file(&format!("file{i}.txt"), vec![1]);
+        }
+        let options = VPKWriteOptions {
+            max_archive_size: 1,
+            md5: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            writer.build(&options),
+            Err(SourceError::Invalid { .. })
+        ));
+
+        writer.add_file("file0.txt", Vec::new());
+        assert_eq!(
+            writer.build(&options).unwrap().1.len(),
+            EMBEDDED_ARCHIVE as usize
+        );
+    }
+}
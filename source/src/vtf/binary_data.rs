@@ -1,10 +1,11 @@
 use std::{
-    io::{self, BufReader, Read, Seek},
+    io::{BufReader, Read, Seek},
     mem,
 };
 
 use crate::{
     binaries::BinaryData,
+    error::{ResultExt, SourceError, SourceResult},
     vtf::{
         consts::ImageFormat,
         header::{ResourceEntryInfo, VTFHeader, VTFHeader73},
@@ -16,7 +17,7 @@ impl BinaryData for VTF {
     fn read<R: Read + Seek>(
         buffer: &mut std::io::BufReader<R>,
         _max_size: Option<usize>,
-    ) -> std::io::Result<Self> {
+    ) -> SourceResult<Self> {
         let mut data_read = 0;
 
         let header = VTFHeader::read(buffer, None)?;
@@ -25,8 +26,14 @@ impl BinaryData for VTF {
 
         //println!("Header size, {} used: {}", header_size, header_read);
 
-        assert!(header.width < 4096);
-        assert!(header.height < 4096);
+        let (width, height) = (header.width, header.height);
+        if width >= 4096 || height >= 4096 {
+            return Err(SourceError::invalid(
+                "VTFHeader",
+                0,
+                format!("Texture too large ({width}x{height})"),
+            ));
+        }
 
         let major = header.version[0];
         let minor = header.version[1];
@@ -50,7 +57,9 @@ impl BinaryData for VTF {
                     "Not all header has been read, skipping {} bytes",
                     remaining_header
                 );
-                buffer.seek_relative(remaining_header)?;
+                buffer
+                    .seek_relative(remaining_header)
+                    .at("VTFHeader", buffer)?;
                 data_read += remaining_header;
             }
 
@@ -58,7 +67,15 @@ impl BinaryData for VTF {
 
             let mut tex = Self::new_from_header(header);
 
-            assert_eq!(header_size as u32, entries[0].offset);
+            if let Some(first) = entries.first() {
+                if first.offset != header_size as u32 {
+                    log::warn!(
+                        "First resource at {} does not follow header of size {}",
+                        first.offset,
+                        header_size
+                    );
+                }
+            }
 
             println!("Loading entries");
             for entry in entries {
@@ -68,7 +85,7 @@ impl BinaryData for VTF {
                 }
 
                 let dist = entry.offset as i64 - data_read;
-                buffer.seek_relative(dist)?;
+                buffer.seek_relative(dist).at("ResourceEntryInfo", buffer)?;
 
                 match entry.tag {
                     [b'\x01', b'\0', b'\0'] => {
@@ -88,8 +105,8 @@ impl BinaryData for VTF {
                     [b'L', b'O', b'D'] => (),      //- Texture LOD control information.
                     [b'T', b'S', b'O'] => (),      //- Game-defined "extended" VTF flags.
                     [b'K', b'V', b'D'] => (),      //- Arbitrary KeyValues data.
-                    _ => {
-                        panic!("Error reading entity")
+                    tag => {
+                        log::warn!("Skipping unknown VTF resource {:?}", tag)
                     }
                 };
             }
@@ -99,7 +116,7 @@ impl BinaryData for VTF {
             // load data
             if minor == 1 {
                 let mut b = [0];
-                buffer.read_exact(&mut b)?;
+                buffer.read_exact(&mut b).at("VTFHeader", buffer)?;
 				data_read += 1;
             }
 
@@ -112,7 +129,9 @@ impl BinaryData for VTF {
                     minor,
                     remaining_header
                 );
-                buffer.seek_relative(remaining_header)?;
+                buffer
+                    .seek_relative(remaining_header)
+                    .at("VTFHeader", buffer)?;
             } 
 			
             let low_res_data: Vec<u8> = read_low_res(&header, buffer)?;
@@ -215,7 +234,7 @@ fn image_format_convert_data(
             dst[i + 1] = data[p + 1];
             dst[i + 2] = data[p + 0];
             dst[i + 3] = 0xFF;
-            p += 3;
+            p += 4;
         }
         *data = dst;
     } else if fmt == ImageFormat::I8 {
@@ -235,7 +254,7 @@ fn image_format_convert_data(
 fn read_low_res<R: Read + Seek>(
     header: &VTFHeader,
     buffer: &mut BufReader<R>,
-) -> io::Result<Vec<u8>> {
+) -> SourceResult<Vec<u8>> {
     let low_res_image_format = header.low_res_image_format;
     // load data
     let low_res_size = low_res_image_format.bytes_for_size(
@@ -246,14 +265,16 @@ fn read_low_res<R: Read + Seek>(
 
     let mut low_res_data = vec![0; low_res_size];
 
-    buffer.read_exact(&mut low_res_data[..])?;
+    buffer
+        .read_exact(&mut low_res_data[..])
+        .at("VTF low res data", buffer)?;
     Ok(low_res_data)
 }
 
 fn read_high_res<R: Read + Seek>(
     header: &VTFHeader,
     buffer: &mut BufReader<R>,
-) -> io::Result<Vec<Vec<u8>>> {
+) -> SourceResult<Vec<Vec<u8>>> {
     let smallest_size = header.width.min(header.height);
 
     // smallest texture is a 4x4
@@ -273,7 +294,7 @@ fn read_high_res<R: Read + Seek>(
             mip_level,
         ) as i64;
     }
-    buffer.seek_relative(offset)?;
+    buffer.seek_relative(offset).at("VTF mipmaps", buffer)?;
     // have to operate in reverse to load correct data
     for mip_level in (0..wanted_mips as usize).rev() {
         high_res_data[mip_level] = vec![
@@ -285,7 +306,9 @@ fn read_high_res<R: Read + Seek>(
             )
         ];
 
-        buffer.read_exact(&mut high_res_data[mip_level][..])?;
+        buffer
+            .read_exact(&mut high_res_data[mip_level][..])
+            .at("VTF high res data", buffer)?;
 
         // Do things like add empty alpha channels
         image_format_convert_data(
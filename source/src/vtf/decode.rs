@@ -0,0 +1,232 @@
+//! Software decoding of VTF image data, for when pixels are needed on the CPU instead of uploaded as they are
+
+use half::f16;
+
+use super::{consts::ImageFormat, VTF};
+use crate::error::{SourceError, SourceResult};
+
+impl VTF {
+    /// Width and height of a high res mip level
+    pub fn mip_size(&self, mip_level: usize) -> (usize, usize) {
+        (
+            (self.width() as usize >> mip_level).max(1),
+            (self.height() as usize >> mip_level).max(1),
+        )
+    }
+
+    /// Decode a high res mip level to tightly packed RGBA8, decompressing DXT blocks
+    pub fn high_res_rgba8(&self, mip_level: usize) -> SourceResult<Vec<u8>> {
+        let (width, height) = self.mip_size(mip_level);
+        decode_rgba8(
+            self.high_res_image_format(),
+            self.high_res_mip(mip_level)?,
+            width,
+            height,
+        )
+    }
+
+    /// Decode a high res mip level of a floating point texture to linear RGBA
+    pub fn high_res_rgba16f(&self, mip_level: usize) -> SourceResult<Vec<f16>> {
+        let (width, height) = self.mip_size(mip_level);
+        let format = self.high_res_image_format();
+        if format != ImageFormat::RGBA16161616F {
+            return Err(unsupported(format));
+        }
+
+        let data = checked(self.high_res_mip(mip_level)?, width * height * 8)?;
+        Ok(data
+            .chunks_exact(2)
+            .map(|c| f16::from_le_bytes([c[0], c[1]]))
+            .collect())
+    }
+
+    fn high_res_mip(&self, mip_level: usize) -> SourceResult<&[u8]> {
+        self.high_res_data()
+            .get(mip_level)
+            .map(Vec::as_slice)
+            .ok_or_else(|| SourceError::NotFound(format!("VTF mip level {mip_level}")))
+    }
+}
+
+/// Decode image data, as stored by [`VTF`] after loading, to tightly packed RGBA8.
+///
+/// Uncompressed 8 bit formats have already been swizzled to RGBA when the texture was read.
+pub fn decode_rgba8(
+    format: ImageFormat,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> SourceResult<Vec<u8>> {
+    match format {
+        ImageFormat::RGBA8888
+        | ImageFormat::ABGR8888
+        | ImageFormat::BGRA8888
+        | ImageFormat::BGRX8888
+        | ImageFormat::RGB888
+        | ImageFormat::BGR888
+        | ImageFormat::I8 => Ok(checked(data, width * height * 4)?.to_vec()),
+        ImageFormat::DXT1 | ImageFormat::DXT1ONEBITALPHA => {
+            decode_blocks::<8>(data, width, height, |block| decode_color_block(block, true))
+        }
+        ImageFormat::DXT3 => decode_blocks::<16>(data, width, height, |block| {
+            let mut colors = decode_color_block(&block[8..], false);
+            let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+            for (i, color) in colors.iter_mut().enumerate() {
+                color[3] = ((alpha >> (i * 4)) & 0xF) as u8 * 17;
+            }
+            colors
+        }),
+        ImageFormat::DXT5 => decode_blocks::<16>(data, width, height, |block| {
+            let mut colors = decode_color_block(&block[8..], false);
+            let alpha = decode_alpha_palette(block[0], block[1]);
+            let mut indices = [0; 8];
+            indices[..6].copy_from_slice(&block[2..8]);
+            let indices = u64::from_le_bytes(indices);
+            for (i, color) in colors.iter_mut().enumerate() {
+                color[3] = alpha[((indices >> (i * 3)) & 7) as usize];
+            }
+            colors
+        }),
+        format => Err(unsupported(format)),
+    }
+}
+
+fn unsupported(format: ImageFormat) -> SourceError {
+    SourceError::invalid("VTF", 0, format!("Cannot decode {format:?} images"))
+}
+
+fn checked(data: &[u8], len: usize) -> SourceResult<&[u8]> {
+    data.get(..len).ok_or_else(|| {
+        SourceError::invalid(
+            "VTF",
+            0,
+            format!("Expected {len} bytes of image data, found {}", data.len()),
+        )
+    })
+}
+
+/// Decode 4x4 blocks of `N` bytes into pixels, clipping blocks that hang over the edge of small mips
+fn decode_blocks<const N: usize>(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    decode: impl Fn(&[u8]) -> [[u8; 4]; 16],
+) -> SourceResult<Vec<u8>> {
+    let blocks_x = width.div_ceil(4);
+    let blocks = blocks_x * height.div_ceil(4);
+    let data = checked(data, blocks * N)?;
+
+    let mut pixels = vec![0; width * height * 4];
+    for (b, block) in data.chunks_exact(N).enumerate() {
+        let (bx, by) = (b % blocks_x * 4, b / blocks_x * 4);
+
+        for (i, color) in decode(block).iter().enumerate() {
+            let (x, y) = (bx + i % 4, by + i / 4);
+            if x < width && y < height {
+                let p = (y * width + x) * 4;
+                pixels[p..p + 4].copy_from_slice(color);
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+fn rgb565(color: u16) -> [u8; 4] {
+    let r = (color >> 11) as u8 & 0x1F;
+    let g = (color >> 5) as u8 & 0x3F;
+    let b = color as u8 & 0x1F;
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 0xFF]
+}
+
+/// The 8 byte colour half of any DXT block. Only DXT1 uses the 3 colour mode with transparent black.
+fn decode_color_block(block: &[u8], dxt1: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u16, wb: u16| {
+        let mut out = [0xFF; 4];
+        for i in 0..3 {
+            out[i] = ((a[i] as u16 * wa + b[i] as u16 * wb) / (wa + wb)) as u8;
+        }
+        out
+    };
+
+    let palette = if c0 > c1 || !dxt1 {
+        [a, b, mix(2, 1), mix(1, 2)]
+    } else {
+        [a, b, mix(1, 1), [0; 4]]
+    };
+
+    std::array::from_fn(|i| palette[(indices >> (i * 2)) as usize & 3])
+}
+
+fn decode_alpha_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a0, a1) = (a0 as u16, a1 as u16);
+    if a0 > a1 {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            i => ((a0 * (8 - i as u16) + a1 * (i as u16 - 1)) / 7) as u8,
+        })
+    } else {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            6 => 0,
+            7 => 0xFF,
+            i => ((a0 * (6 - i as u16) + a1 * (i as u16 - 1)) / 5) as u8,
+        })
+    }
+}
+
+#[cfg(test)]
+mod decode_tests {
+    use super::*;
+
+    #[test]
+    fn dxt1() {
+        // Pure red and pure blue endpoints, every pixel picks colour 0 except the last which is 1
+        let mut block = vec![0x00, 0xF8, 0x1F, 0x00];
+        block.extend_from_slice(&(1u32 << 30).to_le_bytes());
+
+        let pixels = decode_rgba8(ImageFormat::DXT1, &block, 4, 4).unwrap();
+        assert_eq!(&pixels[..4], &[255, 0, 0, 255]);
+        assert_eq!(&pixels[60..], &[0, 0, 255, 255]);
+
+        // c0 <= c1 switches to 3 colours and transparent black
+        let block = [0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        let pixels = decode_rgba8(ImageFormat::DXT1, &block, 4, 4).unwrap();
+        assert!(pixels.iter().all(|&c| c == 0));
+
+        // Mips smaller than a block are clipped
+        let pixels = decode_rgba8(ImageFormat::DXT1, &block, 2, 1).unwrap();
+        assert_eq!(pixels.len(), 8);
+
+        assert!(decode_rgba8(ImageFormat::DXT1, &block[..4], 4, 4).is_err());
+    }
+
+    #[test]
+    fn dxt5_alpha() {
+        // Pixel 0 uses a0, pixel 1 uses a1 and pixel 2 the first interpolated value
+        let alpha = [255, 0, 0b10_001_000, 0, 0, 0, 0, 0];
+        let color = [0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0];
+        let block = [alpha, color].concat();
+
+        let pixels = decode_rgba8(ImageFormat::DXT5, &block, 4, 4).unwrap();
+        assert_eq!(pixels[3], 255);
+        assert_eq!(pixels[7], 0);
+        assert_eq!(pixels[11], 218);
+        assert_eq!(&pixels[..3], &[255, 255, 255]);
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        assert!(decode_rgba8(ImageFormat::RGBA16161616F, &[0; 8], 1, 1).is_err());
+        assert_eq!(
+            decode_rgba8(ImageFormat::BGRA8888, &[1, 2, 3, 4], 1, 1).unwrap(),
+            [1, 2, 3, 4]
+        );
+    }
+}
@@ -2,18 +2,24 @@
 
 pub mod binary_data;
 pub mod consts;
+pub mod decode;
 mod header;
+#[cfg(test)]
+pub(crate) mod test_vtf;
 pub mod vtf;
 
 pub use vtf::VTF;
 
 #[cfg(test)]
 mod vtf_tests {
-    use std::path::PathBuf;
+    use std::{
+        io::{BufReader, Cursor},
+        path::PathBuf,
+    };
 
     use common::vpath::VGlobalPath;
 
-    use crate::vpk::VPKDirectory;
+    use crate::{binaries::BinaryData, error::SourceError, vpk::VPKDirectory, vtf::VTF};
 
     const PATH: &str =
         "D:\\Program Files (x86)\\Steam\\steamapps\\common\\Half-Life 2\\hl2\\hl2_textures_dir.vpk";
@@ -43,4 +49,12 @@ mod vtf_tests {
             .unwrap();
         println!("{:?}", data.header());
     }
+
+    #[test]
+    fn truncated() {
+        let mut buffer = BufReader::new(Cursor::new(b"VTF\0\x07\0\0\0".to_vec()));
+        let err = VTF::read(&mut buffer, None).unwrap_err();
+
+        assert!(matches!(err, SourceError::Io { .. }), "{err}");
+    }
 }
@@ -0,0 +1,31 @@
+//! Builds VTF textures in memory for tests that cannot rely on game files being installed
+
+use std::{
+    io::{BufReader, Cursor},
+    sync::Arc,
+};
+
+use super::{consts::ImageFormat, VTF};
+use crate::binaries::BinaryData;
+
+/// A version 7.2 VTF with a single mip and no low res image
+pub fn vtf(width: u16, height: u16, format: ImageFormat, data: &[u8]) -> Arc<VTF> {
+    let mut bytes = b"VTF\0".to_vec();
+    for v in [7u32, 2, 80] {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    bytes.extend_from_slice(&width.to_le_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]); // flags
+    bytes.extend_from_slice(&[1, 0, 0, 0]); // frames, first frame
+    bytes.extend_from_slice(&[0; 24]); // reflectivity and bumpmap scale
+    bytes.extend_from_slice(&(format as i32).to_le_bytes());
+    bytes.push(1);
+    bytes.extend_from_slice(&(ImageFormat::NONE as i32).to_le_bytes());
+    bytes.extend_from_slice(&[0, 0]);
+    bytes.resize(80, 0);
+    bytes.extend_from_slice(data);
+
+    let len = bytes.len();
+    Arc::new(VTF::read(&mut BufReader::new(Cursor::new(bytes)), Some(len)).unwrap())
+}